@@ -8,16 +8,17 @@ impl CPU {
     pub fn data_processing_instruction(&mut self, instruction: ARMByteCode, memory: &mut Box<dyn MemoryBus>) -> CYCLES {
         let shift_amount;
         let mut cycles = 0;
+        let shift_by_register = !instruction.bit_is_set(25) && instruction.bit_is_set(4);
         if instruction.bit_is_set(25) {
             shift_amount = ((instruction & 0x0000_0F00) >> 8) * 2;
         } else {
             // The first cycle gets the register we shift by
             // The rest of the operation happens on the next cycle in an I cycle
-            if instruction.bit_is_set(4) {
-                // shift by register
+            if shift_by_register {
                 cycles += self.advance_pipeline(memory) + 1;
                 let shift_register = (instruction & 0x0000_0F00) >> 8;
-                shift_amount = self.get_register(shift_register);
+                // only the bottom byte of Rs counts
+                shift_amount = self.get_register(shift_register) & 0xFF;
             } else {
                 shift_amount = (instruction & 0x0000_0F80) >> 7;
             }
@@ -71,18 +72,18 @@ impl CPU {
 
 
         let set_flags = instruction.bit_is_set(20) && rd != PC_REGISTER as u32;
+        // only logical operations take C from the shifter. Arithmetic ones
+        // set it from the ALU, and ADC/SBC/RSC must see the old C as carry in
+        let shifter_sets_carry =
+            set_flags && matches!(opcode, 0x0..=0x1 | 0x8..=0x9 | 0xc..=0xf);
         let operand2 = if instruction.bit_is_set(25) {
             // operand 2 is immediate
             let immediate = instruction & 0x0000_00FF;
 
             let operand2 = immediate.rotate_right(shift_amount);
-            if set_flags && operand2 > 255 {
-                match opcode {
-                    0x0..=0x1 | 0x8..=0x9 | 0xc..=0xf => {
-                        self.set_flag_from_bit(FlagsRegister::C, operand2.get_bit(31) as u8)
-                    }
-                    _ => {}
-                }
+            // an unrotated immediate leaves C alone
+            if shifter_sets_carry && shift_amount != 0 {
+                self.set_flag_from_bit(FlagsRegister::C, operand2.get_bit(31) as u8)
             }
             operand2
         } else {
@@ -92,15 +93,17 @@ impl CPU {
                 instruction,
                 shift_amount,
                 operand_register_value,
-                set_flags,
+                shifter_sets_carry,
             )
         };
         operation(self, rd, self.get_register(rn), operand2, set_flags);
         if rd == 15 {
             if instruction.bit_is_set(20) {
-                if let Some(spsr) = self.get_current_spsr() {
-                    self.cpsr = *spsr;
-                }
+                self.pop_spsr();
+            }
+            if !shift_by_register {
+                // the sequential fetch made while executing is thrown away
+                cycles += 1;
             }
             cycles += self.flush_pipeline(memory);
         }
@@ -179,9 +182,8 @@ impl CPU {
 
     pub fn arm_sbc(&mut self, rd: REGISTER, operand1: u32, operand2: u32, set_flags: bool) {
         let carry = self.get_flag(FlagsRegister::C);
-        let operand2 = operand2.twos_complement();
-        let carry = carry.twos_complement();
-        let result = operand1 + operand2 + carry;
+        let operand2 = !operand2;
+        let result = operand1 + operand2 + carry; // operand1 - operand2 - !carry
 
         self.set_arithmetic_flags(result, operand1, operand2, carry, set_flags);
         self.set_register(rd, result);
@@ -193,9 +195,8 @@ impl CPU {
 
     pub fn arm_rsc(&mut self, rd: REGISTER, operand1: u32, operand2: u32, set_flags: bool) {
         let carry = self.get_flag(FlagsRegister::C);
-        let operand1 = operand2.twos_complement();
-        let carry = carry.twos_complement();
-        let result = operand1 + operand2 + carry;
+        let operand1 = !operand1;
+        let result = operand2 + operand1 + carry; // operand2 - operand1 - !carry
 
         self.set_arithmetic_flags(result, operand1, operand2, carry, set_flags);
         self.set_register(rd, result);
@@ -283,7 +284,7 @@ impl CPU {
             match self.get_current_spsr() {
                 Some(spsr) => *spsr,
                 None => {
-                    return 1;
+                    return 0;
                 }
             }
         } else {
@@ -298,7 +299,7 @@ impl CPU {
         };
 
         self.set_executed_instruction(format_args!("MRS {} {}", rd, psr));
-        1
+        0
     }
 
     pub fn arm_msr(&mut self, instruction: ARMByteCode, memory: &mut Box<dyn MemoryBus>) -> CYCLES {
@@ -357,6 +358,18 @@ impl CPU {
         }
     }
 
+    /// MUL/MLA with S set N and Z from the result and destroy C. The
+    /// ARM7TDMI leaves the carry injected by the last Booth stage of its
+    /// multiplier there, which is only set when the multiplier's top two
+    /// bits are 0b10.
+    pub fn set_multiply_flags(&mut self, result: WORD, multiplier: WORD) {
+        self.set_logical_flags(result, true);
+        self.set_flag_from_bit(FlagsRegister::C, (multiplier >> 30 == 0b10) as u8);
+    }
+
+    /// Flags for `result = operand1 + operand2 + carry`. Subtractions
+    /// pass the inverted subtrahend and a carry in of 1 (or the C flag for
+    /// SBC/RSC), so C is set exactly when the 33-bit sum carries out.
     pub fn set_arithmetic_flags(
         &mut self,
         result: WORD,
@@ -396,84 +409,103 @@ mod tests {
     use rstest::rstest;
 
     use crate::{
-        arm7tdmi::cpu::{CPUMode, FlagsRegister, CPU},
-        memory::memory::GBAMemory,
+        arm7tdmi::cpu::{CPUMode, FlagsRegister, InstructionMode, CPU},
+        memory::memory::{GBAMemory, MemoryBus},
         types::REGISTER,
+        utils::testing::{assert_cpu_state, Harness},
     };
 
+    #[rstest]
+    #[case::lsl_uses_low_byte(0xe1b00211, 0x8000_0001, 0x101, 0x2, 1)]
+    #[case::lsl_zero_keeps_carry(0xe1b00211, 0x1234, 0x100, 0x1234, 1)]
+    #[case::lsl_32(0xe1b00211, 0x1, 32, 0, 1)]
+    #[case::lsl_over_32(0xe1b00211, 0x1, 33, 0, 0)]
+    #[case::lsr_32(0xe1b00231, 0x8000_0000, 32, 0, 1)]
+    #[case::asr_over_32(0xe1b00251, 0x8000_0000, 40, u32::MAX, 1)]
+    #[case::ror_32(0xe1b00271, 0x8000_0001, 32, 0x8000_0001, 1)]
+    #[case::ror_uses_low_byte(0xe1b00271, 0x18, 0x104, 0x8000_0001, 1)]
+    fn register_specified_shifts_use_the_low_byte_of_rs(
+        #[case] instruction: u32, // movs r0, r1, <shift> r2
+        #[case] rm: u32,
+        #[case] rs: u32,
+        #[case] expected: u32,
+        #[case] carry: u32,
+    ) {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        let mut cpu = CPU::new();
+        cpu.set_register(1, rm);
+        cpu.set_register(2, rs);
+        cpu.set_flag(FlagsRegister::C);
+
+        cpu.prefetch[0] = Some(instruction);
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(cpu.get_register(0), expected);
+        assert_eq!(cpu.get_flag(FlagsRegister::C), carry);
+    }
+
     #[test]
     fn add_instruction_should_set_carry_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_register(2, u32::MAX);
         cpu.set_register(3, 2);
 
         cpu.prefetch[0] = Some(0xe0931002); // adds r1, r3, r2;
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
-        assert!(cpu.get_register(1) == 1);
-        assert!(cpu.get_flag(FlagsRegister::C) == 1);
-        assert!(cpu.get_flag(FlagsRegister::N) == 0);
-        assert!(cpu.get_flag(FlagsRegister::Z) == 0);
-        assert!(cpu.get_flag(FlagsRegister::V) == 0);
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+        assert_cpu_state!(cpu, r1 = 1, N = 0, Z = 0, C = 1, V = 0);
     }
 
     #[test]
     fn add_instruction_should_set_overflow_and_carry_flags() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_register(2, 0x8000_0000);
         cpu.set_register(3, 0x8000_0000);
 
         cpu.prefetch[0] = Some(0xe0931002); // adds r1, r3, r2;
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
-        assert!(cpu.get_register(1) == 0);
-        assert!(cpu.get_flag(FlagsRegister::C) == 1);
-        assert!(cpu.get_flag(FlagsRegister::N) == 0);
-        assert!(cpu.get_flag(FlagsRegister::Z) == 1);
-        assert!(cpu.get_flag(FlagsRegister::V) == 1);
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+        assert_cpu_state!(cpu, r1 = 0, N = 0, Z = 1, C = 1, V = 1);
     }
 
     #[test]
     fn add_instruction_should_set_n_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_register(2, 0x8000_0000);
         cpu.set_register(3, 0x0000_0001);
 
         cpu.prefetch[0] = Some(0xe0931002); // adds r1, r3, r2;
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
-        assert!(cpu.get_register(1) == 0x8000_0001);
-        assert!(cpu.get_flag(FlagsRegister::C) == 0);
-        assert!(cpu.get_flag(FlagsRegister::N) == 1);
-        assert!(cpu.get_flag(FlagsRegister::Z) == 0);
-        assert!(cpu.get_flag(FlagsRegister::V) == 0);
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+        assert_cpu_state!(cpu, r1 = 0x8000_0001, N = 1, Z = 0, C = 0, V = 0);
     }
 
     #[test]
     fn and_instruction_should_set_c_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_register(2, 0x0000_FFFF);
         cpu.set_register(3, 0x0000_0001);
 
         cpu.prefetch[0] = Some(0xe01312a2); // ands r1, r3, r2 LSR 5;
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
         assert!(cpu.get_flag(FlagsRegister::C) == 1);
         assert!(cpu.get_flag(FlagsRegister::N) == 0);
         assert!(cpu.get_flag(FlagsRegister::Z) == 0);
@@ -483,17 +515,17 @@ mod tests {
 
     #[test]
     fn and_instruction_should_set_n_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_register(2, 0x8000_FFFF);
         cpu.set_register(3, 0x8000_0001);
 
         cpu.prefetch[0] = Some(0xe0131002); // ands r1, r3, r2;
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
         assert!(cpu.get_flag(FlagsRegister::C) == 0);
         assert!(cpu.get_flag(FlagsRegister::N) == 1);
         assert!(cpu.get_flag(FlagsRegister::Z) == 0);
@@ -503,17 +535,17 @@ mod tests {
 
     #[test]
     fn and_instruction_should_set_z_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_register(2, 0x8000_FFFF);
         cpu.set_register(3, 0x0000_0000);
 
         cpu.prefetch[0] = Some(0xe0131002); // ands r1, r3, r2;
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
         assert!(cpu.get_flag(FlagsRegister::C) == 0);
         assert!(cpu.get_flag(FlagsRegister::N) == 0);
         assert!(cpu.get_flag(FlagsRegister::Z) == 1);
@@ -523,17 +555,17 @@ mod tests {
 
     #[test]
     fn orr_instruction_should_set_z_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_register(2, 0x0000_0000);
         cpu.set_register(3, 0x0000_0000);
 
         cpu.prefetch[0] = Some(0xe1931002); // orrs r1, r3, r2;
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
         assert!(cpu.get_flag(FlagsRegister::C) == 0);
         assert!(cpu.get_flag(FlagsRegister::N) == 0);
         assert!(cpu.get_flag(FlagsRegister::Z) == 1);
@@ -543,17 +575,17 @@ mod tests {
 
     #[test]
     fn orr_instruction_should_not_set_any_flags() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_register(2, 0x0000_0000);
         cpu.set_register(3, 0x0000_0000);
 
         cpu.prefetch[0] = Some(0xe1831002); // orr r1, r3, r2;
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
         assert!(cpu.get_flag(FlagsRegister::C) == 0);
         assert!(cpu.get_flag(FlagsRegister::N) == 0);
         assert!(cpu.get_flag(FlagsRegister::Z) == 0);
@@ -563,17 +595,17 @@ mod tests {
 
     #[test]
     fn eor_instruction_should_set_n_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_register(2, 0x8001_0002);
         cpu.set_register(3, 0x1000_0010);
 
         cpu.prefetch[0] = Some(0xe0331002); // eors r1, r3, r2;
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
         assert!(cpu.get_flag(FlagsRegister::C) == 0);
         assert!(cpu.get_flag(FlagsRegister::N) == 1);
         assert!(cpu.get_flag(FlagsRegister::Z) == 0);
@@ -583,17 +615,17 @@ mod tests {
 
     #[test]
     fn teq_instruction_should_set_n_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_register(2, 0x8001_0002);
         cpu.set_register(3, 0x1000_0010);
 
         cpu.prefetch[0] = Some(0xe1330002); // teq r3, r2;
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
         assert!(cpu.get_flag(FlagsRegister::C) == 0);
         assert!(cpu.get_flag(FlagsRegister::N) == 1);
         assert!(cpu.get_flag(FlagsRegister::Z) == 0);
@@ -602,17 +634,17 @@ mod tests {
 
     #[test]
     fn teq_instruction_should_set_z_flag_when_equal() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_register(2, 0x8001_0002);
         cpu.set_register(3, 0x8001_0002);
 
         cpu.prefetch[0] = Some(0xe1330002); // teq r3, r2;
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
         assert!(cpu.get_flag(FlagsRegister::C) == 0);
         assert!(cpu.get_flag(FlagsRegister::N) == 0);
         assert!(cpu.get_flag(FlagsRegister::Z) == 1);
@@ -621,17 +653,17 @@ mod tests {
 
     #[test]
     fn tst_instruction_should_set_z_flag_when_no_bits_match() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_register(2, 0x8001_0002);
         cpu.set_register(3, 0x0110_2224);
 
         cpu.prefetch[0] = Some(0xe1130002); // tst r3, r2;
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
         assert!(cpu.get_flag(FlagsRegister::C) == 0);
         assert!(cpu.get_flag(FlagsRegister::N) == 0);
         assert!(cpu.get_flag(FlagsRegister::Z) == 1);
@@ -640,17 +672,17 @@ mod tests {
 
     #[test]
     fn bic_instruction_should_reset_all_bits() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_register(3, 0x8001_0002);
         cpu.set_register(2, 0x80F1_0102);
 
         cpu.prefetch[0] = Some(0xe1d31002); // bics r1, r3, r2;
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
         assert!(cpu.get_flag(FlagsRegister::C) == 0);
         assert!(cpu.get_flag(FlagsRegister::N) == 0);
         assert!(cpu.get_flag(FlagsRegister::Z) == 1);
@@ -660,9 +692,9 @@ mod tests {
 
     #[test]
     fn data_processing_with_pc_as_operand2_and_register_shift_delays_pc() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.prefetch[0] = Some(0xe094131f); // adds r1, r3, r15, LSL r3; pc = 0
 
@@ -670,9 +702,9 @@ mod tests {
         let test_pc = 4; // points at next instruction
         cpu.set_pc(test_pc);
 
-        cpu.execute_cpu_cycle(); // pc == 8
-        cpu.execute_cpu_cycle(); // pc == 12
-        cpu.execute_cpu_cycle(); // pc == 16
+        cpu.execute_cpu_cycle(&mut memory); // pc == 8
+        cpu.execute_cpu_cycle(&mut memory); // pc == 12
+        cpu.execute_cpu_cycle(&mut memory); // pc == 16
         assert!(cpu.get_register(1) == (test_pc + 8) << 1);
         assert!(cpu.get_flag(FlagsRegister::C) == 0);
         assert!(cpu.get_flag(FlagsRegister::N) == 0);
@@ -682,9 +714,9 @@ mod tests {
 
     #[test]
     fn data_processing_with_pc_as_operand1_and_register_shift_delays_pc() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.prefetch[0] = Some(0xe09f1314); //  adds r1, pc, r4, lsl r3; pc = 0
 
@@ -693,9 +725,9 @@ mod tests {
         let test_pc = 4; // points at next instruction
         cpu.set_pc(test_pc);
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
         assert!(cpu.get_register(1) == test_pc + 8);
         assert!(cpu.get_flag(FlagsRegister::C) == 0);
         assert!(cpu.get_flag(FlagsRegister::N) == 0);
@@ -705,26 +737,26 @@ mod tests {
 
     #[test]
     fn data_processing_with_pc_as_destination_should_start_from_result() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
-        let _res = cpu.memory.writeu32(0x3000000, 0xe25f1008);
-        let _res = cpu.memory.writeu32(0x3000004, 0xe1a00000);
-        let _res = cpu.memory.writeu32(0x3000008, 0xe1a00000); // nop
-        let _res = cpu.memory.writeu32(0x300000C, 0xe1a00000); // nop
-        let _res = cpu.memory.writeu32(0x3000010, 0xe1a00000); // nop
-        let _res = cpu.memory.writeu32(0x3000014, 0xe281f000);
+        let _res = memory.writeu32(0x3000000, 0xe25f1008);
+        let _res = memory.writeu32(0x3000004, 0xe1a00000);
+        let _res = memory.writeu32(0x3000008, 0xe1a00000); // nop
+        let _res = memory.writeu32(0x300000C, 0xe1a00000); // nop
+        let _res = memory.writeu32(0x3000010, 0xe1a00000); // nop
+        let _res = memory.writeu32(0x3000014, 0xe281f000);
 
         cpu.set_pc(0x3000000);
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
         assert_eq!(
             cpu.decode_instruction(cpu.prefetch[1].unwrap()).instruction,
             0xe25f1008
@@ -733,16 +765,16 @@ mod tests {
 
     #[test]
     fn mov_instruction_should_set_n_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_register(3, 0x8001_0002);
 
         cpu.prefetch[0] = Some(0xe1b04003); // mov r4, r3;
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
         assert!(cpu.get_flag(FlagsRegister::C) == 0);
         assert!(cpu.get_flag(FlagsRegister::N) == 1);
         assert!(cpu.get_flag(FlagsRegister::Z) == 0);
@@ -753,17 +785,17 @@ mod tests {
 
     #[test]
     fn mvn_instruction_should_set_z_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let input = 0xFFFF_FFFF;
         cpu.set_register(4, input);
 
         cpu.prefetch[0] = Some(0xe1f05004); // mvn r5, r4;
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
         assert!(cpu.get_flag(FlagsRegister::C) == 0);
         assert!(cpu.get_flag(FlagsRegister::N) == 0);
         assert!(cpu.get_flag(FlagsRegister::Z) == 1);
@@ -774,9 +806,9 @@ mod tests {
 
     #[test]
     fn adc_instruction_should_add_2_registers_and_carry() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_register(1, 25);
         cpu.set_register(2, 32);
@@ -784,8 +816,8 @@ mod tests {
 
         cpu.prefetch[0] = Some(0xe0b14002); // adcs r4, r2, r1;
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
         assert!(cpu.get_flag(FlagsRegister::C) == 0);
         assert!(cpu.get_flag(FlagsRegister::N) == 0);
         assert!(cpu.get_flag(FlagsRegister::Z) == 0);
@@ -795,9 +827,9 @@ mod tests {
 
     #[test]
     fn adc_instruction_should_set_carry_register() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_register(1, 0xFFFF_FFFF);
         cpu.set_register(2, 0x0);
@@ -805,8 +837,8 @@ mod tests {
 
         cpu.prefetch[0] = Some(0xe0b14002); // adcs r4, r2, r1;
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
         assert!(cpu.get_flag(FlagsRegister::C) == 1);
         assert!(cpu.get_flag(FlagsRegister::N) == 0);
         assert!(cpu.get_flag(FlagsRegister::Z) == 1);
@@ -816,9 +848,9 @@ mod tests {
 
     #[test]
     fn adc_instruction_should_set_v_register() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_register(1, 0x8000_0000);
         cpu.set_register(2, 0x8FFF_FFFF);
@@ -826,8 +858,8 @@ mod tests {
 
         cpu.prefetch[0] = Some(0xe0b14002); // adcs r4, r2, r1;
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
         assert!(cpu.get_flag(FlagsRegister::C) == 1);
         assert!(cpu.get_flag(FlagsRegister::N) == 0);
         assert!(cpu.get_flag(FlagsRegister::Z) == 0);
@@ -837,17 +869,17 @@ mod tests {
 
     #[test]
     fn sub_instruction_should_set_v_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_register(1, 0x7FFF_FFFF);
         cpu.set_register(2, 0xFFFF_FFFF); // twos complement of -1
 
         cpu.prefetch[0] = Some(0xe0514002); // subs r4, r1, r2;
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
         assert_eq!(cpu.get_flag(FlagsRegister::C), 0);
         assert_eq!(cpu.get_flag(FlagsRegister::N), 1);
         assert_eq!(cpu.get_flag(FlagsRegister::Z), 0);
@@ -857,16 +889,16 @@ mod tests {
 
     #[test]
     fn sub_instruction_should_reset_c_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_register(1, 5);
         cpu.set_register(2, 10);
 
         cpu.prefetch[1] = Some(0xe0514002); // subs r4, r1, r2;
 
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
         assert!(cpu.get_register(4) == 0xFFFF_FFFB);
         assert!(cpu.get_flag(FlagsRegister::C) == 0);
         assert!(cpu.get_flag(FlagsRegister::N) == 1);
@@ -876,17 +908,17 @@ mod tests {
 
     #[test]
     fn sub_instruction_should_set_c_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_register(1, 10);
         cpu.set_register(2, 5);
 
         cpu.prefetch[0] = Some(0xe0514002); // subs r4, r1, r2;
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
         assert!(cpu.get_register(4) == 0x5);
         assert!(cpu.get_flag(FlagsRegister::C) == 1);
         assert!(cpu.get_flag(FlagsRegister::N) == 0);
@@ -903,15 +935,15 @@ mod tests {
         #[case] expected_dst: REGISTER,
         #[case] expected_val: u32,
     ) {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.cpsr = cpsr;
 
         cpu.prefetch[0] = Some(opcode);
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(expected_dst), expected_val);
     }
@@ -932,16 +964,16 @@ mod tests {
         #[case] register: u32,
         #[case] expected_val: u32,
     ) {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_mode(mode);
         cpu.set_register(register, psr_val);
 
         cpu.prefetch[0] = Some(opcode);
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.cpsr, expected_val);
     }
@@ -957,16 +989,16 @@ mod tests {
         #[case] register: u32,
         #[case] expected_val: u32,
     ) {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_mode(mode);
         cpu.set_register(register, psr_val);
 
         cpu.prefetch[0] = Some(opcode);
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(*cpu.get_current_spsr().unwrap(), expected_val);
     }
@@ -979,16 +1011,160 @@ mod tests {
         #[case] mode: CPUMode,
         #[case] expected_val: u32,
     ) {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_mode(mode);
 
         cpu.prefetch[0] = Some(opcode);
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.cpsr, expected_val);
     }
+
+    #[derive(Clone, Copy, Debug)]
+    enum ArithmeticOp {
+        Add,
+        Sub,
+        Adc,
+        Sbc,
+    }
+
+    impl ArithmeticOp {
+        /// Result and carry out computed on 64-bit integers.
+        fn reference(self, a: u32, b: u32, carry_in: bool) -> (u32, bool) {
+            let (a, b, c) = (a as u64, b as u64, carry_in as u64);
+            let (result, carry) = match self {
+                ArithmeticOp::Add => (a + b, a + b > 0xFFFF_FFFF),
+                ArithmeticOp::Sub => (a.wrapping_sub(b), a >= b),
+                ArithmeticOp::Adc => (a + b + c, a + b + c > 0xFFFF_FFFF),
+                ArithmeticOp::Sbc => (a.wrapping_sub(b + 1 - c), a >= b + 1 - c),
+            };
+            (result as u32, carry)
+        }
+
+        /// `<op>s r0, r1, r2` with r1 = a and r2 = b.
+        fn run_arm(self, a: u32, b: u32, carry_in: bool) -> (u32, u32) {
+            let opcode = match self {
+                ArithmeticOp::Add => 0xe0910002,
+                ArithmeticOp::Sub => 0xe0510002,
+                ArithmeticOp::Adc => 0xe0b10002,
+                ArithmeticOp::Sbc => 0xe0d10002,
+            };
+            let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+            let mut cpu = CPU::new();
+            cpu.set_flag_from_bit(FlagsRegister::C, carry_in as u8);
+            cpu.set_register(1, a);
+            cpu.set_register(2, b);
+            cpu.prefetch[0] = Some(opcode);
+            cpu.execute_cpu_cycle(&mut memory);
+            cpu.execute_cpu_cycle(&mut memory);
+            (cpu.get_register(0), cpu.cpsr & 0xF000_0000)
+        }
+
+        /// `adds r0, r1, r2`/`subs r0, r1, r2`, or `adc r0, r1`/`sbc r0, r1`.
+        fn run_thumb(self, a: u32, b: u32, carry_in: bool) -> (u32, u32) {
+            let (opcode, first, second) = match self {
+                ArithmeticOp::Add => (0x1888, 1, 2),
+                ArithmeticOp::Sub => (0x1a88, 1, 2),
+                ArithmeticOp::Adc => (0x4148, 0, 1),
+                ArithmeticOp::Sbc => (0x4188, 0, 1),
+            };
+            let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+            let mut cpu = CPU::new();
+            cpu.set_instruction_mode(InstructionMode::THUMB);
+            cpu.set_flag_from_bit(FlagsRegister::C, carry_in as u8);
+            cpu.set_register(first, a);
+            cpu.set_register(second, b);
+            cpu.prefetch[0] = Some(opcode);
+            cpu.execute_cpu_cycle(&mut memory);
+            cpu.execute_cpu_cycle(&mut memory);
+            (cpu.get_register(0), cpu.cpsr & 0xF000_0000)
+        }
+    }
+
+    #[rstest]
+    fn arm_and_thumb_arithmetic_agree_on_carry(
+        #[values(ArithmeticOp::Add, ArithmeticOp::Sub, ArithmeticOp::Adc, ArithmeticOp::Sbc)]
+        op: ArithmeticOp,
+        #[values(
+            (0, 0),
+            (1, u32::MAX),
+            (5, 7),
+            (7, 5),
+            (7, 7),
+            (0x7FFF_FFFF, 1),
+            (0x8000_0000, 0x8000_0000),
+            (u32::MAX, u32::MAX)
+        )]
+        operands: (u32, u32),
+        #[values(false, true)] carry_in: bool,
+    ) {
+        let (a, b) = operands;
+        let (expected_result, expected_carry) = op.reference(a, b, carry_in);
+
+        let (arm_result, arm_flags) = op.run_arm(a, b, carry_in);
+        let (thumb_result, thumb_flags) = op.run_thumb(a, b, carry_in);
+
+        assert_eq!(arm_result, expected_result);
+        assert_eq!(arm_flags & 1 << 29 != 0, expected_carry);
+        assert_eq!((thumb_result, thumb_flags), (arm_result, arm_flags));
+    }
+
+    /// C is the inverted borrow: SBC computes `rn + !op2 + C`, so it only
+    /// borrows when the carry in is clear or the operand is larger.
+    #[rstest]
+    #[case::sbc_borrows_with_carry_clear(0xe0d10002, 5, 5, 0, 0xFFFF_FFFF, 0)]
+    #[case::sbc_no_borrow_with_carry_set(0xe0d10002, 5, 5, 1, 0, 1)]
+    #[case::sbc_no_borrow_from_smaller_operand(0xe0d10002, 5, 3, 0, 1, 1)]
+    #[case::sbc_borrows_from_larger_operand(0xe0d10002, 3, 5, 1, 0xFFFF_FFFE, 0)]
+    #[case::rsc_borrows_with_carry_clear(0xe0f10002, 5, 5, 0, 0xFFFF_FFFF, 0)]
+    #[case::rsc_no_borrow_with_carry_set(0xe0f10002, 5, 5, 1, 0, 1)]
+    fn subtract_with_carry_sets_c_to_not_borrow(
+        #[case] opcode: u32, // sbcs r0, r1, r2 or rscs r0, r1, r2
+        #[case] r1: u32,
+        #[case] r2: u32,
+        #[case] carry_in: u8,
+        #[case] result: u32,
+        #[case] carry_out: u32,
+    ) {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        let mut cpu = CPU::new();
+        cpu.set_flag_from_bit(FlagsRegister::C, carry_in);
+        cpu.set_register(1, r1);
+        cpu.set_register(2, r2);
+
+        cpu.prefetch[0] = Some(opcode);
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_cpu_state!(cpu, r0 = result, Z = (result == 0), C = carry_out, V = 0);
+    }
+
+    /// The shifter carry of one instruction must not reach the next, nor
+    /// the carry in of an arithmetic operation.
+    #[rstest]
+    #[case::ands_register_keeps_the_carry(0x8000_0000, 0xe0132004, 0, 1)]
+    #[case::ands_rotated_immediate_sets_bit_31(0x8000_0000, 0xe2132104, 1, 0)]
+    #[case::adcs_adds_the_old_carry_not_the_shifter_carry(0x1, 0xe0b32084, 1, 0)]
+    fn carry_follows_the_second_instruction_after_a_shifted_movs(
+        #[case] r1: u32,
+        #[case] second: u32, // ands r2, r3, r4 / ands r2, r3, #1 (ror 2) / adcs r2, r3, r4, lsl #1
+        #[case] r2: u32,
+        #[case] carry: u32,
+    ) {
+        let harness = Harness::new()
+            .reg(1, r1)
+            .reg(3, 1)
+            .reg(4, 0x8000_0000)
+            .asm(&[
+                0xe1b00081, // movs r0, r1, lsl #1
+                second,
+            ])
+            .run();
+
+        assert_cpu_state!(harness, r2 = r2, C = carry);
+    }
 }
@@ -1,7 +1,7 @@
 use std::mem::size_of;
 
 use crate::{
-    arm7tdmi::cpu::{CPUMode, CPU, PC_REGISTER}, memory::memory::MemoryBus, types::{CYCLES, REGISTER, WORD}, utils::{bits::{sign_extend, Bits}, utils::print_vec}
+    arm7tdmi::cpu::{CPU, PC_REGISTER}, memory::memory::MemoryBus, types::{CYCLES, REGISTER, WORD}, utils::{bits::{sign_extend, Bits}, utils::print_vec}
 };
 
 impl CPU {
@@ -15,8 +15,6 @@ impl CPU {
         let pre_indexed_addressing: bool = instruction.bit_is_set(24);
         let write_back_address: bool = !pre_indexed_addressing || instruction.bit_is_set(21);
         let rd = (instruction & 0x0000_F000) >> 12;
-        let force_non_privileged_access: bool =
-            pre_indexed_addressing && instruction.bit_is_set(21);
         let is_byte_transfer: bool = instruction.bit_is_set(22);
 
         if use_register_offset {
@@ -50,19 +48,15 @@ impl CPU {
             base_register_address
         };
 
-        let old_cpu_mode = self.get_cpu_mode();
-        if force_non_privileged_access {
-            self.set_mode(CPUMode::USER);
-        }
-
+        // LDRT/STRT (post-indexed with bit 21 set) make the access as if
+        // from User mode. The GBA has no memory protection, so that reaches
+        // the same memory, and the registers stay those of the current mode.
         cycles += if instruction.bit_is_set(20) {
             self.ldr_instruction_execution(rd, access_address, is_byte_transfer, memory)
         } else {
             self.str_instruction_execution(rd, access_address, is_byte_transfer, memory)
         };
 
-        self.set_mode(old_cpu_mode);
-
         if write_back_address {
             self.set_register(base_register, offset_address);
         }
@@ -210,14 +204,16 @@ impl CPU {
         cycles
     }
 
+    /// A halfword load from an odd address reads the aligned halfword and
+    /// rotates it right by 8, putting the addressed byte in the low byte.
     pub fn ldrh_execution(&mut self, rd: REGISTER, address: u32, memory: &mut Box<dyn MemoryBus>) -> CYCLES {
         let mut cycles = 1;
         let memory_fetch = { memory.readu16(address as usize) };
 
         cycles += memory_fetch.cycles;
-        let data = memory_fetch.data;
+        let data = (memory_fetch.data as WORD).rotate_right(8 * (address & 0b1));
 
-        self.set_register(rd, data.into());
+        self.set_register(rd, data);
         if rd as usize == PC_REGISTER {
             cycles += self.flush_pipeline(memory);
         }
@@ -228,9 +224,6 @@ impl CPU {
 
     pub fn block_dt_execution(&mut self, instruction: u32, memory: &mut Box<dyn MemoryBus>) -> CYCLES {
         let mut cycles = 0;
-        if instruction.bit_is_set(22) {
-            todo!("Implement S bit");
-        }
 
         let opcode = (instruction & 0x01F0_0000) >> 20;
 
@@ -243,32 +236,62 @@ impl CPU {
                 register_list.push(i as u32);
             }
         }
+        let loads_pc = instruction.bit_is_set(20) && register_list.contains(&(PC_REGISTER as u32));
 
         cycles += self.advance_pipeline(memory);
 
-        cycles += match opcode {
-            0b00000 => self.stmda_execution(base_address, &register_list, None, memory),
-            0b00001 => self.ldmda_execution(base_address, &register_list, None, memory),
-            0b00010 => self.stmda_execution(base_address, &register_list, Some(base_register), memory),
-            0b00011 => self.ldmda_execution(base_address, &register_list, Some(base_register), memory),
-            0b01000 => self.stmia_execution(base_address, &register_list, None, memory),
-            0b01001 => self.ldmia_execution(base_address, &register_list, None, memory),
-            0b01010 => self.stmia_execution(base_address, &register_list, Some(base_register), memory),
-            0b01011 => self.ldmia_execution(base_address, &register_list, Some(base_register), memory),
-            0b10000 => self.stmdb_execution(base_address, &register_list, None, memory),
-            0b10001 => self.ldmdb_execution(base_address, &register_list, None, memory),
-            0b10010 => self.stmdb_execution(base_address, &register_list, Some(base_register), memory),
-            0b10011 => self.ldmdb_execution(base_address, &register_list, Some(base_register), memory),
-            0b11000 => self.stmib_execution(base_address, &register_list, None, memory),
-            0b11001 => self.ldmib_execution(base_address, &register_list, None, memory),
-            0b11010 => self.stmib_execution(base_address, &register_list, Some(base_register), memory),
-            0b11011 => self.ldmib_execution(base_address, &register_list, Some(base_register), memory),
-            _ => todo!(),
+        // with the S bit, LDM with pc is an exception return and everything
+        // else transfers the user bank
+        cycles += if instruction.bit_is_set(22) && !loads_pc {
+            self.with_user_bank(|cpu| {
+                cpu.block_transfer(opcode, base_address, &register_list, base_register, memory)
+            })
+        } else {
+            self.block_transfer(opcode, base_address, &register_list, base_register, memory)
         };
 
+        if loads_pc {
+            if instruction.bit_is_set(22) {
+                self.pop_spsr();
+            }
+            // ARMv4 never switches state on a loaded pc, the low bits are
+            // just dropped
+            self.set_pc(self.get_pc() & !(self.instruction_size() - 1));
+            cycles += self.flush_pipeline(memory);
+        }
+
         cycles
     }
 
+    fn block_transfer(
+        &mut self,
+        opcode: u32,
+        base_address: usize,
+        register_list: &Vec<REGISTER>,
+        base_register: REGISTER,
+        memory: &mut Box<dyn MemoryBus>
+    ) -> CYCLES {
+        match opcode & 0b11011 {
+            0b00000 => self.stmda_execution(base_address, register_list, None, memory),
+            0b00001 => self.ldmda_execution(base_address, register_list, None, memory),
+            0b00010 => self.stmda_execution(base_address, register_list, Some(base_register), memory),
+            0b00011 => self.ldmda_execution(base_address, register_list, Some(base_register), memory),
+            0b01000 => self.stmia_execution(base_address, register_list, None, memory),
+            0b01001 => self.ldmia_execution(base_address, register_list, None, memory),
+            0b01010 => self.stmia_execution(base_address, register_list, Some(base_register), memory),
+            0b01011 => self.ldmia_execution(base_address, register_list, Some(base_register), memory),
+            0b10000 => self.stmdb_execution(base_address, register_list, None, memory),
+            0b10001 => self.ldmdb_execution(base_address, register_list, None, memory),
+            0b10010 => self.stmdb_execution(base_address, register_list, Some(base_register), memory),
+            0b10011 => self.ldmdb_execution(base_address, register_list, Some(base_register), memory),
+            0b11000 => self.stmib_execution(base_address, register_list, None, memory),
+            0b11001 => self.ldmib_execution(base_address, register_list, None, memory),
+            0b11010 => self.stmib_execution(base_address, register_list, Some(base_register), memory),
+            0b11011 => self.ldmib_execution(base_address, register_list, Some(base_register), memory),
+            _ => unreachable!(),
+        }
+    }
+
     pub fn stmia_execution(
         &mut self,
         base_address: usize,
@@ -310,7 +333,8 @@ impl CPU {
             self.set_register(*register, data);
             curr_address += size_of::<WORD>();
         }
-        if let Some(reg) = writeback_register {
+        // when the base is also loaded, the loaded value wins over writeback
+        if let Some(reg) = writeback_register.filter(|reg| !register_list.contains(reg)) {
             self.set_register(reg, curr_address as u32);
         }
         self.set_executed_instruction(format_args!(
@@ -362,7 +386,7 @@ impl CPU {
             let data = memory_fetch.data;
             self.set_register(*register, data);
         }
-        if let Some(reg) = writeback_register {
+        if let Some(reg) = writeback_register.filter(|reg| !register_list.contains(reg)) {
             self.set_register(reg, curr_address as u32);
         }
         self.set_executed_instruction(format_args!(
@@ -408,7 +432,7 @@ impl CPU {
             base_address,
             print_vec(register_list)
         ));
-        if let Some(reg) = writeback_register {
+        if let Some(reg) = writeback_register.filter(|reg| !register_list.contains(reg)) {
             self.set_register(reg, base_address as u32);
         }
 
@@ -452,7 +476,7 @@ impl CPU {
             print_vec(register_list)
         ));
 
-        if let Some(reg) = writeback_register {
+        if let Some(reg) = writeback_register.filter(|reg| !register_list.contains(reg)) {
             self.set_register(reg, base_address as u32);
         }
 
@@ -462,141 +486,145 @@ impl CPU {
 
 #[cfg(test)]
 mod sdt_tests {
+    use rstest::rstest;
+
     use crate::{
-        arm7tdmi::cpu::CPU,
+        arm7tdmi::cpu::{CPUMode, InstructionMode, CPU},
+        gba::GBA,
         memory::memory::{GBAMemory, MemoryBus},
+        utils::testing::load_arm_program,
     };
 
     #[test]
     fn ldr_should_return_data_at_specified_address() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let value = 0xFABCD321;
         let address: u32 = 0x3000200;
 
-        let _res = cpu.memory.writeu32(address as usize, value);
+        let _res = memory.writeu32(address as usize, value);
 
         cpu.set_register(1, address);
 
         cpu.prefetch[0] = Some(0xe5912000); // ldr r2, [r1]
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(2), value);
     }
 
     #[test]
     fn ldr_should_return_data_at_specified_address_plus_offset() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let value = 0xFABCD321;
         let address: u32 = 0x3000200;
 
-        let _res = cpu.memory.writeu32(address as usize + 8, value);
+        let _res = memory.writeu32(address as usize + 8, value);
 
         cpu.set_register(1, address);
 
         cpu.prefetch[0] = Some(0xe5912008); // ldr r2, [r1, 8]
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(2), value);
     }
 
     #[test]
     fn ldr_should_return_data_at_specified_address_minus_offset() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let value = 0xFABCD321;
         let address: u32 = 0x3000208;
 
-        let _res = cpu.memory.writeu32(address as usize - 8, value);
+        let _res = memory.writeu32(address as usize - 8, value);
 
         cpu.set_register(1, address);
 
         cpu.prefetch[0] = Some(0xe5112008); // ldr r2, [r1, -8]
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(2), value);
     }
 
     #[test]
     fn ldr_should_return_data_at_lsl_shifted_address() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let value = 0xFABCD321;
         let address: u32 = 0x3000200;
 
-        let _res = cpu.memory.writeu32(address as usize + 8, value);
+        let _res = memory.writeu32(address as usize + 8, value);
 
         cpu.set_register(1, address);
         cpu.set_register(3, 4);
 
         cpu.prefetch[0] = Some(0xe7912083); //  ldr r2, [r1, r3, lsl 1]
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(2), value);
     }
 
     #[test]
     fn ldr_should_return_a_byte_at_address() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let value = 0xFABCD321;
         let address: u32 = 0x3000200;
 
-        let _res = cpu.memory.writeu32(address as usize, value);
+        let _res = memory.writeu32(address as usize, value);
 
         cpu.set_register(1, address);
 
         cpu.prefetch[0] = Some(0xe5d12000); //  ldrb r2, [r1]
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(2), value & 0xFF);
 
         cpu.prefetch[0] = Some(0xe5d12001); //  ldrb r2, [r1, 1]
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(2), (value & 0xFF00) >> 8);
     }
 
     #[test]
     fn ldr_should_rotate_value_when_not_word_aligned() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let value = 0xFABCD321;
         let address: u32 = 0x3000202;
 
-        let _res = cpu.memory.writeu32(0x3000200, value);
+        let _res = memory.writeu32(0x3000200, value);
 
         cpu.set_register(1, address);
 
         cpu.prefetch[0] = Some(0xe5912000); // ldr r2, [r1]
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(2), 0xD321FABC);
 
@@ -604,38 +632,142 @@ mod sdt_tests {
 
         cpu.prefetch[0] = Some(0xe5912000); // ldr r2, [r1]
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
         assert_eq!(cpu.get_register(2), 0xBCD321FA);
     }
 
+    #[rstest]
+    #[case(0, 0xFABCD321)]
+    #[case(1, 0x21FABCD3)]
+    #[case(2, 0xD321FABC)]
+    #[case(3, 0xBCD321FA)]
+    fn ldr_rotates_the_addressed_byte_into_the_low_byte(#[case] offset: u32, #[case] expected: u32) {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+
+        let _res = memory.writeu32(0x3000200, 0xFABCD321);
+
+        cpu.set_register(1, 0x3000200 + offset);
+
+        cpu.prefetch[0] = Some(0xe5912000); // ldr r2, [r1]
+
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(cpu.get_register(2), expected);
+    }
+
+    #[rstest]
+    #[case(0, 0xD321)]
+    #[case(1, 0x2100_00D3)]
+    #[case(2, 0xFABC)]
+    #[case(3, 0xBC00_00FA)]
+    fn ldrh_rotates_a_misaligned_halfword(#[case] offset: u32, #[case] expected: u32) {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+
+        let _res = memory.writeu32(0x3000200, 0xFABCD321);
+
+        cpu.set_register(1, 0x3000200 + offset);
+
+        cpu.prefetch[0] = Some(0xe1d120b0); // ldrh r2, [r1]
+
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(cpu.get_register(2), expected);
+    }
+
     #[test]
     fn ldr_should_writeback_when_post_indexed() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let value = 0xFABCD321;
         let address: u32 = 0x3000200;
 
-        let _res = cpu.memory.writeu32(address as usize, value);
+        let _res = memory.writeu32(address as usize, value);
 
         cpu.set_register(1, address);
 
         cpu.prefetch[0] = Some(0xe4912004); // ldr r2, [r1], 4
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(2), value);
         assert_eq!(cpu.get_register(1), address + 4);
     }
 
+    #[test]
+    fn ldrt_and_strt_use_the_banked_registers_of_a_privileged_mode() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+        cpu.set_mode(CPUMode::USER);
+        cpu.set_register(8, 0);
+        cpu.set_mode(CPUMode::FIQ);
+
+        let value = 0xFABCD321;
+        let address: u32 = 0x3000200;
+
+        let _res = memory.writeu32(address as usize, value);
+
+        cpu.set_register(9, address);
+
+        cpu.prefetch[0] = Some(0xe4b98004); // ldrt r8, [r9], 4
+
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(cpu.get_cpu_mode(), CPUMode::FIQ);
+        assert_eq!(cpu.get_register(8), value);
+        assert_eq!(cpu.get_register(9), address + 4);
+
+        cpu.prefetch[0] = Some(0xe4a98004); // strt r8, [r9], 4
+
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(memory.readu32(address as usize + 4).data, value);
+        assert_eq!(cpu.get_register(9), address + 8);
+
+        cpu.set_mode(CPUMode::USER);
+        assert_eq!(cpu.get_register(8), 0);
+    }
+
+    #[test]
+    fn ldr_with_pre_indexed_writeback_stays_in_the_current_mode() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+        cpu.set_mode(CPUMode::FIQ);
+
+        let value = 0xFABCD321;
+        let address: u32 = 0x3000200;
+
+        let _res = memory.writeu32(address as usize + 4, value);
+
+        cpu.set_register(9, address);
+
+        cpu.prefetch[0] = Some(0xe5b98004); // ldr r8, [r9, 4]!
+
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(cpu.get_register(8), value);
+        assert_eq!(cpu.get_register(9), address + 4);
+    }
+
     #[test]
     fn str_should_store_word_at_memory_address() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let value = 0xFABCD321;
         let address: u32 = 0x3000200;
@@ -645,19 +777,19 @@ mod sdt_tests {
 
         cpu.prefetch[0] = Some(0xe5812000); // str r2, [r1]
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
-        let stored_value = cpu.memory.readu32(address as usize).data;
+        let stored_value = memory.readu32(address as usize).data;
 
         assert_eq!(value, stored_value);
     }
 
     #[test]
     fn str_should_store_byte_at_address() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let value: u8 = 0x21;
         let address: u32 = 0x3000203;
@@ -667,19 +799,19 @@ mod sdt_tests {
 
         cpu.prefetch[0] = Some(0xe5c12000); // strb r2, [r1]
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
-        let stored_value = cpu.memory.read(address as usize).data;
+        let stored_value = memory.read(address as usize).data;
 
         assert_eq!(value, stored_value);
     }
 
     #[test]
     fn strh_should_store_hw_at_address() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let value: u16 = 0x21;
         let address: u32 = 0x3000200;
@@ -689,19 +821,19 @@ mod sdt_tests {
 
         cpu.prefetch[0] = Some(0xe1c130b0); // strh r3, [r1]
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
-        let stored_value = cpu.memory.readu16(address as usize).data;
+        let stored_value = memory.readu16(address as usize).data;
 
         assert_eq!(value as u16, stored_value);
     }
 
     #[test]
     fn strh_should_only_store_bottom_half_of_register() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let value: u32 = 0x1234_5678;
         let address: u32 = 0x3000200;
@@ -711,93 +843,93 @@ mod sdt_tests {
 
         cpu.prefetch[0] = Some(0xe1c130b0); // strh r3, [r1]
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
-        let stored_value = cpu.memory.readu32(address as usize).data;
+        let stored_value = memory.readu32(address as usize).data;
 
         assert_eq!(value & 0x0000_FFFF, stored_value);
     }
 
     #[test]
     fn ldrh_should_only_load_bottom_half_of_register() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let value = 0xFABCD321;
         let address: u32 = 0x3000200;
 
-        let _res = cpu.memory.writeu32(address as usize, value);
+        let _res = memory.writeu32(address as usize, value);
 
         cpu.set_register(1, address);
         cpu.prefetch[0] = Some(0xe1d130b0); // ldrh r3, [r1]
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(3), value & 0x0000_FFFF);
     }
 
     #[test]
     fn ldrsh_should_return_a_signed_hw() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let value = 0x0000_FABC;
         let address: u32 = 0x3000200;
 
-        let _res = cpu.memory.writeu32(address as usize, value);
+        let _res = memory.writeu32(address as usize, value);
 
         cpu.set_register(1, address);
         cpu.prefetch[0] = Some(0xe1d130f0); // ldrsh r3, [r1]
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(3), value | 0xFFFF_0000);
     }
 
     #[test]
     fn ldrsh_should_return_a_signed_byte() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let value = 0x0000_0081;
         let address: u32 = 0x3000200;
 
-        let _res = cpu.memory.writeu32(address as usize, value);
+        let _res = memory.writeu32(address as usize, value);
 
         cpu.set_register(1, address);
         cpu.prefetch[0] = Some(0xe1d130d0); // ldrsb r3, [r1]
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(3), value | 0xFFFF_FF00);
     }
 
     #[test]
     fn ldm_should_load_multiple_registers() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let value = 0x0000_0081;
         let address: u32 = 0x3000200;
 
         cpu.set_register(5, address);
 
-        cpu.memory.writeu32(address as usize, value);
+        memory.writeu32(address as usize, value);
 
-        cpu.memory.writeu32(address as usize + 4, 0x55);
+        memory.writeu32(address as usize + 4, 0x55);
 
         cpu.prefetch[0] = Some(0xe8950003); // ldmia r5, {r0, r1}
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), value);
         assert_eq!(cpu.get_register(1), 0x55);
@@ -805,23 +937,23 @@ mod sdt_tests {
 
     #[test]
     fn ldmib_should_load_multiple_registers_and_modify_base_register() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let value = 0x0000_0081;
         let address: u32 = 0x3000200;
 
         cpu.set_register(5, address);
 
-        cpu.memory.writeu32(address as usize + 4, value);
+        memory.writeu32(address as usize + 4, value);
 
-        cpu.memory.writeu32(address as usize + 8, 0x55);
+        memory.writeu32(address as usize + 8, 0x55);
 
         cpu.prefetch[0] = Some(0xe9b500c0); // ldmib r5!, {r6, r7}
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(6), value);
         assert_eq!(cpu.get_register(7), 0x55);
@@ -830,23 +962,23 @@ mod sdt_tests {
 
     #[test]
     fn ldmda_should_load_multiple_registers_and_modify_base_register() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let value = 0x0000_0081;
         let address: u32 = 0x3000200;
 
         cpu.set_register(5, address);
 
-        cpu.memory.writeu32(address as usize, value);
+        memory.writeu32(address as usize, value);
 
-        cpu.memory.writeu32(address as usize - 4, 0x55);
+        memory.writeu32(address as usize - 4, 0x55);
 
         cpu.prefetch[0] = Some(0xe83500c0); // ldmda r5!, {r6, r7}
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(6), 0x55);
         assert_eq!(cpu.get_register(7), value);
@@ -855,23 +987,23 @@ mod sdt_tests {
 
     #[test]
     fn ldmdb_should_load_multiple_registers_and_modify_base_register() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let value = 0x0000_0081;
         let address: u32 = 0x3000200;
 
         cpu.set_register(5, address);
 
-        cpu.memory.writeu32(address as usize - 4, value);
+        memory.writeu32(address as usize - 4, value);
 
-        cpu.memory.writeu32(address as usize - 8, 0x55);
+        memory.writeu32(address as usize - 8, 0x55);
 
         cpu.prefetch[0] = Some(0xe93500c0); // ldmdb r5!, {r6, r7}
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(6), 0x55);
         assert_eq!(cpu.get_register(7), value);
@@ -880,9 +1012,9 @@ mod sdt_tests {
 
     #[test]
     fn stm_should_store_multiple_registers() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let address: u32 = 0x3000200;
 
@@ -892,18 +1024,18 @@ mod sdt_tests {
 
         cpu.prefetch[0] = Some(0xe88500c0); // stm r5, {r6, r7}
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
-        assert_eq!(cpu.memory.readu32(address as usize).data, 123);
-        assert_eq!(cpu.memory.readu32(address as usize + 4).data, 456);
+        assert_eq!(memory.readu32(address as usize).data, 123);
+        assert_eq!(memory.readu32(address as usize + 4).data, 456);
     }
 
     #[test]
     fn stmib_should_store_multiple_registers_and_writeback() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let address: u32 = 0x3000200;
 
@@ -913,19 +1045,19 @@ mod sdt_tests {
 
         cpu.prefetch[0] = Some(0xe9a500c0); // stmib r5!, {r6, r7}
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
-        assert_eq!(cpu.memory.readu32(address as usize + 4).data, 123);
-        assert_eq!(cpu.memory.readu32(address as usize + 8).data, 456);
+        assert_eq!(memory.readu32(address as usize + 4).data, 123);
+        assert_eq!(memory.readu32(address as usize + 8).data, 456);
         assert_eq!(cpu.get_register(5), address + 8);
     }
 
     #[test]
     fn stmdb_should_store_multiple_registers_and_writeback() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let address: u32 = 0x3000200;
 
@@ -935,19 +1067,19 @@ mod sdt_tests {
 
         cpu.prefetch[0] = Some(0xe92500c0); // stmdb r5!, {r6, r7}
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
-        assert_eq!(cpu.memory.readu32((address - 4) as usize).data, 456);
-        assert_eq!(cpu.memory.readu32((address - 8) as usize).data, 123);
+        assert_eq!(memory.readu32((address - 4) as usize).data, 456);
+        assert_eq!(memory.readu32((address - 8) as usize).data, 123);
         assert_eq!(cpu.get_register(5), address - 8);
     }
 
     #[test]
     fn stmda_should_store_multiple_registers_and_writeback() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let address: u32 = 0x3000200;
 
@@ -957,11 +1089,113 @@ mod sdt_tests {
 
         cpu.prefetch[0] = Some(0xe82500c0); // stmda r5!, {r6, r7}
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
-        assert_eq!(cpu.memory.readu32((address) as usize).data, 456);
-        assert_eq!(cpu.memory.readu32((address - 4) as usize).data, 123);
+        assert_eq!(memory.readu32((address) as usize).data, 456);
+        assert_eq!(memory.readu32((address - 4) as usize).data, 123);
         assert_eq!(cpu.get_register(5), address - 8);
     }
+
+    #[test]
+    fn ldm_with_pc_and_s_bit_returns_from_irq() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+        cpu.set_mode(CPUMode::IRQ);
+        *cpu.get_current_spsr().unwrap() = CPUMode::USER as u32;
+
+        let stack: u32 = 0x3007F00;
+        cpu.set_sp(stack);
+        for register in 0..13 {
+            memory.writeu32((stack + register * 4) as usize, register + 100);
+        }
+        memory.writeu32((stack + 13 * 4) as usize, 0x3000100);
+
+        cpu.prefetch[0] = Some(0xe8fd9fff); // ldmfd sp!, {r0-r12, pc}^
+
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(cpu.get_cpu_mode(), CPUMode::USER);
+        for register in 0..13 {
+            assert_eq!(cpu.get_register(register), register + 100);
+        }
+        assert_eq!(cpu.get_pc(), 0x3000108);
+        assert_eq!(cpu.banked_sp(CPUMode::IRQ), stack + 14 * 4);
+    }
+
+    #[test]
+    fn stm_with_s_bit_stores_user_bank_from_svc() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+        cpu.set_mode(CPUMode::USER);
+        cpu.set_register(13, 0x3007F00);
+        cpu.set_register(14, 0x8000200);
+        cpu.set_mode(CPUMode::SVC);
+        cpu.set_register(13, 0x3007FE0);
+        cpu.set_register(14, 0x8000300);
+
+        let address: u32 = 0x3000200;
+        cpu.set_register(0, address);
+
+        cpu.prefetch[0] = Some(0xe8c06000); // stmia r0, {sp, lr}^
+
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(memory.readu32(address as usize).data, 0x3007F00);
+        assert_eq!(memory.readu32(address as usize + 4).data, 0x8000200);
+        assert_eq!(cpu.get_cpu_mode(), CPUMode::SVC);
+        assert_eq!(cpu.get_sp(), 0x3007FE0);
+    }
+
+    #[test]
+    fn stm_with_pc_stores_the_instruction_address_plus_12() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(
+            &mut gba,
+            0x3000000,
+            &[
+                0xE3A00C02, // mov r0, #0x200
+                0xE3800403, // orr r0, r0, #0x3000000
+                0xE8808000, // stmia r0, {pc}
+            ],
+        );
+
+        for _ in 0..3 {
+            gba.step();
+        }
+
+        assert_eq!(gba.memory.readu32(0x3000200).data, 0x3000008 + 12);
+    }
+
+    #[test]
+    fn ldm_with_pc_flushes_the_pipeline_and_stays_in_arm() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(
+            &mut gba,
+            0x3000000,
+            &[
+                0xE8BD8000, // ldmia sp!, {pc}
+                0xE3A00001, // mov r0, #1
+            ],
+        );
+        gba.memory.writeu32(0x3000100, 0xE3A00002); // mov r0, #2
+        gba.cpu.set_sp(0x3007F00);
+        // the low bits are ignored, bit 0 must not switch to thumb
+        gba.memory.writeu32(0x3007F00, 0x3000103);
+
+        gba.step();
+
+        assert_eq!(gba.cpu.get_instruction_mode(), InstructionMode::ARM);
+        assert_eq!(gba.cpu.next_executed_pc(), Some(0x3000100));
+        assert_eq!(gba.cpu.get_sp(), 0x3007F04);
+
+        gba.step();
+
+        assert_eq!(gba.cpu.last_executed_pc(), 0x3000100);
+        assert_eq!(gba.cpu.get_register(0), 2);
+    }
 }
@@ -8,7 +8,7 @@ pub type ARMExecutable = fn(&mut CPU, ARMByteCode, memory: &mut Box<dyn MemoryBu
 pub type ALUOperation =
     fn(&mut CPU, rd: REGISTER, operand1: u32, operand2: u32, set_flags: bool) -> ();
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub struct ARMDecodedInstruction {
     pub executable: ARMExecutable,
     pub instruction: u32,
@@ -23,6 +23,24 @@ impl Default for ARMDecodedInstruction {
     }
 }
 
+/// Internal cycles spent in the multiplier array, which stops early once
+/// the remaining bytes of the multiplier are all zeros, or all ones for a
+/// signed multiply.
+pub fn multiply_internal_cycles(multiplier: u32, signed: bool) -> CYCLES {
+    let terminates = |mask: u32| {
+        multiplier & mask == 0 || (signed && multiplier & mask == mask)
+    };
+    if terminates(0xFFFF_FF00) {
+        1
+    } else if terminates(0xFFFF_0000) {
+        2
+    } else if terminates(0xFF00_0000) {
+        3
+    } else {
+        4
+    }
+}
+
 impl CPU {
     pub fn set_executed_instruction(&mut self, name: Arguments<'_>) {
         self.executed_instruction.clear();
@@ -34,9 +52,10 @@ impl CPU {
         if instruction.bit_is_set(24) {
             self.set_register(LINK_REGISTER, self.get_pc() - 4);
         }
+        // 24 bit word offset, sign extended from bit 25 once shifted into a byte offset
         let offset = instruction & 0x00FF_FFFF;
         let offset = sign_extend(offset << 2, 25);
-        let destination = offset + self.get_pc();
+        let destination = self.get_pc().wrapping_add(offset);
         self.set_pc(destination);
         cycles += self.flush_pipeline(memory);
         self.set_executed_instruction(format_args!("B {:#010x}", destination));
@@ -61,36 +80,88 @@ impl CPU {
         let result = (operand1 * operand2) as u32;
         self.set_register(rd, result);
 
-        self.set_flag_from_bit(FlagsRegister::N, result.get_bit(31) as u8);
         if set_flags {
-            if result == 0 {
-                self.reset_flag(FlagsRegister::Z);
-            } else {
-                self.set_flag(FlagsRegister::Z);
-            }
+            self.set_multiply_flags(result, operand2 as u32);
         }
 
         self.set_executed_instruction(format_args!("MUL {} {} {}", rd, rm, rs));
-        if operand2 & 0xFFFF_FF00 == 0 || operand2 & 0xFFFF_FF00 == 0xFFFF_FF00 {
-            1
-        } else if operand2 & 0xFFFF_0000 == 0 || operand2 & 0xFFFF_0000 == 0xFFFF_0000 {
-            2
-        } else if operand2 & 0xFF00_0000 == 0 || operand2 & 0xFF00_0000 == 0xFFFF_0000 {
-            3
-        } else {
-            4
-        }
+        multiply_internal_cycles(operand2 as u32, true)
     }
 
     pub fn arm_multiply_accumulate(&mut self, instruction: ARMByteCode, memory: &mut Box<dyn MemoryBus>) -> CYCLES {
-        panic!("Not implemented" );
+        let rd = (instruction & 0x000F_0000) >> 16;
+        let rn = (instruction & 0x0000_F000) >> 12;
+        let rs = (instruction & 0x0000_0F00) >> 8;
+        let rm = instruction & 0x0000_000F;
+        let set_flags = instruction.bit_is_set(20);
+
+        let multiplier = self.get_register(rs);
+        let result = self
+            .get_register(rm)
+            .wrapping_mul(multiplier)
+            .wrapping_add(self.get_register(rn));
+        self.set_register(rd, result);
+
+        if set_flags {
+            self.set_multiply_flags(result, multiplier);
+        }
+
+        self.set_executed_instruction(format_args!("MLA {} {} {} {}", rd, rm, rs, rn));
+        // one more internal cycle for the addition
+        multiply_internal_cycles(multiplier, true) + 1
     }
 
     pub fn arm_multiply_long(&mut self, instruction: ARMByteCode, memory: &mut Box<dyn MemoryBus>) -> CYCLES {
-        todo!();
+        let signed = instruction.bit_is_set(22);
+        let accumulate = instruction.bit_is_set(21);
+        let set_flags = instruction.bit_is_set(20);
+        let rd_hi = (instruction & 0x000F_0000) >> 16;
+        let rd_lo = (instruction & 0x0000_F000) >> 12;
+        let rs = (instruction & 0x0000_0F00) >> 8;
+        let rm = instruction & 0x0000_000F;
+
+        let multiplicand = self.get_register(rm);
+        let multiplier = self.get_register(rs);
+        let mut result = if signed {
+            (multiplicand as i32 as i64 * multiplier as i32 as i64) as u64
+        } else {
+            multiplicand as u64 * multiplier as u64
+        };
+        if accumulate {
+            let accumulator =
+                (self.get_register(rd_hi) as u64) << 32 | self.get_register(rd_lo) as u64;
+            result = result.wrapping_add(accumulator);
+        }
+        self.set_register(rd_lo, result as u32);
+        self.set_register(rd_hi, (result >> 32) as u32);
+
+        if set_flags {
+            self.set_flag_from_bit(FlagsRegister::N, (result >> 63) as u8);
+            self.set_flag_from_bit(FlagsRegister::Z, (result == 0) as u8);
+        }
+
+        self.set_executed_instruction(format_args!(
+            "{}{}L {} {} {} {}",
+            if signed { "S" } else { "U" },
+            if accumulate { "MLA" } else { "MUL" },
+            rd_lo,
+            rd_hi,
+            rm,
+            rs
+        ));
+        multiply_internal_cycles(multiplier, signed) + 1 + accumulate as CYCLES
     }
 
-    pub fn arm_software_interrupt(&mut self, _instruction: ARMByteCode, memory: &mut Box<dyn MemoryBus>) -> CYCLES {
+    pub fn arm_software_interrupt(&mut self, instruction: ARMByteCode, memory: &mut Box<dyn MemoryBus>) -> CYCLES {
+        if self.bios_hle {
+            // the BIOS reads the function from bits 16-23 of an ARM SWI
+            let comment_field = match self.get_instruction_mode() {
+                InstructionMode::ARM => (instruction >> 16) & 0xFF,
+                InstructionMode::THUMB => instruction & 0xFF,
+            };
+            self.set_executed_instruction(format_args!("SWI {:#X}", comment_field));
+            return self.handle_swi_hle(comment_field, memory);
+        }
         let mut cycles = 1;
         cycles += self.raise_exception(Exceptions::Software, memory);
         self.set_executed_instruction(format_args!("SWI"));
@@ -98,6 +169,14 @@ impl CPU {
         return cycles;
     }
 
+    pub fn arm_undefined_instruction(&mut self, _instruction: ARMByteCode, memory: &mut Box<dyn MemoryBus>) -> CYCLES {
+        let mut cycles = 1;
+        cycles += self.raise_exception(Exceptions::Undefined, memory);
+        self.set_executed_instruction(format_args!("UNDEFINED"));
+
+        cycles
+    }
+
     pub fn arm_branch_and_exchange(&mut self, instruction: ARMByteCode, memory: &mut Box<dyn MemoryBus>) -> CYCLES {
         let mut destination = self.get_register(instruction & 0x0000_000F);
         let mut cycles = 1;
@@ -124,33 +203,39 @@ impl CPU {
 #[cfg(test)]
 mod instruction_tests {
 
+    use rstest::rstest;
+
     use crate::{
-        arm7tdmi::cpu::{CPUMode, CPU, LINK_REGISTER},
-        memory::memory::GBAMemory,
+        arm7tdmi::cpu::{CPUMode, FlagsRegister, InstructionMode, CPU, LINK_REGISTER},
+        utils::bits::Bits,
+        memory::memory::{GBAMemory, MemoryBus},
+        types::CYCLES,
     };
 
+    use super::multiply_internal_cycles;
+
     #[test]
     fn branch_ends_up_at_correct_address() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.prefetch[0] = Some(0xea000002); // b 0x10
         cpu.set_pc(4);
 
         let expected_destination = 0x10 + 0x8;
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_pc(), expected_destination);
     }
 
     #[test]
     fn branch_can_go_backwards() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.prefetch[0] = Some(0xeafffffa); // b 0x0
         cpu.prefetch[1] = Some(0xe1a00000);
@@ -159,25 +244,48 @@ mod instruction_tests {
 
         let expected_destination = 0x8;
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_pc(), expected_destination);
     }
 
+    #[rstest]
+    #[case::max_forward_offset(0x3000000, 0xea7fffff, 0x5000004)]
+    #[case::max_backward_offset(0x8000000, 0xea800000, 0x6000008)]
+    #[case::max_forward_offset_with_link(0x3000000, 0xeb7fffff, 0x5000004)]
+    #[case::max_backward_offset_with_link(0x8000000, 0xeb800000, 0x6000008)]
+    fn branch_reaches_the_limits_of_its_offset(
+        #[case] address: u32,
+        #[case] instruction: u32,
+        #[case] expected_destination: u32,
+    ) {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+
+        cpu.prefetch[0] = Some(instruction);
+        cpu.set_pc(address + 4);
+
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(cpu.get_pc(), expected_destination + 8);
+    }
+
     #[test]
     fn branch_with_link_stores_the_instruction_correctly() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.prefetch[0] = Some(0xebfffffa); // b 0
         cpu.set_pc(0x14);
 
         let expected_destination = 0x8;
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert!(cpu.get_pc() == expected_destination);
         assert!(cpu.get_register(LINK_REGISTER) == 0x14);
@@ -185,17 +293,205 @@ mod instruction_tests {
 
     #[test]
     fn software_interrupt_goes_to_the_correct_interrupt_vec() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_mode(CPUMode::USER);
         cpu.set_pc(0xF8);
 
         cpu.prefetch[1] = Some(0xef000000); // SWI
 
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
         assert_eq!(cpu.get_pc(), 0x10);
         assert!(cpu.get_cpu_mode() == CPUMode::SVC);
         assert_eq!(cpu.get_register(LINK_REGISTER), 0xF4);
     }
+
+    #[test]
+    fn software_interrupt_banks_cpsr_and_return_address_into_svc() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+        cpu.set_mode(CPUMode::USER);
+        cpu.cpsr &= !(1 << 7); // IRQs enabled
+        cpu.set_flag(FlagsRegister::N);
+        cpu.set_flag(FlagsRegister::C);
+        cpu.set_register(LINK_REGISTER, 0x1234);
+        let user_cpsr = cpu.cpsr;
+        cpu.set_pc(0x3000108);
+
+        cpu.prefetch[1] = Some(0xef000000); // swi 0 at 0x3000100
+
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(cpu.get_cpu_mode(), CPUMode::SVC);
+        assert_eq!(cpu.get_instruction_mode(), InstructionMode::ARM);
+        assert!(cpu.cpsr.bit_is_set(7));
+        assert_eq!(cpu.get_register(LINK_REGISTER), 0x3000104);
+        assert_eq!(*cpu.get_current_spsr().unwrap(), user_cpsr);
+        // the vector at 0x08 has been fetched and the pipeline refilled
+        assert_eq!(cpu.last_executed_pc(), 0x3000100);
+        assert_eq!(cpu.get_pc(), 0x08 + 8);
+
+        cpu.set_mode(CPUMode::USER);
+        assert_eq!(cpu.get_register(LINK_REGISTER), 0x1234);
+    }
+
+    #[test]
+    fn blx_immediate_encoding_raises_undefined_instruction() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+        cpu.set_mode(CPUMode::USER);
+        cpu.set_pc(0xF8);
+
+        cpu.prefetch[1] = Some(0xfa000010); // blx 0x48 on ARMv5
+
+        cpu.execute_cpu_cycle(&mut memory);
+        assert_eq!(cpu.get_pc(), 0x0C);
+        assert!(cpu.get_cpu_mode() == CPUMode::UND);
+        assert!(matches!(cpu.get_instruction_mode(), InstructionMode::ARM));
+        assert_eq!(cpu.get_register(LINK_REGISTER), 0xF4);
+    }
+
+    #[rstest]
+    #[case::booth_carry(3, 0x8000_0001, 0, 0x8000_0003, [1, 0, 1])]
+    #[case::carry_is_not_preserved(5, 0x4000_0000, 1, 0x4000_0000, [0, 0, 0])]
+    #[case::top_bits_set(2, 0xC000_0000, 1, 0x8000_0000, [1, 0, 0])]
+    #[case::zero(0, 0x8000_0000, 0, 0, [0, 1, 1])]
+    fn muls_leaves_the_arm7tdmi_carry(
+        #[case] multiplicand: u32,
+        #[case] multiplier: u32,
+        #[case] carry_in: u8,
+        #[case] expected: u32,
+        #[case] nzc: [u32; 3],
+    ) {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+        cpu.set_register(2, multiplicand);
+        cpu.set_register(3, multiplier);
+        cpu.set_flag_from_bit(FlagsRegister::C, carry_in);
+
+        cpu.prefetch[0] = Some(0xe0110392); // muls r1, r2, r3
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(cpu.get_register(1), expected);
+        assert_eq!(
+            [
+                cpu.get_flag(FlagsRegister::N),
+                cpu.get_flag(FlagsRegister::Z),
+                cpu.get_flag(FlagsRegister::C)
+            ],
+            nzc
+        );
+    }
+
+    #[rstest]
+    #[case::one_byte(0x0000_00FF, false, 1)]
+    #[case::two_bytes(0x0000_FFFF, false, 2)]
+    #[case::three_bytes(0x00FF_FFFF, false, 3)]
+    #[case::four_bytes(0xFF00_0000, false, 4)]
+    #[case::unsigned_all_ones(0xFFFF_FFFF, false, 4)]
+    #[case::signed_one_byte(0xFFFF_FF80, true, 1)]
+    #[case::signed_two_bytes(0xFFFF_8000, true, 2)]
+    #[case::signed_three_bytes(0xFF80_0000, true, 3)]
+    #[case::signed_four_bytes(0x8000_0000, true, 4)]
+    fn multiply_internal_cycles_count_the_significant_multiplier_bytes(
+        #[case] multiplier: u32,
+        #[case] signed: bool,
+        #[case] expected: CYCLES,
+    ) {
+        assert_eq!(multiply_internal_cycles(multiplier, signed), expected);
+    }
+
+    #[test]
+    fn mla_adds_the_accumulator() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+        cpu.set_register(2, 6);
+        cpu.set_register(3, 7);
+        cpu.set_register(4, -50i32 as u32);
+
+        cpu.prefetch[0] = Some(0xe0314392); // mlas r1, r2, r3, r4
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(cpu.get_register(1), -8i32 as u32);
+        assert_eq!(cpu.get_flag(FlagsRegister::N), 1);
+        assert_eq!(cpu.get_flag(FlagsRegister::Z), 0);
+    }
+
+    #[test]
+    fn thumb_mul_uses_rd_as_the_multiplier_for_carry() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+        cpu.set_instruction_mode(InstructionMode::THUMB);
+        cpu.set_register(0, 0x8000_0001);
+        cpu.set_register(1, 3);
+
+        cpu.prefetch[0] = Some(0x4348); // mul r0, r1
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(cpu.get_register(0), 0x8000_0003);
+        assert_eq!(cpu.get_flag(FlagsRegister::C), 1);
+    }
+
+    #[test]
+    fn umull_of_two_max_operands_fills_both_words() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+        cpu.set_register(2, 0xFFFF_FFFF);
+        cpu.set_register(3, 0xFFFF_FFFF);
+
+        cpu.prefetch[0] = Some(0xe0910392); // umulls r0, r1, r2, r3
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(cpu.get_register(0), 0x0000_0001);
+        assert_eq!(cpu.get_register(1), 0xFFFF_FFFE);
+        assert_eq!(cpu.get_flag(FlagsRegister::N), 1);
+        assert_eq!(cpu.get_flag(FlagsRegister::Z), 0);
+    }
+
+    #[test]
+    fn smlal_accumulates_into_a_negative_high_word() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+        cpu.set_register(0, 0);
+        cpu.set_register(1, 0xFFFF_FFFF);
+        cpu.set_register(2, -2i32 as u32);
+        cpu.set_register(3, 3);
+
+        cpu.prefetch[0] = Some(0xe0e10392); // smlal r0, r1, r2, r3
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(cpu.get_register(0), 0xFFFF_FFFA);
+        assert_eq!(cpu.get_register(1), 0xFFFF_FFFE);
+    }
+
+    #[rstest]
+    #[case::unsigned_small(0xe0810392, 0x0000_00FF, 2)]
+    #[case::unsigned_negative(0xe0810392, 0xFFFF_FFFF, 5)]
+    #[case::signed_negative(0xe0c10392, 0xFFFF_FFFF, 2)]
+    #[case::accumulate(0xe0a10392, 0x0000_FFFF, 4)]
+    fn multiply_long_terminates_early(
+        #[case] opcode: u32,
+        #[case] multiplier: u32,
+        #[case] expected_cycles: u8,
+    ) {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+        cpu.set_register(3, multiplier);
+
+        assert_eq!(cpu.arm_multiply_long(opcode, &mut memory), expected_cycles);
+    }
 }
@@ -26,7 +26,13 @@ impl CPU {
             memory_fetch.data
         };
 
-        self.set_executed_instruction(format_args!("SWP {} {} [{:#X}]", rd, rm, address));
+        self.set_executed_instruction(format_args!(
+            "SWP{} {} {} [{:#X}]",
+            if is_byte_swap { "B" } else { "" },
+            rd,
+            rm,
+            address
+        ));
         self.set_register(rd, memory_data);
 
         cycles
@@ -39,74 +45,74 @@ mod single_data_swap_test {
 
     #[test]
     fn swap_instruction_should_store_and_load_at_the_same_time() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
         
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_register(1, 0x3000200);
         cpu.set_register(3, 10);
-        cpu.memory.writeu32(0x3000200, 5);
+        memory.writeu32(0x3000200, 5);
 
         cpu.prefetch[0] = Some(0xe1014093); // swp r4, r3, [r1]
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(4), 5);
-        assert_eq!(cpu.memory.readu32(0x3000200).data, 10);
+        assert_eq!(memory.readu32(0x3000200).data, 10);
     }
 
     #[test]
     fn swap_instruction_should_work_with_equal_rn_and_rm() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
         
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let address = 0x3000200;
 
         cpu.set_register(1, address);
-        cpu.memory.writeu32(address as usize, 5);
+        memory.writeu32(address as usize, 5);
 
         cpu.prefetch[0] = Some(0xe1014091); // swp r4, r1, [r1]
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(4), 5);
-        assert_eq!(cpu.memory.readu32(0x3000200).data, 0x3000200);
+        assert_eq!(memory.readu32(0x3000200).data, 0x3000200);
     }
 
     #[test]
     fn swap_should_work_with_equal_rm_and_rd() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
         
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let address = 0x3000200;
 
         cpu.set_register(4, 15);
 
         cpu.set_register(1, address);
-        cpu.memory.writeu32(address as usize, 5);
+        memory.writeu32(address as usize, 5);
 
         cpu.prefetch[0] = Some(0xe1014094); // swp r4, r4, [r1]
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(4), 5);
-        assert_eq!(cpu.memory.readu32(0x3000200).data, 15);
+        assert_eq!(memory.readu32(0x3000200).data, 15);
     }
 
     #[test]
     fn swpb_should_only_store_and_load_a_byte_and_clear_upper_rd() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
         
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         let address = 0x3000200;
 
@@ -114,14 +120,56 @@ mod single_data_swap_test {
         cpu.set_register(4, 0xFFFF_FFFF);
 
         cpu.set_register(1, address);
-        cpu.memory.writeu32(address as usize, 0x7890_DD12);
+        memory.writeu32(address as usize, 0x7890_DD12);
 
         cpu.prefetch[0] = Some(0xe1414093); // swpb r4, r3, [r1]
 
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(4), 0x12);
-        assert_eq!(cpu.memory.read(0x3000200).data, 0xBC);
+        assert_eq!(memory.read(0x3000200).data, 0xBC);
+    }
+
+    #[test]
+    fn swpb_at_an_odd_address_leaves_the_rest_of_the_word_alone() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+
+        let address = 0x3000203;
+
+        cpu.set_register(3, 0x1234_56AB);
+        cpu.set_register(1, address);
+        memory.writeu32(0x3000200, 0x7890_DD12);
+
+        cpu.prefetch[0] = Some(0xe1414093); // swpb r4, r3, [r1]
+
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(cpu.get_register(4), 0x78);
+        assert_eq!(memory.readu32(0x3000200).data, 0xAB90_DD12);
+    }
+
+    #[test]
+    fn swpb_with_equal_rd_and_rn_loads_the_old_byte() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+
+        let address = 0x3000201;
+
+        cpu.set_register(1, address);
+        cpu.set_register(3, 0x55);
+        memory.writeu32(0x3000200, 0x7890_DD12);
+
+        cpu.prefetch[0] = Some(0xe1411093); // swpb r1, r3, [r1]
+
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(cpu.get_register(1), 0xDD);
+        assert_eq!(memory.readu32(0x3000200).data, 0x7890_5512);
     }
 }
@@ -0,0 +1,354 @@
+use std::f64::consts::TAU;
+
+use crate::{
+    memory::{
+        io_handlers::{HALTCNT, IME, IO_BASE},
+        memory::MemoryBus,
+    },
+    types::{CYCLES, WORD},
+};
+
+use super::{
+    cpu::{CPUMode, InstructionMode, CPU, LINK_REGISTER, STACK_POINTER},
+    interrupts::Exceptions,
+};
+
+/// Interrupts acknowledged by the game's handler, which IntrWait polls.
+const BIOS_INTERRUPT_FLAGS: usize = 0x3007FF8;
+/// The VBlank bit of the BIOS flags, and of r0 and r1 for IntrWait.
+const VBLANK_FLAG: u16 = 1 << 0;
+/// Non-zero makes SoftReset start from EWRAM instead of the cartridge.
+const SOFT_RESET_RETURN: usize = 0x3007FFA;
+/// The BIOS clears the top of IWRAM where it keeps its stacks and flags.
+const BIOS_IWRAM: usize = 0x3007E00;
+
+const SOFT_RESET: u32 = 0x00;
+const REGISTER_RAM_RESET: u32 = 0x01;
+const VBLANK_INTR_WAIT: u32 = 0x05;
+const DIV: u32 = 0x06;
+const DIV_ARM: u32 = 0x07;
+const SQRT: u32 = 0x08;
+const ARC_TAN2: u32 = 0x0A;
+const CPU_SET: u32 = 0x0B;
+const CPU_FAST_SET: u32 = 0x0C;
+
+/// Cycles charged for a call that doesn't touch memory.
+const CALL_CYCLES: CYCLES = 3;
+
+impl CPU {
+    /// Runs BIOS function `comment_field` in place of the BIOS, taking
+    /// arguments from and returning results in r0-r3 like it does. Calls
+    /// that aren't emulated go to the SWI vector as usual.
+    pub fn handle_swi_hle(&mut self, comment_field: u32, memory: &mut Box<dyn MemoryBus>) -> CYCLES {
+        match comment_field {
+            SOFT_RESET => self.soft_reset(memory),
+            REGISTER_RAM_RESET => register_ram_reset(self.get_register(0), memory),
+            VBLANK_INTR_WAIT => self.vblank_intr_wait(memory),
+            DIV => self.divide(self.get_register(0), self.get_register(1)),
+            DIV_ARM => self.divide(self.get_register(1), self.get_register(0)),
+            SQRT => {
+                self.set_register(0, integer_sqrt(self.get_register(0)));
+                CALL_CYCLES
+            }
+            ARC_TAN2 => {
+                self.set_register(0, arc_tan2(self.get_register(0), self.get_register(1)));
+                CALL_CYCLES
+            }
+            CPU_SET => cpu_set(self.get_register(0), self.get_register(1), self.get_register(2), memory),
+            CPU_FAST_SET => {
+                cpu_fast_set(self.get_register(0), self.get_register(1), self.get_register(2), memory)
+            }
+            _ => 1 + self.raise_exception(Exceptions::Software, memory),
+        }
+    }
+
+    /// Clears the BIOS area of IWRAM, resets the stacks of each mode and
+    /// restarts the game in System mode.
+    fn soft_reset(&mut self, memory: &mut Box<dyn MemoryBus>) -> CYCLES {
+        let from_ewram = memory.read(SOFT_RESET_RETURN).data != 0;
+        for address in (BIOS_IWRAM..0x3008000).step_by(4) {
+            memory.writeu32(address, 0);
+        }
+        for (mode, sp) in [(CPUMode::SVC, 0x3007FE0), (CPUMode::IRQ, 0x3007FA0), (CPUMode::SYS, 0x3007F00)] {
+            self.set_mode(mode);
+            self.set_register(STACK_POINTER, sp);
+            self.set_register(LINK_REGISTER, 0);
+            if let Some(spsr) = self.get_current_spsr() {
+                *spsr = 0;
+            }
+        }
+        for register in 0..13 {
+            self.set_register(register, 0);
+        }
+        self.cpsr = CPUMode::SYS as WORD;
+        self.set_instruction_mode(InstructionMode::ARM);
+        self.set_pc(if from_ewram { 0x2000000 } else { 0x8000000 });
+        CALL_CYCLES + self.flush_pipeline(memory)
+    }
+
+    /// Drops VBlanks acknowledged before the call, then halts with IME set
+    /// until the game's handler acknowledges a new one in the BIOS flags.
+    /// To wait again after an interrupt, the pc is left on the SWI, which
+    /// the handler returns to.
+    fn vblank_intr_wait(&mut self, memory: &mut Box<dyn MemoryBus>) -> CYCLES {
+        let mut acknowledged = memory.readu16(BIOS_INTERRUPT_FLAGS).data;
+        if !self.intr_wait {
+            acknowledged &= !VBLANK_FLAG;
+        }
+        self.set_register(0, 1);
+        self.set_register(1, 1);
+        if acknowledged & VBLANK_FLAG > 0 {
+            self.intr_wait = false;
+            memory.writeu16(BIOS_INTERRUPT_FLAGS, acknowledged & !VBLANK_FLAG);
+            return CALL_CYCLES;
+        }
+        self.intr_wait = true;
+        memory.writeu16(BIOS_INTERRUPT_FLAGS, acknowledged);
+        memory.writeu16(IO_BASE + IME, 1);
+        memory.write(IO_BASE + HALTCNT, 0);
+        self.set_pc(self.last_executed_pc);
+        CALL_CYCLES + self.flush_pipeline(memory)
+    }
+
+    /// Signed division of r0 by r1 leaving the quotient in r0, the
+    /// remainder in r1 and the absolute quotient in r3. The BIOS never
+    /// returns from a division by zero, this gives what it would compute.
+    fn divide(&mut self, numerator: WORD, denominator: WORD) -> CYCLES {
+        let numerator = numerator as i32;
+        let denominator = denominator as i32;
+        let (quotient, remainder) = match denominator {
+            0 => (if numerator < 0 { -1 } else { 1 }, numerator),
+            _ => (numerator.wrapping_div(denominator), numerator.wrapping_rem(denominator)),
+        };
+        self.set_register(0, quotient as WORD);
+        self.set_register(1, remainder as WORD);
+        self.set_register(3, quotient.unsigned_abs());
+        CALL_CYCLES
+    }
+}
+
+/// Zeroes the memory selected by each of the low five bits of `flags`:
+/// EWRAM, IWRAM below the BIOS area, palette, VRAM and OAM. The IO
+/// register resets in the upper bits aren't emulated.
+fn register_ram_reset(flags: WORD, memory: &mut Box<dyn MemoryBus>) -> CYCLES {
+    let regions = [
+        (0x2000000, 0x40000),
+        (0x3000000, BIOS_IWRAM - 0x3000000),
+        (0x5000000, 0x400),
+        (0x6000000, 0x18000),
+        (0x7000000, 0x400),
+    ];
+    for (bit, (base, size)) in regions.into_iter().enumerate() {
+        if flags & (1 << bit) == 0 {
+            continue;
+        }
+        for address in (base..base + size).step_by(4) {
+            memory.writeu32(address, 0);
+        }
+    }
+    CALL_CYCLES
+}
+
+fn integer_sqrt(value: WORD) -> WORD {
+    let value = value as u64;
+    let mut root = (value as f64).sqrt() as u64;
+    while root * root > value {
+        root -= 1;
+    }
+    while (root + 1) * (root + 1) <= value {
+        root += 1;
+    }
+    root as WORD
+}
+
+/// Angle of the point (r0, r1), both signed 1.14 fixed point, as a
+/// fraction of a full turn in 0x0000-0xFFFF.
+fn arc_tan2(x: WORD, y: WORD) -> WORD {
+    let angle = (y as i16 as f64).atan2(x as i16 as f64);
+    ((angle / TAU * 65536.0).round() as i64 & 0xFFFF) as WORD
+}
+
+/// Copies or fills (bit 24) r2 bits 0-20 units from r0 to r1, as words
+/// if bit 26 is set and halfwords otherwise.
+fn cpu_set(source: WORD, destination: WORD, control: WORD, memory: &mut Box<dyn MemoryBus>) -> CYCLES {
+    let count = (control & 0x1F_FFFF) as usize;
+    let fill = control & (1 << 24) != 0;
+    let mut cycles = CALL_CYCLES as u32;
+    if control & (1 << 26) != 0 {
+        let (source, destination) = (source as usize & !0x3, destination as usize & !0x3);
+        for i in 0..count {
+            let fetch = memory.readu32(if fill { source } else { source + i * 4 });
+            cycles += fetch.cycles as u32 + memory.writeu32(destination + i * 4, fetch.data) as u32;
+        }
+    } else {
+        let (source, destination) = (source as usize & !0x1, destination as usize & !0x1);
+        for i in 0..count {
+            let fetch = memory.readu16(if fill { source } else { source + i * 2 });
+            cycles += fetch.cycles as u32 + memory.writeu16(destination + i * 2, fetch.data) as u32;
+        }
+    }
+    cycles.min(CYCLES::MAX as u32) as CYCLES
+}
+
+/// CpuSet in words only, with the count rounded up to a multiple of 8.
+fn cpu_fast_set(source: WORD, destination: WORD, control: WORD, memory: &mut Box<dyn MemoryBus>) -> CYCLES {
+    let count = ((control & 0x1F_FFFF) + 7) & !7;
+    cpu_set(source, destination, (control & (1 << 24)) | (1 << 26) | count, memory)
+}
+
+#[cfg(test)]
+mod bios_hle_tests {
+    use rstest::rstest;
+
+    use crate::{
+        arm7tdmi::{
+            cpu::CPUMode,
+            interrupts::{Exceptions, Interrupt},
+        },
+        gba::GBA,
+        memory::io_handlers::{DISPSTAT, IE, IO_BASE, TM0CNT_H, TM0CNT_L, VCOUNT},
+        utils::testing::{load_arm_program, load_thumb_program},
+    };
+
+    use super::BIOS_INTERRUPT_FLAGS;
+
+    fn gba_with_hle() -> GBA {
+        let mut gba = GBA::new_no_bios();
+        gba.set_bios_hle(true);
+        gba
+    }
+
+    #[rstest]
+    #[case(100, 7, 14, 2, 14)]
+    #[case(-100, 7, -14, -2, 14)]
+    #[case(100, -7, -14, 2, 14)]
+    #[case(7, 100, 0, 7, 0)]
+    fn div_returns_the_quotient_remainder_and_absolute_quotient(
+        #[case] numerator: i32,
+        #[case] denominator: i32,
+        #[case] quotient: i32,
+        #[case] remainder: i32,
+        #[case] absolute: u32,
+    ) {
+        let mut gba = gba_with_hle();
+        load_thumb_program(&mut gba, 0x3000000, &[0xdf06]); // swi 6
+        gba.cpu.set_register(0, numerator as u32);
+        gba.cpu.set_register(1, denominator as u32);
+
+        let result = gba.step();
+
+        assert_eq!(result.took_exception, None);
+        assert_eq!(gba.cpu.get_register(0), quotient as u32);
+        assert_eq!(gba.cpu.get_register(1), remainder as u32);
+        assert_eq!(gba.cpu.get_register(3), absolute);
+        assert_eq!(gba.cpu.next_executed_pc(), Some(0x3000002));
+    }
+
+    #[test]
+    fn div_arm_takes_the_denominator_first() {
+        let mut gba = gba_with_hle();
+        load_arm_program(&mut gba, 0x3000000, &[0xef070000]); // swi 0x70000
+        gba.cpu.set_register(0, 7);
+        gba.cpu.set_register(1, 100);
+
+        gba.step();
+
+        assert_eq!(gba.cpu.get_register(0), 14);
+        assert_eq!(gba.cpu.get_register(1), 2);
+    }
+
+    #[rstest]
+    #[case(0, 0)]
+    #[case(1, 1)]
+    #[case(15, 3)]
+    #[case(16, 4)]
+    #[case(0xFFFF_FFFF, 0xFFFF)]
+    fn sqrt_rounds_down(#[case] value: u32, #[case] root: u32) {
+        let mut gba = gba_with_hle();
+        load_thumb_program(&mut gba, 0x3000000, &[0xdf08]); // swi 8
+        gba.cpu.set_register(0, value);
+
+        gba.step();
+
+        assert_eq!(gba.cpu.get_register(0), root);
+    }
+
+    #[test]
+    fn cpu_fast_set_fills_whole_blocks_of_eight_words() {
+        let mut gba = gba_with_hle();
+        load_thumb_program(&mut gba, 0x3000000, &[0xdf0c]); // swi 0xC
+        gba.memory.writeu32(0x3001000, 0xDEAD_BEEF);
+        gba.cpu.set_register(0, 0x3001000);
+        gba.cpu.set_register(1, 0x2000000);
+        gba.cpu.set_register(2, (1 << 24) | 3);
+
+        gba.step();
+
+        assert_eq!(gba.memory.readu32(0x200001C).data, 0xDEAD_BEEF);
+        assert_eq!(gba.memory.readu32(0x2000020).data, 0);
+    }
+
+    #[test]
+    fn swi_goes_to_the_bios_without_hle() {
+        let mut gba = GBA::new_no_bios();
+        assert!(!gba.bios_hle());
+        load_thumb_program(&mut gba, 0x3000000, &[0xdf06]); // swi 6
+        gba.cpu.set_register(0, 100);
+        gba.cpu.set_register(1, 7);
+
+        let result = gba.step();
+
+        assert_eq!(result.took_exception, Some(Exceptions::Software));
+        assert_eq!(gba.cpu.get_register(0), 100);
+    }
+
+    #[test]
+    fn vblank_intr_wait_keeps_waiting_through_other_interrupts() {
+        let mut gba = gba_with_hle();
+        load_arm_program(&mut gba, 0x3000000, &[
+            0xef050000, // swi 5
+            0xeafffffe, // b .
+        ]);
+        gba.cpu.cpsr = CPUMode::SYS as u32;
+        gba.memory.writeu16(IO_BASE + DISPSTAT, 1 << 3); // VBlank IRQ
+        // timer 0 overflows every 0x800 * 64 cycles, once before the VBlank
+        gba.memory.writeu16(IO_BASE + TM0CNT_L, 0xF800);
+        gba.memory.writeu16(IO_BASE + TM0CNT_H, 1 << 7 | 1 << 6 | 1);
+        gba.memory.writeu16(IO_BASE + IE, Interrupt::VBlank.bit() | Interrupt::Timer0.bit());
+        gba.memory.writeu16(BIOS_INTERRUPT_FLAGS, 1); // a VBlank from before the call
+        // acknowledges IF and adds it to the BIOS flags, like a game's handler
+        let handler = [
+            0xe3a00404, // mov r0, 0x4000000
+            0xe2800c02, // add r0, r0, 0x200
+            0xe5901000, // ldr r1, [r0]
+            0xe0011821, // and r1, r1, r1, lsr 16
+            0xe1c010b2, // strh r1, [r0, 2]
+            0xe3a03403, // mov r3, 0x3000000
+            0xe3833c7f, // orr r3, r3, 0x7F00
+            0xe38330f8, // orr r3, r3, 0xF8
+            0xe1d320b0, // ldrh r2, [r3]
+            0xe1822001, // orr r2, r2, r1
+            0xe1c320b0, // strh r2, [r3]
+            0xe25ef004, // subs pc, lr, 4
+        ];
+
+        let mut interrupts = 0;
+        for _ in 0..1_000_000 {
+            let result = gba.step();
+            if result.executed_pc == 0x3000004 {
+                break;
+            }
+            if result.took_exception == Some(Exceptions::IRQ) {
+                // stands in for the BIOS IRQ vector calling the handler
+                interrupts += 1;
+                load_arm_program(&mut gba, 0x3001000, &handler);
+            }
+        }
+
+        assert_eq!(gba.memory.readu16(IO_BASE + VCOUNT).data, 160);
+        assert_eq!(interrupts, 2);
+        assert_eq!(gba.cpu.get_register(0), 1);
+        assert_eq!(gba.cpu.get_register(1), 1);
+        assert_eq!(gba.memory.readu16(BIOS_INTERRUPT_FLAGS).data & 1, 0);
+    }
+}
@@ -6,26 +6,25 @@ use std::{
 };
 
 use crate::{
-    memory::{
-        io_handlers::{IE, IF, IME, IO_BASE},
-        memory::MemoryBus,
-    },
+    memory::memory::MemoryBus,
+    state::{trace::TraceFormat, CpuState},
     types::*,
     utils::bits::Bits,
 };
 
-use super::interrupts::Exceptions;
+use super::{instruction_cache::InstructionCache, interrupts::Exceptions};
 
 pub const PC_REGISTER: usize = 15;
 pub const LINK_REGISTER: u32 = 14;
 pub const STACK_POINTER: u32 = 13;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum InstructionMode {
     ARM,
     THUMB,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum CPUMode {
     USER = 0b10000,
     FIQ = 0b10001, // Fast Interrupt
@@ -47,8 +46,7 @@ pub enum FlagsRegister {
 #[derive(Default, Debug)]
 struct Status {
     pub instruction_count: usize,
-    pub registers: [WORD; 16],
-    pub cpsr: WORD,
+    pub state: CpuState,
     pub cycles: u64,
 }
 
@@ -66,9 +64,20 @@ pub struct CPU {
     pub cpsr: WORD,
     pub spsr: [WORD; 5],
     pub output_file: File,
+    /// Line format of the trace written to `output_file`. None writes the
+    /// instruction count, registers, CPSR and cycles.
+    pub trace_format: Option<TraceFormat>,
     pub cycles: u64,
     pub relative_cycles: u64,
     status_history: VecDeque<Status>,
+    pub(super) last_executed_pc: WORD,
+    pub(super) last_exception: Option<Exceptions>,
+    /// Runs BIOS calls with `handle_swi_hle` instead of the SWI vector.
+    pub(crate) bios_hle: bool,
+    /// Set while an emulated VBlankIntrWait waits, so the SWI it reruns
+    /// after each interrupt doesn't discard the flags again.
+    pub(crate) intr_wait: bool,
+    pub instruction_cache: InstructionCache,
 }
 
 
@@ -99,9 +108,15 @@ impl CPU {
                 .write(true)
                 .open(OUTPUT_FILE)
                 .unwrap(),
+            trace_format: None,
             cycles: 0,
             relative_cycles: 3,
             status_history: VecDeque::with_capacity(HISTORY_SIZE),
+            last_executed_pc: 0,
+            last_exception: None,
+            bios_hle: false,
+            intr_wait: false,
+            instruction_cache: InstructionCache::default(),
         };
         cpu
     }
@@ -109,6 +124,7 @@ impl CPU {
     #[no_mangle]
     pub fn execute_cpu_cycle(&mut self, memory: &mut Box<dyn MemoryBus>) -> CYCLES {
         self.set_executed_instruction(format_args!(""));
+        self.last_exception = None;
         if self.status_history.len() >= HISTORY_SIZE {
             self.status_history.pop_front();
         }
@@ -116,19 +132,14 @@ impl CPU {
             INSTRUCTION_COUNT += 1;
         }
         self.status_history.push_back(self.get_status());
-        let ime = memory.readu16(IO_BASE + IME).data;
-        let interrupt_flags_register = memory.readu16(IO_BASE + IF).data;
-        let interrupt_enable_register = memory.readu16(IO_BASE + IE).data;
-
-        if (interrupt_flags_register & interrupt_enable_register) > 0
-            && ime > 0
-            && !self.cpsr.bit_is_set(7)
-        {
-            self.raise_exception(Exceptions::IRQ, memory);
+        self.check_interrupts(memory);
+        for range in memory.code_writes().drain() {
+            self.instruction_cache.invalidate(range);
         }
         let mut execution_cycles = 0;
         if let Some(value) = self.prefetch[1] {
-            let decoded_instruction = self.decode_instruction(value);
+            self.last_executed_pc = self.get_pc().wrapping_sub(2 * self.instruction_size());
+            let decoded_instruction = self.decode_cached(self.last_executed_pc, value);
             self.executed_instruction_hex = decoded_instruction.instruction;
             self.prefetch[1] = None;
             execution_cycles +=
@@ -139,6 +150,7 @@ impl CPU {
             // refill pipeline if decoded instruction doesn't advance the pipeline
             execution_cycles += self.advance_pipeline(memory) as u64;
         }
+        memory.run_prefetcher(execution_cycles);
         self.cycles += execution_cycles;
         execution_cycles as u8
     }
@@ -159,6 +171,30 @@ impl CPU {
         self.fetch_instruction(memory)
     }
 
+    /// Decodes and executes `instruction` as if it were at the pc, without
+    /// going through the prefetch queue. The pc reads two instructions
+    /// ahead while it executes, as it would when prefetched, and is left
+    /// at the next instruction to run, which is the target if it branched.
+    #[cfg(test)]
+    pub fn execute_single(&mut self, instruction: WORD, memory: &mut Box<dyn MemoryBus>) -> CYCLES {
+        let address = self.get_pc();
+        let size = self.instruction_size();
+        self.prefetch = [None; 2];
+        self.set_pc(address + 2 * size);
+
+        let decoded_instruction = self.decode_instruction(instruction);
+        let cycles = (decoded_instruction.executable)(self, decoded_instruction.instruction, memory);
+
+        if self.prefetch[0].is_some() {
+            // a branch refilled the pipeline from its target
+            self.set_pc(self.get_pc() - 2 * self.instruction_size());
+            self.prefetch = [None; 2];
+        } else {
+            self.set_pc(address + size);
+        }
+        cycles
+    }
+
     pub fn get_pc(&self) -> u32 {
         self.registers[PC_REGISTER] & 0xFFFF_FFFE
     }
@@ -175,6 +211,41 @@ impl CPU {
         self.get_register(13)
     }
 
+    /// Address of the instruction executed by the most recent cycle.
+    pub fn last_executed_pc(&self) -> WORD {
+        self.last_executed_pc
+    }
+
+    /// The states recorded at the start of each of the last `count` cycles,
+    /// oldest first.
+    pub fn recent_states(&self, count: usize) -> Vec<CpuState> {
+        let skip = self.status_history.len().saturating_sub(count);
+        self.status_history
+            .iter()
+            .skip(skip)
+            .map(|status| status.state.clone())
+            .collect()
+    }
+
+    /// Address of the instruction the next cycle decodes, the oldest one
+    /// in the prefetch queue, or None while the queue is empty.
+    pub fn next_executed_pc(&self) -> Option<WORD> {
+        self.prefetch[1]
+            .map(|_| self.get_pc().wrapping_sub(2 * self.instruction_size()))
+    }
+
+    /// Exception entered during the most recent cycle, if any.
+    pub fn last_exception(&self) -> Option<Exceptions> {
+        self.last_exception
+    }
+
+    pub(crate) fn instruction_size(&self) -> WORD {
+        match self.get_instruction_mode() {
+            InstructionMode::ARM => 4,
+            InstructionMode::THUMB => 2,
+        }
+    }
+
     pub fn increment_pc(&mut self) {
         match self.get_instruction_mode() {
             InstructionMode::ARM => self.registers[PC_REGISTER] += 4,
@@ -182,11 +253,11 @@ impl CPU {
         }
     }
 
-    fn get_register_ref(&self, register_num: REGISTER) -> &WORD {
+    fn banked_register_ref(&self, mode: CPUMode, register_num: REGISTER) -> &WORD {
         if register_num < 8 || register_num == 15 {
             return &self.registers[register_num as usize];
         }
-        match self.get_cpu_mode() {
+        match mode {
             CPUMode::FIQ => &self.registers_fiq[(register_num - 8) as usize],
             CPUMode::USER | CPUMode::SYS => &self.registers[register_num as usize],
             _ if register_num < 13 => &self.registers[register_num as usize],
@@ -199,7 +270,33 @@ impl CPU {
 
     pub fn get_register(&self, register_num: REGISTER) -> WORD {
         assert!(register_num < 16);
-        *self.get_register_ref(register_num)
+        *self.banked_register_ref(self.get_cpu_mode(), register_num)
+    }
+
+    /// Reads a register from `mode`'s bank, whichever mode the CPU is in.
+    pub fn banked_register(&self, mode: CPUMode, register_num: REGISTER) -> WORD {
+        assert!(register_num < 16);
+        *self.banked_register_ref(mode, register_num)
+    }
+
+    pub fn banked_sp(&self, mode: CPUMode) -> WORD {
+        self.banked_register(mode, STACK_POINTER)
+    }
+
+    pub fn banked_lr(&self, mode: CPUMode) -> WORD {
+        self.banked_register(mode, LINK_REGISTER)
+    }
+
+    /// The SPSR of `mode`, or None for user and system mode which have none.
+    pub fn banked_spsr(&self, mode: CPUMode) -> Option<WORD> {
+        match mode {
+            CPUMode::FIQ => Some(self.spsr[0]),
+            CPUMode::SVC => Some(self.spsr[1]),
+            CPUMode::ABT => Some(self.spsr[2]),
+            CPUMode::IRQ => Some(self.spsr[3]),
+            CPUMode::UND => Some(self.spsr[4]),
+            CPUMode::USER | CPUMode::SYS => None,
+        }
     }
 
     fn get_register_ref_mut(&mut self, register_num: REGISTER) -> &mut WORD {
@@ -275,6 +372,24 @@ impl CPU {
         }
     }
 
+    /// Restores CPSR from the current mode's SPSR, as exception returns do.
+    /// User and system mode have no SPSR, so CPSR is left alone there.
+    pub fn pop_spsr(&mut self) {
+        if let Some(spsr) = self.get_current_spsr() {
+            self.cpsr = *spsr;
+        }
+    }
+
+    /// Runs `transfer` with r8-r14 mapped to the user bank, as block
+    /// transfers with the S bit do, then switches back to the active bank.
+    pub fn with_user_bank<T>(&mut self, transfer: impl FnOnce(&mut CPU) -> T) -> T {
+        let mode = self.get_cpu_mode();
+        self.set_mode(CPUMode::SYS);
+        let result = transfer(self);
+        self.set_mode(mode);
+        result
+    }
+
     pub fn get_current_spsr(&mut self) -> Option<&mut WORD> {
         match self.get_cpu_mode() {
             CPUMode::FIQ => Some(&mut self.spsr[0]),
@@ -286,6 +401,34 @@ impl CPU {
         }
     }
 
+    pub fn cpu_state(&self) -> CpuState {
+        CpuState {
+            registers: self.registers,
+            registers_fiq: self.registers_fiq,
+            registers_svc: self.registers_svc,
+            registers_abt: self.registers_abt,
+            registers_irq: self.registers_irq,
+            registers_und: self.registers_und,
+            cpsr: self.cpsr,
+            spsr: self.spsr,
+            prefetch: self.prefetch,
+        }
+    }
+
+    /// Loads every register and the pipeline from `state`. The instruction
+    /// mode follows from the T bit of the restored CPSR.
+    pub fn restore_cpu_state(&mut self, state: &CpuState) {
+        self.registers = state.registers;
+        self.registers_fiq = state.registers_fiq;
+        self.registers_svc = state.registers_svc;
+        self.registers_abt = state.registers_abt;
+        self.registers_irq = state.registers_irq;
+        self.registers_und = state.registers_und;
+        self.cpsr = state.cpsr;
+        self.spsr = state.spsr;
+        self.prefetch = state.prefetch;
+    }
+
     pub fn set_flag_from_bit(&mut self, flag: FlagsRegister, bit: u8) {
         assert!(bit == 0 || bit == 1);
         if bit == 0 {
@@ -295,11 +438,14 @@ impl CPU {
         self.set_flag(flag);
     }
 
+    /// Fetches the word or halfword at the pc, aligned down to the
+    /// instruction size in case a stray low bit was left in the pc.
     pub(super) fn fetch_instruction(&mut self, memory: &mut Box<dyn MemoryBus>) -> CYCLES {
+        let address = (self.get_pc() & !(self.instruction_size() - 1)) as usize;
         let memory_fetch = {
             match self.get_instruction_mode() {
-                InstructionMode::ARM => memory.readu32(self.get_pc() as usize),
-                InstructionMode::THUMB => memory.readu16(self.get_pc() as usize).into(),
+                InstructionMode::ARM => memory.fetch_u32(address),
+                InstructionMode::THUMB => memory.fetch_u16(address).into(),
             }
         };
         self.prefetch[0] = Some(memory_fetch.data);
@@ -317,7 +463,16 @@ impl CPU {
     ) -> u32 {
         let shift_type = (instruction & 0x0000_0060) >> 5;
 
-        if !instruction.bit_is_set(4) && shift_amount == 0 {
+        if instruction.bit_is_set(4) {
+            return self.register_specified_shift(
+                shift_type,
+                shift_amount & 0xFF,
+                operand_register_value,
+                set_flags,
+            );
+        }
+
+        if shift_amount == 0 {
             // special case for shifting
             return match shift_type {
                 // no change
@@ -398,28 +553,79 @@ impl CPU {
         }
     }
 
-    fn get_status(&self) -> Status {
-        let mut status = Status::default();
-        for i in 0..self.registers.len() {
-            status.registers[i] = self.get_register(i as REGISTER);
+    /// Shifts by the low byte of Rs. An amount of 0 leaves the value and
+    /// carry alone, and amounts of 32 or more shift every bit out instead
+    /// of acting like the immediate encodings do.
+    fn register_specified_shift(
+        &mut self,
+        shift_type: u32,
+        shift_amount: u32,
+        operand_register_value: u32,
+        set_flags: bool,
+    ) -> u32 {
+        if shift_amount == 0 {
+            return operand_register_value;
         }
-        status.cpsr = self.cpsr;
-        status.cycles = 0;
-        unsafe {
-            status.instruction_count = INSTRUCTION_COUNT;
+        let (result, carry) = match shift_type {
+            0x00 => match shift_amount {
+                1..=31 => (
+                    operand_register_value << shift_amount,
+                    operand_register_value.get_bit((32 - shift_amount) as u8),
+                ),
+                32 => (0, operand_register_value.get_bit(0)),
+                _ => (0, 0),
+            },
+            0x01 => match shift_amount {
+                1..=31 => (
+                    operand_register_value >> shift_amount,
+                    operand_register_value.get_bit((shift_amount - 1) as u8),
+                ),
+                32 => (0, operand_register_value.get_bit(31)),
+                _ => (0, 0),
+            },
+            0x02 => match shift_amount {
+                1..=31 => (
+                    (operand_register_value as i32 >> shift_amount) as u32,
+                    operand_register_value.get_bit((shift_amount - 1) as u8),
+                ),
+                _ => (
+                    (operand_register_value as i32 >> 31) as u32,
+                    operand_register_value.get_bit(31),
+                ),
+            },
+            0x03 => {
+                let rotation = shift_amount % 32;
+                let carry_bit = if rotation == 0 { 31 } else { rotation - 1 };
+                (
+                    operand_register_value.rotate_right(rotation),
+                    operand_register_value.get_bit(carry_bit as u8),
+                )
+            }
+            _ => panic!("Invalid Shift Type"),
+        };
+        if set_flags {
+            self.set_flag_from_bit(FlagsRegister::C, carry as u8);
+        }
+        result
+    }
+
+    fn get_status(&self) -> Status {
+        Status {
+            instruction_count: unsafe { INSTRUCTION_COUNT },
+            state: self.cpu_state(),
+            cycles: 0,
         }
-        status
     }
 }
 
 impl Display for Status {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{} ", self.instruction_count)?;
-        for i in self.registers {
-            write!(f, "{:08x} ", i)?;
+        for i in 0..16 {
+            write!(f, "{:08x} ", self.state.register(i))?;
         }
 
-        write!(f, "{:08x} ", self.cpsr)?;
+        write!(f, "{:08x} ", self.state.cpsr)?;
         write!(f, "{}\n", self.cycles)
     }
 }
@@ -427,7 +633,11 @@ impl Display for Status {
 impl Drop for CPU {
     fn drop(&mut self) {
         for i in self.status_history.iter().skip(1) {
-            self.output_file.write(format!("{}", i).as_bytes()).unwrap();
+            let line = match &self.trace_format {
+                Some(format) => format!("{}\n", format.format(&i.state)),
+                None => format!("{}", i),
+            };
+            self.output_file.write_all(line.as_bytes()).unwrap();
         }
     }
 }
@@ -435,15 +645,17 @@ impl Drop for CPU {
 #[cfg(test)]
 mod cpu_tests {
 
-    use crate::{arm7tdmi::cpu::CPUMode, memory::memory::GBAMemory, utils::bits::Bits};
+    use crate::{
+        arm7tdmi::cpu::{CPUMode, InstructionMode},
+        memory::memory::{GBAMemory, MemoryBus},
+        utils::bits::Bits,
+    };
 
-    use super::CPU;
+    use super::{CPU, PC_REGISTER};
 
     #[test]
     fn it_sets_and_resets_the_corrects_flags() {
-        let memory = GBAMemory::new();
-
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
 
         cpu.set_flag(super::FlagsRegister::C);
         cpu.set_flag(super::FlagsRegister::N);
@@ -462,10 +674,96 @@ mod cpu_tests {
 
     #[test]
     fn cpu_starts_in_svc_mode() {
-        let memory = GBAMemory::new();
-
-        let cpu = CPU::new(memory);
+        let cpu = CPU::new();
 
         assert!(matches!(cpu.get_cpu_mode(), CPUMode::SVC));
     }
+
+    #[test]
+    fn banked_registers_can_be_read_from_any_mode() {
+        let mut cpu = CPU::new();
+        cpu.set_mode(CPUMode::IRQ);
+        cpu.set_sp(0x3007FA0);
+        cpu.set_register(14, 0x8000123);
+        cpu.set_mode(CPUMode::USER);
+        cpu.set_sp(0x3007F00);
+
+        assert_eq!(cpu.banked_sp(CPUMode::IRQ), 0x3007FA0);
+        assert_eq!(cpu.banked_lr(CPUMode::IRQ), 0x8000123);
+        assert_eq!(cpu.banked_sp(CPUMode::USER), 0x3007F00);
+        assert_eq!(cpu.banked_sp(CPUMode::SYS), 0x3007F00);
+        assert_eq!(cpu.get_sp(), 0x3007F00);
+        assert_eq!(cpu.banked_spsr(CPUMode::USER), None);
+    }
+
+    #[test]
+    fn execute_single_reads_pc_as_if_prefetched() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        let mut cpu = CPU::new();
+        cpu.set_pc(0x3000000);
+
+        cpu.execute_single(0xe28f1004, &mut memory); // add r1, pc, 4
+
+        assert_eq!(cpu.get_register(1), 0x300000C);
+        assert_eq!(cpu.get_pc(), 0x3000004);
+    }
+
+    #[test]
+    fn execute_single_loads_pc_relative() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        memory.writeu32(0x3000010, 0xDEADBEEF);
+        let mut cpu = CPU::new();
+        cpu.set_pc(0x3000000);
+
+        cpu.execute_single(0xe59f0008, &mut memory); // ldr r0, [pc, 8]
+
+        assert_eq!(cpu.get_register(0), 0xDEADBEEF);
+        assert_eq!(cpu.get_pc(), 0x3000004);
+    }
+
+    #[test]
+    fn execute_single_leaves_pc_at_a_branch_target() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        let mut cpu = CPU::new();
+        cpu.set_pc(0x3000000);
+
+        cpu.execute_single(0xea000002, &mut memory); // b 0x3000010
+
+        assert_eq!(cpu.get_pc(), 0x3000010);
+    }
+
+    #[test]
+    fn thumb_fetches_consecutive_halfwords() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        let stream: [u16; 4] = [0x2001, 0x3102, 0x1840, 0xE7FE];
+        for (i, opcode) in stream.iter().enumerate() {
+            memory.writeu16(0x3000000 + i * 2, *opcode);
+        }
+        let mut cpu = CPU::new();
+        cpu.set_instruction_mode(InstructionMode::THUMB);
+        // a stray bit 0, which set_pc would have cleared
+        cpu.registers[PC_REGISTER] = 0x3000001;
+
+        cpu.flush_pipeline(&mut memory);
+        assert_eq!(cpu.prefetch, [Some(0x3102), Some(0x2001)]);
+        assert_eq!(cpu.get_pc(), 0x3000004);
+
+        cpu.advance_pipeline(&mut memory);
+        cpu.advance_pipeline(&mut memory);
+        assert_eq!(cpu.prefetch, [Some(0xE7FE), Some(0x1840)]);
+        assert_eq!(cpu.get_pc(), 0x3000008);
+    }
+
+    #[test]
+    fn arm_fetches_ignore_a_misaligned_pc() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        memory.writeu32(0x3000000, 0xE3A00001); // mov r0, #1
+        memory.writeu32(0x3000004, 0xE3A01002); // mov r1, #2
+        let mut cpu = CPU::new();
+        cpu.registers[PC_REGISTER] = 0x3000002;
+
+        cpu.flush_pipeline(&mut memory);
+
+        assert_eq!(cpu.prefetch, [Some(0xE3A01002), Some(0xE3A00001)]);
+    }
 }
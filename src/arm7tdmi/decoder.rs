@@ -13,8 +13,35 @@ impl CPU {
         };
     }
 
+    /// `decode_instruction` for an instruction fetched from `address`,
+    /// going through the instruction cache. ARM conditions depend on the
+    /// flags at the time, so they're checked after the cache.
+    pub(super) fn decode_cached(&mut self, address: WORD, instruction: WORD) -> ARMDecodedInstruction {
+        let mode = self.get_instruction_mode();
+        let decoded = match self.instruction_cache.get(address, mode, instruction) {
+            Some(decoded) => decoded,
+            None => {
+                let decoded = match mode {
+                    InstructionMode::ARM => self.decode_arm_opcode(instruction),
+                    InstructionMode::THUMB => self.decode_thumb_instruction(instruction),
+                };
+                self.instruction_cache.insert(address, mode, decoded);
+                decoded
+            }
+        };
+        match mode {
+            InstructionMode::ARM => self.check_condition(decoded),
+            InstructionMode::THUMB => decoded,
+        }
+    }
+
     fn condition_passed(&self, instruction: ARMByteCode) -> bool {
-        let condition = (instruction & 0xF0000000) >> 28;
+        self.condition_code_passed((instruction & 0xF0000000) >> 28)
+    }
+
+    /// Evaluates a 4 bit condition field against the CPSR flags, shared by
+    /// ARM instructions and Thumb conditional branches.
+    pub(super) fn condition_code_passed(&self, condition: u32) -> bool {
         match condition {
             0b0000 => self.get_flag(FlagsRegister::Z) == 1, //EQ
             0b0001 => self.get_flag(FlagsRegister::Z) == 0, //NE
@@ -37,16 +64,35 @@ impl CPU {
                     || self.get_flag(FlagsRegister::N) != self.get_flag(FlagsRegister::V)
             } //LE
             0b1110 => true,                                                               //AL
-            _ => panic!("Impossible Condition Code Encountered"),
+            _ => false, // NV never executes on ARMv4
         }
     }
 
     fn decode_arm_instruction(&mut self, instruction: ARMByteCode) -> ARMDecodedInstruction {
-        if !(self.condition_passed(instruction)) {
+        let decoded = self.decode_arm_opcode(instruction);
+        self.check_condition(decoded)
+    }
+
+    /// An instruction whose condition fails runs as a nop.
+    fn check_condition(&self, decoded: ARMDecodedInstruction) -> ARMDecodedInstruction {
+        let instruction = decoded.instruction;
+        // BLX <imm> is undefined whatever its condition field says
+        if arm_decoders::is_blx_immediate(instruction) || self.condition_passed(instruction) {
+            return decoded;
+        }
+        ARMDecodedInstruction {
+            executable: CPU::arm_nop,
+            instruction,
+        }
+    }
+
+    /// Decodes an ARM instruction without looking at its condition.
+    fn decode_arm_opcode(&mut self, instruction: ARMByteCode) -> ARMDecodedInstruction {
+        // BLX <imm> is ARMv5; on the ARM7TDMI it is an undefined instruction
+        if arm_decoders::is_blx_immediate(instruction) {
             return ARMDecodedInstruction {
-                executable: CPU::arm_nop,
+                executable: CPU::arm_undefined_instruction,
                 instruction,
-                ..Default::default()
             };
         }
 
@@ -56,6 +102,11 @@ impl CPU {
                 instruction,
                 ..Default::default()
             },
+            _ if arm_decoders::is_multiply_long_instruction(instruction) => ARMDecodedInstruction {
+                executable: CPU::arm_multiply_long,
+                instruction,
+                ..Default::default()
+            },
             _ if arm_decoders::is_multiply_instruction(instruction) => {
                 self.decode_multiply(instruction)
             }
@@ -73,11 +124,6 @@ impl CPU {
                     instruction,
                 }
             }
-            _ if arm_decoders::is_multiply_long_instruction(instruction) => ARMDecodedInstruction {
-                executable: CPU::arm_multiply_long,
-                instruction,
-                ..Default::default()
-            },
             _ if arm_decoders::is_branch_and_exchange_instruction(instruction) => {
                 ARMDecodedInstruction {
                     executable: CPU::arm_branch_and_exchange,
@@ -212,6 +258,18 @@ impl CPU {
                     executable: CPU::thumb_multiple_load_or_store
                 }
             }
+            _ if thumb_decoders::is_thumb_software_interrupt(instruction) => {
+                ARMDecodedInstruction {
+                    instruction,
+                    executable: CPU::arm_software_interrupt
+                }
+            }
+            _ if thumb_decoders::is_thumb_undefined_branch(instruction) => {
+                ARMDecodedInstruction {
+                    instruction,
+                    executable: CPU::arm_undefined_instruction
+                }
+            }
             _ if thumb_decoders::is_conditional_branch(instruction) => {
                 ARMDecodedInstruction {
                     instruction,
@@ -256,7 +314,7 @@ mod arm_decoders {
     #[inline(always)]
     pub fn is_multiply_long_instruction(instruction: ARMByteCode) -> bool {
         instruction & 0b0000_1111_1000_0000_0000_0000_1111_0000
-            == 0b0000_0000_1000_0000_0000_0000_0000_1001_0000
+            == 0b0000_0000_1000_0000_0000_0000_1001_0000
     }
 
     #[inline(always)]
@@ -264,6 +322,11 @@ mod arm_decoders {
         instruction & 0x0E00_0000 == 0x0A00_0000
     }
 
+    #[inline(always)]
+    pub fn is_blx_immediate(instruction: ARMByteCode) -> bool {
+        instruction & 0xFE00_0000 == 0xFA00_0000
+    }
+
     #[inline(always)]
     pub fn is_single_data_swap(instruction: ARMByteCode) -> bool {
         instruction & 0x0FB0_0FF0 == 0x0100_0090
@@ -363,8 +426,18 @@ mod thumb_decoders {
         instruction & 0xF000 == 0xC000
     }
 
+    /// Condition 0b1111 in the conditional branch encoding is SWI.
+    pub fn is_thumb_software_interrupt(instruction: u32) -> bool {
+        instruction & 0xFF00 == 0xDF00
+    }
+
+    /// Condition 0b1110 (AL) in the conditional branch encoding is undefined.
+    pub fn is_thumb_undefined_branch(instruction: u32) -> bool {
+        instruction & 0xFF00 == 0xDE00
+    }
+
     pub fn is_conditional_branch(instruction: u32) -> bool {
-        instruction & 0xF000 == 0xD000
+        instruction & 0xF000 == 0xD000 && instruction & 0x0E00 != 0x0E00
     }
     pub fn is_unconditional_branch(instruction: u32) -> bool {
         instruction & 0xF800 == 0xE000
@@ -409,8 +482,9 @@ mod sub_decoders {
 mod arm_decoders_tests {
     
     use arm_decoders::*;
+    use rstest::rstest;
 
-    use crate::memory::memory::GBAMemory;
+    use crate::utils::testing::assert_condition_codes;
 
     use super::*;
 
@@ -426,6 +500,12 @@ mod arm_decoders_tests {
         test_decoder(is_multiply_instruction, multiplication_instructions);
     }
 
+    #[test]
+    fn it_recognizes_a_multiply_long_instruction() {
+        let multiply_long_instructions = vec![0xE0810392, 0xE0E10392];
+        test_decoder(is_multiply_long_instruction, multiply_long_instructions);
+    }
+
     #[test]
     fn it_recognizes_a_single_data_swap_instruction() {
         let single_data_swap_instructions = vec![0xE1013092, 0xE1413092];
@@ -494,53 +574,63 @@ mod arm_decoders_tests {
 
     #[test]
     fn it_finds_single_data_swap() {
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         let instruction = 0xe1014093;
         assert!(cpu.decode_arm_instruction(instruction).executable == CPU::single_data_swap)
     }
 
     #[test]
     fn it_finds_block_data_transfer() {
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         let instruction = 0xe895001f;
         assert!(cpu.decode_arm_instruction(instruction).executable == CPU::block_dt_execution)
     }
     #[test]
     fn it_finds_a_branch_and_exchange_instruction() {
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         let instruction = 0xe12fff10;
         assert!(cpu.decode_arm_instruction(instruction).executable == CPU::arm_branch_and_exchange)
     }
 
     #[test]
     fn it_finds_swi_instruction() {
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         let instruction = 0xef001234;
 
         assert!(cpu.decode_arm_instruction(instruction).executable == CPU::arm_software_interrupt);
 
     }
+
+    #[test]
+    fn it_decodes_blx_immediate_as_undefined() {
+        let mut cpu = CPU::new();
+        for instruction in [0xfa000010, 0xfbfffffe] {
+            assert!(is_blx_immediate(instruction));
+            assert!(cpu.decode_arm_instruction(instruction).executable == CPU::arm_undefined_instruction);
+        }
+    }
+
+    #[rstest]
+    #[case::no_flags(0x0, &["NE", "CC", "PL", "VC", "LS", "GE", "GT", "AL"])]
+    #[case::zero(0x4 << 28, &["EQ", "CC", "PL", "VC", "LS", "GE", "LE", "AL"])]
+    #[case::carry(0x2 << 28, &["NE", "CS", "PL", "VC", "HI", "GE", "GT", "AL"])]
+    #[case::zero_and_carry(0x6 << 28, &["EQ", "CS", "PL", "VC", "LS", "GE", "LE", "AL"])]
+    #[case::negative(0x8 << 28, &["NE", "CC", "MI", "VC", "LS", "LT", "LE", "AL"])]
+    #[case::negative_and_overflow(0x9 << 28, &["NE", "CC", "MI", "VS", "LS", "GE", "GT", "AL"])]
+    fn mov_runs_only_under_passing_conditions(#[case] flags: u32, #[case] passing: &[&str]) {
+        assert_condition_codes(0xE3A00001, flags, passing); // mov r0, #1
+    }
 }
 
 #[cfg(test)]
 mod sub_decoder_tests {
     
 
-    use crate::{arm7tdmi::decoder::*, memory::memory::GBAMemory};
+    use crate::arm7tdmi::decoder::*;
 
     #[test]
     fn it_decodes_an_instruction_if_eq_satisfied() {
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         let instruction: ARMByteCode = 0x028210c8; // addeq r1, r2, 200
         cpu.set_flag(FlagsRegister::Z);
         let decoded_instruction = cpu.decode_instruction(instruction);
@@ -550,9 +640,7 @@ mod sub_decoder_tests {
 
     #[test]
     fn it_does_not_decode_an_instruction_if_eq_not_satisfied() {
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         let instruction: ARMByteCode = 0x028210c8; // addeq r1, r2, 200
         cpu.reset_flag(FlagsRegister::Z);
         let decoded_instruction = cpu.decode_instruction(instruction);
@@ -562,9 +650,7 @@ mod sub_decoder_tests {
 
     #[test]
     fn it_does_decode_an_instruction_if_ne_satisfied() {
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         let instruction: ARMByteCode = 0x128210c8; // addne r1, r2, 200
         cpu.reset_flag(FlagsRegister::Z);
         let decoded_instruction = cpu.decode_instruction(instruction);
@@ -574,9 +660,7 @@ mod sub_decoder_tests {
 
     #[test]
     fn it_does_not_decode_an_instruction_if_ne_not_satisfied() {
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         let instruction: ARMByteCode = 0x128210c8; // addne r1, r2, 200
         cpu.set_flag(FlagsRegister::Z);
         let decoded_instruction = cpu.decode_instruction(instruction);
@@ -586,9 +670,7 @@ mod sub_decoder_tests {
 
     #[test]
     fn it_does_decode_an_instruction_if_cs_satisfied() {
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         let instruction: ARMByteCode = 0x228210c8; // addcs r1, r2, 200
         cpu.set_flag(FlagsRegister::C);
         let decoded_instruction = cpu.decode_instruction(instruction);
@@ -597,9 +679,7 @@ mod sub_decoder_tests {
     }
     #[test]
     fn it_does_decode_an_instruction_if_cc_satisfied() {
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         let instruction: ARMByteCode = 0x328210c8; // addcc r1, r2, 200
         cpu.reset_flag(FlagsRegister::C);
         let decoded_instruction = cpu.decode_instruction(instruction);
@@ -608,9 +688,7 @@ mod sub_decoder_tests {
     }
     #[test]
     fn it_does_decode_an_instruction_if_mi_satisfied() {
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         let instruction: ARMByteCode = 0x428210c8; // addmi r1, r2, 200
         cpu.set_flag(FlagsRegister::N);
         let decoded_instruction = cpu.decode_instruction(instruction);
@@ -619,9 +697,7 @@ mod sub_decoder_tests {
     }
     #[test]
     fn it_does_decode_an_instruction_if_pl_satisfied() {
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         let instruction: ARMByteCode = 0x528210c8; // addpl r1, r2, 200
         cpu.reset_flag(FlagsRegister::C);
         let decoded_instruction = cpu.decode_instruction(instruction);
@@ -630,9 +706,7 @@ mod sub_decoder_tests {
     }
     #[test]
     fn it_does_decode_an_instruction_if_vs_satisfied() {
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         let instruction: ARMByteCode = 0x628210c8; // addvs r1, r2, 200
         cpu.set_flag(FlagsRegister::V);
         let decoded_instruction = cpu.decode_instruction(instruction);
@@ -641,9 +715,7 @@ mod sub_decoder_tests {
     }
     #[test]
     fn it_does_decode_an_instruction_if_vc_satisfied() {
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         let instruction: ARMByteCode = 0x728210c8; // addvc r1, r2, 200
         cpu.reset_flag(FlagsRegister::V);
         let decoded_instruction = cpu.decode_instruction(instruction);
@@ -652,9 +724,7 @@ mod sub_decoder_tests {
     }
     #[test]
     fn it_does_decode_an_instruction_if_hi_satisfied() {
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         let instruction: ARMByteCode = 0x828210c8; // addhi r1, r2, 200
         cpu.set_flag(FlagsRegister::C);
         cpu.reset_flag(FlagsRegister::Z);
@@ -664,9 +734,7 @@ mod sub_decoder_tests {
     }
     #[test]
     fn it_does_decode_an_instruction_if_ls_satisfied() {
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         let instruction: ARMByteCode = 0x928210c8; // addls r1, r2, 200
         cpu.reset_flag(FlagsRegister::C);
         cpu.reset_flag(FlagsRegister::Z);
@@ -689,9 +757,7 @@ mod sub_decoder_tests {
 
     #[test]
     fn it_does_decode_an_instruction_if_ge_satisfied() {
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         let instruction: ARMByteCode = 0xa28210c8; // addge r1, r2, 200
         cpu.set_flag(FlagsRegister::N);
         cpu.set_flag(FlagsRegister::V);
@@ -707,9 +773,7 @@ mod sub_decoder_tests {
     }
     #[test]
     fn it_does_decode_an_instruction_if_lt_satisfied() {
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         let instruction: ARMByteCode = 0xb28210c8; // addlt r1, r2, 200
         cpu.reset_flag(FlagsRegister::N);
         cpu.set_flag(FlagsRegister::V);
@@ -726,9 +790,7 @@ mod sub_decoder_tests {
 
     #[test]
     fn it_does_decode_an_instruction_if_gt_satisfied() {
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         let instruction: ARMByteCode = 0xc28210c8; // addgt r1, r2, 200
         cpu.reset_flag(FlagsRegister::Z);
         cpu.set_flag(FlagsRegister::N);
@@ -747,9 +809,7 @@ mod sub_decoder_tests {
 
     #[test]
     fn it_does_decode_an_instruction_if_le_satisfied() {
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         let instruction: ARMByteCode = 0xd28210c8; // addle r1, r2, 200
 
         cpu.set_flag(FlagsRegister::Z);
@@ -775,9 +835,7 @@ mod sub_decoder_tests {
     }
     #[test]
     fn it_does_decode_an_instruction_if_al_satisfied() {
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         let instruction: ARMByteCode = 0xe28210c8; // addal r1, r2, 200
         let decoded_instruction = cpu.decode_instruction(instruction);
         assert!(decoded_instruction.executable != CPU::arm_nop);
@@ -788,7 +846,7 @@ mod sub_decoder_tests {
     //    fn it_returns_a_multiply_instruction() {
     //        let memory = Memory::new().unwrap();
     //        
-    //        let mut cpu = CPU::new(memory);
+    //        let mut cpu = CPU::new();
     //
     //        let instruction: ARMByteCode = 0xE0230192;
     //        let decoded_instruction = cpu.decode_instruction(instruction);
@@ -802,7 +860,7 @@ mod sub_decoder_tests {
     //    fn it_returns_a_branch_instruction() {
     //        let memory = Memory::new().unwrap();
     //        
-    //        let mut cpu = CPU::new(memory);
+    //        let mut cpu = CPU::new();
     //
     //        let instruction: ARMByteCode = 0xea000005;
     //        let decoded_instruction = cpu.decode_instruction(instruction);
@@ -813,15 +871,15 @@ mod sub_decoder_tests {
     //    fn it_returns_a_cmp_instruction() {
     //        let memory = Memory::new().unwrap();
     //        
-    //        let mut cpu = CPU::new(memory);
+    //        let mut cpu = CPU::new();
     //
     //        let instruction: ARMByteCode = 0xe1530312; //  cmp r3, r2, lsl r3
     //                                                   //  shift by register in
     //                                                   // order to stall the alu by
     //                                                   // one clock cycle
     //        cpu.prefetch[0] = instruction;
-    //        cpu.execute_cpu_cycle();
-    //        cpu.execute_cpu_cycle();
+    //        cpu.execute_cpu_cycle(&mut memory);
+    //        cpu.execute_cpu_cycle(&mut memory);
     //        assert!(cpu.alu_executable.operation == CPU::arm_cmp);
     //    }
 
@@ -829,7 +887,7 @@ mod sub_decoder_tests {
     //    fn it_returns_an_add_instruction_with_an_imm_op2() {
     //        let memory = Memory::new().unwrap();
     //        
-    //        let mut cpu = CPU::new(memory);
+    //        let mut cpu = CPU::new();
     //
     //        let instruction: ARMByteCode = 0xe2812020; // add r
     //        let decoded_instruction = cpu.decode_instruction(instruction);
@@ -843,7 +901,7 @@ mod sub_decoder_tests {
     //    fn it_returns_an_add_instruction_an_lsl_operand2() {
     //        let memory = Memory::new().unwrap();
     //        
-    //        let mut cpu = CPU::new(memory);
+    //        let mut cpu = CPU::new();
     //
     //        let instruction: ARMByteCode = 0xe0831102; // add r1, r3, r2 LSL 2
     //        cpu.set_register(2, 1);
@@ -858,7 +916,7 @@ mod sub_decoder_tests {
     //    fn it_returns_an_add_instruction_with_ror_10() {
     //        let memory = Memory::new().unwrap();
     //        
-    //        let mut cpu = CPU::new(memory);
+    //        let mut cpu = CPU::new();
     //
     //        let instruction: ARMByteCode = 0xe0831562; // add r1, r3, r2 ROR#10
     //        cpu.set_register(2, 5);
@@ -873,7 +931,7 @@ mod sub_decoder_tests {
     //    fn it_returns_an_add_instruction_with_asr_10() {
     //        let memory = Memory::new().unwrap();
     //        
-    //        let mut cpu = CPU::new(memory);
+    //        let mut cpu = CPU::new();
     //
     //        let instruction: ARMByteCode = 0xe0831542; // add r1, r3, r2 ASR#10
     //        cpu.set_register(2, 0xB000_0000);
@@ -888,7 +946,7 @@ mod sub_decoder_tests {
     //    fn it_returns_an_add_instruction_with_lsr_10() {
     //        let memory = Memory::new().unwrap();
     //        
-    //        let mut cpu = CPU::new(memory);
+    //        let mut cpu = CPU::new();
     //
     //        let instruction: ARMByteCode = 0xe0831522; // add r1, r3, r2 LSR#10
     //        cpu.set_register(2, 0xB000_0000);
@@ -903,7 +961,7 @@ mod sub_decoder_tests {
     //    fn it_returns_an_add_instruction_with_lsr_32() {
     //        let memory = Memory::new().unwrap();
     //        
-    //        let mut cpu = CPU::new(memory);
+    //        let mut cpu = CPU::new();
     //
     //        let instruction: ARMByteCode = 0xe0931022; // adds r1, r3, r2 LSR#32
     //        cpu.set_register(2, u32::MAX);
@@ -919,7 +977,7 @@ mod sub_decoder_tests {
     //    fn it_returns_an_add_instruction_with_an_asr_32_negative() {
     //        let memory = Memory::new().unwrap();
     //        
-    //        let mut cpu = CPU::new(memory);
+    //        let mut cpu = CPU::new();
     //
     //        let instruction: ARMByteCode = 0xe0931042; // adds r1, r3, r2 ASR#32
     //        cpu.set_register(2, 0xF000_1000);
@@ -935,7 +993,7 @@ mod sub_decoder_tests {
     //    fn it_returns_an_add_instruction_with_an_asr_32_positive() {
     //        let memory = Memory::new().unwrap();
     //        
-    //        let mut cpu = CPU::new(memory);
+    //        let mut cpu = CPU::new();
     //
     //        let instruction: ARMByteCode = 0xe0831042; // add r1, r3, r2 ASR#32
     //        cpu.set_register(2, 0x0000_1000);
@@ -951,7 +1009,7 @@ mod sub_decoder_tests {
     //    fn it_returns_an_add_instruction_with_op2_shifted_by_register() {
     //        let memory = Memory::new().unwrap();
     //        
-    //        let mut cpu = CPU::new(memory);
+    //        let mut cpu = CPU::new();
     //
     //        let instruction: ARMByteCode = 0xe0831412; // add r1, r3, r2 LSL r4
     //        cpu.set_register(2, 0x0000_1000);
@@ -968,15 +1026,13 @@ mod sub_decoder_tests {
 mod thumb_decoder_tests {
     
 
-    use crate::{arm7tdmi::cpu::{InstructionMode, CPU}, memory::memory::GBAMemory};
+    use crate::arm7tdmi::cpu::{InstructionMode, CPU};
 
     #[test]
     fn it_recognizes_sdt_imm_offset() {
 
         let instruction = 0x68cd; // ldr r5, [r1, 12]
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         let decoded_instruction = cpu.decode_instruction(instruction);
@@ -989,9 +1045,7 @@ mod thumb_decoder_tests {
     fn it_recognizes_sdt_sp_imm_offset() {
 
         let instruction = 0x9d03; // ldr r5, [sp, 12]
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         let decoded_instruction = cpu.decode_instruction(instruction);
@@ -1004,9 +1058,7 @@ mod thumb_decoder_tests {
     fn it_recognizes_add_offset_to_sp() {
 
         let instruction = 0xb07d; // add sp, 500
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         let decoded_instruction = cpu.decode_instruction(instruction);
@@ -1018,9 +1070,7 @@ mod thumb_decoder_tests {
     fn it_recognizes_thumb_push() {
 
         let instruction = 0xb503; // push {r0-r1, lr}
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         let decoded_instruction = cpu.decode_instruction(instruction);
@@ -1032,9 +1082,7 @@ mod thumb_decoder_tests {
     fn it_recognizes_thumb_bdt() {
 
         let instruction = 0xc107; // stmia r1 {r0-r2}
-        let memory = GBAMemory::new();
-        
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         let decoded_instruction = cpu.decode_instruction(instruction);
@@ -1042,5 +1090,26 @@ mod thumb_decoder_tests {
         assert!(decoded_instruction.executable == CPU::thumb_multiple_load_or_store);
     }
 
+    #[test]
+    fn it_routes_conditional_branch_nv_condition_to_swi() {
+        let instruction = 0xdf05; // swi 5
+        let mut cpu = CPU::new();
+        cpu.set_instruction_mode(InstructionMode::THUMB);
+
+        let decoded_instruction = cpu.decode_instruction(instruction);
+        assert!(decoded_instruction.executable == CPU::arm_software_interrupt);
+    }
+
+    #[test]
+    fn it_treats_conditional_branch_al_condition_as_undefined() {
+        let instruction = 0xde05;
+        let mut cpu = CPU::new();
+        cpu.set_instruction_mode(InstructionMode::THUMB);
+
+        let decoded_instruction = cpu.decode_instruction(instruction);
+        assert!(decoded_instruction.executable == CPU::arm_undefined_instruction);
+        assert!(decoded_instruction.executable != CPU::thumb_conditional_branch);
+    }
+
 
 }
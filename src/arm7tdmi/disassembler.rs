@@ -0,0 +1,668 @@
+use std::{fs::File, io::Write, ops::Range};
+
+use crate::{
+    memory::memory::MemoryBus,
+    types::{HWORD, WORD},
+    utils::bits::sign_extend,
+};
+
+use super::cpu::{InstructionMode, CPU};
+
+/// Condition code suffixes by their encoding, empty for AL.
+pub(crate) const CONDITIONS: [&str; 16] = [
+    "EQ", "NE", "CS", "CC", "MI", "PL", "VS", "VC", "HI", "LS", "GE", "LT", "GT", "LE", "", "NV",
+];
+const DATA_PROCESSING_OPCODES: [&str; 16] = [
+    "AND", "EOR", "SUB", "RSB", "ADD", "ADC", "SBC", "RSC", "TST", "TEQ", "CMP", "CMN", "ORR",
+    "MOV", "BIC", "MVN",
+];
+const SHIFTS: [&str; 4] = ["LSL", "LSR", "ASR", "ROR"];
+const THUMB_ALU_OPCODES: [&str; 16] = [
+    "AND", "EOR", "LSL", "LSR", "ASR", "ADC", "SBC", "ROR", "TST", "NEG", "CMP", "CMN", "ORR",
+    "MUL", "BIC", "MVN",
+];
+
+fn register_name(register: WORD) -> String {
+    match register {
+        13 => String::from("sp"),
+        14 => String::from("lr"),
+        15 => String::from("pc"),
+        _ => format!("r{}", register),
+    }
+}
+
+fn register_list(list: WORD, extra: Option<&str>) -> String {
+    let mut registers: Vec<String> = (0..16)
+        .filter(|register| list & (1 << register) > 0)
+        .map(register_name)
+        .collect();
+    if let Some(extra) = extra {
+        registers.push(extra.to_string());
+    }
+    format!("{{{}}}", registers.join(", "))
+}
+
+fn arm_shifted_register(instruction: WORD) -> String {
+    let rm = register_name(instruction & 0xF);
+    let shift_type = (instruction >> 5) & 0x3;
+    if instruction & (1 << 4) > 0 {
+        let rs = register_name((instruction >> 8) & 0xF);
+        return format!("{}, {} {}", rm, SHIFTS[shift_type as usize], rs);
+    }
+    let amount = (instruction >> 7) & 0x1F;
+    match (shift_type, amount) {
+        (0, 0) => rm,
+        (3, 0) => format!("{}, RRX", rm),
+        (1 | 2, 0) => format!("{}, {} #32", rm, SHIFTS[shift_type as usize]),
+        _ => format!("{}, {} #{}", rm, SHIFTS[shift_type as usize], amount),
+    }
+}
+
+fn arm_data_processing(instruction: WORD, condition: &str) -> String {
+    let opcode = ((instruction >> 21) & 0xF) as usize;
+    let rd = register_name((instruction >> 12) & 0xF);
+    let rn = register_name((instruction >> 16) & 0xF);
+    let operand = if instruction & (1 << 25) > 0 {
+        let rotate = ((instruction >> 8) & 0xF) * 2;
+        format!("#{:#X}", (instruction & 0xFF).rotate_right(rotate))
+    } else {
+        arm_shifted_register(instruction)
+    };
+    let set_flags = instruction & (1 << 20) > 0;
+    let mnemonic = DATA_PROCESSING_OPCODES[opcode];
+    match opcode {
+        0x8..=0xB => format!("{}{} {}, {}", mnemonic, condition, rn, operand),
+        0xD | 0xF => format!(
+            "{}{}{} {}, {}",
+            mnemonic,
+            condition,
+            if set_flags { "S" } else { "" },
+            rd,
+            operand
+        ),
+        _ => format!(
+            "{}{}{} {}, {}, {}",
+            mnemonic,
+            condition,
+            if set_flags { "S" } else { "" },
+            rd,
+            rn,
+            operand
+        ),
+    }
+}
+
+fn arm_address(instruction: WORD, offset: String, has_offset: bool) -> String {
+    let rn = register_name((instruction >> 16) & 0xF);
+    let sign = if instruction & (1 << 23) > 0 { "" } else { "-" };
+    let pre_indexed = instruction & (1 << 24) > 0;
+    let write_back = instruction & (1 << 21) > 0;
+    match (pre_indexed, has_offset) {
+        (true, false) => format!("[{}]", rn),
+        (true, true) => format!("[{}, {}{}]{}", rn, sign, offset, if write_back { "!" } else { "" }),
+        (false, false) => format!("[{}]", rn),
+        (false, true) => format!("[{}], {}{}", rn, sign, offset),
+    }
+}
+
+fn arm_single_data_transfer(instruction: WORD, condition: &str) -> String {
+    let load = instruction & (1 << 20) > 0;
+    let byte = if instruction & (1 << 22) > 0 { "B" } else { "" };
+    let translate = if instruction & (1 << 24) == 0 && instruction & (1 << 21) > 0 {
+        "T"
+    } else {
+        ""
+    };
+    let rd = register_name((instruction >> 12) & 0xF);
+    let (offset, has_offset) = if instruction & (1 << 25) > 0 {
+        (arm_shifted_register(instruction), true)
+    } else {
+        let offset = instruction & 0xFFF;
+        (format!("#{:#X}", offset), offset != 0)
+    };
+    format!(
+        "{}{}{}{} {}, {}",
+        if load { "LDR" } else { "STR" },
+        condition,
+        byte,
+        translate,
+        rd,
+        arm_address(instruction, offset, has_offset)
+    )
+}
+
+fn arm_halfword_transfer(instruction: WORD, condition: &str) -> String {
+    let load = instruction & (1 << 20) > 0;
+    let suffix = match ((instruction >> 5) & 0x3, load) {
+        (1, _) => "H",
+        (2, true) => "SB",
+        (3, true) => "SH",
+        _ => return String::from("UNDEFINED"),
+    };
+    let rd = register_name((instruction >> 12) & 0xF);
+    let (offset, has_offset) = if instruction & (1 << 22) > 0 {
+        let offset = (instruction >> 4) & 0xF0 | instruction & 0xF;
+        (format!("#{:#X}", offset), offset != 0)
+    } else {
+        (register_name(instruction & 0xF), true)
+    };
+    format!(
+        "{}{}{} {}, {}",
+        if load { "LDR" } else { "STR" },
+        condition,
+        suffix,
+        rd,
+        arm_address(instruction, offset, has_offset)
+    )
+}
+
+fn arm_block_transfer(instruction: WORD, condition: &str) -> String {
+    let load = instruction & (1 << 20) > 0;
+    let mode = match (instruction >> 23) & 0x3 {
+        0b00 => "DA",
+        0b01 => "IA",
+        0b10 => "DB",
+        _ => "IB",
+    };
+    format!(
+        "{}{}{} {}{}, {}{}",
+        if load { "LDM" } else { "STM" },
+        condition,
+        mode,
+        register_name((instruction >> 16) & 0xF),
+        if instruction & (1 << 21) > 0 { "!" } else { "" },
+        register_list(instruction & 0xFFFF, None),
+        if instruction & (1 << 22) > 0 { "^" } else { "" }
+    )
+}
+
+fn arm_psr_transfer(instruction: WORD, condition: &str) -> String {
+    let psr = if instruction & (1 << 22) > 0 { "SPSR" } else { "CPSR" };
+    if instruction & (1 << 21) == 0 {
+        return format!(
+            "MRS{} {}, {}",
+            condition,
+            register_name((instruction >> 12) & 0xF),
+            psr
+        );
+    }
+    let fields: String = [(19, 'f'), (18, 's'), (17, 'x'), (16, 'c')]
+        .iter()
+        .filter(|(bit, _)| instruction & (1 << bit) > 0)
+        .map(|(_, field)| field)
+        .collect();
+    let operand = if instruction & (1 << 25) > 0 {
+        let rotate = ((instruction >> 8) & 0xF) * 2;
+        format!("#{:#X}", (instruction & 0xFF).rotate_right(rotate))
+    } else {
+        register_name(instruction & 0xF)
+    };
+    format!("MSR{} {}_{}, {}", condition, psr, fields, operand)
+}
+
+/// Renders a single ARM instruction located at `address`.
+pub fn disassemble_arm(address: WORD, instruction: WORD) -> String {
+    let condition_code = (instruction >> 28) as usize;
+    if condition_code == 0xF {
+        return String::from("UNDEFINED");
+    }
+    let condition = CONDITIONS[condition_code];
+
+    if instruction & 0x0FFF_FFF0 == 0x012F_FF10 {
+        format!("BX{} {}", condition, register_name(instruction & 0xF))
+    } else if instruction & 0x0E00_0000 == 0x0A00_0000 {
+        let offset = sign_extend((instruction & 0x00FF_FFFF) << 2, 25);
+        let destination = address.wrapping_add(8).wrapping_add(offset);
+        let link = if instruction & (1 << 24) > 0 { "L" } else { "" };
+        format!("B{}{} {:#010X}", link, condition, destination)
+    } else if instruction & 0x0F00_0000 == 0x0F00_0000 {
+        format!("SWI{} #{:#X}", condition, instruction & 0x00FF_FFFF)
+    } else if instruction & 0x0FC0_00F0 == 0x0000_0090 {
+        let rd = register_name((instruction >> 16) & 0xF);
+        let rn = register_name((instruction >> 12) & 0xF);
+        let rs = register_name((instruction >> 8) & 0xF);
+        let rm = register_name(instruction & 0xF);
+        let set_flags = if instruction & (1 << 20) > 0 { "S" } else { "" };
+        if instruction & (1 << 21) > 0 {
+            format!("MLA{}{} {}, {}, {}, {}", condition, set_flags, rd, rm, rs, rn)
+        } else {
+            format!("MUL{}{} {}, {}, {}", condition, set_flags, rd, rm, rs)
+        }
+    } else if instruction & 0x0F80_00F0 == 0x0080_0090 {
+        let mnemonic = match (instruction >> 21) & 0x3 {
+            0b00 => "UMULL",
+            0b01 => "UMLAL",
+            0b10 => "SMULL",
+            _ => "SMLAL",
+        };
+        format!(
+            "{}{}{} {}, {}, {}, {}",
+            mnemonic,
+            condition,
+            if instruction & (1 << 20) > 0 { "S" } else { "" },
+            register_name((instruction >> 12) & 0xF),
+            register_name((instruction >> 16) & 0xF),
+            register_name(instruction & 0xF),
+            register_name((instruction >> 8) & 0xF)
+        )
+    } else if instruction & 0x0FB0_0FF0 == 0x0100_0090 {
+        format!(
+            "SWP{}{} {}, {}, [{}]",
+            condition,
+            if instruction & (1 << 22) > 0 { "B" } else { "" },
+            register_name((instruction >> 12) & 0xF),
+            register_name(instruction & 0xF),
+            register_name((instruction >> 16) & 0xF)
+        )
+    } else if instruction & 0x0E00_0090 == 0x0000_0090 {
+        arm_halfword_transfer(instruction, condition)
+    } else if instruction & 0x0FBF_0FFF == 0x010F_0000
+        || instruction & 0x0DB0_F000 == 0x0120_F000
+    {
+        arm_psr_transfer(instruction, condition)
+    } else if instruction & 0x0C00_0000 == 0x0000_0000 {
+        arm_data_processing(instruction, condition)
+    } else if instruction & 0x0E00_0010 == 0x0600_0010 {
+        String::from("UNDEFINED")
+    } else if instruction & 0x0C00_0000 == 0x0400_0000 {
+        arm_single_data_transfer(instruction, condition)
+    } else if instruction & 0x0E00_0000 == 0x0800_0000 {
+        arm_block_transfer(instruction, condition)
+    } else {
+        format!("COPROCESSOR{} {:#010X}", condition, instruction)
+    }
+}
+
+/// Renders a single Thumb instruction located at `address`. BL is split
+/// across two halfwords, see `disassemble_thumb_bl` for the paired form.
+pub fn disassemble_thumb(address: WORD, instruction: HWORD) -> String {
+    let instruction = instruction as WORD;
+    let low_register = |shift: u32| register_name((instruction >> shift) & 0x7);
+
+    match instruction >> 11 {
+        0b00000..=0b00010 => {
+            let amount = (instruction >> 6) & 0x1F;
+            let shift = SHIFTS[(instruction >> 11) as usize];
+            format!("{} {}, {}, #{}", shift, low_register(0), low_register(3), amount)
+        }
+        0b00011 => {
+            let mnemonic = if instruction & (1 << 9) > 0 { "SUB" } else { "ADD" };
+            let operand = if instruction & (1 << 10) > 0 {
+                format!("#{:#X}", (instruction >> 6) & 0x7)
+            } else {
+                low_register(6)
+            };
+            format!("{} {}, {}, {}", mnemonic, low_register(0), low_register(3), operand)
+        }
+        0b00100..=0b00111 => {
+            let mnemonic = ["MOV", "CMP", "ADD", "SUB"][((instruction >> 11) & 0x3) as usize];
+            format!("{} {}, #{:#X}", mnemonic, low_register(8), instruction & 0xFF)
+        }
+        0b01000 if instruction & (1 << 10) == 0 => {
+            let opcode = ((instruction >> 6) & 0xF) as usize;
+            format!("{} {}, {}", THUMB_ALU_OPCODES[opcode], low_register(0), low_register(3))
+        }
+        0b01000 => {
+            let rd = register_name((instruction & 0x7) | (instruction >> 4) & 0x8);
+            let rs = register_name((instruction >> 3) & 0xF);
+            match (instruction >> 8) & 0x3 {
+                0b00 => format!("ADD {}, {}", rd, rs),
+                0b01 => format!("CMP {}, {}", rd, rs),
+                0b10 => format!("MOV {}, {}", rd, rs),
+                _ => format!("BX {}", rs),
+            }
+        }
+        0b01001 => {
+            let offset = (instruction & 0xFF) << 2;
+            let literal = (address.wrapping_add(4) & !0x3).wrapping_add(offset);
+            format!("LDR {}, [pc, #{:#X}] ; {:#010X}", low_register(8), offset, literal)
+        }
+        0b01010..=0b01011 => {
+            let mnemonic = if instruction & (1 << 9) == 0 {
+                ["STR", "STRB", "LDR", "LDRB"][((instruction >> 10) & 0x3) as usize]
+            } else {
+                ["STRH", "LDSB", "LDRH", "LDSH"][((instruction >> 10) & 0x3) as usize]
+            };
+            format!(
+                "{} {}, [{}, {}]",
+                mnemonic,
+                low_register(0),
+                low_register(3),
+                low_register(6)
+            )
+        }
+        0b01100..=0b10001 => {
+            let (mnemonic, scale) = match instruction >> 11 {
+                0b01100 => ("STR", 4),
+                0b01101 => ("LDR", 4),
+                0b01110 => ("STRB", 1),
+                0b01111 => ("LDRB", 1),
+                0b10000 => ("STRH", 2),
+                _ => ("LDRH", 2),
+            };
+            let offset = ((instruction >> 6) & 0x1F) * scale;
+            format!("{} {}, [{}, #{:#X}]", mnemonic, low_register(0), low_register(3), offset)
+        }
+        0b10010..=0b10011 => {
+            let mnemonic = if instruction & (1 << 11) > 0 { "LDR" } else { "STR" };
+            format!("{} {}, [sp, #{:#X}]", mnemonic, low_register(8), (instruction & 0xFF) << 2)
+        }
+        0b10100..=0b10101 => {
+            let base = if instruction & (1 << 11) > 0 { "sp" } else { "pc" };
+            format!("ADD {}, {}, #{:#X}", low_register(8), base, (instruction & 0xFF) << 2)
+        }
+        0b10110..=0b10111 if instruction & 0x0F00 == 0x0000 => {
+            let sign = if instruction & (1 << 7) > 0 { "-" } else { "" };
+            format!("ADD sp, #{}{:#X}", sign, (instruction & 0x7F) << 2)
+        }
+        0b10110..=0b10111 if instruction & 0x0600 == 0x0400 => {
+            let load = instruction & (1 << 11) > 0;
+            let extra = match (instruction & (1 << 8) > 0, load) {
+                (false, _) => None,
+                (true, true) => Some("pc"),
+                (true, false) => Some("lr"),
+            };
+            format!(
+                "{} {}",
+                if load { "POP" } else { "PUSH" },
+                register_list(instruction & 0xFF, extra)
+            )
+        }
+        0b11000..=0b11001 => {
+            let mnemonic = if instruction & (1 << 11) > 0 { "LDMIA" } else { "STMIA" };
+            format!("{} {}!, {}", mnemonic, low_register(8), register_list(instruction & 0xFF, None))
+        }
+        0b11010..=0b11011 => match (instruction >> 8) & 0xF {
+            0b1111 => format!("SWI #{:#X}", instruction & 0xFF),
+            0b1110 => String::from("UNDEFINED"),
+            condition => {
+                let offset = sign_extend((instruction & 0xFF) << 1, 8);
+                let destination = address.wrapping_add(4).wrapping_add(offset);
+                format!("B{} {:#010X}", CONDITIONS[condition as usize], destination)
+            }
+        },
+        0b11100 => {
+            let offset = sign_extend((instruction & 0x7FF) << 1, 11);
+            format!("B {:#010X}", address.wrapping_add(4).wrapping_add(offset))
+        }
+        0b11110 => format!("BL (high) #{:#X}", instruction & 0x7FF),
+        0b11111 => format!("BL (low) #{:#X}", instruction & 0x7FF),
+        _ => String::from("UNDEFINED"),
+    }
+}
+
+/// Renders a BL instruction pair whose first half is located at `address`.
+pub fn disassemble_thumb_bl(address: WORD, high: HWORD, low: HWORD) -> String {
+    let offset = sign_extend(((high as WORD) & 0x7FF) << 12 | ((low as WORD) & 0x7FF) << 1, 22);
+    format!("BL {:#010X}", address.wrapping_add(4).wrapping_add(offset))
+}
+
+fn is_bl_pair(high: HWORD, low: HWORD) -> bool {
+    high >> 11 == 0b11110 && low >> 11 == 0b11111
+}
+
+/// An instruction read by `disassemble_at`.
+struct Disassembled {
+    /// The instruction's halfwords or word in hex.
+    raw: String,
+    mnemonic: String,
+    size: WORD,
+}
+
+/// Disassembles the instruction at `address` in `mode`, taking a Thumb BL
+/// pair as one instruction if `pair_bl` is set.
+fn disassemble_at(memory: &dyn MemoryBus, address: WORD, mode: InstructionMode, pair_bl: bool) -> Disassembled {
+    match mode {
+        InstructionMode::ARM => {
+            let instruction = memory.peeku32(address as usize);
+            Disassembled {
+                raw: format!("{:08X}", instruction),
+                mnemonic: disassemble_arm(address, instruction),
+                size: 4,
+            }
+        }
+        InstructionMode::THUMB => {
+            let instruction = memory.peeku16(address as usize);
+            let next = memory.peeku16(address.wrapping_add(2) as usize);
+            if pair_bl && is_bl_pair(instruction, next) {
+                Disassembled {
+                    raw: format!("{:04X} {:04X}", instruction, next),
+                    mnemonic: disassemble_thumb_bl(address, instruction, next),
+                    size: 4,
+                }
+            } else {
+                Disassembled {
+                    raw: format!("{:04X}", instruction),
+                    mnemonic: disassemble_thumb(address, instruction),
+                    size: 2,
+                }
+            }
+        }
+    }
+}
+
+impl CPU {
+    /// Disassembles `count` instructions from `start` in `mode` into address
+    /// and mnemonic pairs. A Thumb BL pair is one instruction.
+    pub fn disassemble_range(
+        &self,
+        memory: &dyn MemoryBus,
+        start: WORD,
+        count: usize,
+        mode: InstructionMode,
+    ) -> Vec<(WORD, String)> {
+        let mut instructions = Vec::with_capacity(count);
+        let mut address = start;
+        while instructions.len() < count {
+            let instruction = disassemble_at(memory, address, mode, true);
+            instructions.push((address, instruction.mnemonic));
+            address = address.wrapping_add(instruction.size);
+        }
+        instructions
+    }
+}
+
+/// Address ranges known to hold ARM or Thumb code. Addresses outside every
+/// range fall back to a heuristic: word aligned instructions with the AL
+/// condition are treated as ARM, everything else as Thumb.
+#[derive(Default)]
+pub struct ModeMap {
+    ranges: Vec<(Range<WORD>, InstructionMode)>,
+}
+
+impl ModeMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_range(mut self, range: Range<WORD>, mode: InstructionMode) -> Self {
+        self.ranges.push((range, mode));
+        self
+    }
+
+    pub fn mode_at(&self, address: WORD, memory: &dyn MemoryBus) -> InstructionMode {
+        if let Some((_, mode)) = self.ranges.iter().find(|(range, _)| range.contains(&address)) {
+            return *mode;
+        }
+        if address & 0x3 == 0 && memory.peeku32(address as usize) >> 28 == 0xE {
+            InstructionMode::ARM
+        } else {
+            InstructionMode::THUMB
+        }
+    }
+}
+
+/// Disassembles `range` into lines of address, raw bytes and mnemonic.
+pub fn disassemble_lines(memory: &dyn MemoryBus, range: Range<WORD>, modes: &ModeMap) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut address = range.start;
+    while address < range.end {
+        let mode = modes.mode_at(address, memory);
+        // a BL whose second half is past the end is left unpaired
+        let instruction = disassemble_at(memory, address, mode, address + 2 < range.end);
+        lines.push(format!("{:08X}: {:<9}  {}", address, instruction.raw, instruction.mnemonic));
+        address += instruction.size;
+    }
+    lines
+}
+
+pub fn export_disassembly(
+    memory: &dyn MemoryBus,
+    range: Range<WORD>,
+    modes: &ModeMap,
+    path: &str,
+) -> Result<(), std::io::Error> {
+    let mut file = File::create(path)?;
+    for line in disassemble_lines(memory, range, modes) {
+        writeln!(file, "{}", line)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod disassembler_tests {
+    use rstest::rstest;
+
+    use crate::{
+        arm7tdmi::cpu::{InstructionMode, CPU},
+        memory::memory::{GBAMemory, MemoryBus},
+    };
+
+    use super::{disassemble_arm, disassemble_lines, disassemble_thumb, export_disassembly, ModeMap};
+
+    #[rstest]
+    #[case(0xE3A00001, "MOV r0, #0x1")]
+    #[case(0xE0912003, "ADDS r2, r1, r3")]
+    #[case(0x10412183, "SUBNE r2, r1, r3, LSL #3")]
+    #[case(0xE3510000, "CMP r1, #0x0")]
+    #[case(0xE5912004, "LDR r2, [r1, #0x4]")]
+    #[case(0xE4D12001, "LDRB r2, [r1], #0x1")]
+    #[case(0xE4B12004, "LDRT r2, [r1], #0x4")]
+    #[case(0xE4A12004, "STRT r2, [r1], #0x4")]
+    #[case(0xE5B12004, "LDR r2, [r1, #0x4]!")]
+    #[case(0xE1D120B2, "LDRH r2, [r1, #0x2]")]
+    #[case(0xE92D4010, "STMDB sp!, {r4, lr}")]
+    #[case(0xE12FFF1E, "BX lr")]
+    #[case(0xEAFFFFFE, "B 0x08000000")]
+    #[case(0xEB000001, "BL 0x0800000C")]
+    #[case(0xE10F0000, "MRS r0, CPSR")]
+    #[case(0xE129F000, "MSR CPSR_fc, r0")]
+    #[case(0xE0010392, "MUL r1, r2, r3")]
+    #[case(0xEF000005, "SWI #0x5")]
+    fn disassembles_arm_instructions(#[case] instruction: u32, #[case] expected: &str) {
+        assert_eq!(disassemble_arm(0x08000000, instruction), expected);
+    }
+
+    #[rstest]
+    #[case(0x2001, "MOV r0, #0x1")]
+    #[case(0x0088, "LSL r0, r1, #2")]
+    #[case(0x1888, "ADD r0, r1, r2")]
+    #[case(0x4048, "EOR r0, r1")]
+    #[case(0x4770, "BX lr")]
+    #[case(0x4801, "LDR r0, [pc, #0x4] ; 0x08000008")]
+    #[case(0x6848, "LDR r0, [r1, #0x4]")]
+    #[case(0xB510, "PUSH {r4, lr}")]
+    #[case(0xBD10, "POP {r4, pc}")]
+    #[case(0xD0FE, "BEQ 0x08000000")]
+    #[case(0xDF01, "SWI #0x1")]
+    #[case(0xE7FE, "B 0x08000000")]
+    fn disassembles_thumb_instructions(#[case] instruction: u16, #[case] expected: &str) {
+        assert_eq!(disassemble_thumb(0x08000000, instruction), expected);
+    }
+
+    fn memory_with_program() -> Box<dyn MemoryBus> {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        memory.writeu32(0x3000000, 0xE3A00001); // mov r0, #1
+        memory.writeu32(0x3000004, 0xE12FFF1E); // bx lr
+        memory.writeu16(0x3000008, 0x2001); // mov r0, #1
+        memory.writeu16(0x300000A, 0xF000); // bl
+        memory.writeu16(0x300000C, 0xF802);
+        memory.writeu16(0x300000E, 0x4770); // bx lr
+        memory
+    }
+
+    #[test]
+    fn disassembles_range_using_mode_map() {
+        let memory = memory_with_program();
+        let modes = ModeMap::new()
+            .with_range(0x3000000..0x3000008, InstructionMode::ARM)
+            .with_range(0x3000008..0x3000010, InstructionMode::THUMB);
+
+        assert_eq!(
+            disassemble_lines(memory.as_ref(), 0x3000000..0x3000010, &modes),
+            vec![
+                "03000000: E3A00001   MOV r0, #0x1",
+                "03000004: E12FFF1E   BX lr",
+                "03000008: 2001       MOV r0, #0x1",
+                "0300000A: F000 F802  BL 0x03000012",
+                "0300000E: 4770       BX lr",
+            ]
+        );
+    }
+
+    #[test]
+    fn heuristic_switches_between_arm_and_thumb() {
+        let memory = memory_with_program();
+
+        let lines = disassemble_lines(memory.as_ref(), 0x3000000..0x3000010, &ModeMap::new());
+
+        assert_eq!(lines[0], "03000000: E3A00001   MOV r0, #0x1");
+        assert_eq!(lines[2], "03000008: 2001       MOV r0, #0x1");
+    }
+
+    #[test]
+    fn exports_disassembly_to_file() {
+        let memory = memory_with_program();
+        let path = std::env::temp_dir().join(format!("gba_disassembly_{}.txt", std::process::id()));
+        let modes = ModeMap::new().with_range(0x3000000..0x3000008, InstructionMode::ARM);
+
+        export_disassembly(memory.as_ref(), 0x3000000..0x3000008, &modes, path.to_str().unwrap())
+            .unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            contents,
+            "03000000: E3A00001   MOV r0, #0x1\n03000004: E12FFF1E   BX lr\n"
+        );
+    }
+
+    #[test]
+    fn disassembles_a_range_of_arm_instructions() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        memory.writeu32(0x3000100, 0xE3A00001); // mov r0, #1
+        memory.writeu32(0x3000104, 0x10412183); // subne r2, r1, r3, lsl #3
+        memory.writeu32(0x3000108, 0x0AFFFFFC); // beq 0x3000100
+        memory.writeu32(0x300010C, 0xE12FFF1E); // bx lr
+
+        let instructions =
+            CPU::new().disassemble_range(memory.as_ref(), 0x3000100, 4, InstructionMode::ARM);
+
+        assert_eq!(
+            instructions,
+            vec![
+                (0x3000100, String::from("MOV r0, #0x1")),
+                (0x3000104, String::from("SUBNE r2, r1, r3, LSL #3")),
+                (0x3000108, String::from("BEQ 0x03000100")),
+                (0x300010C, String::from("BX lr")),
+            ]
+        );
+    }
+
+    #[test]
+    fn disassembles_a_range_of_thumb_instructions_pairing_bl() {
+        let memory = memory_with_program();
+
+        let instructions =
+            CPU::new().disassemble_range(memory.as_ref(), 0x3000008, 3, InstructionMode::THUMB);
+
+        assert_eq!(
+            instructions,
+            vec![
+                (0x3000008, String::from("MOV r0, #0x1")),
+                (0x300000A, String::from("BL 0x03000012")),
+                (0x300000E, String::from("BX lr")),
+            ]
+        );
+    }
+}
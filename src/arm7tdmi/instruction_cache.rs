@@ -0,0 +1,176 @@
+use std::ops::Range;
+
+use crate::types::WORD;
+
+use super::{arm::instructions::ARMDecodedInstruction, cpu::InstructionMode};
+
+/// Entries in the cache, indexed by the halfword address.
+const CACHE_SIZE: usize = 4096;
+
+#[derive(Clone, Copy, Debug)]
+struct CacheEntry {
+    address: WORD,
+    mode: InstructionMode,
+    decoded: ARMDecodedInstruction,
+}
+
+/// Direct-mapped cache of decoded instructions by the address and mode
+/// they were fetched in, so a loop isn't decoded again every time round.
+///
+/// Entries are dropped when the memory they came from is written. Writes
+/// are tracked at the unmirrored address and the pipeline can already hold
+/// the old opcode by then, so a hit also needs the fetched opcode to match.
+#[derive(Debug)]
+pub struct InstructionCache {
+    entries: Vec<Option<CacheEntry>>,
+    /// Instructions that had to be decoded.
+    pub misses: u64,
+}
+
+impl Default for InstructionCache {
+    fn default() -> Self {
+        Self {
+            entries: vec![None; CACHE_SIZE],
+            misses: 0,
+        }
+    }
+}
+
+impl InstructionCache {
+    fn index(address: WORD) -> usize {
+        (address as usize >> 1) % CACHE_SIZE
+    }
+
+    pub fn get(&self, address: WORD, mode: InstructionMode, instruction: WORD) -> Option<ARMDecodedInstruction> {
+        self.entries[Self::index(address)]
+            .filter(|cached| {
+                cached.address == address && cached.mode == mode && cached.decoded.instruction == instruction
+            })
+            .map(|cached| cached.decoded)
+    }
+
+    /// Caches an instruction that missed in `get` once it's decoded.
+    pub fn insert(&mut self, address: WORD, mode: InstructionMode, decoded: ARMDecodedInstruction) {
+        self.misses += 1;
+        self.entries[Self::index(address)] = Some(CacheEntry {
+            address,
+            mode,
+            decoded,
+        });
+    }
+
+    /// Drops the entries for instructions in the written `range`.
+    pub fn invalidate(&mut self, range: Range<usize>) {
+        if range.len() / 2 >= CACHE_SIZE {
+            self.entries.fill(None);
+            return;
+        }
+        // an ARM instruction can start 2 bytes before the write
+        for address in ((range.start & !0x3)..range.end).step_by(2) {
+            let entry = &mut self.entries[Self::index(address as WORD)];
+            if entry.is_some_and(|cached| cached.address as usize == address) {
+                *entry = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod instruction_cache_tests {
+    use crate::{
+        arm7tdmi::{arm::instructions::ARMDecodedInstruction, cpu::{InstructionMode, CPU}},
+        gba::GBA,
+        utils::testing::load_arm_program,
+    };
+
+    use super::InstructionCache;
+
+    #[test]
+    fn hot_loop_is_decoded_once() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(
+            &mut gba,
+            0x3000000,
+            &[
+                0xe3a00000, // mov r0, #0
+                0xe2800001, // loop: add r0, r0, #1
+                0xe3500c01, // cmp r0, #0x100
+                0x1afffffc, // bne loop
+                0xeafffffe, // b .
+            ],
+        );
+
+        for _ in 0..1000 {
+            gba.step();
+        }
+
+        assert_eq!(gba.cpu.get_register(0), 0x100);
+        assert!(gba.cpu.instruction_cache.misses <= 5);
+    }
+
+    #[test]
+    fn overwritten_instruction_is_decoded_again() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(
+            &mut gba,
+            0x3000000,
+            &[
+                0xe59f1008, // ldr r1, [pc, #8]
+                0xe2800001, // loop: add r0, r0, #1
+                0xe50f100c, // str r1, [pc, #-12]
+                0xeafffffc, // b loop
+                0xe2800010, // add r0, r0, #0x10
+            ],
+        );
+
+        for _ in 0..20 {
+            gba.step();
+        }
+
+        // the first pass adds 1, every pass after the store adds 0x10
+        let r0 = gba.cpu.get_register(0);
+        assert!(r0 > 0x10 && r0 % 0x10 == 1, "r0 = {r0:#x}");
+    }
+
+    #[test]
+    fn instruction_overwritten_through_a_mirror_is_decoded_again() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(
+            &mut gba,
+            0x3000000,
+            &[
+                0xe2800001, // loop: add r0, r0, #1
+                0xe5821000, // str r1, [r2]
+                0xeafffffc, // b loop
+            ],
+        );
+        gba.cpu.set_register(1, 0xe2800010); // add r0, r0, #0x10
+        gba.cpu.set_register(2, 0x3008000); // IWRAM mirror of the loop
+
+        for _ in 0..20 {
+            gba.step();
+        }
+
+        // the first pass adds 1, every pass after the store adds 0x10
+        let r0 = gba.cpu.get_register(0);
+        assert!(r0 > 0x10 && r0 % 0x10 == 1, "r0 = {r0:#x}");
+    }
+
+    #[test]
+    fn entries_are_kept_apart_by_mode_and_dropped_by_writes() {
+        let mut cache = InstructionCache::default();
+        let decoded = ARMDecodedInstruction {
+            executable: CPU::arm_nop,
+            instruction: 0xe1a00000,
+        };
+        cache.insert(0x3000100, InstructionMode::ARM, decoded);
+
+        assert!(cache.get(0x3000100, InstructionMode::ARM, 0xe1a00000).is_some());
+        assert!(cache.get(0x3000100, InstructionMode::THUMB, 0xe1a00000).is_none());
+        assert!(cache.get(0x3000100, InstructionMode::ARM, 0xe1a00001).is_none());
+
+        // a halfword write to the top half of the instruction
+        cache.invalidate(0x3000102..0x3000104);
+        assert!(cache.get(0x3000100, InstructionMode::ARM, 0xe1a00000).is_none());
+    }
+}
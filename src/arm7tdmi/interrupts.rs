@@ -1,8 +1,116 @@
-use crate::{memory::memory::MemoryBus, types::CYCLES, utils::bits::Bits};
+use std::{fmt::Display, str::FromStr};
+
+use crate::{
+    memory::{
+        io_handlers::{IE, IF, IME},
+        memory::MemoryBus,
+    },
+    types::CYCLES,
+    utils::bits::Bits,
+};
 
 use super::cpu::{CPUMode, InstructionMode, CPU, LINK_REGISTER};
 
-#[derive(Clone, Copy)]
+/// Interrupt sources, in IE/IF bit order.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interrupt {
+    VBlank,
+    HBlank,
+    VCounter,
+    Timer0,
+    Timer1,
+    Timer2,
+    Timer3,
+    Serial,
+    Dma0,
+    Dma1,
+    Dma2,
+    Dma3,
+    Keypad,
+    GamePak,
+}
+
+impl Interrupt {
+    pub const ALL: [Interrupt; 14] = [
+        Interrupt::VBlank,
+        Interrupt::HBlank,
+        Interrupt::VCounter,
+        Interrupt::Timer0,
+        Interrupt::Timer1,
+        Interrupt::Timer2,
+        Interrupt::Timer3,
+        Interrupt::Serial,
+        Interrupt::Dma0,
+        Interrupt::Dma1,
+        Interrupt::Dma2,
+        Interrupt::Dma3,
+        Interrupt::Keypad,
+        Interrupt::GamePak,
+    ];
+
+    pub const fn bit(self) -> u16 {
+        1 << self as u16
+    }
+
+    pub fn timer(timer: usize) -> Interrupt {
+        Interrupt::ALL[Interrupt::Timer0 as usize + timer]
+    }
+
+    pub fn dma(channel: usize) -> Interrupt {
+        Interrupt::ALL[Interrupt::Dma0 as usize + channel]
+    }
+
+    /// The sources whose bits are set in an IE/IF value.
+    pub fn from_flags(flags: u16) -> Vec<Interrupt> {
+        Interrupt::ALL
+            .into_iter()
+            .filter(|interrupt| flags & interrupt.bit() > 0)
+            .collect()
+    }
+}
+
+impl Display for Interrupt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Interrupt::VBlank => "vblank".fmt(f),
+            Interrupt::HBlank => "hblank".fmt(f),
+            Interrupt::VCounter => "vcount".fmt(f),
+            Interrupt::Timer0 => "timer0".fmt(f),
+            Interrupt::Timer1 => "timer1".fmt(f),
+            Interrupt::Timer2 => "timer2".fmt(f),
+            Interrupt::Timer3 => "timer3".fmt(f),
+            Interrupt::Serial => "serial".fmt(f),
+            Interrupt::Dma0 => "dma0".fmt(f),
+            Interrupt::Dma1 => "dma1".fmt(f),
+            Interrupt::Dma2 => "dma2".fmt(f),
+            Interrupt::Dma3 => "dma3".fmt(f),
+            Interrupt::Keypad => "keypad".fmt(f),
+            Interrupt::GamePak => "gamepak".fmt(f),
+        }
+    }
+}
+
+impl FromStr for Interrupt {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_lowercase();
+        Interrupt::ALL
+            .into_iter()
+            .find(|interrupt| interrupt.to_string() == name)
+            .ok_or_else(|| format!("Unknown interrupt {}", s))
+    }
+}
+
+/// Sets the IF bit of `interrupt`, as its hardware source does when it
+/// fires. Writes the raw register, since a CPU write to IF acknowledges
+/// bits instead of setting them.
+pub fn request_interrupt(memory: &mut dyn MemoryBus, interrupt: Interrupt) {
+    let interrupt_flags = memory.ppu_io_read(IF);
+    memory.ppu_io_write(IF, interrupt_flags | interrupt.bit());
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Exceptions {
     Reset,
     Undefined,
@@ -22,12 +130,31 @@ impl From<Exceptions> for CPUMode {
 }
 
 impl CPU {
+    /// Takes an IRQ if an interrupt is both requested in IF and enabled in
+    /// IE, IME is set and the CPSR I bit is clear. Returns whether it did.
+    pub fn check_interrupts(&mut self, memory: &mut Box<dyn MemoryBus>) -> bool {
+        // read the raw registers so interrupt polling stays out of the IO trace
+        let ime = memory.ppu_io_read(IME) & 0x1;
+        let pending = memory.ppu_io_read(IF) & memory.ppu_io_read(IE);
+        if pending == 0 || ime == 0 || self.cpsr.bit_is_set(7) {
+            return false;
+        }
+        self.raise_exception(Exceptions::IRQ, memory);
+        true
+    }
+
     pub fn raise_exception(&mut self, exception: Exceptions, memory: &mut Box<dyn MemoryBus>) -> CYCLES{
-        let instruction_size = match self.get_instruction_mode() {
-            super::cpu::InstructionMode::ARM => 4,
-            super::cpu::InstructionMode::THUMB => 0,
+        // SWI and undefined instructions return to the following instruction.
+        // IRQs are taken before the instruction in decode executes, and LR
+        // is that instruction + 4 in both states, which the pc already is
+        // in Thumb.
+        let instruction_size = match (self.get_instruction_mode(), exception) {
+            (super::cpu::InstructionMode::ARM, _) => 4,
+            (super::cpu::InstructionMode::THUMB, Exceptions::Software | Exceptions::Undefined) => 2,
+            (super::cpu::InstructionMode::THUMB, _) => 0,
         };
         
+        self.last_exception = Some(exception);
         // Store CPSR in SPSR_new_mode
         let cpsr = self.cpsr;
         self.set_mode(exception.into());
@@ -65,3 +192,149 @@ impl CPU {
         self.flush_pipeline(memory)
     }
 }
+
+#[cfg(test)]
+mod exception_tests {
+    use rstest::rstest;
+
+    use crate::{
+        arm7tdmi::cpu::{CPUMode, InstructionMode, LINK_REGISTER},
+        gba::GBA,
+        memory::io_handlers::{IE, IF, IME},
+        utils::{bits::Bits, testing::{load_arm_program, load_thumb_program}},
+    };
+
+    use super::{request_interrupt, Exceptions, Interrupt};
+
+    const SYSTEM_CPSR: u32 = 0b11111 | 1 << 5; // Thumb, interrupts enabled
+
+    fn assert_entered_from_thumb(gba: &mut GBA, mode: CPUMode, return_address: u32) {
+        assert_eq!(gba.cpu.get_cpu_mode(), mode);
+        assert_eq!(gba.cpu.get_instruction_mode(), InstructionMode::ARM);
+        assert_eq!(gba.cpu.get_register(LINK_REGISTER), return_address);
+        assert!(gba.cpu.get_current_spsr().unwrap().bit_is_set(5));
+    }
+
+    #[rstest]
+    #[case::swi(0xdf05, Exceptions::Software, CPUMode::SVC, 0x08)]
+    #[case::undefined(0xde00, Exceptions::Undefined, CPUMode::UND, 0x04)]
+    fn thumb_exceptions_return_to_the_next_instruction(
+        #[case] instruction: u16,
+        #[case] exception: Exceptions,
+        #[case] mode: CPUMode,
+        #[case] vector: u32,
+    ) {
+        let mut gba = GBA::new_no_bios();
+        load_thumb_program(&mut gba, 0x3000000, &[
+            0x46c0, // mov r8, r8
+            instruction,
+        ]);
+        gba.cpu.cpsr = SYSTEM_CPSR;
+
+        gba.step();
+        let result = gba.step();
+
+        assert_eq!(result.took_exception, Some(exception));
+        assert_entered_from_thumb(&mut gba, mode, 0x3000004);
+        assert_eq!(gba.cpu.get_pc(), vector + 8);
+    }
+
+    #[test]
+    fn thumb_irq_returns_to_the_interrupted_instruction_plus_4() {
+        let mut gba = GBA::new_no_bios();
+        load_thumb_program(&mut gba, 0x3000000, &[
+            0x46c0, // mov r8, r8
+            0x46c0, // mov r8, r8
+        ]);
+        gba.cpu.cpsr = SYSTEM_CPSR;
+        gba.step();
+
+        gba.memory.ppu_io_write(IME, 1);
+        gba.memory.ppu_io_write(IE, Interrupt::VBlank.bit());
+        gba.memory.ppu_io_write(IF, Interrupt::VBlank.bit());
+        gba.step();
+
+        // the handler does `subs pc, lr, #4` to resume at 0x3000002
+        assert_entered_from_thumb(&mut gba, CPUMode::IRQ, 0x3000006);
+        assert_eq!(gba.cpu.last_executed_pc(), 0x18);
+    }
+
+    #[test]
+    fn requested_vblank_interrupt_enters_irq_mode() {
+        let mut gba = GBA::new_no_bios();
+        let cpsr = 0b11111 | 1 << 29; // System, ARM, C set
+        gba.cpu.cpsr = cpsr;
+        gba.memory.ppu_io_write(IME, 1);
+        gba.memory.ppu_io_write(IE, Interrupt::VBlank.bit());
+        request_interrupt(gba.memory.as_mut(), Interrupt::VBlank);
+
+        assert!(gba.cpu.check_interrupts(&mut gba.memory));
+
+        assert_eq!(gba.cpu.get_cpu_mode(), CPUMode::IRQ);
+        assert_eq!(gba.cpu.get_current_spsr().copied(), Some(cpsr));
+        assert!(gba.cpu.cpsr.bit_is_set(7));
+        assert_eq!(gba.cpu.get_pc(), 0x18 + 8);
+        assert_eq!(gba.memory.ppu_io_read(IF), Interrupt::VBlank.bit());
+    }
+
+    #[rstest]
+    #[case::ime_clear(0, 0b11111)]
+    #[case::irqs_masked_in_cpsr(1, 0b11111 | 1 << 7)]
+    fn masked_interrupts_are_not_taken(#[case] ime: u16, #[case] cpsr: u32) {
+        let mut gba = GBA::new_no_bios();
+        gba.cpu.cpsr = cpsr;
+        gba.memory.ppu_io_write(IME, ime);
+        gba.memory.ppu_io_write(IE, Interrupt::VBlank.bit());
+        request_interrupt(gba.memory.as_mut(), Interrupt::VBlank);
+
+        assert!(!gba.cpu.check_interrupts(&mut gba.memory));
+        assert_eq!(gba.cpu.get_cpu_mode(), CPUMode::SYS);
+    }
+
+    #[test]
+    fn swi_inside_an_irq_handler_returns_through_both_modes() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, 0x3000000, &[
+            0xe3a00001, // mov r0, 1
+            0xe3a00002, // mov r0, 2
+        ]);
+        let user_cpsr = 0b10000;
+        gba.cpu.cpsr = user_cpsr;
+        gba.step();
+
+        gba.memory.ppu_io_write(IME, 1);
+        gba.memory.ppu_io_write(IE, Interrupt::VBlank.bit());
+        request_interrupt(gba.memory.as_mut(), Interrupt::VBlank);
+        gba.step();
+        assert_eq!(gba.cpu.get_cpu_mode(), CPUMode::IRQ);
+        let irq_cpsr = gba.cpu.cpsr;
+        gba.memory.ppu_io_write(IF, 0);
+
+        // the IRQ handler issues a SWI, then returns to the interrupted code
+        load_arm_program(&mut gba, 0x3001000, &[
+            0xef000005, // swi 5
+            0xe25ef004, // subs pc, lr, 4
+        ]);
+        gba.step();
+        assert_eq!(gba.cpu.get_cpu_mode(), CPUMode::SVC);
+        assert_eq!(gba.cpu.get_current_spsr().copied(), Some(irq_cpsr));
+        assert_eq!(gba.cpu.get_register(LINK_REGISTER), 0x3001004);
+
+        // the SWI handler returns to the IRQ handler
+        load_arm_program(&mut gba, 0x3002000, &[
+            0xe1b0f00e, // movs pc, lr
+        ]);
+        gba.step();
+        assert_eq!(gba.cpu.cpsr, irq_cpsr);
+        assert_eq!(gba.cpu.get_register(LINK_REGISTER), 0x3000008);
+        assert_eq!(gba.cpu.get_current_spsr().copied(), Some(user_cpsr));
+
+        let result = gba.step();
+        assert_eq!(result.executed_pc, 0x3001004);
+        assert_eq!(gba.cpu.cpsr, user_cpsr);
+
+        let result = gba.step();
+        assert_eq!(result.executed_pc, 0x3000004);
+        assert_eq!(gba.cpu.get_register(0), 2);
+    }
+}
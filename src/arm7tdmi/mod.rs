@@ -3,3 +3,8 @@ pub mod arm;
 pub mod decoder;
 pub mod cpu;
 pub mod interrupts;
+pub mod bios_hle;
+pub mod instruction_cache;
+pub mod disassembler;
+#[cfg(test)]
+mod timing_tests;
@@ -1,5 +1,5 @@
 use crate::{
-    arm7tdmi::cpu::{FlagsRegister, InstructionMode, CPU, PC_REGISTER}, memory::memory::MemoryBus, types::{CYCLES, REGISTER}, utils::bits::{sign_extend, Bits}
+    arm7tdmi::{arm::instructions::multiply_internal_cycles, cpu::{FlagsRegister, InstructionMode, CPU, PC_REGISTER}}, memory::memory::MemoryBus, types::{CYCLES, REGISTER}, utils::bits::Bits
 };
 
 impl CPU {
@@ -199,9 +199,9 @@ impl CPU {
 
     fn thumb_cmp_imm(&mut self, rd: REGISTER, imm: u8) {
         let minuend = self.get_register(rd);
-        let imm = !(imm as u32);
-        let result = minuend + imm + 1;
-        self.set_arithmetic_flags(result, minuend, imm, 1, true);
+        let subtrahend = !(imm as u32);
+        let result = minuend + subtrahend + 1;
+        self.set_arithmetic_flags(result, minuend, subtrahend, 1, true);
         self.set_executed_instruction(format_args!("CMP r{} {:#X}", rd, imm));
     }
 
@@ -215,9 +215,9 @@ impl CPU {
 
     fn thumb_sub_imm(&mut self, rd: REGISTER, imm: u8) {
         let minuend = self.get_register(rd);
-        let imm: u32 = sign_extend(imm as u32, 7).twos_complement();
-        let result = minuend + imm;
-        self.set_arithmetic_flags(result, minuend, imm, 0, true);
+        let subtrahend = !(imm as u32);
+        let result = minuend + subtrahend + 1;
+        self.set_arithmetic_flags(result, minuend, subtrahend, 1, true);
         self.set_register(rd, result);
         self.set_executed_instruction(format_args!("SUB {} {:#X}", rd, imm));
     }
@@ -256,17 +256,7 @@ impl CPU {
             0xB => CPU::arm_cmn,
             0xC => CPU::arm_orr,
             0xD => {
-                let multiplier = self.get_register(rd);
-                cycles +=
-                    if multiplier & 0xFFFF_FF00 == 0 || multiplier & 0xFFFF_FF00 == 0xFFFF_FF00 {
-                        1
-                    } else if multiplier & 0xFFFF_0000 == 0 || multiplier & 0xFFFF_0000 == 0xFFFF_0000 {
-                        2
-                    } else if multiplier & 0xFF00_0000 == 0 || multiplier & 0xFF00_0000 == 0xFFFF_0000 {
-                        3
-                    } else {
-                        4
-                    };
+                cycles += multiply_internal_cycles(self.get_register(rd), true);
                 CPU::thumb_mul
             }
             0xE => CPU::arm_bic,
@@ -351,13 +341,9 @@ impl CPU {
 
     fn thumb_mul(&mut self, rd: REGISTER, operand1: u32, operand2: u32, set_flags: bool) {
         let result = (operand1 as u64 * operand2 as u64) as u32;
+        // the multiplier is Rd, as in the cycle count
         if set_flags {
-            self.set_flag_from_bit(FlagsRegister::N, result.get_bit(31) as u8);
-            if result == 0 {
-                self.set_flag(FlagsRegister::Z);
-            } else {
-                self.reset_flag(FlagsRegister::Z);
-            }
+            self.set_multiply_flags(result, operand1);
         }
         self.set_register(rd, result);
     }
@@ -384,7 +370,8 @@ impl CPU {
             false,
         );
 
-        if rd == PC_REGISTER as u32 {
+        // CMP only sets flags, so comparing against pc never branches
+        if rd == PC_REGISTER as u32 && opcode != 0b01 {
             cycles += self.flush_pipeline(memory);
         }
 
@@ -431,8 +418,8 @@ impl CPU {
         let imm = (instruction & 0x007F) * 4;
 
         let result = match opcode {
-            0b0 => self.get_sp() + imm,
-            0b1 => self.get_sp() - imm,
+            0b0 => self.get_sp().wrapping_add(imm),
+            0b1 => self.get_sp().wrapping_sub(imm),
             _ => panic!(),
         };
 
@@ -452,21 +439,21 @@ mod thumb_add_and_subtract_tests {
 
     use crate::{
         arm7tdmi::cpu::{FlagsRegister, InstructionMode, CPU},
-        memory::memory::GBAMemory,
+        memory::memory::{GBAMemory, MemoryBus},
     };
 
     #[test]
     fn should_add_two_registers_together() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(1, 20);
         cpu.set_register(2, 43);
         cpu.prefetch[0] = Some(0x1888); // adds r0, r1, r2
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 63);
         assert!(cpu.get_flag(FlagsRegister::C) == 0);
@@ -477,16 +464,16 @@ mod thumb_add_and_subtract_tests {
 
     #[test]
     fn should_add_two_registers_together_and_set_n_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(1, 20);
         cpu.set_register(2, (-43 as i32) as u32);
         cpu.prefetch[0] = Some(0x1888); // adds r0, r1, r2
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), (-23 as i32) as u32);
         assert!(cpu.get_flag(FlagsRegister::C) == 0);
@@ -497,16 +484,16 @@ mod thumb_add_and_subtract_tests {
 
     #[test]
     fn should_add_two_registers_together_and_set_z_and_c_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(1, 20);
         cpu.set_register(2, (-20 as i32) as u32);
         cpu.prefetch[0] = Some(0x1888); // adds r0, r1, r2
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 0);
         assert!(cpu.get_flag(FlagsRegister::C) == 1);
@@ -517,16 +504,16 @@ mod thumb_add_and_subtract_tests {
 
     #[test]
     fn should_add_two_registers_together_and_set_z_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(1, 0);
         cpu.set_register(2, 0);
         cpu.prefetch[0] = Some(0x1888); // adds r0, r1, r2
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 0);
         assert!(cpu.get_flag(FlagsRegister::C) == 0);
@@ -537,16 +524,16 @@ mod thumb_add_and_subtract_tests {
 
     #[test]
     fn should_add_two_registers_together_and_set_v_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(1, 0x1);
         cpu.set_register(2, 0x7FFF_FFFF);
         cpu.prefetch[0] = Some(0x1888); // adds r0, r1, r2
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 0x8000_0000);
         assert!(cpu.get_flag(FlagsRegister::C) == 0);
@@ -557,15 +544,15 @@ mod thumb_add_and_subtract_tests {
 
     #[test]
     fn should_add_register_and_immediate_and_set_n_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(1, -10 as i32 as u32);
         cpu.prefetch[0] = Some(0x1d48); // adds r0, r1, 5
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), -5 as i32 as u32);
         assert!(cpu.get_flag(FlagsRegister::C) == 0);
@@ -576,15 +563,15 @@ mod thumb_add_and_subtract_tests {
 
     #[test]
     fn should_add_register_and_immediate_and_set_z_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(1, -5 as i32 as u32);
         cpu.prefetch[0] = Some(0x1d48); // adds r0, r1, 5
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 0 as i32 as u32);
         assert!(cpu.get_flag(FlagsRegister::C) == 1);
@@ -595,15 +582,15 @@ mod thumb_add_and_subtract_tests {
 
     #[test]
     fn should_add_register_and_immediate_and_set_v_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(1, 0x7FFF_FFFF);
         cpu.prefetch[0] = Some(0x1d48); // adds r0, r1, 5
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 0x8000_0004);
         assert!(cpu.get_flag(FlagsRegister::C) == 0);
@@ -614,15 +601,15 @@ mod thumb_add_and_subtract_tests {
 
     #[test]
     fn should_add_register_and_immediate_and_set_c_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(1, 0xFFFF_FFFF);
         cpu.prefetch[0] = Some(0x1d48); // adds r0, r1, 5
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 0x0000_0004);
         assert!(cpu.get_flag(FlagsRegister::C) == 1);
@@ -633,16 +620,16 @@ mod thumb_add_and_subtract_tests {
 
     #[test]
     fn should_sub_two_registers() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(1, 50);
         cpu.set_register(2, 20);
         cpu.prefetch[0] = Some(0x1a88); // subs r0, r1, r2
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 30);
         assert!(cpu.get_flag(FlagsRegister::C) == 1);
@@ -653,16 +640,16 @@ mod thumb_add_and_subtract_tests {
 
     #[test]
     fn should_sub_two_registers_and_reset_c_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(1, 25);
         cpu.set_register(2, 50);
         cpu.prefetch[0] = Some(0x1a88); // subs r0, r1, r2
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), -25 as i32 as u32);
         assert_eq!(cpu.get_flag(FlagsRegister::C), 0);
@@ -670,6 +657,23 @@ mod thumb_add_and_subtract_tests {
         assert_eq!(cpu.get_flag(FlagsRegister::Z), 0);
         assert_eq!(cpu.get_flag(FlagsRegister::V), 0);
     }
+
+    #[test]
+    fn sub_immediate_traces_the_encoded_immediate() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+        cpu.set_instruction_mode(InstructionMode::THUMB);
+
+        cpu.set_register(0, 7);
+        cpu.prefetch[0] = Some(0x3805); // subs r0, #5
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(cpu.get_register(0), 2);
+        assert_eq!(cpu.get_flag(FlagsRegister::C), 1);
+        assert_eq!(cpu.executed_instruction, "SUB 0 0x5");
+    }
 }
 
 #[cfg(test)]
@@ -677,20 +681,20 @@ mod thumb_move_shifted_register_tests {
 
     use crate::{
         arm7tdmi::cpu::{FlagsRegister, InstructionMode, CPU},
-        memory::memory::GBAMemory,
+        memory::memory::{GBAMemory, MemoryBus},
     };
 
     #[test]
     fn should_left_shift_a_register_and_set_c_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(1, 0x0F00_0000);
         cpu.prefetch[0] = Some(0x0148); // lsls r0, r1, 5
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 0x0F00_0000 << 5);
         assert_eq!(cpu.get_flag(FlagsRegister::C), 1);
@@ -700,16 +704,16 @@ mod thumb_move_shifted_register_tests {
 
     #[test]
     fn should_not_left_shift_register_and_not_change_c_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(1, 0x0F00_0000);
         cpu.set_flag(FlagsRegister::C);
         cpu.prefetch[0] = Some(0x0008); // lsls r0, r1, 0
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 0x0F00_0000);
         assert_eq!(cpu.get_flag(FlagsRegister::C), 1);
@@ -719,16 +723,16 @@ mod thumb_move_shifted_register_tests {
 
     #[test]
     fn should_lsl_register_and_not_affect_v_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(1, 0xF000_0000);
         cpu.set_flag(FlagsRegister::V);
         cpu.prefetch[0] = Some(0x0148); // lsls r0, r1, 5
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 0x0);
         assert_eq!(cpu.get_flag(FlagsRegister::C), 0);
@@ -739,15 +743,15 @@ mod thumb_move_shifted_register_tests {
 
     #[test]
     fn should_asr_register_and_set_c_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(1, 0x0000_008F);
         cpu.prefetch[0] = Some(0x1108); // asrs r0, r1, 4
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 0x0000_0008);
         assert_eq!(cpu.get_flag(FlagsRegister::C), 1);
@@ -757,15 +761,15 @@ mod thumb_move_shifted_register_tests {
 
     #[test]
     fn should_asr_register_and_maintain_sign() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(1, 0x8000_008F);
         cpu.prefetch[0] = Some(0x1108); // asrs r0, r1, 4
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 0xF800_0008);
         assert_eq!(cpu.get_flag(FlagsRegister::C), 1);
@@ -775,15 +779,15 @@ mod thumb_move_shifted_register_tests {
 
     #[test]
     fn should_asr_register_and_set_all_ones() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(1, 0x8000_0000);
         cpu.prefetch[0] = Some(0x1008); // asrs r0, r1, 32
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 0xFFFF_FFFF);
         assert_eq!(cpu.get_flag(FlagsRegister::C), 1);
@@ -793,15 +797,15 @@ mod thumb_move_shifted_register_tests {
 
     #[test]
     fn should_lsr_register() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(1, 0x8000_008F);
         cpu.prefetch[0] = Some(0x0a88); // lsrs r0, r1, 10
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 0x8000_008F >> 10);
         assert_eq!(cpu.get_flag(FlagsRegister::C), 0);
@@ -811,15 +815,15 @@ mod thumb_move_shifted_register_tests {
 
     #[test]
     fn should_lsr_register_and_clear_register() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(1, 0x8000_008F);
         cpu.prefetch[0] = Some(0x0808); // lsrs r0, r1, 32
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 0);
         assert_eq!(cpu.get_flag(FlagsRegister::C), 1);
@@ -831,21 +835,23 @@ mod thumb_move_shifted_register_tests {
 #[cfg(test)]
 mod thumb_move_compare_add_subtract_tests {
 
+    use rstest::rstest;
+
     use crate::{
         arm7tdmi::cpu::{FlagsRegister, InstructionMode, CPU},
-        memory::memory::GBAMemory,
+        memory::memory::{GBAMemory, MemoryBus},
     };
 
     #[test]
     fn should_move_immediate_into_r0() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.prefetch[0] = Some(0x200f); // movs r0, 15
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 15);
         assert_eq!(cpu.get_flag(FlagsRegister::C), 0);
@@ -855,14 +861,14 @@ mod thumb_move_compare_add_subtract_tests {
 
     #[test]
     fn should_move_immediate_into_r0_and_not_set_n_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.prefetch[0] = Some(0x2096); // movs r0, 150
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 150);
         assert_eq!(cpu.get_flag(FlagsRegister::C), 0);
@@ -872,14 +878,14 @@ mod thumb_move_compare_add_subtract_tests {
 
     #[test]
     fn should_move_immediate_into_r0_and_set_z_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.prefetch[0] = Some(0x2000); // movs r0, 0
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 0);
         assert_eq!(cpu.get_flag(FlagsRegister::C), 0);
@@ -889,15 +895,15 @@ mod thumb_move_compare_add_subtract_tests {
 
     #[test]
     fn should_sub_imm_from_r0_and_set_z_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(0, 15);
         cpu.prefetch[0] = Some(0x380f); // subs r0, 15
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 0);
         assert_eq!(cpu.get_flag(FlagsRegister::C), 1);
@@ -905,17 +911,41 @@ mod thumb_move_compare_add_subtract_tests {
         assert_eq!(cpu.get_flag(FlagsRegister::Z), 1);
     }
 
+    #[rstest]
+    #[case(0x3880, 0x100, 0x80, 1)] // subs r0, 0x80
+    #[case(0x38ff, 0x10, 0xFFFF_FF11, 0)] // subs r0, 0xff
+    #[case(0x3800, 0x10, 0x10, 1)] // subs r0, 0
+    fn sub_imm_zero_extends_the_immediate(
+        #[case] opcode: u32,
+        #[case] r0: u32,
+        #[case] expected: u32,
+        #[case] carry: u32,
+    ) {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+        cpu.set_instruction_mode(InstructionMode::THUMB);
+
+        cpu.set_register(0, r0);
+        cpu.prefetch[0] = Some(opcode);
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(cpu.get_register(0), expected);
+        assert_eq!(cpu.get_flag(FlagsRegister::C), carry);
+    }
+
     #[test]
     fn should_add_imm_to_r0_and_set_n_flag() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(0, 0x7FFF_FFFF);
         cpu.prefetch[0] = Some(0x300f); // adds r0, 15
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 0x8000_000E);
         assert_eq!(cpu.get_flag(FlagsRegister::C), 0);
@@ -929,21 +959,21 @@ mod thumb_alu_operations_tests {
 
     use crate::{
         arm7tdmi::cpu::{FlagsRegister, InstructionMode, CPU},
-        memory::memory::GBAMemory,
+        memory::memory::{GBAMemory, MemoryBus},
     };
 
     #[test]
     fn should_and_two_numbers_together() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(0, 0x8123_2344);
         cpu.set_register(1, 0x8000_2344);
         cpu.prefetch[0] = Some(0x4008); // ands r0, r1
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 0x8000_2344);
         assert_eq!(cpu.get_flag(FlagsRegister::N), 1);
@@ -952,16 +982,16 @@ mod thumb_alu_operations_tests {
 
     #[test]
     fn should_eor_two_numbers_together() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(0, 0x1010_1010);
         cpu.set_register(1, 0x0101_0101);
         cpu.prefetch[0] = Some(0x4048); // eors r0, r1
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 0x1111_1111);
         assert_eq!(cpu.get_flag(FlagsRegister::N), 0);
@@ -970,16 +1000,16 @@ mod thumb_alu_operations_tests {
 
     #[test]
     fn should_lsl_rd_by_5() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(0, 0x0F11_1230);
         cpu.set_register(1, 5);
         cpu.prefetch[0] = Some(0x4088); // lsl r0, r1
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 0xE222_4600);
         assert_eq!(cpu.get_flag(FlagsRegister::N), 1);
@@ -989,16 +1019,16 @@ mod thumb_alu_operations_tests {
 
     #[test]
     fn should_lsr_rd_by_0() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(0, 0x0F11_1230);
         cpu.set_register(1, 0);
         cpu.prefetch[0] = Some(0x40c8); // lsr r0, r1
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 0x0F11_1230);
         assert_eq!(cpu.get_flag(FlagsRegister::N), 0);
@@ -1010,23 +1040,25 @@ mod thumb_alu_operations_tests {
 #[cfg(test)]
 mod thumb_hi_reg_operations {
 
+    use rstest::rstest;
+
     use crate::{
         arm7tdmi::cpu::{FlagsRegister, InstructionMode, CPU},
-        memory::memory::GBAMemory,
+        memory::memory::{GBAMemory, MemoryBus},
     };
 
     #[test]
     fn should_add_two_regs_together_and_not_affect_flags() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(0, 20);
         cpu.set_register(11, 15);
         cpu.set_flag(FlagsRegister::N);
         cpu.prefetch[1] = Some(0x4458); // add r0, r11
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 35);
         assert_eq!(cpu.get_flag(FlagsRegister::N), 1);
@@ -1036,17 +1068,17 @@ mod thumb_hi_reg_operations {
 
     #[test]
     fn should_cmp_registers_and_set_flags() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(0, 20);
         cpu.set_register(11, 20);
         cpu.set_flag(FlagsRegister::N);
         cpu.prefetch[0] = Some(0x4558); // cmp r0, r11
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 20);
         assert_eq!(cpu.get_flag(FlagsRegister::N), 0);
@@ -1054,19 +1086,64 @@ mod thumb_hi_reg_operations {
         assert_eq!(cpu.get_flag(FlagsRegister::Z), 1);
     }
 
+    #[rstest]
+    #[case::equal_to_pc(0x18, 0, 1, 1)]
+    #[case::below_pc(0x14, 1, 0, 0)]
+    #[case::above_pc(0x1C, 0, 0, 1)]
+    fn should_cmp_against_pc_plus_4(
+        #[case] value: u32,
+        #[case] n: u32,
+        #[case] z: u32,
+        #[case] c: u32,
+    ) {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+        cpu.set_instruction_mode(InstructionMode::THUMB);
+
+        cpu.set_pc(0x16);
+        cpu.set_register(0, value);
+        cpu.prefetch[0] = Some(0x4578); // cmp r0, pc at 0x14
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(cpu.get_register(0), value);
+        assert_eq!(cpu.get_flag(FlagsRegister::N), n);
+        assert_eq!(cpu.get_flag(FlagsRegister::Z), z);
+        assert_eq!(cpu.get_flag(FlagsRegister::C), c);
+    }
+
+    #[test]
+    fn should_not_branch_when_cmp_destination_is_pc() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+        cpu.set_instruction_mode(InstructionMode::THUMB);
+
+        cpu.set_pc(0x16);
+        cpu.set_register(0, 0x18);
+        cpu.prefetch[0] = Some(0x4587); // cmp pc, r0 at 0x14
+        cpu.prefetch[1] = None;
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(cpu.get_pc(), 0x1A);
+        assert_eq!(cpu.get_flag(FlagsRegister::Z), 1);
+    }
+
     #[test]
     fn should_mov_register() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(0, 20);
         cpu.set_register(11, 55);
         cpu.set_flag(FlagsRegister::N);
         cpu.prefetch[0] = Some(0x4658); // cmp r0, r11
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(0), 55);
     }
@@ -1077,20 +1154,20 @@ mod thumb_bx_tests {
 
     use crate::{
         arm7tdmi::cpu::{InstructionMode, CPU},
-        memory::memory::GBAMemory,
+        memory::memory::{GBAMemory, MemoryBus},
     };
 
     #[test]
     fn should_switch_to_arm_mode_and_align_address() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_register(5, 0x16);
         cpu.prefetch[0] = Some(0x4728); // bx r5
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_pc(), 0x1C);
         assert!(matches!(cpu.get_instruction_mode(), InstructionMode::ARM));
@@ -1098,15 +1175,15 @@ mod thumb_bx_tests {
 
     #[test]
     fn should_switch_to_arm_mode_when_pc_operand() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.set_pc(0x16);
         cpu.prefetch[0] = Some(0x4778); // bx r15
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_pc(), 0x20);
         assert!(matches!(cpu.get_instruction_mode(), InstructionMode::ARM));
@@ -1117,21 +1194,21 @@ mod thumb_bx_tests {
 mod get_relative_address_tests {
 
     use crate::{
-        arm7tdmi::cpu::{InstructionMode, CPU},
-        memory::memory::GBAMemory,
+        arm7tdmi::cpu::{CPUMode, InstructionMode, CPU},
+        memory::memory::{GBAMemory, MemoryBus},
     };
 
     #[test]
     fn should_add_12_to_pc() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.prefetch[0] = Some(0xa503); // add r5, pc, 12
         cpu.set_pc(2);
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_pc(), 6);
         assert_eq!(cpu.get_register(5), 16);
@@ -1139,46 +1216,70 @@ mod get_relative_address_tests {
 
     #[test]
     fn should_add_16_to_sp_and_store_in_r5() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.prefetch[0] = Some(0xad04); // add r5, sp, 16
         cpu.set_sp(2);
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(5), 18);
     }
 
     #[test]
     fn should_add_500_to_sp() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.prefetch[0] = Some(0xb07d); // add sp, 500
         cpu.set_sp(2);
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_sp(), 502);
     }
 
     #[test]
     fn should_sub_500_to_sp() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.prefetch[0] = Some(0xb0fd); // add sp, 500
         cpu.set_sp(2);
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_sp(), (2 - 500) as i32 as u32);
     }
+
+    #[test]
+    fn sub_then_add_508_round_trips_the_banked_sp() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+        cpu.set_mode(CPUMode::SYS);
+        cpu.set_sp(0x0300_7F00);
+        cpu.set_mode(CPUMode::IRQ);
+        cpu.set_sp(0x100);
+        cpu.set_instruction_mode(InstructionMode::THUMB);
+
+        cpu.prefetch[0] = Some(0xb0ff); // sub sp, 508
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.prefetch[0] = Some(0xb07f); // add sp, 508
+        cpu.execute_cpu_cycle(&mut memory);
+        assert_eq!(cpu.get_sp(), 0x100u32.wrapping_sub(508));
+
+        cpu.execute_cpu_cycle(&mut memory);
+        assert_eq!(cpu.get_sp(), 0x100);
+
+        cpu.set_mode(CPUMode::SYS);
+        assert_eq!(cpu.get_sp(), 0x0300_7F00);
+    }
 }
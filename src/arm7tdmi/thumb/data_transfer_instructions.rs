@@ -175,12 +175,50 @@ impl CPU {
 
         let base_address = self.get_register(rb) as usize;
 
+        if register_list.is_empty() {
+            return self.thumb_empty_list_transfer(opcode, rb, base_address, memory);
+        }
+
         match opcode {
-            0b0 => self.stmia_execution(base_address, &register_list, Some(rb), memory),
+            0b0 => {
+                // the base is written back after the first transfer, so a base
+                // later in the list stores the final address
+                if register_list.iter().position(|register| *register == rb) > Some(0) {
+                    let final_address = base_address + register_list.len() * 4;
+                    self.set_register(rb, final_address as u32);
+                }
+                self.stmia_execution(base_address, &register_list, Some(rb), memory)
+            }
             0b1 => self.ldmia_execution(base_address, &register_list, Some(rb), memory),
             _ => panic!(),
         }
     }
+
+    /// An empty list transfers pc on ARMv4, and the base still moves on as
+    /// if all 16 registers were transferred.
+    fn thumb_empty_list_transfer(
+        &mut self,
+        opcode: u32,
+        rb: REGISTER,
+        base_address: usize,
+        memory: &mut Box<dyn MemoryBus>,
+    ) -> CYCLES {
+        self.set_register(rb, (base_address + 0x40) as u32);
+        match opcode {
+            0b0 => {
+                self.set_executed_instruction(format_args!("STMIA [{:#X}], [15]", base_address));
+                // pc reads one instruction further ahead than usual
+                memory.writeu32(base_address, self.get_pc() + 2)
+            }
+            0b1 => {
+                self.set_executed_instruction(format_args!("LDMIA [{:#X}], [15]", base_address));
+                let memory_fetch = memory.readu32(base_address);
+                self.set_pc(memory_fetch.data);
+                memory_fetch.cycles + self.flush_pipeline(memory)
+            }
+            _ => panic!(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -193,17 +231,188 @@ mod thumb_ldr_str_tests {
 
     #[test]
     fn should_load_data_relative_to_pc() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
-        cpu.memory.writeu32(0x3000024, 0x55);
+        memory.writeu32(0x3000024, 0x55);
 
         cpu.set_pc(0x3000016);
         cpu.prefetch[0] = Some(0x4d03); // ldr r5, [pc, 12]
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_register(5), 0x55);
     }
 }
+
+#[cfg(test)]
+mod thumb_multiple_load_store_tests {
+
+    use crate::{
+        arm7tdmi::cpu::{InstructionMode, CPU, LINK_REGISTER},
+        gba::GBA,
+        memory::memory::{GBAMemory, MemoryBus},
+        utils::testing::load_thumb_program,
+    };
+
+    #[test]
+    fn stmia_writes_back_the_final_address() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+        cpu.set_instruction_mode(InstructionMode::THUMB);
+        cpu.set_register(0, 0x3000100);
+        cpu.set_register(1, 0x11);
+        cpu.set_register(2, 0x22);
+
+        cpu.prefetch[0] = Some(0xc006); // stmia r0!, {r1, r2}
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(memory.readu32(0x3000100).data, 0x11);
+        assert_eq!(memory.readu32(0x3000104).data, 0x22);
+        assert_eq!(cpu.get_register(0), 0x3000108);
+    }
+
+    #[test]
+    fn ldmia_with_base_in_list_keeps_the_loaded_value() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        memory.writeu32(0x3000100, 0xAAAA);
+        memory.writeu32(0x3000104, 0xBBBB);
+
+        let mut cpu = CPU::new();
+        cpu.set_instruction_mode(InstructionMode::THUMB);
+        cpu.set_register(0, 0x3000100);
+
+        cpu.prefetch[0] = Some(0xc803); // ldmia r0!, {r0, r1}
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(cpu.get_register(0), 0xAAAA);
+        assert_eq!(cpu.get_register(1), 0xBBBB);
+    }
+
+    #[test]
+    fn ldmia_without_base_in_list_writes_back_the_final_address() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        memory.writeu32(0x3000100, 0xAAAA);
+        memory.writeu32(0x3000104, 0xBBBB);
+
+        let mut cpu = CPU::new();
+        cpu.set_instruction_mode(InstructionMode::THUMB);
+        cpu.set_register(0, 0x3000100);
+
+        cpu.prefetch[0] = Some(0xc806); // ldmia r0!, {r1, r2}
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(cpu.get_register(0), 0x3000108);
+        assert_eq!(cpu.get_register(1), 0xAAAA);
+        assert_eq!(cpu.get_register(2), 0xBBBB);
+    }
+
+    #[test]
+    fn stmia_with_the_base_first_stores_the_original_base() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+        cpu.set_instruction_mode(InstructionMode::THUMB);
+        cpu.set_register(0, 0x3000100);
+        cpu.set_register(1, 0x11);
+
+        cpu.prefetch[0] = Some(0xc003); // stmia r0!, {r0, r1}
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(memory.readu32(0x3000100).data, 0x3000100);
+        assert_eq!(memory.readu32(0x3000104).data, 0x11);
+        assert_eq!(cpu.get_register(0), 0x3000108);
+    }
+
+    #[test]
+    fn stmia_with_the_base_later_in_the_list_stores_the_final_address() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+        cpu.set_instruction_mode(InstructionMode::THUMB);
+        cpu.set_register(0, 0x11);
+        cpu.set_register(1, 0x3000100);
+        cpu.set_register(2, 0x22);
+
+        cpu.prefetch[0] = Some(0xc107); // stmia r1!, {r0, r1, r2}
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(memory.readu32(0x3000100).data, 0x11);
+        assert_eq!(memory.readu32(0x3000104).data, 0x300010C);
+        assert_eq!(memory.readu32(0x3000108).data, 0x22);
+        assert_eq!(cpu.get_register(1), 0x300010C);
+    }
+
+    #[test]
+    fn stmia_with_an_empty_list_stores_pc_and_adds_0x40() {
+        let mut gba = GBA::new_no_bios();
+        load_thumb_program(&mut gba, 0x3000000, &[
+            0xc000, // stmia r0!, {}
+        ]);
+        gba.cpu.set_register(0, 0x3000100);
+
+        gba.step();
+
+        assert_eq!(gba.memory.readu32(0x3000100).data, 0x3000006);
+        assert_eq!(gba.cpu.get_register(0), 0x3000140);
+    }
+
+    #[test]
+    fn ldmia_with_an_empty_list_loads_pc_and_adds_0x40() {
+        let mut gba = GBA::new_no_bios();
+        gba.memory.writeu32(0x3000100, 0x3000041);
+        gba.memory.writeu16(0x3000040, 0x2407); // movs r4, #7
+        load_thumb_program(&mut gba, 0x3000000, &[
+            0xc800, // ldmia r0!, {}
+        ]);
+        gba.cpu.set_register(0, 0x3000100);
+
+        gba.step();
+        assert_eq!(gba.cpu.get_register(0), 0x3000140);
+
+        assert_eq!(gba.step().executed_pc, 0x3000040);
+        assert_eq!(gba.cpu.get_register(4), 7);
+    }
+
+    #[test]
+    fn push_with_lr_then_pop_with_pc_restores_and_returns() {
+        let mut gba = GBA::new_no_bios();
+        gba.memory.writeu16(0x3000100, 0x2407); // movs r4, #7
+        load_thumb_program(&mut gba, 0x3000000, &[
+            0xb50f, // push {r0-r3, lr}
+            0x2000, // movs r0, #0
+            0x2100, // movs r1, #0
+            0x2200, // movs r2, #0
+            0x2300, // movs r3, #0
+            0xbd0f, // pop {r0-r3, pc}
+        ]);
+        for register in 0..4 {
+            gba.cpu.set_register(register, 0x11 * (register + 1));
+        }
+        gba.cpu.set_register(LINK_REGISTER, 0x3000101);
+        gba.cpu.set_sp(0x3007F00);
+
+        gba.step();
+        assert_eq!(gba.cpu.get_sp(), 0x3007F00 - 5 * 4);
+        assert_eq!(gba.memory.readu32(0x3007F00 - 5 * 4).data, 0x11);
+        assert_eq!(gba.memory.readu32(0x3007F00 - 4).data, 0x3000101);
+
+        for _ in 0..5 {
+            gba.step();
+        }
+        assert_eq!(gba.cpu.get_sp(), 0x3007F00);
+        for register in 0..4 {
+            assert_eq!(gba.cpu.get_register(register), 0x11 * (register + 1));
+        }
+
+        assert_eq!(gba.step().executed_pc, 0x3000100);
+        assert_eq!(gba.cpu.get_register(4), 7);
+    }
+}
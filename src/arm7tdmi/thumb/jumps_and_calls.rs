@@ -1,5 +1,5 @@
 use crate::{
-    arm7tdmi::cpu::{FlagsRegister, CPU, LINK_REGISTER}, memory::memory::MemoryBus, types::CYCLES, utils::bits::sign_extend
+    arm7tdmi::cpu::{CPU, LINK_REGISTER}, memory::memory::MemoryBus, types::CYCLES, utils::bits::sign_extend
 };
 
 impl CPU {
@@ -8,34 +8,13 @@ impl CPU {
         let condition = (instruction & 0x0F00) >> 8;
         let offset = (instruction & 0x00FF) << 1;
 
-        let condition_passed = match condition {
-            0b0000 => self.get_flag(FlagsRegister::Z) == 1, //EQ
-            0b0001 => self.get_flag(FlagsRegister::Z) == 0, //NE
-            0b0010 => self.get_flag(FlagsRegister::C) == 1, //CS
-            0b0011 => self.get_flag(FlagsRegister::C) == 0, //CC
-            0b0100 => self.get_flag(FlagsRegister::N) == 1, //MI
-            0b0101 => self.get_flag(FlagsRegister::N) == 0, //PL
-            0b0110 => self.get_flag(FlagsRegister::V) == 1, //VS
-            0b0111 => self.get_flag(FlagsRegister::V) == 0, //VC
-            0b1000 => self.get_flag(FlagsRegister::C) == 1 && self.get_flag(FlagsRegister::Z) == 0, //HI
-            0b1001 => self.get_flag(FlagsRegister::C) == 0 || self.get_flag(FlagsRegister::Z) == 1, //LS
-            0b1010 => self.get_flag(FlagsRegister::N) == self.get_flag(FlagsRegister::V), //GE
-            0b1011 => self.get_flag(FlagsRegister::N) != self.get_flag(FlagsRegister::V), //LT
-            0b1100 => {
-                self.get_flag(FlagsRegister::Z) == 0
-                    && self.get_flag(FlagsRegister::N) == self.get_flag(FlagsRegister::V)
-            } //GT
-            0b1101 => {
-                self.get_flag(FlagsRegister::Z) == 1
-                    || self.get_flag(FlagsRegister::N) != self.get_flag(FlagsRegister::V)
-            } //LE
-            _ => panic!("Impossible/Undefined condition code"),
-        };
+        // 0b1110 and 0b1111 are decoded as undefined and SWI, never as a branch
+        let condition_passed = self.condition_code_passed(condition);
 
         // We don't use the fetched instruction but we need to do it to get the correct cycle count
         let memory_fetch = memory.readu16(self.get_pc() as usize);
         cycles += memory_fetch.cycles;
-        let destination = self.get_pc() + sign_extend(offset, 8);
+        let destination = self.get_pc().wrapping_add(sign_extend(offset, 8));
         self.set_executed_instruction(format_args!("B {:#b} {:#X}", condition, destination));
         if !condition_passed {
             return 0;
@@ -58,7 +37,8 @@ impl CPU {
     }
 
     pub fn thumb_set_link_register(&mut self, instruction: u32, memory: &mut Box<dyn MemoryBus>) -> CYCLES {
-        let value = self.get_pc() + sign_extend((instruction & 0x07FF) << 12, 22);
+        // the high half of the 23 bit offset, sign extended from its top bit
+        let value = self.get_pc().wrapping_add(sign_extend((instruction & 0x07FF) << 12, 22));
         self.set_executed_instruction(format_args!("SET LR: {:#X}", value));
         self.set_register(LINK_REGISTER, value);
 
@@ -69,7 +49,7 @@ impl CPU {
         let mut cycles = 0;
         let link_register_val = self.get_register(LINK_REGISTER);
         self.set_register(LINK_REGISTER, (self.get_pc() - 2) | 1);
-        let destination = link_register_val + ((instruction & 0x7FF) << 1);
+        let destination = link_register_val.wrapping_add((instruction & 0x7FF) << 1);
         self.set_pc(destination);
 
         // We don't use the fetched instruction but we need to do it to get the correct cycle count
@@ -85,58 +65,158 @@ impl CPU {
 #[cfg(test)]
 mod branch_tests {
 
+    use rstest::rstest;
+
     use crate::{
-        arm7tdmi::cpu::{FlagsRegister, InstructionMode, CPU, LINK_REGISTER},
-        memory::memory::GBAMemory,
+        arm7tdmi::cpu::{CPUMode, FlagsRegister, InstructionMode, CPU, LINK_REGISTER},
+        gba::GBA,
+        memory::memory::{GBAMemory, MemoryBus},
+        utils::testing::load_thumb_program,
     };
 
     #[test]
     fn should_branch_ahead() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.prefetch[0] = Some(0xd006); // beq 12
         cpu.set_pc(0x1a);
         cpu.set_flag(FlagsRegister::Z);
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_pc(), 0x2c);
     }
 
     #[test]
     fn should_branch_behind() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.prefetch[0] = Some(0xd0f9); // beq 12
         cpu.set_pc(0x24);
         cpu.set_flag(FlagsRegister::Z);
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_pc(), 0x1c);
     }
 
     #[test]
     fn should_set_link_register_and_branch() {
-        let memory = GBAMemory::new();
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
 
-        let mut cpu = CPU::new(memory);
+        let mut cpu = CPU::new();
         cpu.set_instruction_mode(InstructionMode::THUMB);
 
         cpu.prefetch[0] = Some(0xf000); // set link_register
         cpu.set_pc(0x1a);
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
         cpu.prefetch[0] = Some(0xf802); // bl 0x20
-        cpu.execute_cpu_cycle();
-        cpu.execute_cpu_cycle();
+        cpu.execute_cpu_cycle(&mut memory);
+        cpu.execute_cpu_cycle(&mut memory);
 
         assert_eq!(cpu.get_pc(), 0x24);
         assert_eq!(cpu.get_register(LINK_REGISTER), 0x1d);
     }
+
+    #[rstest]
+    #[case::taken(0, 0x300000A)]
+    #[case::not_taken(1, 0x3000004)]
+    fn beq_branches_only_when_z_is_set(#[case] r0: u32, #[case] next_pc: u32) {
+        let mut gba = GBA::new_no_bios();
+        load_thumb_program(&mut gba, 0x3000000, &[
+            0x2800, // cmp r0, #0
+            0xd002, // beq 0x300000A
+            0x2101, // movs r1, #1
+            0x2101, // movs r1, #1
+            0x2101, // movs r1, #1
+        ]);
+        gba.cpu.set_register(0, r0);
+
+        gba.step();
+        gba.step();
+
+        assert_eq!(gba.step().executed_pc, next_pc);
+    }
+
+    #[test]
+    fn backward_bne_loops_until_the_counter_reaches_zero() {
+        let mut gba = GBA::new_no_bios();
+        load_thumb_program(&mut gba, 0x3000000, &[
+            0x3801, // subs r0, #1
+            0xd1fd, // bne 0x3000000
+            0x2109, // movs r1, #9
+        ]);
+        gba.cpu.set_register(0, 3);
+
+        let executed: Vec<u32> = (0..7).map(|_| gba.step().executed_pc).collect();
+
+        assert_eq!(
+            executed,
+            [0x3000000, 0x3000002, 0x3000000, 0x3000002, 0x3000000, 0x3000002, 0x3000004]
+        );
+        assert_eq!(gba.cpu.get_register(0), 0);
+        assert_eq!(gba.cpu.get_register(1), 9);
+    }
+
+    #[rstest]
+    #[case::forward(0x2000000, 0x2030000, [0xf02f, 0xfffe])]
+    #[case::backward(0x2030000, 0x2000000, [0xf7cf, 0xfffe])]
+    fn bl_spans_a_large_offset_and_links_the_return_address(
+        #[case] address: u32,
+        #[case] target: u32,
+        #[case] program: [u16; 2],
+    ) {
+        let mut gba = GBA::new_no_bios();
+        gba.memory.writeu16(target as usize, 0x2407); // movs r4, #7
+        load_thumb_program(&mut gba, address as usize, &program);
+
+        gba.step();
+        gba.step();
+        assert_eq!(gba.cpu.get_register(LINK_REGISTER), (address + 4) | 1);
+
+        assert_eq!(gba.step().executed_pc, target);
+        assert_eq!(gba.cpu.get_register(4), 7);
+    }
+
+    #[test]
+    fn conditional_branch_nv_encoding_is_a_software_interrupt() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+        cpu.set_mode(CPUMode::USER);
+        cpu.set_instruction_mode(InstructionMode::THUMB);
+        cpu.set_pc(0xFC);
+        cpu.prefetch[1] = Some(0xdf05); // swi 5 at 0xF8
+
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(cpu.get_pc(), 0x10);
+        assert!(cpu.get_cpu_mode() == CPUMode::SVC);
+        assert!(matches!(cpu.get_instruction_mode(), InstructionMode::ARM));
+        assert_eq!(cpu.get_register(LINK_REGISTER), 0xFA);
+    }
+
+    #[test]
+    fn conditional_branch_al_encoding_is_undefined() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+
+        let mut cpu = CPU::new();
+        cpu.set_mode(CPUMode::USER);
+        cpu.set_instruction_mode(InstructionMode::THUMB);
+        cpu.set_pc(0xFC);
+        cpu.prefetch[1] = Some(0xde05); // at 0xF8
+        cpu.set_flag(FlagsRegister::Z);
+
+        cpu.execute_cpu_cycle(&mut memory);
+
+        assert_eq!(cpu.get_pc(), 0x0C);
+        assert!(cpu.get_cpu_mode() == CPUMode::UND);
+        assert_eq!(cpu.get_register(LINK_REGISTER), 0xFA);
+    }
 }
@@ -0,0 +1,56 @@
+//! ARM7TDMI instruction timings from the data sheet, run from IWRAM where
+//! every access takes a single cycle. S, N and I cycles therefore all
+//! count as one and the expected values are just their sum.
+
+use rstest::rstest;
+
+use crate::{
+    gba::GBA,
+    utils::testing::{load_arm_program, step_one_cycles},
+};
+
+const IWRAM_START: usize = 0x3000000;
+const DATA_ADDRESS: usize = 0x3000100;
+
+#[rstest]
+#[case::data_processing_immediate(0xe3a0100a, 1)] // mov r1, 10                1S
+#[case::data_processing_immediate_shift(0xe0811102, 1)] // add r1, r1, r2, lsl 2    1S
+#[case::data_processing_register_shift(0xe0811312, 2)] // add r1, r1, r2, lsl r3    1S + 1I
+#[case::data_processing_to_pc(0xe1a0f005, 3)] // mov pc, r5                2S + 1N
+#[case::data_processing_register_shift_to_pc(0xe085f312, 4)] // add pc, r5, r2, lsl r3    2S + 1N + 1I
+#[case::mrs(0xe10f1000, 1)] // mrs r1, cpsr              1S
+#[case::msr(0xe129f001, 1)] // msr cpsr_fc, r1           1S
+#[case::ldr(0xe5902000, 3)] // ldr r2, [r0]              1S + 1N + 1I
+#[case::ldr_to_pc(0xe590f000, 5)] // ldr pc, [r0]              2S + 2N + 1I
+#[case::ldrh(0xe1d010b0, 3)] // ldrh r1, [r0]             1S + 1N + 1I
+#[case::str(0xe5802000, 2)] // str r2, [r0]              2N
+#[case::strh(0xe1c010b0, 2)] // strh r1, [r0]             2N
+#[case::ldm(0xe890001e, 6)] // ldmia r0, {r1-r4}         4S + 1N + 1I
+#[case::stm(0xe880001e, 5)] // stmia r0, {r1-r4}         3S + 2N
+#[case::swp(0xe1001092, 4)] // swp r1, r2, [r0]          1S + 2N + 1I
+#[case::mul_one_byte(0xe0010392, 2)] // mul r1, r2, r3            1S + 1I
+#[case::mul_two_bytes(0xe0010892, 3)] // mul r1, r2, r8            1S + 2I
+#[case::mul_three_bytes(0xe0010792, 4)] // mul r1, r2, r7            1S + 3I
+#[case::mul_four_bytes(0xe0010692, 5)] // mul r1, r2, r6            1S + 4I
+#[case::mla(0xe0213892, 4)] // mla r1, r2, r8, r3        1S + 3I
+#[case::branch(0xeaffffff, 3)] // b next                    2S + 1N
+#[case::branch_with_link(0xebffffff, 3)] // bl next                   2S + 1N
+#[case::branch_and_exchange(0xe12fff15, 3)] // bx r5                     2S + 1N
+fn arm_instruction_takes_documented_cycles(#[case] instruction: u32, #[case] expected_cycles: u8) {
+    let mut gba = GBA::new_no_bios();
+    gba.cpu.set_register(0, DATA_ADDRESS as u32);
+    gba.cpu.set_register(2, 3);
+    gba.cpu.set_register(3, 5);
+    gba.cpu.set_register(5, IWRAM_START as u32 + 8);
+    gba.cpu.set_register(6, 0x1234_5678);
+    gba.cpu.set_register(7, 0xFF12_3456);
+    gba.cpu.set_register(8, 0xFFFF_1234);
+    gba.memory.writeu32(DATA_ADDRESS, IWRAM_START as u32 + 8);
+    load_arm_program(&mut gba, IWRAM_START, &[
+        instruction,
+        0xe1a00000, // nop
+        0xe1a00000, // nop
+    ]);
+
+    assert_eq!(step_one_cycles(&mut gba), expected_cycles);
+}
@@ -0,0 +1,234 @@
+use std::fmt::Display;
+
+use crate::memory::memory::MemoryBus;
+
+const TEA_DELTA: u32 = 0x9E37_79B9;
+const GAMESHARK_SEEDS: [u32; 4] = [0x09F4_FBBD, 0x9681_884A, 0x3520_27E9, 0xF3DE_E5A7];
+const ACTION_REPLAY_SEEDS: [u32; 4] = [0x7AA9_648F, 0x7FAE_6994, 0xC0EF_AAD5, 0x4271_2C57];
+const ROM_BASE: usize = 0x8000000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheatDevice {
+    /// GameShark / Action Replay v1 and v2
+    GameShark,
+    /// Action Replay v3 / GameShark SP
+    ActionReplay,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CheatError {
+    InvalidFormat(String),
+    UnsupportedCode(u32, u32),
+}
+
+impl Display for CheatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheatError::InvalidFormat(code) => write!(f, "Invalid cheat code: {}", code),
+            CheatError::UnsupportedCode(address, value) => {
+                write!(f, "Unsupported cheat code type: {:08X} {:08X}", address, value)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cheat {
+    Write8 { address: usize, value: u8 },
+    Write16 { address: usize, value: u16 },
+    Write32 { address: usize, value: u32 },
+    RomPatch { address: usize, value: u16 },
+}
+
+impl Cheat {
+    /// Parses a `XXXXXXXX YYYYYYYY` code, decrypting it first if `encrypted`.
+    pub fn parse(code: &str, device: CheatDevice, encrypted: bool) -> Result<Self, CheatError> {
+        let digits: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+        if digits.len() != 16 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(CheatError::InvalidFormat(code.to_string()));
+        }
+        let parse_word = |word: &str| {
+            u32::from_str_radix(word, 16).map_err(|_| CheatError::InvalidFormat(code.to_string()))
+        };
+        let (mut address, mut value) = (parse_word(&digits[..8])?, parse_word(&digits[8..])?);
+
+        if encrypted {
+            let seeds = match device {
+                CheatDevice::GameShark => &GAMESHARK_SEEDS,
+                CheatDevice::ActionReplay => &ACTION_REPLAY_SEEDS,
+            };
+            (address, value) = tea_decrypt(address, value, seeds);
+        }
+
+        match device {
+            CheatDevice::GameShark => Self::from_gameshark(address, value),
+            CheatDevice::ActionReplay => Self::from_action_replay(address, value),
+        }
+    }
+
+    fn from_gameshark(address: u32, value: u32) -> Result<Self, CheatError> {
+        let target = (address & 0x0FFF_FFFF) as usize;
+        match address >> 28 {
+            0x0 => Ok(Cheat::Write8 { address: target, value: value as u8 }),
+            0x1 => Ok(Cheat::Write16 { address: target, value: value as u16 }),
+            0x2 => Ok(Cheat::Write32 { address: target, value }),
+            0x6 => Ok(Cheat::RomPatch {
+                address: ROM_BASE + ((target << 1) & 0x1FF_FFFE),
+                value: value as u16,
+            }),
+            _ => Err(CheatError::UnsupportedCode(address, value)),
+        }
+    }
+
+    fn from_action_replay(address: u32, value: u32) -> Result<Self, CheatError> {
+        // the region nibble is stored in bits 20-23 and the code type above it
+        let target = (((address & 0x00F0_0000) << 4) | (address & 0x000F_FFFF)) as usize;
+        match address >> 25 {
+            0x00 => Ok(Cheat::Write8 { address: target, value: value as u8 }),
+            0x01 => Ok(Cheat::Write16 { address: target, value: value as u16 }),
+            0x02 => Ok(Cheat::Write32 { address: target, value }),
+            _ => Err(CheatError::UnsupportedCode(address, value)),
+        }
+    }
+
+    pub fn apply(&self, memory: &mut Box<dyn MemoryBus>) {
+        match *self {
+            Cheat::Write8 { address, value } => {
+                memory.write(address, value);
+            }
+            Cheat::Write16 { address, value } => {
+                memory.writeu16(address, value);
+            }
+            Cheat::Write32 { address, value } => {
+                memory.writeu32(address, value);
+            }
+            Cheat::RomPatch { address, value } => memory.patch_rom(address, value),
+        }
+    }
+}
+
+fn tea_decrypt(mut v0: u32, mut v1: u32, seeds: &[u32; 4]) -> (u32, u32) {
+    let mut sum = TEA_DELTA.wrapping_mul(32);
+    for _ in 0..32 {
+        v1 = v1.wrapping_sub(
+            (v0 << 4).wrapping_add(seeds[2])
+                ^ v0.wrapping_add(sum)
+                ^ (v0 >> 5).wrapping_add(seeds[3]),
+        );
+        v0 = v0.wrapping_sub(
+            (v1 << 4).wrapping_add(seeds[0])
+                ^ v1.wrapping_add(sum)
+                ^ (v1 >> 5).wrapping_add(seeds[1]),
+        );
+        sum = sum.wrapping_sub(TEA_DELTA);
+    }
+    (v0, v1)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gba::GBA;
+
+    use super::{tea_decrypt, Cheat, CheatDevice, CheatError, GAMESHARK_SEEDS, TEA_DELTA};
+
+    fn tea_encrypt(mut v0: u32, mut v1: u32, seeds: &[u32; 4]) -> (u32, u32) {
+        let mut sum: u32 = 0;
+        for _ in 0..32 {
+            sum = sum.wrapping_add(TEA_DELTA);
+            v0 = v0.wrapping_add(
+                (v1 << 4).wrapping_add(seeds[0])
+                    ^ v1.wrapping_add(sum)
+                    ^ (v1 >> 5).wrapping_add(seeds[1]),
+            );
+            v1 = v1.wrapping_add(
+                (v0 << 4).wrapping_add(seeds[2])
+                    ^ v0.wrapping_add(sum)
+                    ^ (v0 >> 5).wrapping_add(seeds[3]),
+            );
+        }
+        (v0, v1)
+    }
+
+    #[test]
+    fn parses_plaintext_gameshark_ram_writes() {
+        assert_eq!(
+            Cheat::parse("0200A000 000000AB", CheatDevice::GameShark, false),
+            Ok(Cheat::Write8 { address: 0x200A000, value: 0xAB })
+        );
+        assert_eq!(
+            Cheat::parse("1200A000 00001234", CheatDevice::GameShark, false),
+            Ok(Cheat::Write16 { address: 0x200A000, value: 0x1234 })
+        );
+        assert_eq!(
+            Cheat::parse("23000010 DEADBEEF", CheatDevice::GameShark, false),
+            Ok(Cheat::Write32 { address: 0x3000010, value: 0xDEADBEEF })
+        );
+    }
+
+    #[test]
+    fn parses_action_replay_addresses() {
+        // 16-bit write (type 0x01) to 0x03001234
+        assert_eq!(
+            Cheat::parse("02301234 0000BEEF", CheatDevice::ActionReplay, false),
+            Ok(Cheat::Write16 { address: 0x3001234, value: 0xBEEF })
+        );
+    }
+
+    #[test]
+    fn decrypts_the_tea_reference_vector() {
+        // the published TEA vector: an all-zero key and block encrypt to this
+        assert_eq!(tea_decrypt(0x41EA_3A0A, 0x94BA_A940, &[0; 4]), (0, 0));
+    }
+
+    #[test]
+    fn decrypts_encrypted_codes() {
+        let (address, value) = tea_encrypt(0x1200A000, 0x1234, &GAMESHARK_SEEDS);
+        assert_eq!(tea_decrypt(address, value, &GAMESHARK_SEEDS), (0x1200A000, 0x1234));
+
+        let code = format!("{:08X} {:08X}", address, value);
+        assert_eq!(
+            Cheat::parse(&code, CheatDevice::GameShark, true),
+            Ok(Cheat::Write16 { address: 0x200A000, value: 0x1234 })
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_codes() {
+        assert!(matches!(
+            Cheat::parse("1200A000", CheatDevice::GameShark, false),
+            Err(CheatError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            Cheat::parse("1200A000 0000123G", CheatDevice::GameShark, false),
+            Err(CheatError::InvalidFormat(_))
+        ));
+        // 16 bytes with the é across the split between the two words
+        assert!(matches!(
+            Cheat::parse("1200A00é 0001234", CheatDevice::GameShark, false),
+            Err(CheatError::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn ram_write_is_poked_every_frame() {
+        let mut gba = GBA::new_no_bios();
+        gba.add_cheat("1300A000 00001234", CheatDevice::GameShark, false)
+            .unwrap();
+        gba.memory.writeu32(0x3000000, 0xeafffffe); // b .
+        gba.cpu.set_pc(0x3000000);
+        gba.cpu.flush_pipeline(&mut gba.memory);
+
+        let frame = gba.ppu.frame_count;
+        while gba.ppu.frame_count == frame {
+            gba.step();
+        }
+        assert_eq!(gba.memory.readu16(0x300A000).data, 0x1234);
+
+        gba.memory.writeu16(0x300A000, 0);
+        let frame = gba.ppu.frame_count;
+        while gba.ppu.frame_count == frame {
+            gba.step();
+        }
+        assert_eq!(gba.memory.readu16(0x300A000).data, 0x1234);
+    }
+}
@@ -2,17 +2,28 @@ use std::
     fmt::Display
 ;
 
-use crate::{memory::memory::MemoryError, types::REGISTER};
+use crate::{
+    memory::{debugger_memory::MemoryAccess, memory::MemoryError},
+    types::REGISTER,
+};
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum BreakType {
+    /// Stops before the instruction at the address is executed.
     Break(u32),
     WatchRegister(REGISTER, u32),
+    /// Inclusive address range watched for reads and writes.
     WatchAddress(usize, usize),
+    /// Inclusive address range watched for reads only.
+    WatchRead(usize, usize),
+    /// Inclusive address range watched for writes only.
+    WatchWrite(usize, usize),
+    /// Stops before execution moves into the inclusive PC range.
+    PcRange(u32, u32),
 }
 
 pub enum TriggeredWatchpoints {
-    Address(usize),
+    Address(usize, MemoryAccess),
     Error(MemoryError)
 }
 
@@ -29,14 +40,160 @@ impl Breakpoint {
     }
 }
 
+impl BreakType {
+    /// Whether an execution breakpoint stops the CPU with `next_pc`, the
+    /// instruction it decodes next, still unexecuted.
+    pub fn stops_before(&self, next_pc: Option<u32>) -> bool {
+        matches!(*self, BreakType::Break(break_pc) if next_pc == Some(break_pc))
+    }
+
+    pub fn watches(&self, address: usize, access: MemoryAccess) -> bool {
+        let (start, end) = match *self {
+            BreakType::WatchAddress(start, end) => (start, end),
+            BreakType::WatchRead(start, end) if access == MemoryAccess::Read => (start, end),
+            BreakType::WatchWrite(start, end) if access == MemoryAccess::Write => (start, end),
+            _ => return false,
+        };
+        start <= address && address <= end
+    }
+
+    /// Whether a PC range breakpoint stops the CPU with `next_pc` inside
+    /// the range, still unexecuted, after `executed_pc` outside it.
+    pub fn pc_range_entered(&self, executed_pc: u32, next_pc: Option<u32>) -> bool {
+        let BreakType::PcRange(start, end) = *self else {
+            return false;
+        };
+        let in_range = |pc: u32| start <= pc && pc <= end;
+        next_pc.is_some_and(in_range) && !in_range(executed_pc)
+    }
+}
+
 impl Display for BreakType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            BreakType::Break(breakpoint) => write!(f, "PC == {}", breakpoint),
+            BreakType::Break(breakpoint) => write!(f, "PC == {:#X}", breakpoint),
             BreakType::WatchRegister(register, value) => {
                 write!(f, "r{} == {}", register, value)
             }
             BreakType::WatchAddress(address, address1) => write!(f, "address == {}", address),
+            BreakType::WatchRead(start, end) => write!(f, "read in {:#X}-{:#X}", start, end),
+            BreakType::WatchWrite(start, end) => write!(f, "write in {:#X}-{:#X}", start, end),
+            BreakType::PcRange(start, end) => write!(f, "PC in {:#X}-{:#X}", start, end),
         }
     }
 }
+
+#[cfg(test)]
+mod breakpoint_tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::{
+        debugger::{debugger::Debugger, terminal_commands::parse_command},
+        gba::GBA,
+        memory::{
+            debugger_memory::{DebuggerMemory, MemoryAccess},
+            memory::{GBAMemory, MemoryBus},
+        },
+        utils::testing::load_arm_program,
+    };
+
+    use super::BreakType;
+
+    fn run_command(debugger: &mut Debugger, command: &str) -> String {
+        debugger.terminal_buffer = String::from(command);
+        parse_command(debugger).unwrap_or_else(|err| err.to_string())
+    }
+
+    #[test]
+    fn execution_breakpoint_halts_before_the_instruction_runs() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(
+            &mut gba,
+            0x3000000,
+            &[
+                0xE3A00001, // mov r0, #1
+                0xE3A01002, // mov r1, #2
+                0xE3A02003, // mov r2, #3
+                0xE3A03004, // mov r3, #4
+            ],
+        );
+        let breakpoint = BreakType::Break(0x3000008);
+
+        let mut steps = 0;
+        while !breakpoint.stops_before(gba.cpu.next_executed_pc()) && steps < 8 {
+            gba.step();
+            steps += 1;
+        }
+
+        assert_eq!(steps, 2);
+        assert_eq!(gba.cpu.last_executed_pc(), 0x3000004);
+        assert_eq!(gba.cpu.get_register(1), 2);
+        assert_eq!(gba.cpu.get_register(2), 0);
+
+        gba.step();
+        assert_eq!(gba.cpu.get_register(2), 3);
+    }
+
+    #[test]
+    fn watchpoints_only_trigger_on_their_access_kind() {
+        let watchpoints = [
+            BreakType::WatchRead(0x2000000, 0x20000FF),
+            BreakType::WatchWrite(0x2000100, 0x20001FF),
+        ];
+        let hits = Rc::new(RefCell::new(Vec::new()));
+        let mut memory = {
+            let hits = hits.clone();
+            DebuggerMemory::new(
+                GBAMemory::new(),
+                Box::new(move |address, access| {
+                    if watchpoints.iter().any(|w| w.watches(address, access)) {
+                        hits.borrow_mut().push((address, access));
+                    }
+                }),
+                Box::new(|_| {}),
+            )
+        };
+
+        memory.writeu32(0x2000010, 1);
+        memory.readu32(0x2000010);
+        memory.readu32(0x2000110);
+        memory.writeu32(0x2000110, 1);
+
+        assert_eq!(
+            *hits.borrow(),
+            [(0x2000010, MemoryAccess::Read), (0x2000110, MemoryAccess::Write)]
+        );
+    }
+
+    #[test]
+    fn pc_range_breakpoint_stops_before_execution_enters_range() {
+        let mut debugger = Debugger::with_memory(GBAMemory::new());
+        load_arm_program(
+            &mut debugger.cpu,
+            0x3000000,
+            &[
+                0xE3A00001, // mov r0, #1
+                0xEA00003D, // b 0x3000100
+            ],
+        );
+        debugger.cpu.memory.writeu32(0x3000100, 0xE3A00002); // mov r0, #2
+        run_command(&mut debugger, "breakr 0x3000100 0x30001FF");
+        let output = run_command(&mut debugger, "next 10");
+        assert!(output.starts_with("Breakpoint encountered PC in 0x3000100-0x30001FF"));
+        assert_eq!(debugger.cpu.cpu.next_executed_pc(), Some(0x3000100));
+        assert_eq!(debugger.cpu.cpu.get_register(0), 1);
+
+        run_command(&mut debugger, "next 1");
+        assert_eq!(debugger.cpu.cpu.get_register(0), 2);
+    }
+
+    #[test]
+    fn pc_range_breakpoint_ignores_execution_already_inside_range() {
+        let breakpoint = BreakType::PcRange(0x6000000, 0x6017FFF);
+
+        assert!(breakpoint.pc_range_entered(0x8000000, Some(0x6000000)));
+        assert!(!breakpoint.pc_range_entered(0x6000000, Some(0x6000004)));
+        assert!(!breakpoint.pc_range_entered(0x6000000, Some(0x8000000)));
+        assert!(!breakpoint.pc_range_entered(0x8000000, None));
+    }
+}
@@ -1,4 +1,10 @@
-use super::breakpoints::{BreakType, Breakpoint, TriggeredWatchpoints};
+use super::{
+    breakpoints::{Breakpoint, TriggeredWatchpoints},
+    loop_detector::LoopDetector,
+    panic_capture::{step_catching_panic, PanicReport},
+    stack_guard::StackGuard,
+    watch_expressions::WatchExpression,
+};
 use crossterm::{
     event::{
         self, read, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers,
@@ -9,6 +15,7 @@ use crossterm::{
 use std::{
     cell::RefCell,
     io::{self, Stdout},
+    path::PathBuf,
     rc::Rc,
     thread,
     time::Duration,
@@ -22,8 +29,8 @@ use tui::{
 };
 
 use crate::{
-    arm7tdmi::cpu::{CPUMode, FlagsRegister, InstructionMode, CPU}, gba::GBA, memory::{
-        debugger_memory::DebuggerMemory, io_handlers::{IO_BASE, VCOUNT}, memory::GBAMemory
+    arm7tdmi::cpu::{CPUMode, FlagsRegister, InstructionMode, CPU}, gba::{StepResult, GBA}, graphics::display::FrameHandoff, io::keypad::KeyInput, memory::{
+        backup::BackupType, debugger_memory::DebuggerMemory, io_handlers::{IO_BASE, VCOUNT}, memory::GBAMemory
     }, utils::bits::Bits
 };
 
@@ -38,11 +45,26 @@ pub struct Debugger {
     pub cpu: GBA,
     pub breakpoints: Rc<RefCell<Vec<Breakpoint>>>,
     pub triggered_watchpoints: Rc<RefCell<Vec<TriggeredWatchpoints>>>,
+    pub loop_detector: LoopDetector,
+    pub stack_guard: StackGuard,
+    pub watch_expressions: Vec<WatchExpression>,
+    /// Catch panics in the emulator and keep the session open with
+    /// `panic_report` instead of ending the process.
+    pub open_on_panic: bool,
+    pub panic_report: Option<Box<PanicReport>>,
 }
 
 impl Debugger {
-    pub fn new(bios: String, rom: String) -> Self {
-        let memory = GBAMemory::new();
+    pub fn new(bios: String, rom: String, save_type: Option<BackupType>) -> Self {
+        let mut memory = GBAMemory::new();
+        memory.initialize_bios(bios).unwrap();
+        memory.initialize_rom(rom).unwrap();
+        let mut debugger = Self::with_memory(memory);
+        debugger.cpu.memory.backup_config().forced = save_type;
+        debugger
+    }
+
+    pub fn with_memory(memory: Box<GBAMemory>) -> Self {
         let breakpoints = Rc::new(RefCell::new(Vec::<Breakpoint>::new()));
         let triggered_watchpoints = Rc::new(RefCell::new(Vec::<TriggeredWatchpoints>::new()));
 
@@ -53,14 +75,12 @@ impl Debugger {
 
             DebuggerMemory::new(
                 memory,
-                Box::new(move |address| {
+                Box::new(move |address, access| {
                     for bp in breakpoints.borrow().iter() {
-                        if let BreakType::WatchAddress(adr1, adr2) = bp.break_type {
-                            if adr1 <= address && address <= adr2 {
-                                triggered_watchpoints
-                                    .borrow_mut()
-                                    .push(TriggeredWatchpoints::Address(address));
-                            }
+                        if bp.break_type.watches(address, access) {
+                            triggered_watchpoints
+                                .borrow_mut()
+                                .push(TriggeredWatchpoints::Address(address, access));
                         }
                     }
                 }),
@@ -72,7 +92,7 @@ impl Debugger {
             )
         };
 
-        let cpu = GBA::new(bios, rom);
+        let cpu = GBA::with_memory(memory);
 
         Self {
             memory_start_address: 0x0000000,
@@ -83,18 +103,54 @@ impl Debugger {
             cpu,
             breakpoints,
             triggered_watchpoints,
+            loop_detector: LoopDetector::default(),
+            stack_guard: StackGuard::default(),
+            watch_expressions: Vec::new(),
+            open_on_panic: false,
+            panic_report: None,
+        }
+    }
+
+    /// Steps the emulator. With `open_on_panic` set a panic is kept in
+    /// `panic_report` and None is returned.
+    pub fn step(&mut self) -> Option<StepResult> {
+        if !self.open_on_panic {
+            return Some(self.cpu.step());
+        }
+        match step_catching_panic(&mut self.cpu) {
+            Ok(result) => Some(result),
+            Err(report) => {
+                self.panic_report = Some(report);
+                None
+            }
         }
     }
 }
 
-pub fn start_debugger(bios: String, rom: String) -> Result<(), std::io::Error> {
+pub fn start_debugger(
+    bios: String,
+    rom: String,
+    save_type: Option<BackupType>,
+    save: PathBuf,
+    open_on_panic: bool,
+    key_input: KeyInput,
+    frame_output: FrameHandoff,
+) -> Result<(), std::io::Error> {
+    let debugger = &mut Debugger::new(bios, rom, save_type);
+    match debugger.cpu.memory.load_save(&save) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+        _ => {}
+    }
+
     enable_raw_mode()?;
     execute!(io::stdout(), EnterAlternateScreen, EnableMouseCapture)?;
     let backend = CrosstermBackend::new(io::stdout());
     let mut terminal = Terminal::new(backend)?;
     terminal.clear()?;
 
-    let debugger = &mut Debugger::new(bios, rom);
+    debugger.open_on_panic = open_on_panic;
+    debugger.cpu.key_input = key_input;
+    debugger.cpu.frame_output = Some(frame_output);
 
     while !debugger.end_debugger {
         loop {
@@ -161,8 +217,10 @@ pub fn start_debugger(bios: String, rom: String) -> Result<(), std::io::Error> {
             let register_chunk_2 = horizontal_chunks[2];
             let flags_chunk = horizontal_chunks[3];
             let ppu_chunk = horizontal_chunks[4];
+            let banked_chunk = horizontal_chunks[5];
             let memory_chunk = horizontal_chunks_1[0];
             let terminal_chunk = horizontal_chunks_1[1];
+            let watch_chunk = horizontal_chunks_1[2];
 
             {
                 let cpu = &debugger.cpu;
@@ -171,8 +229,10 @@ pub fn start_debugger(bios: String, rom: String) -> Result<(), std::io::Error> {
                 draw_registers(f, register_chunk, 0, &cpu.cpu).unwrap();
                 draw_registers(f, register_chunk_2, 10, &cpu.cpu).unwrap();
                 draw_cpsr(f, flags_chunk, &cpu.cpu).unwrap();
+                draw_banked_registers(f, banked_chunk, &cpu.cpu).unwrap();
                 draw_memory(f, memory_chunk, &cpu, &debugger).unwrap();
                 draw_terminal(f, terminal_chunk, &debugger).unwrap();
+                draw_watch_expressions(f, watch_chunk, debugger).unwrap();
             }
         }) else {
             break;
@@ -188,7 +248,7 @@ pub fn start_debugger(bios: String, rom: String) -> Result<(), std::io::Error> {
     )?;
     terminal.show_cursor()?;
 
-    Ok(())
+    debugger.cpu.memory.save_to(&save)
 }
 
 fn draw_ppu(
@@ -222,7 +282,7 @@ fn draw_ppu(
     );
 
     f.render_widget(
-        Paragraph::new(format!("{}", cpu.memory.readu16(IO_BASE + VCOUNT).data)).alignment(Alignment::Center),
+        Paragraph::new(format!("{}", cpu.memory.peeku16(IO_BASE + VCOUNT))).alignment(Alignment::Center),
         ppu_values[1],
     );
 
@@ -232,7 +292,7 @@ fn draw_ppu(
     );
 
     f.render_widget(
-        Paragraph::new(format!("{}", cpu.ppu.x)).alignment(Alignment::Center),
+        Paragraph::new(format!("{}", cpu.ppu_x())).alignment(Alignment::Center),
         ppu_values[2],
     );
 
@@ -363,7 +423,9 @@ fn handle_terminal_events(debugger: &mut Debugger, event: KeyEvent) {
 
 fn handle_normal_mode_events(debugger: &mut Debugger, event: KeyEvent) {
     match event.code {
-        KeyCode::Char('n') => debugger.cpu.step(),
+        KeyCode::Char('n') => {
+            debugger.step();
+        }
         KeyCode::Char('M') => debugger.memory_start_address -= 0x100,
         KeyCode::Char('m') => debugger.memory_start_address += 0x100,
         _ => {}
@@ -617,8 +679,7 @@ fn draw_memory(
         for row in 2..memory_grid[column].len() {
             let value = cpu
                 .memory
-                .read((start_address + ((row as u32 - 2) * 0x10) + (column as u32 - 1)) as usize)
-                .data;
+                .peek((start_address + ((row as u32 - 2) * 0x10) + (column as u32 - 1)) as usize);
 
             let widget = Paragraph::new(format!("0x{:0>2x}", value))
                 .style(Style::default().fg(if value > 0 {
@@ -638,6 +699,72 @@ fn draw_memory(
     Ok(())
 }
 
+fn draw_banked_registers(
+    f: &mut Frame<'_, CrosstermBackend<Stdout>>,
+    banked_chunk: Rect,
+    cpu: &CPU,
+) -> Result<(), std::io::Error> {
+    let block = Block::default()
+        .title("Banked")
+        .title_alignment(Alignment::Center)
+        .borders(Borders::ALL);
+
+    let banks = [
+        ("usr", CPUMode::USER),
+        ("fiq", CPUMode::FIQ),
+        ("irq", CPUMode::IRQ),
+        ("svc", CPUMode::SVC),
+        ("abt", CPUMode::ABT),
+        ("und", CPUMode::UND),
+    ];
+    let mut output = vec![String::from("     sp         lr         spsr")];
+    for (name, mode) in banks {
+        let spsr = match cpu.banked_spsr(mode) {
+            Some(spsr) => format!("{:#010X}", spsr),
+            None => String::from("-"),
+        };
+        output.push(format!(
+            "{} {:#010X} {:#010X} {}",
+            name,
+            cpu.banked_sp(mode),
+            cpu.banked_lr(mode),
+            spsr
+        ));
+    }
+
+    f.render_widget(Paragraph::new(output.join("\n")).block(block), banked_chunk);
+    Ok(())
+}
+
+fn draw_watch_expressions(
+    f: &mut Frame<'_, CrosstermBackend<Stdout>>,
+    watch_chunk: Rect,
+    debugger: &Debugger,
+) -> Result<(), std::io::Error> {
+    let block = Block::default()
+        .title("Watch")
+        .title_alignment(tui::layout::Alignment::Center)
+        .borders(Borders::ALL);
+
+    let gba = &debugger.cpu;
+    let output: Vec<String> = debugger
+        .watch_expressions
+        .iter()
+        .enumerate()
+        .map(|(i, expression)| {
+            format!(
+                "{}: {} = {:#010X}",
+                i + 1,
+                expression.source(),
+                expression.evaluate(&gba.cpu, gba.memory.as_ref())
+            )
+        })
+        .collect();
+
+    f.render_widget(Paragraph::new(output.join("\n")).block(block), watch_chunk);
+    Ok(())
+}
+
 fn draw_terminal(
     f: &mut Frame<'_, CrosstermBackend<Stdout>>,
     terminal_chunk: Rect,
@@ -0,0 +1,137 @@
+use std::fmt::Display;
+
+use crate::{arm7tdmi::cpu::CPU, types::WORD};
+
+const DEFAULT_THRESHOLD: u32 = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoopAction {
+    Off,
+    Warn,
+    Halt,
+}
+
+impl Display for LoopAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoopAction::Off => "off".fmt(f),
+            LoopAction::Warn => "warn".fmt(f),
+            LoopAction::Halt => "halt".fmt(f),
+        }
+    }
+}
+
+/// Spots a game spinning on a self-branch such as `b .`: the same PC is
+/// executed with every register and the CPSR unchanged for `threshold`
+/// consecutive steps.
+#[derive(Debug)]
+pub struct LoopDetector {
+    pub action: LoopAction,
+    pub threshold: u32,
+    last_state: Option<([WORD; 16], WORD)>,
+    repeat_count: u32,
+}
+
+impl Default for LoopDetector {
+    fn default() -> Self {
+        Self {
+            action: LoopAction::Off,
+            threshold: DEFAULT_THRESHOLD,
+            last_state: None,
+            repeat_count: 0,
+        }
+    }
+}
+
+impl LoopDetector {
+    /// Records the cpu state after a step. Returns true once, on the step
+    /// where the unchanged state has repeated `threshold` times.
+    pub fn observe(&mut self, cpu: &CPU) -> bool {
+        if self.action == LoopAction::Off {
+            return false;
+        }
+
+        let mut registers = [0; 16];
+        for (i, register) in registers.iter_mut().enumerate() {
+            *register = cpu.get_register(i as u32);
+        }
+        let state = (registers, cpu.cpsr);
+
+        if self.last_state == Some(state) {
+            self.repeat_count += 1;
+        } else {
+            self.last_state = Some(state);
+            self.repeat_count = 0;
+        }
+
+        self.repeat_count == self.threshold
+    }
+
+    pub fn reset(&mut self) {
+        self.last_state = None;
+        self.repeat_count = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{gba::GBA, utils::testing::load_arm_program};
+
+    use super::{LoopAction, LoopDetector};
+
+    #[test]
+    fn self_branch_triggers_after_threshold() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, 0x3000000, &[
+            0xeafffffe, // b .
+        ]);
+        let mut detector = LoopDetector {
+            action: LoopAction::Halt,
+            threshold: 10,
+            ..Default::default()
+        };
+
+        // the first step only establishes the state to compare against
+        for _ in 0..10 {
+            gba.step();
+            assert!(!detector.observe(&gba.cpu));
+        }
+        gba.step();
+        assert!(detector.observe(&gba.cpu));
+        assert_eq!(gba.cpu.last_executed_pc(), 0x3000000);
+    }
+
+    #[test]
+    fn changing_registers_do_not_trigger() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, 0x3000000, &[
+            0xe2811001, // add r1, r1, 1
+            0xeafffffd, // b 0x3000000
+        ]);
+        let mut detector = LoopDetector {
+            action: LoopAction::Halt,
+            threshold: 10,
+            ..Default::default()
+        };
+
+        for _ in 0..100 {
+            gba.step();
+            assert!(!detector.observe(&gba.cpu));
+        }
+    }
+
+    #[test]
+    fn disabled_detector_never_triggers() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, 0x3000000, &[0xeafffffe]);
+        let mut detector = LoopDetector {
+            threshold: 1,
+            ..Default::default()
+        };
+
+        for _ in 0..10 {
+            gba.step();
+            assert!(!detector.observe(&gba.cpu));
+        }
+    }
+}
@@ -1,3 +1,7 @@
 pub mod debugger;
 pub mod terminal_commands;
 pub mod breakpoints;
+pub mod loop_detector;
+pub mod stack_guard;
+pub mod watch_expressions;
+pub mod panic_capture;
@@ -0,0 +1,138 @@
+use std::{
+    any::Any,
+    cell::Cell,
+    fmt::Display,
+    panic::{self, AssertUnwindSafe},
+    sync::Once,
+};
+
+use crate::{
+    gba::{StepResult, GBA},
+    state::{trace::TraceFormat, CpuState},
+};
+
+/// Number of instructions kept in a report, the CPU history holds more.
+pub const BACKTRACE_LENGTH: usize = 32;
+
+/// What the debugger keeps of a panic inside the emulator so it can be
+/// inspected instead of ending the process.
+#[derive(Clone, Debug)]
+pub struct PanicReport {
+    pub message: String,
+    /// The CPU as the panic left it.
+    pub state: CpuState,
+    /// States before each of the last instructions, oldest first. The last
+    /// one is the instruction that panicked.
+    pub backtrace: Vec<CpuState>,
+}
+
+impl PanicReport {
+    pub fn format_backtrace(&self) -> String {
+        self.backtrace
+            .iter()
+            .map(|state| TraceFormat::Mgba.format(state))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+impl Display for PanicReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Emulator panicked: {}\n{}",
+            self.message,
+            TraceFormat::Mgba.format(&self.state)
+        )
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        return message.to_string();
+    }
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(_) => String::from("unknown panic"),
+    }
+}
+
+thread_local! {
+    static CAPTURING: Cell<bool> = const { Cell::new(false) };
+}
+
+static QUIET_HOOK: Once = Once::new();
+
+/// Wraps the panic hook, once, so it stays quiet for panics that
+/// `step_catching_panic` turns into a report and prints everything else.
+fn install_quiet_hook() {
+    QUIET_HOOK.call_once(|| {
+        let previous_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !CAPTURING.get() {
+                previous_hook(info);
+            }
+        }));
+    });
+}
+
+/// Steps the emulator, turning a panic into a report. The emulator is left
+/// in whatever state the panic interrupted. The report replaces the message
+/// the panic hook would print over the debugger's screen. Only catches
+/// anything in builds that unwind on panic.
+pub fn step_catching_panic(gba: &mut GBA) -> Result<StepResult, Box<PanicReport>> {
+    install_quiet_hook();
+    CAPTURING.set(true);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| gba.step()));
+    CAPTURING.set(false);
+    result.map_err(|payload| {
+        Box::new(PanicReport {
+            message: panic_message(payload),
+            state: gba.cpu.cpu_state(),
+            backtrace: gba.cpu.recent_states(BACKTRACE_LENGTH),
+        })
+    })
+}
+
+#[cfg(test)]
+mod panic_capture_tests {
+    use crate::{
+        debugger::{debugger::Debugger, terminal_commands::parse_command},
+        memory::memory::GBAMemory,
+        utils::testing::load_arm_program,
+    };
+
+    fn run_command(debugger: &mut Debugger, command: &str) -> String {
+        debugger.terminal_buffer = String::from(command);
+        parse_command(debugger).unwrap_or_else(|err| err.to_string())
+    }
+
+    #[test]
+    fn internal_error_keeps_the_session_with_a_backtrace() {
+        let mut debugger = Debugger::with_memory(GBAMemory::new());
+        debugger.open_on_panic = true;
+        load_arm_program(&mut debugger.cpu, 0x3000000, &[
+            0xe3a00001, // mov r0, #1
+            0xe2800001, // add r0, r0, #1
+            0xe2800001, // add r0, r0, #1
+            0xe2800001, // add r0, r0, #1
+        ]);
+        run_command(&mut debugger, "next 3");
+        assert!(debugger.panic_report.is_none());
+
+        // no mode has these bits, so the next step panics decoding them
+        debugger.cpu.cpu.cpsr &= !0x1F;
+        let output = run_command(&mut debugger, "next 5");
+
+        let report = debugger.panic_report.clone().unwrap();
+        assert!(report.message.contains("Impossible cpsr value"));
+        assert!(output.starts_with("Emulator panicked"));
+        assert_eq!(report.backtrace.len(), 4);
+        assert_eq!(report.backtrace[3].cpsr & 0x1F, 0);
+        assert_eq!(report.backtrace[3].register(0), 3);
+        assert_eq!(
+            run_command(&mut debugger, "backtrace").lines().count(),
+            report.backtrace.len()
+        );
+    }
+}
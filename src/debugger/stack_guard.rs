@@ -0,0 +1,160 @@
+use std::{collections::HashMap, fmt::Display, ops::RangeInclusive};
+
+use crate::{arm7tdmi::cpu::CPU, types::WORD};
+
+const IWRAM_STACK_REGION: RangeInclusive<WORD> = 0x300_0000..=0x300_7FFF;
+const MODE_BITS: WORD = 0x1F;
+const SYSTEM_MODE: WORD = 0b11111;
+const USER_MODE: WORD = 0b10000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StackViolation {
+    /// SP left the configured stack region.
+    OutOfRegion(WORD),
+    /// SP was popped above where the stack started.
+    Underflow { sp: WORD, base: WORD },
+}
+
+impl Display for StackViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StackViolation::OutOfRegion(sp) => {
+                write!(f, "Stack pointer {:#X} left the stack region", sp)
+            }
+            StackViolation::Underflow { sp, base } => {
+                write!(f, "Stack underflow: SP {:#X} is above its base {:#X}", sp, base)
+            }
+        }
+    }
+}
+
+/// Warns about stack corruption in homebrew: SP leaving `region`, or SP
+/// rising above the value it had when the guard first saw the current
+/// mode's stack. Stacks grow down, so that value is the push base.
+#[derive(Debug)]
+pub struct StackGuard {
+    pub enabled: bool,
+    pub region: RangeInclusive<WORD>,
+    /// Push base per banked stack, keyed by mode bits.
+    bases: HashMap<WORD, WORD>,
+    violation: Option<StackViolation>,
+}
+
+impl Default for StackGuard {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            region: IWRAM_STACK_REGION,
+            bases: HashMap::new(),
+            violation: None,
+        }
+    }
+}
+
+impl StackGuard {
+    /// Checks SP after a step. A violation is reported once, on the step
+    /// where SP goes bad, rather than on every step it stays bad.
+    pub fn observe(&mut self, cpu: &CPU) -> Option<StackViolation> {
+        if !self.enabled {
+            return None;
+        }
+
+        let sp = cpu.get_sp();
+        let bank = match cpu.cpsr & MODE_BITS {
+            SYSTEM_MODE => USER_MODE,
+            mode => mode,
+        };
+        let base = *self.bases.entry(bank).or_insert(sp);
+
+        let violation = if !self.region.contains(&sp) {
+            Some(StackViolation::OutOfRegion(sp))
+        } else if sp > base {
+            Some(StackViolation::Underflow { sp, base })
+        } else {
+            None
+        };
+
+        let newly_violated = violation.is_some() && self.violation.is_none();
+        self.violation = violation;
+        if newly_violated {
+            violation
+        } else {
+            None
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.bases.clear();
+        self.violation = None;
+    }
+}
+
+#[cfg(test)]
+mod stack_guard_tests {
+    use crate::{gba::GBA, utils::testing::load_arm_program};
+
+    use super::{StackGuard, StackViolation};
+
+    fn enabled_guard() -> StackGuard {
+        StackGuard {
+            enabled: true,
+            region: 0x3007000..=0x3007FFF,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sp_leaving_the_region_warns_once() {
+        let mut gba = GBA::new_no_bios();
+        gba.cpu.set_sp(0x3007010);
+        load_arm_program(&mut gba, 0x3000000, &[
+            0xe24dd010, // sub sp, sp, 0x10
+            0xe24dd010, // sub sp, sp, 0x10
+            0xe24dd010, // sub sp, sp, 0x10
+        ]);
+        let mut guard = enabled_guard();
+
+        assert_eq!(guard.observe(&gba.cpu), None);
+        gba.step();
+        assert_eq!(guard.observe(&gba.cpu), None);
+        gba.step();
+        assert_eq!(guard.observe(&gba.cpu), Some(StackViolation::OutOfRegion(0x3006FF0)));
+        gba.step();
+        assert_eq!(guard.observe(&gba.cpu), None);
+    }
+
+    #[test]
+    fn popping_past_the_push_base_warns() {
+        let mut gba = GBA::new_no_bios();
+        gba.cpu.set_sp(0x3007F00);
+        load_arm_program(&mut gba, 0x3000000, &[
+            0xe92d0003, // push {r0, r1}
+            0xe8bd0003, // pop {r0, r1}
+            0xe8bd0003, // pop {r0, r1}
+        ]);
+        let mut guard = enabled_guard();
+        guard.observe(&gba.cpu);
+
+        gba.step();
+        assert_eq!(guard.observe(&gba.cpu), None);
+        gba.step();
+        assert_eq!(guard.observe(&gba.cpu), None);
+        gba.step();
+        assert_eq!(
+            guard.observe(&gba.cpu),
+            Some(StackViolation::Underflow {
+                sp: 0x3007F08,
+                base: 0x3007F00
+            })
+        );
+    }
+
+    #[test]
+    fn disabled_guard_never_warns() {
+        let mut gba = GBA::new_no_bios();
+        gba.cpu.set_sp(0x2000000);
+        let mut guard = StackGuard::default();
+
+        assert_eq!(guard.observe(&gba.cpu), None);
+    }
+}
@@ -1,7 +1,19 @@
 use super::{
     breakpoints::{BreakType, Breakpoint, TriggeredWatchpoints},
     debugger::Debugger,
+    loop_detector::LoopAction,
+    watch_expressions::{WatchExpression, WatchExpressionError},
 };
+use crate::arm7tdmi::{
+    cpu::InstructionMode,
+    disassembler::{export_disassembly, ModeMap},
+    interrupts::Interrupt,
+};
+use crate::graphics::layers::Layer;
+use crate::io::input_script::InputScript;
+use crate::memory::io_report::io_report;
+use crate::memory::rom_write_guard::RomWriteAction;
+use crate::state::{mgba::load_mgba_state, trace::TraceFormat};
 use crate::utils::utils::{try_parse_num, try_parse_reg, ParsingError};
 use std::fmt::Display;
 
@@ -40,7 +52,7 @@ pub struct TerminalHistoryEntry {
     pub result: String,
 }
 
-pub const TERMINAL_COMMANDS: [TerminalCommand; 8] = [
+pub const TERMINAL_COMMANDS: [TerminalCommand; 26] = [
     TerminalCommand {
         name: "next",
         _arguments: 1,
@@ -59,6 +71,12 @@ pub const TERMINAL_COMMANDS: [TerminalCommand; 8] = [
         _description: "Sets a breakpoint at specified address",
         handler: set_breakpoint_handler,
     },
+    TerminalCommand {
+        name: "breakr",
+        _arguments: 2,
+        _description: "Breaks when execution enters the specified address range",
+        handler: set_pc_range_breakpoint_handler,
+    },
     TerminalCommand {
         name: "delete",
         _arguments: 1,
@@ -83,12 +101,114 @@ pub const TERMINAL_COMMANDS: [TerminalCommand; 8] = [
         _description: "Sets a watch point on an address range",
         handler: set_watch_address_range_handler,
     },
+    TerminalCommand {
+        name: "watchread",
+        _arguments: 2,
+        _description: "Sets a watch point on reads from an address range",
+        handler: set_watch_read_handler,
+    },
+    TerminalCommand {
+        name: "watchwrite",
+        _arguments: 2,
+        _description: "Sets a watch point on writes to an address range",
+        handler: set_watch_write_handler,
+    },
     TerminalCommand {
         name: "mem",
         _arguments: 1,
         _description: "Sets start memory address",
         handler: set_mem_start,
     },
+    TerminalCommand {
+        name: "iotrace",
+        _arguments: 1,
+        _description: "IO access trace: on, off, filter <start> <end>, nofilter, show [count], clear",
+        handler: io_trace_handler,
+    },
+    TerminalCommand {
+        name: "loopdetect",
+        _arguments: 2,
+        _description: "Detects self-branch spin loops: off, warn or halt, with an optional step threshold",
+        handler: loop_detect_handler,
+    },
+    TerminalCommand {
+        name: "stackguard",
+        _arguments: 3,
+        _description: "Warns when SP leaves <low> <high> or pops past its base: on [low high] or off",
+        handler: stack_guard_handler,
+    },
+    TerminalCommand {
+        name: "romwrite",
+        _arguments: 1,
+        _description: "Action on writes to ROM: ignore, log or break; show lists logged writes",
+        handler: rom_write_handler,
+    },
+    TerminalCommand {
+        name: "inputscript",
+        _arguments: 1,
+        _description: "Loads a keypad input script of <frame> <button> <press|release> lines",
+        handler: input_script_handler,
+    },
+    TerminalCommand {
+        name: "disasm",
+        _arguments: 4,
+        _description: "Disassembles <start> <end> to <file>, optionally forcing arm or thumb",
+        handler: disassemble_handler,
+    },
+    TerminalCommand {
+        name: "watchexpr",
+        _arguments: 1,
+        _description: "Evaluates an expression like [r0 + 4] or *0x3000000 after every step",
+        handler: watch_expression_handler,
+    },
+    TerminalCommand {
+        name: "unwatchexpr",
+        _arguments: 1,
+        _description: "Removes a watch expression by its index",
+        handler: delete_watch_expression_handler,
+    },
+    TerminalCommand {
+        name: "irq",
+        _arguments: 1,
+        _description: "Requests an interrupt such as vblank, timer0 or keypad by setting its IF bit",
+        handler: irq_handler,
+    },
+    TerminalCommand {
+        name: "pixel",
+        _arguments: 2,
+        _description: "Pixel inspector: on, off, or <x> <y> to show the layer and palette index drawn there",
+        handler: pixel_handler,
+    },
+    TerminalCommand {
+        name: "traceformat",
+        _arguments: 1,
+        _description: "Trace line format: mgba, nba, custom <template> with {r0}..{pc} {cpsr} {spsr}, or native",
+        handler: trace_format_handler,
+    },
+    TerminalCommand {
+        name: "isolate",
+        _arguments: 2,
+        _description: "Saves the current frame with only <bg0-bg3|obj> drawn to a PPM <file>",
+        handler: isolate_layer_handler,
+    },
+    TerminalCommand {
+        name: "io",
+        _arguments: 0,
+        _description: "Dumps every IO register from DISPCNT to IME with its decoded fields",
+        handler: io_report_handler,
+    },
+    TerminalCommand {
+        name: "backtrace",
+        _arguments: 0,
+        _description: "Shows the instructions leading up to a caught emulator panic",
+        handler: backtrace_handler,
+    },
+    TerminalCommand {
+        name: "mgbastate",
+        _arguments: 1,
+        _description: "Loads the CPU and memory from an uncompressed mGBA savestate <file>",
+        handler: mgba_state_handler,
+    },
 ];
 
 fn find_command(command: &str) -> Result<&TerminalCommand, TerminalCommandErrors> {
@@ -121,20 +241,43 @@ fn next_handler(debugger: &mut Debugger, args: Vec<&str>) -> Result<String, Term
         None => 1,
     };
 
-    let cpu = &mut debugger.cpu;
+    let mut warning = String::new();
     for _ in 0..num_executions {
-        cpu.step();
+        // drop hits from the memory view and commands between steps
+        debugger.triggered_watchpoints.borrow_mut().clear();
+        let Some(step) = debugger.step() else {
+            let report = debugger.panic_report.as_ref().unwrap();
+            return Ok(format!("{}\nUse backtrace to list the last instructions", report));
+        };
+        let executed_pc = step.executed_pc;
+        let cpu = &debugger.cpu;
+        if debugger.loop_detector.observe(&cpu.cpu) {
+            let message = format!("Infinite loop detected at {:#X}", executed_pc);
+            if debugger.loop_detector.action == LoopAction::Halt {
+                return Ok(message);
+            }
+            warning = message;
+        }
+        if let Some(violation) = debugger.stack_guard.observe(&cpu.cpu) {
+            warning = format!("{} at {:#X}", violation, executed_pc);
+        }
+        let next_pc = cpu.cpu.next_executed_pc();
         for breakpoint in debugger.breakpoints.borrow().iter() {
             match breakpoint.break_type {
-                BreakType::Break(break_pc) => {
-                    if cpu.cpu.get_pc() == break_pc {
-                        return Ok(String::from("Breakpoint encountered"));
-                    }
+                BreakType::Break(_) | BreakType::PcRange(..)
+                    if breakpoint.break_type.stops_before(next_pc)
+                        || breakpoint.break_type.pc_range_entered(executed_pc, next_pc) =>
+                {
+                    return Ok(format!(
+                        "Breakpoint encountered {}\n{}",
+                        breakpoint.break_type,
+                        TraceFormat::Mgba.format(&cpu.cpu.cpu_state())
+                    ));
                 }
-                BreakType::WatchRegister(register, value) => {
-                    if cpu.cpu.get_register(register) == value {
-                        return Ok(format!("Watchpoint encountered {}", breakpoint.break_type));
-                    }
+                BreakType::WatchRegister(register, value)
+                    if cpu.cpu.get_register(register) == value =>
+                {
+                    return Ok(format!("Watchpoint encountered {}", breakpoint.break_type));
                 }
                 _ => {}
             }
@@ -142,8 +285,11 @@ fn next_handler(debugger: &mut Debugger, args: Vec<&str>) -> Result<String, Term
         let mut encountered_watchpoints = String::new();
         for watchpoint in debugger.triggered_watchpoints.borrow_mut().drain(..) {
             match watchpoint {
-                TriggeredWatchpoints::Address(address) => {
-                    encountered_watchpoints.push_str(&format!("Watchpoint encountered {:#X}\n", address));
+                TriggeredWatchpoints::Address(address, access) => {
+                    encountered_watchpoints.push_str(&format!(
+                        "Watchpoint encountered {} {:#X} at {:#X}\n",
+                        access, address, executed_pc
+                    ));
                 }
                 TriggeredWatchpoints::Error(memory_error) =>{
                     encountered_watchpoints.push_str(&format!("Memory Error encountered\n{}\n", memory_error));
@@ -152,11 +298,12 @@ fn next_handler(debugger: &mut Debugger, args: Vec<&str>) -> Result<String, Term
         }
 
         if !encountered_watchpoints.is_empty() {
+            encountered_watchpoints.push_str(&TraceFormat::Mgba.format(&cpu.cpu.cpu_state()));
             return Ok(encountered_watchpoints);
         }
     }
 
-    Ok(String::new())
+    Ok(warning)
 }
 
 fn quit_handler(
@@ -187,6 +334,23 @@ fn set_breakpoint_handler(
     Ok(format!("Breakpoint set at address {:#X}", breakpoint))
 }
 
+fn set_pc_range_breakpoint_handler(
+    debugger: &mut Debugger,
+    args: Vec<&str>,
+) -> Result<String, TerminalCommandErrors> {
+    let [start, end, ..] = args[..] else {
+        return Err(TerminalCommandErrors::NotEnoughArguments);
+    };
+    let start = try_parse_num(start)?;
+    let end = try_parse_num(end)?;
+
+    debugger
+        .breakpoints
+        .borrow_mut()
+        .push(Breakpoint::new(BreakType::PcRange(start, end)));
+    Ok(format!("Breakpoint set for PC range {:#X}-{:#X}", start, end))
+}
+
 fn delete_breakpoint_handler(
     debugger: &mut Debugger,
     args: Vec<&str>,
@@ -233,6 +397,13 @@ fn list_breakpoint_handler(
             }
             BreakType::WatchAddress(address, address2) => breakpoint_list
                 .push_str(format!("{}: watch address: {:#X}-{:#X}\n", i + 1, address, address2).as_str()),
+            BreakType::WatchRead(start, end) => breakpoint_list
+                .push_str(format!("{}: watch read: {:#X}-{:#X}\n", i + 1, start, end).as_str()),
+            BreakType::WatchWrite(start, end) => breakpoint_list
+                .push_str(format!("{}: watch write: {:#X}-{:#X}\n", i + 1, start, end).as_str()),
+            BreakType::PcRange(start, end) => {
+                breakpoint_list.push_str(format!("{}: breakr {:#X}-{:#X}\n", i + 1, start, end).as_str())
+            }
         }
     }
 
@@ -266,19 +437,25 @@ fn set_watchpoint_handler(
     ))
 }
 
+/// Parses `<start> [end]`, where a missing or unparsable end watches
+/// only the start address.
+fn parse_address_range(args: &[&str]) -> Result<(usize, usize), TerminalCommandErrors> {
+    let Some(start) = args.first() else {
+        return Err(TerminalCommandErrors::NotEnoughArguments);
+    };
+    let start = try_parse_num(start)?;
+    let end = args
+        .get(1)
+        .and_then(|end| try_parse_num(end).ok())
+        .unwrap_or(start);
+    Ok((start, end))
+}
+
 fn set_watch_address_range_handler(
     debugger: &mut Debugger,
     args: Vec<&str>,
 ) -> Result<String, TerminalCommandErrors> {
-    if args.len() < 1 {
-        return Err(TerminalCommandErrors::NotEnoughArguments);
-    }
-    let address1 = try_parse_num(args[0])?;
-    let address2 = if args.len() >= 2 {
-        try_parse_num(args[1]).unwrap_or(address1)
-    } else {
-        address1
-    };
+    let (address1, address2) = parse_address_range(&args)?;
 
     debugger
         .breakpoints
@@ -290,6 +467,32 @@ fn set_watch_address_range_handler(
     ))
 }
 
+fn set_watch_read_handler(
+    debugger: &mut Debugger,
+    args: Vec<&str>,
+) -> Result<String, TerminalCommandErrors> {
+    let (start, end) = parse_address_range(&args)?;
+
+    debugger
+        .breakpoints
+        .borrow_mut()
+        .push(Breakpoint::new(BreakType::WatchRead(start, end)));
+    Ok(format!("Read watchpoint set for range {:#X}-{:#X}", start, end))
+}
+
+fn set_watch_write_handler(
+    debugger: &mut Debugger,
+    args: Vec<&str>,
+) -> Result<String, TerminalCommandErrors> {
+    let (start, end) = parse_address_range(&args)?;
+
+    debugger
+        .breakpoints
+        .borrow_mut()
+        .push(Breakpoint::new(BreakType::WatchWrite(start, end)));
+    Ok(format!("Write watchpoint set for range {:#X}-{:#X}", start, end))
+}
+
 fn set_mem_start(
     debugger: &mut Debugger,
     args: Vec<&str>,
@@ -303,3 +506,321 @@ fn set_mem_start(
 
     Ok(String::new())
 }
+
+fn io_trace_handler(
+    debugger: &mut Debugger,
+    args: Vec<&str>,
+) -> Result<String, TerminalCommandErrors> {
+    let Some(subcommand) = args.first() else {
+        return Err(TerminalCommandErrors::NotEnoughArguments);
+    };
+    let mut trace = debugger.cpu.memory.io_trace().borrow_mut();
+
+    match *subcommand {
+        "on" => {
+            trace.enabled = true;
+            Ok(String::from("IO trace enabled"))
+        }
+        "off" => {
+            trace.enabled = false;
+            Ok(String::from("IO trace disabled"))
+        }
+        "filter" => {
+            if args.len() < 3 {
+                return Err(TerminalCommandErrors::NotEnoughArguments);
+            }
+            let start: usize = try_parse_num(args[1])?;
+            let end: usize = try_parse_num(args[2])?;
+            trace.set_filter(start, end);
+            Ok(format!("IO trace filtered to {:#X}-{:#X}", start, end))
+        }
+        "nofilter" => {
+            trace.clear_filter();
+            Ok(String::from("IO trace filter removed"))
+        }
+        "show" => {
+            let count = match args.get(1) {
+                Some(value) => try_parse_num(value)?,
+                None => 20,
+            };
+            let entries = trace.entries();
+            let mut output = match trace.filter() {
+                Some((start, end)) => format!("Filtered to {:#X}-{:#X}\n", start, end),
+                None => String::new(),
+            };
+            for access in entries.iter().skip(entries.len().saturating_sub(count)) {
+                output.push_str(&format!("{access}\n"));
+            }
+            Ok(output)
+        }
+        "clear" => {
+            trace.clear();
+            Ok(String::from("IO trace cleared"))
+        }
+        _ => Err(TerminalCommandErrors::InvalidArgument(subcommand.to_string())),
+    }
+}
+
+fn loop_detect_handler(
+    debugger: &mut Debugger,
+    args: Vec<&str>,
+) -> Result<String, TerminalCommandErrors> {
+    let Some(action) = args.first() else {
+        return Err(TerminalCommandErrors::NotEnoughArguments);
+    };
+    let detector = &mut debugger.loop_detector;
+    detector.action = match *action {
+        "off" => LoopAction::Off,
+        "warn" => LoopAction::Warn,
+        "halt" => LoopAction::Halt,
+        _ => return Err(TerminalCommandErrors::InvalidArgument(action.to_string())),
+    };
+    if let Some(threshold) = args.get(1) {
+        detector.threshold = try_parse_num(threshold)?;
+    }
+    detector.reset();
+
+    Ok(format!(
+        "Loop detection set to {} after {} repeated steps",
+        detector.action, detector.threshold
+    ))
+}
+
+fn stack_guard_handler(
+    debugger: &mut Debugger,
+    args: Vec<&str>,
+) -> Result<String, TerminalCommandErrors> {
+    let Some(state) = args.first() else {
+        return Err(TerminalCommandErrors::NotEnoughArguments);
+    };
+    let guard = &mut debugger.stack_guard;
+    guard.enabled = match *state {
+        "on" => true,
+        "off" => false,
+        _ => return Err(TerminalCommandErrors::InvalidArgument(state.to_string())),
+    };
+    if let [_, low, high, ..] = args[..] {
+        let low: u32 = try_parse_num(low)?;
+        let high: u32 = try_parse_num(high)?;
+        guard.region = low..=high;
+    }
+    guard.reset();
+
+    if !guard.enabled {
+        return Ok(String::from("Stack guard disabled"));
+    }
+    Ok(format!(
+        "Stack guard watching SP in {:#X}-{:#X}",
+        guard.region.start(),
+        guard.region.end()
+    ))
+}
+
+fn rom_write_handler(
+    debugger: &mut Debugger,
+    args: Vec<&str>,
+) -> Result<String, TerminalCommandErrors> {
+    let Some(action) = args.first() else {
+        return Err(TerminalCommandErrors::NotEnoughArguments);
+    };
+    let guard = debugger.cpu.memory.rom_write_guard();
+    guard.action = match *action {
+        "ignore" => RomWriteAction::Ignore,
+        "log" => RomWriteAction::Log,
+        "break" => RomWriteAction::Break,
+        "show" => {
+            let mut output = String::new();
+            for write in guard.writes() {
+                output.push_str(&format!("{write}\n"));
+            }
+            return Ok(output);
+        }
+        _ => return Err(TerminalCommandErrors::InvalidArgument(action.to_string())),
+    };
+    guard.clear();
+
+    Ok(format!("ROM writes set to {}", guard.action))
+}
+
+fn trace_format_handler(
+    debugger: &mut Debugger,
+    args: Vec<&str>,
+) -> Result<String, TerminalCommandErrors> {
+    if args.is_empty() {
+        return Err(TerminalCommandErrors::NotEnoughArguments);
+    }
+    let format = args.join(" ");
+    debugger.cpu.cpu.trace_format = match format.as_str() {
+        "native" => None,
+        _ => Some(
+            format
+                .parse::<TraceFormat>()
+                .map_err(TerminalCommandErrors::InvalidArgument)?,
+        ),
+    };
+
+    Ok(format!("Trace format set to {}", format))
+}
+
+fn irq_handler(debugger: &mut Debugger, args: Vec<&str>) -> Result<String, TerminalCommandErrors> {
+    let Some(source) = args.first() else {
+        return Err(TerminalCommandErrors::NotEnoughArguments);
+    };
+    let interrupt = source
+        .parse::<Interrupt>()
+        .map_err(TerminalCommandErrors::InvalidArgument)?;
+    debugger.cpu.request_interrupt(interrupt);
+
+    Ok(format!(
+        "Requested {} interrupt, IF = {:#06X}",
+        interrupt,
+        debugger.cpu.interrupt_flags()
+    ))
+}
+
+fn pixel_handler(debugger: &mut Debugger, args: Vec<&str>) -> Result<String, TerminalCommandErrors> {
+    let ppu = &mut debugger.cpu.ppu;
+    match args[..] {
+        ["on"] => {
+            ppu.record_pixel_sources = true;
+            Ok(String::from("Pixel inspector enabled from the next scanline"))
+        }
+        ["off"] => {
+            ppu.record_pixel_sources = false;
+            Ok(String::from("Pixel inspector disabled"))
+        }
+        [x, y, ..] => {
+            let x: usize = try_parse_num(x)?;
+            let y: usize = try_parse_num(y)?;
+            let Some(source) = debugger.cpu.inspect_pixel(x, y) else {
+                return Err(TerminalCommandErrors::InvalidArgument(format!(
+                    "no pixel source for ({x}, {y}), is the inspector on?"
+                )));
+            };
+            let palette_index = match source.palette_index {
+                Some(index) => format!("palette index {index}"),
+                None => String::from("direct colour"),
+            };
+            Ok(format!(
+                "({x}, {y}): {:?} priority {}, {}",
+                source.layer, source.priority, palette_index
+            ))
+        }
+        [state] => Err(TerminalCommandErrors::InvalidArgument(state.to_string())),
+        [] => Err(TerminalCommandErrors::NotEnoughArguments),
+    }
+}
+
+fn isolate_layer_handler(
+    debugger: &mut Debugger,
+    args: Vec<&str>,
+) -> Result<String, TerminalCommandErrors> {
+    let [layer, path, ..] = args[..] else {
+        return Err(TerminalCommandErrors::NotEnoughArguments);
+    };
+    let layer: Layer = layer
+        .parse()
+        .map_err(TerminalCommandErrors::InvalidArgument)?;
+    debugger
+        .cpu
+        .export_isolated_layer(layer, path)
+        .map_err(|err| TerminalCommandErrors::InvalidArgument(err.to_string()))?;
+
+    Ok(format!("Saved {:?} to {}", layer, path))
+}
+
+fn io_report_handler(debugger: &mut Debugger, _args: Vec<&str>) -> Result<String, TerminalCommandErrors> {
+    Ok(io_report(debugger.cpu.memory.as_ref()))
+}
+
+fn backtrace_handler(debugger: &mut Debugger, _args: Vec<&str>) -> Result<String, TerminalCommandErrors> {
+    match &debugger.panic_report {
+        Some(report) => Ok(report.format_backtrace()),
+        None => Ok(String::from("No panic has been caught")),
+    }
+}
+
+fn mgba_state_handler(
+    debugger: &mut Debugger,
+    args: Vec<&str>,
+) -> Result<String, TerminalCommandErrors> {
+    let Some(path) = args.first() else {
+        return Err(TerminalCommandErrors::NotEnoughArguments);
+    };
+    let state = load_mgba_state(path)
+        .map_err(|err| TerminalCommandErrors::InvalidArgument(err.to_string()))?;
+    debugger.cpu.restore_state(&state);
+
+    Ok(format!("Loaded mGBA state from {}", path))
+}
+
+fn input_script_handler(
+    debugger: &mut Debugger,
+    args: Vec<&str>,
+) -> Result<String, TerminalCommandErrors> {
+    let Some(path) = args.first() else {
+        return Err(TerminalCommandErrors::NotEnoughArguments);
+    };
+    let script = InputScript::load(path)
+        .map_err(|err| TerminalCommandErrors::InvalidArgument(err.to_string()))?;
+    let event_count = script.events().len();
+    debugger.cpu.set_input_script(script);
+
+    Ok(format!("Loaded {} input events from {}", event_count, path))
+}
+
+fn disassemble_handler(
+    debugger: &mut Debugger,
+    args: Vec<&str>,
+) -> Result<String, TerminalCommandErrors> {
+    let [start, end, path, ..] = args[..] else {
+        return Err(TerminalCommandErrors::NotEnoughArguments);
+    };
+    let start: u32 = try_parse_num(start)?;
+    let end: u32 = try_parse_num(end)?;
+    let modes = match args.get(3) {
+        None => ModeMap::new(),
+        Some(&"arm") => ModeMap::new().with_range(start..end, InstructionMode::ARM),
+        Some(&"thumb") => ModeMap::new().with_range(start..end, InstructionMode::THUMB),
+        Some(mode) => return Err(TerminalCommandErrors::InvalidArgument(mode.to_string())),
+    };
+    export_disassembly(debugger.cpu.memory.as_ref(), start..end, &modes, path)
+        .map_err(|err| TerminalCommandErrors::InvalidArgument(err.to_string()))?;
+
+    Ok(format!("Disassembled {:#010X}..{:#010X} to {}", start, end, path))
+}
+
+fn watch_expression_handler(
+    debugger: &mut Debugger,
+    args: Vec<&str>,
+) -> Result<String, TerminalCommandErrors> {
+    if args.is_empty() {
+        return Err(TerminalCommandErrors::NotEnoughArguments);
+    }
+    let expression: WatchExpression = args
+        .join(" ")
+        .parse()
+        .map_err(|err: WatchExpressionError| TerminalCommandErrors::InvalidArgument(err.to_string()))?;
+    let gba = &debugger.cpu;
+    let value = expression.evaluate(&gba.cpu, gba.memory.as_ref());
+    let result = format!("Watching {} = {:#010X}", expression.source(), value);
+    debugger.watch_expressions.push(expression);
+
+    Ok(result)
+}
+
+fn delete_watch_expression_handler(
+    debugger: &mut Debugger,
+    args: Vec<&str>,
+) -> Result<String, TerminalCommandErrors> {
+    let Some(index) = args.first() else {
+        return Err(TerminalCommandErrors::NotEnoughArguments);
+    };
+    let index: usize = try_parse_num(index)?;
+    if index == 0 || index > debugger.watch_expressions.len() {
+        return Err(TerminalCommandErrors::InvalidArgument(index.to_string()));
+    }
+    let expression = debugger.watch_expressions.remove(index - 1);
+
+    Ok(format!("Removed watch expression {}", expression.source()))
+}
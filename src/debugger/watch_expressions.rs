@@ -0,0 +1,275 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::{
+    arm7tdmi::cpu::{CPU, LINK_REGISTER, PC_REGISTER, STACK_POINTER},
+    memory::memory::MemoryBus,
+    types::WORD,
+    utils::utils::try_parse_num,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WatchExpressionError {
+    InvalidToken(String),
+    UnexpectedToken(String),
+    UnexpectedEnd,
+}
+
+impl Display for WatchExpressionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WatchExpressionError::InvalidToken(token) => write!(f, "Invalid token: {}", token),
+            WatchExpressionError::UnexpectedToken(token) => {
+                write!(f, "Unexpected token: {}", token)
+            }
+            WatchExpressionError::UnexpectedEnd => "Unexpected end of expression".fmt(f),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(WORD),
+    Register(u32),
+    Plus,
+    Minus,
+    Star,
+    OpenBracket,
+    CloseBracket,
+    OpenParen,
+    CloseParen,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "{:#X}", value),
+            Token::Register(register) => write!(f, "r{}", register),
+            Token::Plus => "+".fmt(f),
+            Token::Minus => "-".fmt(f),
+            Token::Star => "*".fmt(f),
+            Token::OpenBracket => "[".fmt(f),
+            Token::CloseBracket => "]".fmt(f),
+            Token::OpenParen => "(".fmt(f),
+            Token::CloseParen => ")".fmt(f),
+        }
+    }
+}
+
+fn parse_word(word: &str) -> Result<Token, WatchExpressionError> {
+    let register = match word {
+        "sp" => Some(STACK_POINTER),
+        "lr" => Some(LINK_REGISTER),
+        "pc" => Some(PC_REGISTER as u32),
+        _ => word
+            .strip_prefix('r')
+            .and_then(|number| number.parse::<u32>().ok())
+            .filter(|register| *register <= 15),
+    };
+    if let Some(register) = register {
+        return Ok(Token::Register(register));
+    }
+    try_parse_num(word)
+        .map(Token::Number)
+        .map_err(|_| WatchExpressionError::InvalidToken(word.to_string()))
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, WatchExpressionError> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            ' ' => continue,
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Star,
+            '[' => Token::OpenBracket,
+            ']' => Token::CloseBracket,
+            '(' => Token::OpenParen,
+            ')' => Token::CloseParen,
+            c if c.is_ascii_alphanumeric() => {
+                let mut word = String::from(c);
+                while let Some(next) = chars.next_if(|next| next.is_ascii_alphanumeric()) {
+                    word.push(next);
+                }
+                parse_word(&word)?
+            }
+            c => return Err(WatchExpressionError::InvalidToken(c.to_string())),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expression {
+    Number(WORD),
+    Register(u32),
+    /// Word read from the address the inner expression evaluates to.
+    Dereference(Box<Expression>),
+    Add(Box<Expression>, Box<Expression>),
+    Subtract(Box<Expression>, Box<Expression>),
+}
+
+/// Recursive descent over
+///   sum     := operand (('+' | '-') operand)*
+///   operand := '*' operand | '[' sum ']' | '(' sum ')' | number | register
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), WatchExpressionError> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(WatchExpressionError::UnexpectedToken(token.to_string())),
+            None => Err(WatchExpressionError::UnexpectedEnd),
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expression, WatchExpressionError> {
+        let mut expression = self.operand()?;
+        loop {
+            match self.tokens.get(self.position) {
+                Some(Token::Plus) => {
+                    self.position += 1;
+                    expression = Expression::Add(Box::new(expression), Box::new(self.operand()?));
+                }
+                Some(Token::Minus) => {
+                    self.position += 1;
+                    expression =
+                        Expression::Subtract(Box::new(expression), Box::new(self.operand()?));
+                }
+                _ => return Ok(expression),
+            }
+        }
+    }
+
+    fn operand(&mut self) -> Result<Expression, WatchExpressionError> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Expression::Number(value)),
+            Some(Token::Register(register)) => Ok(Expression::Register(register)),
+            Some(Token::Star) => Ok(Expression::Dereference(Box::new(self.operand()?))),
+            Some(Token::OpenBracket) => {
+                let address = self.sum()?;
+                self.expect(Token::CloseBracket)?;
+                Ok(Expression::Dereference(Box::new(address)))
+            }
+            Some(Token::OpenParen) => {
+                let expression = self.sum()?;
+                self.expect(Token::CloseParen)?;
+                Ok(expression)
+            }
+            Some(token) => Err(WatchExpressionError::UnexpectedToken(token.to_string())),
+            None => Err(WatchExpressionError::UnexpectedEnd),
+        }
+    }
+}
+
+impl Expression {
+    fn evaluate(&self, cpu: &CPU, memory: &dyn MemoryBus) -> WORD {
+        match self {
+            Expression::Number(value) => *value,
+            Expression::Register(register) => cpu.get_register(*register),
+            Expression::Dereference(address) => {
+                memory.peeku32(address.evaluate(cpu, memory) as usize)
+            }
+            Expression::Add(left, right) => left
+                .evaluate(cpu, memory)
+                .wrapping_add(right.evaluate(cpu, memory)),
+            Expression::Subtract(left, right) => left
+                .evaluate(cpu, memory)
+                .wrapping_sub(right.evaluate(cpu, memory)),
+        }
+    }
+}
+
+/// A debugger watch such as `[r0 + 4]`, `r1 + r2` or `*0x3000000`.
+/// `[x]` and `*x` both read the word at address `x`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WatchExpression {
+    source: String,
+    expression: Expression,
+}
+
+impl FromStr for WatchExpression {
+    type Err = WatchExpressionError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+        };
+        let expression = parser.sum()?;
+        if let Some(token) = parser.next() {
+            return Err(WatchExpressionError::UnexpectedToken(token.to_string()));
+        }
+        Ok(Self {
+            source: source.trim().to_string(),
+            expression,
+        })
+    }
+}
+
+impl WatchExpression {
+    pub fn evaluate(&self, cpu: &CPU, memory: &dyn MemoryBus) -> WORD {
+        self.expression.evaluate(cpu, memory)
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+}
+
+#[cfg(test)]
+mod watch_expression_tests {
+    use rstest::rstest;
+
+    use crate::gba::GBA;
+
+    use super::{WatchExpression, WatchExpressionError};
+
+    #[test]
+    fn dereferenced_stack_pointer_plus_offset_evaluates_against_state() {
+        let mut gba = GBA::new_no_bios();
+        gba.cpu.set_register(13, 0x3000100);
+        gba.memory.writeu32(0x3000100, 0x1234);
+
+        let expression: WatchExpression = "[sp] + 4".parse().unwrap();
+
+        assert_eq!(expression.evaluate(&gba.cpu, gba.memory.as_ref()), 0x1238);
+    }
+
+    #[rstest]
+    #[case("r1 + r2", 0x30)]
+    #[case("[r0 + 4]", 0xBEEF)]
+    #[case("*0x3000004", 0xBEEF)]
+    #[case("*(r0 + 4) - 0xEF", 0xBE00)]
+    #[case("r1 - r2", 0xFFFF_FFF0)]
+    fn evaluates_expressions(#[case] source: &str, #[case] expected: u32) {
+        let mut gba = GBA::new_no_bios();
+        gba.cpu.set_register(0, 0x3000000);
+        gba.cpu.set_register(1, 0x10);
+        gba.cpu.set_register(2, 0x20);
+        gba.memory.writeu32(0x3000004, 0xBEEF);
+
+        let expression: WatchExpression = source.parse().unwrap();
+
+        assert_eq!(expression.evaluate(&gba.cpu, gba.memory.as_ref()), expected);
+    }
+
+    #[rstest]
+    #[case("[r0 + 4", WatchExpressionError::UnexpectedEnd)]
+    #[case("r0 r1", WatchExpressionError::UnexpectedToken(String::from("r1")))]
+    #[case("r16", WatchExpressionError::InvalidToken(String::from("r16")))]
+    #[case("r0 / 2", WatchExpressionError::InvalidToken(String::from("/")))]
+    fn rejects_malformed_expressions(#[case] source: &str, #[case] expected: WatchExpressionError) {
+        assert_eq!(source.parse::<WatchExpression>(), Err(expected));
+    }
+}
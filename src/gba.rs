@@ -1,32 +1,1281 @@
-use crate::memory::memory::MemoryBus;
-use crate::{arm7tdmi::cpu::CPU, memory::memory::GBAMemory};
+use crate::arm7tdmi::interrupts::{self, Exceptions, Interrupt};
+use crate::cheats::{Cheat, CheatDevice, CheatError};
+use crate::io::input_script::InputScript;
+use crate::io::keypad::{check_keypad_interrupt, set_keys, KeyInput};
+use crate::io::sound::{Sound, SoundConfig};
+use crate::io::timers::Timers;
+use crate::memory::dma::{DmaController, DmaTiming};
+use crate::memory::io_handlers::{HaltMode, IE, IF, IME};
+use crate::memory::memory::{BusState, MemoryBus};
+use crate::scheduler::{Event, Scheduler};
+use crate::state::{
+    savestate::{StateError, StateReader, StateWriter},
+    MachineState, MemoryRegion, RegionSnapshot,
+};
+use crate::types::{CYCLES, WORD};
+use crate::{
+    arm7tdmi::cpu::{CPUMode, InstructionMode, CPU},
+    memory::memory::GBAMemory,
+};
 
-use crate::graphics::ppu::PPU;
+use crate::graphics::display::FrameHandoff;
+use crate::graphics::layers::{Layer, PixelSource};
+use crate::graphics::screenshot::{color_to_rgb32, export_ppm};
+use crate::graphics::ppu::{PPU, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StepResult {
+    pub cycles: CYCLES,
+    pub executed_pc: WORD,
+    pub took_exception: Option<Exceptions>,
+}
+
+/// Outcome of `GBA::step_frame`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameResult {
+    Completed { instructions: u64 },
+    /// The frame was cut short by `frame_instruction_limit`; `scanline`
+    /// is how far the PPU had got.
+    InstructionLimitHit { instructions: u64, scanline: u64 },
+    /// The CPU entered stop mode, which turns the PPU off, so the frame
+    /// won't finish until a keypad, serial or cartridge interrupt wakes it.
+    Stopped { instructions: u64, scanline: u64 },
+}
 
 pub struct GBA {
     pub cpu: CPU,
     pub memory: Box<dyn MemoryBus>,
     pub ppu: PPU,
+    pub cheats: Vec<Cheat>,
+    pub halt_mode: Option<HaltMode>,
+    pub input_script: Option<InputScript>,
+    /// Keys from the display, latched at the start of each step.
+    pub key_input: KeyInput,
+    /// Gets each frame as VBlank starts, for the display to show.
+    pub frame_output: Option<FrameHandoff>,
+    pub dma: DmaController,
+    pub timers: Timers,
+    pub sound: Sound,
+    pub scheduler: Scheduler,
+    synced: SyncedAt,
+    /// Caps the instructions `step_frame` executes, to bound a runaway
+    /// frame while debugging performance or desyncs.
+    pub frame_instruction_limit: Option<u64>,
+    /// The last frame `run_frame` returned, as 32-bit colours.
+    rendered_frame: Vec<u32>,
 }
 
+/// Scheduler time that each part of the system has been run up to.
+#[derive(Debug, Default)]
+struct SyncedAt {
+    ppu: u64,
+    timers: u64,
+    sound: u64,
+}
+
+/// Cycles a step takes to wake from halt, or while stopped.
+const HALTED_STEP_CYCLES: CYCLES = 4;
+const STOP_WAKE_INTERRUPTS: u16 =
+    Interrupt::Keypad.bit() | Interrupt::Serial.bit() | Interrupt::GamePak.bit();
+
 
 impl GBA {
     pub fn new(bios: String, rom: String) -> Self {
         let mut memory = GBAMemory::new();
         memory.initialize_bios(bios).unwrap();
         memory.initialize_rom(rom).unwrap();
+        Self::with_memory(memory)
+    }
+
+    pub fn new_no_bios() -> Self {
+        Self::with_memory(GBAMemory::new())
+    }
+
+    pub fn with_memory(memory: Box<dyn MemoryBus>) -> Self {
         let mut gba = Self {
             memory,
             cpu: CPU::new(),
-            ppu: PPU::default()
+            ppu: PPU::default(),
+            cheats: Vec::new(),
+            halt_mode: None,
+            input_script: None,
+            key_input: KeyInput::default(),
+            frame_output: None,
+            dma: DmaController::default(),
+            timers: Timers::default(),
+            sound: Sound::default(),
+            scheduler: Scheduler::default(),
+            synced: SyncedAt::default(),
+            frame_instruction_limit: None,
+            rendered_frame: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
         };
         gba.cpu.flush_pipeline(&mut gba.memory);
+        gba.reschedule();
         gba
     }
 
-    pub fn step(&mut self) {
+    /// Registers a cheat code. RAM writes are re-applied at the start of
+    /// every VBlank; ROM patches are applied once.
+    pub fn add_cheat(&mut self, code: &str, device: CheatDevice, encrypted: bool) -> Result<Cheat, CheatError> {
+        let cheat = Cheat::parse(code, device, encrypted)?;
+        match cheat {
+            Cheat::RomPatch { .. } => cheat.apply(&mut self.memory),
+            _ => self.cheats.push(cheat),
+        }
+        Ok(cheat)
+    }
+
+    /// Captures the CPU and RAM contents for comparison with `diff_states`.
+    pub fn capture_state(&self) -> MachineState {
+        MachineState {
+            cpu: self.cpu.cpu_state(),
+            memory: MemoryRegion::ALL
+                .iter()
+                .map(|&region| RegionSnapshot {
+                    region,
+                    bytes: self.memory.region_snapshot(region),
+                })
+                .collect(),
+        }
+    }
+
+    /// Loads the CPU and RAM from a `MachineState`, such as one imported
+    /// from mGBA. An empty pipeline is refetched from the instruction the
+    /// PC is two ahead of. The PPU, timers and sound keep running from
+    /// where they are, with timers reloaded from the restored IO registers.
+    pub fn restore_state(&mut self, state: &MachineState) {
+        self.cpu.restore_cpu_state(&state.cpu);
+        for snapshot in &state.memory {
+            self.memory.restore_region(snapshot.region, &snapshot.bytes);
+        }
+        self.halt_mode = None;
+        self.cpu.intr_wait = false;
+        if state.cpu.prefetch.contains(&None) {
+            let pc = self.cpu.get_pc();
+            self.cpu.set_pc(pc.wrapping_sub(2 * self.cpu.instruction_size()));
+            self.cpu.flush_pipeline(&mut self.memory);
+        }
+        self.reschedule();
+    }
+
+    /// Snapshots the whole machine for `load_state` to resume from: the
+    /// CPU, the scheduler clock and how far each part has been run, the
+    /// PPU, timers, DMA and sound state, every RAM region and the state
+    /// the bus keeps outside of them.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.cpu(&self.cpu.cpu_state());
+        writer.halt_mode(self.halt_mode);
+        writer.flag(self.cpu.intr_wait);
+        writer.u64(self.scheduler.now());
+        writer.u64(self.synced.ppu);
+        writer.u64(self.synced.timers);
+        writer.u64(self.synced.sound);
+        self.ppu.save_state(&mut writer);
+        self.timers.save_state(&mut writer);
+        self.dma.save_state(&mut writer);
+        self.sound.save_state(&mut writer);
+        for region in MemoryRegion::ALL {
+            writer.region(&self.memory.region_snapshot(region));
+        }
+        self.memory.bus_state().save_state(&mut writer);
+        writer.finish()
+    }
+
+    /// Restores a snapshot from `save_state`. The whole snapshot is checked
+    /// before anything is changed, so on error the machine is untouched.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::new(bytes)?;
+        let cpu = reader.cpu()?;
+        let halt_mode = reader.halt_mode()?;
+        let intr_wait = reader.flag()?;
+        let now = reader.u64()?;
+        let mut synced = SyncedAt::default();
+        for synced_at in [&mut synced.ppu, &mut synced.timers, &mut synced.sound] {
+            let offset = reader.offset();
+            *synced_at = match reader.u64()? {
+                at if at <= now => at,
+                _ => return Err(StateError::InvalidValue { offset }),
+            };
+        }
+        let mut ppu = PPU::default();
+        ppu.load_state(&mut reader)?;
+        let mut timers = Timers::default();
+        timers.load_state(&mut reader)?;
+        let mut dma = DmaController::default();
+        dma.load_state(&mut reader)?;
+        let mut sound = Sound::default();
+        sound.load_state(&mut reader)?;
+        let mut regions = Vec::with_capacity(MemoryRegion::ALL.len());
+        for region in MemoryRegion::ALL {
+            let expected = self.memory.region_snapshot(region).len();
+            regions.push((region, reader.region(region, expected)?));
+        }
+        let mut bus_state = BusState::default();
+        bus_state.load_state(&mut reader)?;
+
+        self.cpu.restore_cpu_state(&cpu);
+        self.halt_mode = halt_mode;
+        self.cpu.intr_wait = intr_wait;
+        self.ppu.restore(ppu);
+        self.timers = timers;
+        self.dma = dma;
+        self.sound.restore(sound);
+        for (region, bytes) in regions {
+            self.memory.restore_region(region, bytes);
+        }
+        // after the IO region, since writing WAITCNT flushes the prefetcher
+        self.memory.restore_bus_state(bus_state);
+        self.scheduler = Scheduler::starting_at(now);
+        self.synced = synced;
+        self.schedule_events();
+        Ok(())
+    }
+
+    /// Stable FNV-1a hash of the last composited frame, for detecting
+    /// rendering changes. Pixels are hashed in screen order as 15-bit
+    /// colours, so the result only depends on what is displayed.
+    pub fn frame_hash(&self) -> u64 {
+        const FNV_OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+
+        let mut hash = FNV_OFFSET_BASIS;
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let color = self.ppu.framebuffer[y * SCREEN_WIDTH + x] & 0x7FFF;
+                for byte in color.to_le_bytes() {
+                    hash ^= byte as u64;
+                    hash = hash.wrapping_mul(FNV_PRIME);
+                }
+            }
+        }
+        hash
+    }
+
+    pub fn instruction_mode(&self) -> InstructionMode {
+        self.cpu.get_instruction_mode()
+    }
+
+    /// Switches between ARM and Thumb and refetches the pipeline with the
+    /// new instruction width, so the next step executes the instruction
+    /// that was next in line at its new alignment.
+    pub fn set_instruction_mode(&mut self, instruction_mode: InstructionMode) {
+        if self.cpu.get_instruction_mode() == instruction_mode {
+            return;
+        }
+        let prefetched_bytes = match self.cpu.get_instruction_mode() {
+            InstructionMode::ARM => 8,
+            InstructionMode::THUMB => 4,
+        };
+        let next_instruction = self.cpu.get_pc().wrapping_sub(prefetched_bytes);
+        self.cpu.set_instruction_mode(instruction_mode);
+        self.cpu.set_pc(match instruction_mode {
+            InstructionMode::ARM => next_instruction & !0x3,
+            InstructionMode::THUMB => next_instruction,
+        });
+        self.cpu.flush_pipeline(&mut self.memory);
+    }
+
+    /// Whether SWIs call the emulated BIOS functions instead of jumping
+    /// to the BIOS, for running without a BIOS image.
+    pub fn bios_hle(&self) -> bool {
+        self.cpu.bios_hle
+    }
+
+    pub fn set_bios_hle(&mut self, enabled: bool) {
+        self.cpu.bios_hle = enabled;
+    }
+
+    /// Samples for the time already run are produced in the old format.
+    pub fn set_sound_config(&mut self, config: SoundConfig) {
+        self.sync_sound();
+        self.sound.set_config(config);
+    }
+
+    pub fn cpu_mode(&self) -> CPUMode {
+        self.cpu.get_cpu_mode()
+    }
+
+    /// Changes the privilege mode. Banked registers are selected by the
+    /// mode bits, so r8-r14 switch to the new mode's bank immediately.
+    pub fn set_cpu_mode(&mut self, mode: CPUMode) {
+        self.cpu.set_mode(mode);
+    }
+
+    /// Whether IME lets enabled interrupts reach the CPU.
+    pub fn interrupt_master_enable(&self) -> bool {
+        self.memory.ppu_io_read(IME) & 1 > 0
+    }
+
+    /// The IE register.
+    pub fn interrupt_enable(&self) -> u16 {
+        self.memory.ppu_io_read(IE)
+    }
+
+    /// The IF register.
+    pub fn interrupt_flags(&self) -> u16 {
+        self.memory.ppu_io_read(IF)
+    }
+
+    /// Interrupts that are both requested in IF and enabled in IE. They
+    /// are only taken once IME is set and the CPSR I bit is clear.
+    pub fn pending_interrupts(&self) -> Vec<Interrupt> {
+        Interrupt::from_flags(self.interrupt_enable() & self.interrupt_flags())
+    }
+
+    /// Raises `interrupt` in IF as if its source had fired. It is taken
+    /// on the next step if IE, IME and the CPSR allow it.
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        interrupts::request_interrupt(self.memory.as_mut(), interrupt);
+    }
+
+    /// Feeds scripted keypad input. Events for the current frame are
+    /// applied immediately, the rest at the start of their frame.
+    pub fn set_input_script(&mut self, script: InputScript) {
+        script.apply_frame(self.ppu.frame_count, self.memory.as_mut());
+        self.input_script = Some(script);
+    }
+
+    pub fn step(&mut self) -> StepResult {
+        if let Some(keys) = self.key_input.take_keys() {
+            set_keys(self.memory.as_mut(), keys);
+            check_keypad_interrupt(self.memory.as_mut());
+        }
+        if let Some(halt_mode) = self.memory.take_halt_request() {
+            self.halt_mode = Some(halt_mode);
+            if halt_mode == HaltMode::Stop {
+                self.ppu.blank_screen();
+            }
+        }
+        if let Some(halt_mode) = self.halt_mode {
+            return self.step_halted(halt_mode);
+        }
+
+        if self.memory.vram_contention().enabled {
+            let framebuffer = self.ppu.scanned_out_framebuffer(self.memory.as_ref());
+            self.memory.vram_contention().set_framebuffer(framebuffer);
+        }
         let cpu_cycles = self.cpu.execute_cpu_cycle(&mut self.memory);
-        self.ppu
-            .advance_ppu(cpu_cycles, &mut self.memory);
+        self.scheduler.advance(cpu_cycles as u64);
+        let changes = self.memory.take_register_changes();
+        if changes.timers {
+            // the timers ran with their old settings up to here
+            self.sync_timers();
+            self.timers.latch(self.memory.as_ref());
+            self.schedule_timer_overflow();
+        }
+        if changes.dma {
+            self.scheduler.schedule(Event::DmaStart, 0);
+        }
+        self.run_due_events();
+        StepResult {
+            cycles: cpu_cycles,
+            executed_pc: self.cpu.last_executed_pc(),
+            took_exception: self.cpu.last_exception(),
+        }
+    }
+
+    fn advance_ppu(&mut self, cycles: CYCLES) {
+        let events = self.ppu.advance_ppu(cycles, &mut self.memory);
+        let mut dma_cycles = 0;
+        if events.hblank {
+            dma_cycles += self.dma.trigger(DmaTiming::HBlank, &mut self.memory);
+        }
+        if events.video_capture {
+            dma_cycles += self.dma.trigger_video_capture(&mut self.memory);
+        }
+        if events.video_capture_end {
+            self.dma.stop_video_capture(&mut self.memory);
+        }
+        if events.vblank {
+            dma_cycles += self.dma.trigger(DmaTiming::VBlank, &mut self.memory);
+            if let Some(frame_output) = &self.frame_output {
+                frame_output.publish(&self.ppu.framebuffer);
+            }
+            for cheat in &self.cheats {
+                cheat.apply(&mut self.memory);
+            }
+            if let Some(script) = &self.input_script {
+                script.apply_frame(self.ppu.frame_count, self.memory.as_mut());
+                check_keypad_interrupt(self.memory.as_mut());
+            }
+        }
+        self.scheduler.advance(dma_cycles as u64);
+    }
+
+    /// Runs the parts of the system with an event due up to the current
+    /// time. Everything else waits until its own next event.
+    fn run_due_events(&mut self) {
+        while let Some((_, event)) = self.scheduler.pop_due() {
+            match event {
+                Event::HBlank | Event::LineEnd => {
+                    self.sync_ppu();
+                    self.sync_sound();
+                    self.schedule_ppu_event();
+                }
+                Event::TimerOverflow => {
+                    self.sync_timers();
+                    self.schedule_timer_overflow();
+                }
+                Event::DmaStart => {
+                    let dma_cycles = self.dma.step(&mut self.memory);
+                    self.scheduler.advance(dma_cycles as u64);
+                }
+            }
+        }
+        // reads of TMxCNT_L up to the next step are made at this time
+        self.memory.timer_readout().now = self.scheduler.now();
+    }
+
+    /// Picks up state the scheduler didn't see change, after a reset or
+    /// loading a state, and schedules the events that follow from it.
+    fn reschedule(&mut self) {
+        let now = self.scheduler.now();
+        self.synced = SyncedAt {
+            ppu: now,
+            timers: now,
+            sound: now,
+        };
+        self.schedule_events();
+    }
+
+    /// Schedules the next event of each part of the system from the state
+    /// it was last synced to.
+    fn schedule_events(&mut self) {
+        self.timers.latch(self.memory.as_ref());
+        self.memory.timer_readout().now = self.scheduler.now();
+        self.schedule_ppu_event();
+        self.schedule_timer_overflow();
+        self.scheduler.schedule(Event::DmaStart, 0);
+    }
+
+    fn sync_ppu(&mut self) {
+        let cycles = self.scheduler.elapsed_since(&mut self.synced.ppu);
+        self.advance_ppu_by(cycles as u32);
+    }
+
+    /// Where the PPU is on its line now, which it may not have run up to.
+    fn ppu_position(&self) -> (u64, u64) {
+        self.ppu.position_after(self.scheduler.now() - self.synced.ppu)
+    }
+
+    /// The dot the PPU is drawing on the current line.
+    pub fn ppu_x(&self) -> u64 {
+        self.ppu_position().1
+    }
+
+    fn schedule_ppu_event(&mut self) {
+        let event = if self.ppu.in_hblank() { Event::LineEnd } else { Event::HBlank };
+        // a DMA may have run since the PPU was synced
+        let due = self.synced.ppu + self.ppu.cycles_until_next_boundary();
+        self.scheduler.schedule(event, due.saturating_sub(self.scheduler.now()));
+    }
+
+    /// Runs the timers up to now. Overflows play the next Direct Sound
+    /// samples, and a FIFO that runs low is refilled by its sound DMA.
+    fn sync_timers(&mut self) {
+        self.sync_sound();
+        let cycles = self.scheduler.elapsed_since(&mut self.synced.timers);
+        let overflows = self.timers.advance(cycles as u32, &mut self.memory);
+        let refills = self.sound.timer_overflows(overflows, &mut self.memory);
+        let mut dma_cycles = 0;
+        for (fifo, refill) in refills.into_iter().enumerate() {
+            if refill {
+                dma_cycles += self.dma.trigger_sound_fifo(fifo, &mut self.memory);
+            }
+        }
+        self.scheduler.advance(dma_cycles as u64);
+    }
+
+    /// The running counter of `timer`. The timers only run when one of
+    /// their events comes due, so they are brought up to now first.
+    pub fn read_timer_counter(&mut self, timer: usize) -> u16 {
+        self.sync_timers();
+        self.schedule_timer_overflow();
+        self.timers.read_counter(timer)
+    }
+
+    /// Called whenever the timers have been run or latched.
+    fn schedule_timer_overflow(&mut self) {
+        self.memory.timer_readout().sync(&self.timers, self.synced.timers);
+        match self.timers.cycles_until_overflow() {
+            Some(cycles) => {
+                let due = self.synced.timers + cycles;
+                self.scheduler.schedule(Event::TimerOverflow, due.saturating_sub(self.scheduler.now()));
+            }
+            None => self.scheduler.cancel(Event::TimerOverflow),
+        }
+    }
+
+    fn sync_sound(&mut self) {
+        let cycles = self.scheduler.elapsed_since(&mut self.synced.sound);
+        self.sound.tick(cycles as u32, &mut self.memory);
+    }
+
+    /// Steps until the PPU starts the next frame, until
+    /// `frame_instruction_limit` instructions have run, or until the CPU
+    /// is in stop mode. Steps spent halted don't count as instructions.
+    pub fn step_frame(&mut self) -> FrameResult {
+        let frame = self.ppu.frame_count;
+        let mut instructions = 0;
+        while self.ppu.frame_count == frame {
+            if self
+                .frame_instruction_limit
+                .is_some_and(|limit| instructions >= limit)
+            {
+                return FrameResult::InstructionLimitHit {
+                    instructions,
+                    scanline: self.ppu.y,
+                };
+            }
+            if self.halt_mode.is_none() {
+                instructions += 1;
+            }
+            self.step();
+            if self.halt_mode == Some(HaltMode::Stop) {
+                return FrameResult::Stopped {
+                    instructions,
+                    scanline: self.ppu.y,
+                };
+            }
+        }
+        FrameResult::Completed { instructions }
+    }
+
+    /// Runs `frames` frames and returns the `frame_hash` of each, so a
+    /// test can check a whole animation rather than a single frame. Stops
+    /// at the first frame `frame_instruction_limit` or stop mode cuts
+    /// short, leaving it out, so fewer hashes than `frames` means one of
+    /// them was hit.
+    pub fn capture_frame_hashes(&mut self, frames: usize) -> Vec<u64> {
+        let mut hashes = Vec::with_capacity(frames);
+        for _ in 0..frames {
+            if !matches!(self.step_frame(), FrameResult::Completed { .. }) {
+                break;
+            }
+            hashes.push(self.frame_hash());
+        }
+        hashes
+    }
+
+    /// Runs a frame without a display attached and returns the framebuffer
+    /// as 0x00RRGGBB colours in screen order. The frame ends as VBlank
+    /// starts, once all 160 visible lines are drawn, unless
+    /// `frame_instruction_limit` or stop mode cuts it short, in which case
+    /// `step_frame` says how far it got.
+    pub fn run_frame(&mut self) -> &[u32] {
+        self.step_frame();
+        for (pixel, color) in self.rendered_frame.iter_mut().zip(&self.ppu.framebuffer) {
+            *pixel = color_to_rgb32(*color);
+        }
+        &self.rendered_frame
+    }
+
+    /// Which layer, priority and palette entry drew screen pixel (x, y) in
+    /// the last rendered frame. Needs `ppu.record_pixel_sources` set
+    /// before the frame is drawn.
+    pub fn inspect_pixel(&self, x: usize, y: usize) -> Option<PixelSource> {
+        self.ppu.pixel_source(x, y)
+    }
+
+    /// Renders the current video memory with only `layer` over the
+    /// backdrop and saves it as a PPM image at `path`.
+    pub fn export_isolated_layer(&mut self, layer: Layer, path: &str) -> Result<(), std::io::Error> {
+        let frame = self.ppu.render_isolated_frame(layer, self.memory.as_ref());
+        export_ppm(&frame, path)
+    }
+
+    fn advance_ppu_by(&mut self, mut cycles: u32) {
+        while cycles > 0 {
+            let chunk = cycles.min(CYCLES::MAX as u32);
+            self.advance_ppu(chunk as CYCLES);
+            cycles -= chunk;
+        }
+    }
+
+    /// Halt wakes on any requested and enabled interrupt, whether or not
+    /// IME is set; the PPU keeps running meanwhile, and a halted step skips
+    /// ahead to the next event. Stop also turns off the PPU and only wakes
+    /// on keypad, serial or cartridge interrupts.
+    fn step_halted(&mut self, halt_mode: HaltMode) -> StepResult {
+        let mut cycles = HALTED_STEP_CYCLES;
+        let pending_interrupts = self.memory.ppu_io_read(IE) & self.memory.ppu_io_read(IF);
+        let wake_interrupts = match halt_mode {
+            HaltMode::Halt => pending_interrupts,
+            HaltMode::Stop => pending_interrupts & STOP_WAKE_INTERRUPTS,
+        };
+        if wake_interrupts > 0 {
+            self.halt_mode = None;
+        } else if halt_mode == HaltMode::Halt {
+            let until_event = self.scheduler.cycles_until_next_event().unwrap_or(1);
+            cycles = until_event.clamp(1, CYCLES::MAX as u64) as CYCLES;
+            self.scheduler.advance(cycles as u64);
+            self.run_due_events();
+        }
+
+        StepResult {
+            cycles,
+            executed_pc: self.cpu.last_executed_pc(),
+            took_exception: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod gba_tests {
+    use rstest::rstest;
+
+    use crate::{
+        arm7tdmi::cpu::{CPUMode, InstructionMode},
+        arm7tdmi::interrupts::{Exceptions, Interrupt},
+        debugger::{debugger::Debugger, terminal_commands::parse_command},
+        graphics::{
+            background::{PALETTE_BASE, VRAM_BASE},
+            display::FrameHandoff,
+            layers::{Layer, PixelSource},
+            objects::OAM_BASE,
+            ppu::{SCREEN_HEIGHT, SCREEN_WIDTH},
+        },
+        memory::{
+            io_handlers::{
+                HaltMode, BG0CNT, DISPCNT, DISPSTAT, DMA0CNT_H, DMA0CNT_L, DMA0DAD, DMA0SAD, DMY, DX,
+                IE, IF, IME, IO_BASE, TM0CNT_H, TM0CNT_L,
+            },
+            memory::GBAMemory,
+        },
+        scheduler::Event,
+        state::{diff_states, savestate::StateError},
+        types::CYCLES,
+        utils::testing::{load_arm_program, step_one_cycles},
+    };
+
+    use super::{FrameResult, GBA};
+
+    const IWRAM_START: usize = 0x3000000;
+
+    #[test]
+    fn data_processing_instruction_takes_one_cycle_in_iwram() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, IWRAM_START, &[
+            0xe3a0100a, // mov r1, 10
+            0xe2811005, // add r1, r1, 5
+        ]);
+
+        assert_eq!(step_one_cycles(&mut gba), 1);
+        assert_eq!(step_one_cycles(&mut gba), 1);
+        assert_eq!(gba.cpu.get_register(1), 15);
+    }
+
+    #[test]
+    fn ldr_takes_three_cycles_in_iwram() {
+        let mut gba = GBA::new_no_bios();
+        gba.memory.writeu32(IWRAM_START + 0x100, 0xDEADBEEF);
+        load_arm_program(&mut gba, IWRAM_START, &[
+            0xe3a01403, // mov r1, 0x3000000
+            0xe5912100, // ldr r2, [r1, 0x100]
+        ]);
+
+        assert_eq!(step_one_cycles(&mut gba), 1);
+        assert_eq!(step_one_cycles(&mut gba), 3);
+        assert_eq!(gba.cpu.get_register(2), 0xDEADBEEF);
+    }
+
+    #[test]
+    fn step_reports_executed_pc_and_exception() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, IWRAM_START, &[
+            0xe3a0100a, // mov r1, 10
+            0xef000000, // swi 0
+        ]);
+
+        let result = gba.step();
+        assert_eq!(result.executed_pc, IWRAM_START as u32);
+        assert_eq!(result.took_exception, None);
+
+        let result = gba.step();
+        assert_eq!(result.executed_pc, IWRAM_START as u32 + 4);
+        assert_eq!(result.took_exception, Some(Exceptions::Software));
+        assert_eq!(gba.cpu.get_pc(), 0x10);
+    }
+
+    #[test]
+    fn stop_mode_wakes_on_keypad_interrupt_only() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, IWRAM_START, &[
+            0xe3a00301, // mov r0, 0x4000000
+            0xe3a01080, // mov r1, 0x80
+            0xe5c01301, // strb r1, [r0, 0x301]
+            0xe3a02001, // mov r2, 1
+        ]);
+        gba.memory.writeu16(IO_BASE + IE, Interrupt::Timer0.bit() | Interrupt::Keypad.bit());
+        for _ in 0..4 {
+            gba.step();
+        }
+        assert_eq!(gba.halt_mode, Some(HaltMode::Stop));
+        assert!(gba.ppu.framebuffer.iter().all(|pixel| *pixel == 0x7FFF));
+
+        gba.memory.ppu_io_write(IF, Interrupt::Timer0.bit());
+        for _ in 0..100 {
+            gba.step();
+        }
+        assert_eq!(gba.halt_mode, Some(HaltMode::Stop));
+        assert_eq!(gba.cpu.get_register(2), 0);
+
+        gba.memory.ppu_io_write(IF, Interrupt::Timer0.bit() | Interrupt::Keypad.bit());
+        gba.step();
+        assert_eq!(gba.halt_mode, None);
+        gba.step();
+        assert_eq!(gba.cpu.get_register(2), 1);
+    }
+
+    #[test]
+    fn halt_mode_wakes_on_any_enabled_interrupt() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, IWRAM_START, &[
+            0xe3a00301, // mov r0, 0x4000000
+            0xe3a01000, // mov r1, 0
+            0xe5c01301, // strb r1, [r0, 0x301]
+            0xe3a02001, // mov r2, 1
+        ]);
+        gba.memory.writeu16(IO_BASE + IE, Interrupt::Timer0.bit());
+        for _ in 0..4 {
+            gba.step();
+        }
+        assert_eq!(gba.halt_mode, Some(HaltMode::Halt));
+
+        gba.memory.ppu_io_write(IF, Interrupt::Timer0.bit());
+        gba.step();
+        assert_eq!(gba.halt_mode, None);
+    }
+
+    #[test]
+    fn switching_modes_rebanks_the_stack_pointer() {
+        let mut gba = GBA::new_no_bios();
+        gba.set_cpu_mode(CPUMode::SYS);
+        gba.cpu.set_register(13, 0x3007F00);
+        gba.set_cpu_mode(CPUMode::SVC);
+        gba.cpu.set_register(13, 0x3007FE0);
+
+        gba.set_instruction_mode(InstructionMode::THUMB);
+        gba.set_cpu_mode(CPUMode::IRQ);
+        gba.cpu.set_register(13, 0x3007FA0);
+        assert_eq!(gba.instruction_mode(), InstructionMode::THUMB);
+        assert_eq!(gba.cpu_mode(), CPUMode::IRQ);
+
+        gba.set_cpu_mode(CPUMode::SYS);
+        assert_eq!(gba.cpu.get_sp(), 0x3007F00);
+        gba.set_cpu_mode(CPUMode::SVC);
+        assert_eq!(gba.cpu.get_sp(), 0x3007FE0);
+        gba.set_cpu_mode(CPUMode::IRQ);
+        assert_eq!(gba.cpu.get_sp(), 0x3007FA0);
+        assert_eq!(gba.instruction_mode(), InstructionMode::THUMB);
+    }
+
+    #[test]
+    fn switching_to_thumb_refetches_the_next_instruction() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, IWRAM_START, &[
+            0x2207_2105, // movs r1, 5; movs r2, 7
+        ]);
+
+        gba.set_instruction_mode(InstructionMode::THUMB);
+        gba.step();
+        gba.step();
+
+        assert_eq!(gba.cpu.get_register(1), 5);
+        assert_eq!(gba.cpu.get_register(2), 7);
+    }
+
+    fn run_one_frame_of_mode_3(pixel: u16) -> GBA {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, IWRAM_START, &[
+            0xeafffffe, // b .
+        ]);
+        gba.memory.writeu16(IO_BASE + DISPCNT, 0x3 | 1 << 10); // Mode 3, BG2 on
+        gba.memory.writeu16(IO_BASE + DX, 0x100);
+        gba.memory.writeu16(IO_BASE + DMY, 0x100);
+        for i in 0..64 {
+            gba.memory.writeu16(VRAM_BASE + i * 2, (i as u16) << 5);
+        }
+        gba.memory.writeu16(VRAM_BASE + 0x200, pixel);
+        let frame = gba.ppu.frame_count;
+        while gba.ppu.frame_count < frame + 2 {
+            gba.step();
+        }
+        gba
+    }
+
+    #[test]
+    fn identical_runs_produce_the_same_frame_hash() {
+        let first = run_one_frame_of_mode_3(0x7C00);
+        let second = run_one_frame_of_mode_3(0x7C00);
+        let changed = run_one_frame_of_mode_3(0x001F);
+
+        assert_eq!(first.frame_hash(), second.frame_hash());
+        assert_ne!(first.frame_hash(), changed.frame_hash());
+    }
+
+    #[test]
+    fn moving_sprite_produces_a_distinct_hash_every_frame() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, IWRAM_START, &[
+            0xe3a00407, // mov r0, 0x7000000
+            0xe3a01404, // mov r1, 0x4000000
+            0xe1d120b6, // loop: ldrh r2, [r1, 6]
+            0xe35200a0, // cmp r2, 160
+            0x1afffffc, // bne loop
+            0xe1d030b2, // ldrh r3, [r0, 2]
+            0xe2833008, // add r3, r3, 8
+            0xe1c030b2, // strh r3, [r0, 2]
+            0xe1d120b6, // wait: ldrh r2, [r1, 6]
+            0xe35200a0, // cmp r2, 160
+            0x0afffffc, // beq wait
+            0xeafffff5, // b loop
+        ]);
+        gba.memory.writeu16(IO_BASE + DISPCNT, 1 << 6 | 1 << 12); // Mode 0, 1D OBJ, OBJ on
+        for i in 1..128 {
+            gba.memory.writeu16(OAM_BASE + i * 8, 1 << 9); // hidden
+        }
+        for i in 0..16 {
+            gba.memory.writeu16(VRAM_BASE + 0x10000 + i * 2, 0x1111);
+        }
+        gba.memory.writeu16(PALETTE_BASE + 0x202, 0x7C00);
+
+        let hashes = gba.capture_frame_hashes(4);
+
+        assert_eq!(hashes.len(), 4);
+        assert_eq!(gba.memory.readu16(OAM_BASE + 2).data, 24);
+        for (i, hash) in hashes.iter().enumerate() {
+            assert!(!hashes[i + 1..].contains(hash), "frame {} repeats", i);
+        }
+    }
+
+    #[test]
+    fn capture_frame_hashes_stops_at_a_frame_cut_short() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, IWRAM_START, &[
+            0xeafffffe, // b .
+        ]);
+        gba.step_frame();
+        let FrameResult::Completed { instructions } = gba.step_frame() else {
+            panic!("frame should complete without a limit");
+        };
+        gba.frame_instruction_limit = Some(instructions + 100);
+
+        assert_eq!(gba.capture_frame_hashes(3).len(), 3);
+
+        gba.frame_instruction_limit = Some(100);
+        assert_eq!(gba.capture_frame_hashes(3), vec![]);
+    }
+
+    #[test]
+    fn loaded_state_resumes_like_the_run_it_was_saved_from() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, IWRAM_START, &[
+            0xe3a02402, // mov r2, 0x2000000
+            0xe2800001, // loop: add r0, r0, 1
+            0xe0811000, // add r1, r1, r0
+            0xe4821004, // str r1, [r2], 4
+            0xeafffffb, // b loop
+        ]);
+        for _ in 0..1000 {
+            gba.step();
+        }
+        let snapshot = gba.save_state();
+        for _ in 0..1000 {
+            gba.step();
+        }
+        let reference = gba.capture_state();
+
+        assert_eq!(
+            gba.load_state(&snapshot[..100]),
+            Err(StateError::Truncated { offset: 100, needed: 4 })
+        );
+        gba.load_state(&snapshot).unwrap();
+        for _ in 0..1000 {
+            gba.step();
+        }
+
+        assert_eq!(diff_states(&reference, &gba.capture_state()), vec![]);
+    }
+
+    #[test]
+    fn loaded_state_keeps_running_timers_and_repeating_dmas() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, IWRAM_START, &[
+            0xeafffffe, // b .
+        ]);
+        gba.memory.writeu16(IO_BASE + TM0CNT_H, 0x81); // enabled, prescaler 64
+        // copy the counter to the next halfword of EWRAM every HBlank
+        gba.memory.writeu32(IO_BASE + DMA0SAD, (IO_BASE + TM0CNT_L) as u32);
+        gba.memory.writeu32(IO_BASE + DMA0DAD, 0x2000000);
+        gba.memory.writeu16(IO_BASE + DMA0CNT_L, 1);
+        gba.memory.writeu16(IO_BASE + DMA0CNT_H, 0xA300); // enabled, HBlank, repeat, fixed source
+        for _ in 0..5000 {
+            gba.step();
+        }
+        let snapshot = gba.save_state();
+        for _ in 0..3 {
+            gba.step_frame();
+        }
+        let reference = gba.capture_state();
+
+        gba.load_state(&snapshot).unwrap();
+        for _ in 0..3 {
+            gba.step_frame();
+        }
+
+        assert_eq!(diff_states(&reference, &gba.capture_state()), vec![]);
+    }
+
+    #[test]
+    fn run_frame_returns_the_frame_drawn_before_vblank() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, IWRAM_START, &[
+            0xeafffffe, // b .
+        ]);
+        gba.memory.writeu16(IO_BASE + DISPCNT, 0x3 | 1 << 10); // Mode 3, BG2 on
+        gba.memory.writeu16(IO_BASE + DX, 0x100);
+        gba.memory.writeu16(IO_BASE + DMY, 0x100);
+        gba.memory.writeu16(VRAM_BASE + 2 * (SCREEN_WIDTH + 16), 0x7C00);
+        gba.memory.writeu16(VRAM_BASE + 2 * (159 * SCREEN_WIDTH + 239), 0x001F);
+
+        let framebuffer = gba.run_frame().to_vec();
+
+        assert_eq!(framebuffer.len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+        assert_eq!(framebuffer[SCREEN_WIDTH + 16], 0x0000FF);
+        assert_eq!(framebuffer[159 * SCREEN_WIDTH + 239], 0xFF0000);
+        assert_eq!(gba.ppu.y, SCREEN_HEIGHT as u64);
+    }
+
+    #[test]
+    fn consecutive_bx_switches_refetch_with_the_new_instruction_width() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, IWRAM_START, &[
+            0xe3a00001, // mov r0, 1
+            0xe28f1001, // add r1, pc, 1
+            0xe12fff11, // bx r1
+            0xa2013002, // adds r0, 2; add r2, pc, 4
+            0x20ff4710, // bx r2; movs r0, 0xff (skipped)
+            0xe28f3001, // add r3, pc, 1
+            0xe12fff13, // bx r3
+            0x20ff4778, // bx pc; movs r0, 0xff (skipped)
+            0xe2800004, // add r0, r0, 4
+            0xeafffffe, // b .
+        ]);
+        let (arm, thumb) = (InstructionMode::ARM, InstructionMode::THUMB);
+        let expected = [
+            (0x00, arm, 1),
+            (0x04, arm, 1),
+            (0x08, thumb, 1),
+            (0x0C, thumb, 3),
+            (0x0E, thumb, 3),
+            (0x10, arm, 3),
+            (0x14, arm, 3),
+            (0x18, thumb, 3),
+            (0x1C, arm, 3),
+            (0x20, arm, 7),
+        ];
+
+        for (offset, mode_after, r0) in expected {
+            let result = gba.step();
+            assert_eq!(result.executed_pc, IWRAM_START as u32 + offset);
+            assert_eq!(gba.instruction_mode(), mode_after, "after {:#X}", offset);
+            assert_eq!(gba.cpu.get_register(0), r0, "after {:#X}", offset);
+        }
+        assert_eq!(gba.cpu.get_register(2), IWRAM_START as u32 + 0x14);
+    }
+
+    #[test]
+    fn inspected_pixels_report_the_layer_that_drew_them() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, IWRAM_START, &[
+            0xeafffffe, // b .
+        ]);
+        gba.memory.writeu16(IO_BASE + DISPCNT, 1 << 8 | 1 << 12 | 1 << 6); // Mode 0, BG0 and 1D OBJ on
+        gba.memory.writeu16(IO_BASE + BG0CNT, 8 << 8 | 1); // screen block 8, priority 1
+        // tile 1 is solid colour 3, placed top left using palette bank 2
+        for byte in 0..32 {
+            gba.memory.write(VRAM_BASE + 32 + byte, 0x33);
+        }
+        gba.memory.writeu16(VRAM_BASE + 0x4000, 2 << 12 | 1);
+        // 8x8 sprite at (4, 4) using OBJ tile 1, solid colour 1
+        for byte in 0..32 {
+            gba.memory.write(VRAM_BASE + 0x10000 + 32 + byte, 0x11);
+        }
+        gba.memory.writeu16(OAM_BASE, 4);
+        gba.memory.writeu16(OAM_BASE + 2, 4);
+        gba.memory.writeu16(OAM_BASE + 4, 1);
+        for i in 1..128 {
+            gba.memory.writeu16(OAM_BASE + i * 8, 1 << 9); // disabled
+        }
+        gba.memory.writeu16(PALETTE_BASE + 2 * 35, 0x001F);
+        gba.ppu.record_pixel_sources = true;
+
+        gba.run_frame();
+
+        assert_eq!(
+            gba.inspect_pixel(1, 1),
+            Some(PixelSource {
+                layer: Layer::Bg0,
+                priority: 1,
+                palette_index: Some(35),
+            })
+        );
+        assert_eq!(
+            gba.inspect_pixel(6, 6),
+            Some(PixelSource {
+                layer: Layer::Obj,
+                priority: 0,
+                palette_index: Some(1),
+            })
+        );
+        assert_eq!(gba.inspect_pixel(100, 100), Some(PixelSource::backdrop()));
+        assert_eq!(gba.inspect_pixel(SCREEN_WIDTH, 0), None);
+        assert_eq!(gba.run_frame()[SCREEN_WIDTH + 1], 0xFF0000);
+    }
+
+    #[test]
+    fn finished_frames_are_handed_off_to_the_display() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, IWRAM_START, &[
+            0xeafffffe, // b .
+        ]);
+        let frames = FrameHandoff::default();
+        gba.frame_output = Some(frames.clone());
+        gba.memory.writeu16(IO_BASE + DISPCNT, 1 << 7); // forced blank
+
+        gba.step_frame();
+
+        assert_eq!(frames.take_latest(), Some(vec![0x7FFF; SCREEN_WIDTH * SCREEN_HEIGHT]));
+        assert_eq!(frames.take_latest(), None);
+    }
+
+    #[test]
+    fn step_frame_stops_early_when_the_instruction_limit_is_hit() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, IWRAM_START, &[
+            0xeafffffe, // b .
+        ]);
+
+        let FrameResult::Completed { instructions } = gba.step_frame() else {
+            panic!("frame should complete without a limit");
+        };
+        assert!(instructions > 1000);
+
+        gba.frame_instruction_limit = Some(1000);
+        let frame = gba.ppu.frame_count;
+
+        assert_eq!(
+            gba.step_frame(),
+            FrameResult::InstructionLimitHit {
+                instructions: 1000,
+                scanline: gba.ppu.y
+            }
+        );
+        assert_eq!(gba.ppu.frame_count, frame);
+        gba.run_frame();
+        assert_eq!(gba.ppu.frame_count, frame);
+    }
+
+    #[test]
+    fn step_frame_returns_once_the_cpu_stops() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, IWRAM_START, &[
+            0xe3a00301, // mov r0, 0x4000000
+            0xe3a01080, // mov r1, 0x80
+            0xe5c01301, // strb r1, [r0, 0x301]
+            0xeafffffe, // b .
+        ]);
+
+        let frame = gba.ppu.frame_count;
+        assert!(matches!(gba.step_frame(), FrameResult::Stopped { .. }));
+        assert!(matches!(gba.step_frame(), FrameResult::Stopped { instructions: 0, .. }));
+        assert_eq!(gba.ppu.frame_count, frame);
+        assert!(gba.capture_frame_hashes(3).is_empty());
+    }
+
+    #[test]
+    fn pending_interrupts_include_vblank_once_the_ppu_raises_it() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, IWRAM_START, &[
+            0xeafffffe, // b .
+        ]);
+        gba.memory.writeu16(IO_BASE + DISPSTAT, 1 << 3); // VBlank IRQ enable
+        gba.memory.writeu16(IO_BASE + IE, Interrupt::VBlank.bit() | Interrupt::Timer0.bit());
+        assert!(gba.pending_interrupts().is_empty());
+
+        gba.step_frame();
+
+        assert_eq!(gba.pending_interrupts(), vec![Interrupt::VBlank]);
+        assert_eq!(gba.interrupt_flags() & Interrupt::VBlank.bit(), Interrupt::VBlank.bit());
+        assert_eq!(gba.interrupt_enable(), 0x9);
+        assert!(!gba.interrupt_master_enable());
+    }
+
+    #[test]
+    fn requested_timer0_interrupt_is_taken_on_the_next_step() {
+        let mut debugger = Debugger::with_memory(GBAMemory::new());
+        let gba = &mut debugger.cpu;
+        load_arm_program(gba, IWRAM_START, &[
+            0xeafffffe, // b .
+        ]);
+        gba.cpu.set_mode(CPUMode::SYS);
+        gba.cpu.cpsr &= !(1 << 7);
+
+        debugger.terminal_buffer = String::from("irq timer0");
+        let output = parse_command(&mut debugger).unwrap_or_else(|err| err.to_string());
+
+        assert_eq!(output, "Requested timer0 interrupt, IF = 0x0008");
+        let gba = &mut debugger.cpu;
+        assert_eq!(gba.interrupt_flags(), Interrupt::Timer0.bit());
+        assert_eq!(gba.step().took_exception, None);
+
+        gba.memory.writeu16(IO_BASE + IE, Interrupt::Timer0.bit());
+        gba.memory.writeu16(IO_BASE + IME, 1);
+        let result = gba.step();
+
+        assert_eq!(result.took_exception, Some(Exceptions::IRQ));
+        assert_eq!(gba.cpu.get_cpu_mode(), CPUMode::IRQ);
+    }
+
+    #[test]
+    fn ldrh_from_tm0cnt_l_sees_the_counter_advance() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, IWRAM_START, &[
+            0xe1a00000, // mov r0, r0
+            0xe1d310b0, // ldrh r1, [r3]
+            0xe1a00000, // mov r0, r0
+            0xe1a00000, // mov r0, r0
+            0xe1d320b0, // ldrh r2, [r3]
+            0xeafffffe, // b .
+        ]);
+        gba.cpu.set_register(3, (IO_BASE + TM0CNT_L) as u32);
+        gba.memory.writeu16(IO_BASE + TM0CNT_L, 0x1000);
+        gba.memory.writeu16(IO_BASE + TM0CNT_H, 0x80); // enabled, prescaler 1
+        gba.step();
+
+        let first_read_at = gba.scheduler.now();
+        gba.step();
+        gba.step();
+        gba.step();
+        let second_read_at = gba.scheduler.now();
+        gba.step();
+
+        let first = gba.cpu.get_register(1);
+        let second = gba.cpu.get_register(2);
+        assert!(first >= 0x1000);
+        assert_eq!(second - first, (second_read_at - first_read_at) as u32);
+        // the reload written stays latched for the next overflow
+        assert_eq!(gba.memory.ppu_io_read(TM0CNT_L), 0x1000);
+    }
+
+    #[test]
+    fn read_timer_counter_catches_up_between_overflows() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, IWRAM_START, &[
+            0xeafffffe, // b .
+        ]);
+        gba.memory.writeu16(IO_BASE + TM0CNT_L, 0x1000);
+        gba.memory.writeu16(IO_BASE + TM0CNT_H, 0x80); // enabled, prescaler 1
+        gba.step();
+        let enabled_at = gba.scheduler.now();
+
+        for _ in 0..10 {
+            gba.step();
+        }
+
+        let elapsed = (gba.scheduler.now() - enabled_at) as u16;
+        assert_eq!(gba.read_timer_counter(0), 0x1000 + elapsed);
+        assert_eq!(gba.memory.readu16(IO_BASE + TM0CNT_L).data, 0x1000 + elapsed);
+    }
+
+    #[test]
+    fn hblank_and_timer_events_interleave_in_time_order() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, IWRAM_START, &[
+            0xeafffffe, // b .
+        ]);
+        // overflows every 1000 cycles, against HBlanks every 1232
+        gba.memory.writeu16(IO_BASE + TM0CNT_L, (0x10000 - 1000) as u16);
+        gba.memory.writeu16(IO_BASE + TM0CNT_H, 0xC0); // enabled, IRQ, prescaler 1
+        gba.step();
+        let enabled_at = gba.scheduler.now();
+        // halted steps go straight to the next event
+        gba.halt_mode = Some(HaltMode::Halt);
+
+        let timer0 = Interrupt::timer(0).bit();
+        let mut fired = vec![];
+        let mut was_in_hblank = gba.ppu.in_hblank();
+        while gba.scheduler.now() < 5000 {
+            gba.step();
+            if gba.ppu.in_hblank() && !was_in_hblank {
+                fired.push((gba.scheduler.now(), Event::HBlank));
+            }
+            was_in_hblank = gba.ppu.in_hblank();
+            if gba.interrupt_flags() & timer0 > 0 {
+                fired.push((gba.scheduler.now(), Event::TimerOverflow));
+                gba.memory.ppu_io_write(IF, 0);
+            }
+        }
+
+        let mut expected: Vec<(u64, Event)> = (0..5)
+            .map(|line| (960 + 1232 * line, Event::HBlank))
+            .chain((1..6).map(|overflow| (enabled_at + 1000 * overflow, Event::TimerOverflow)))
+            .filter(|&(at, _)| at <= gba.scheduler.now())
+            .collect();
+        expected.sort();
+        assert_eq!(fired, expected);
+        assert!(fired.len() >= 8);
+    }
+
+    #[test]
+    fn timer_overflow_fires_at_the_scheduled_cycle() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, IWRAM_START, &[
+            0xeafffffe, // b .
+        ]);
+        gba.memory.writeu16(IO_BASE + TM0CNT_L, 0xFF00);
+        gba.memory.writeu16(IO_BASE + TM0CNT_H, 0xC0); // enabled, IRQ, prescaler 1
+        gba.step();
+        let enabled_at = gba.scheduler.now();
+
+        let due = gba.scheduler.scheduled_at(Event::TimerOverflow);
+        assert_eq!(due, Some(enabled_at + 256));
+
+        while gba.scheduler.now() < enabled_at + 256 {
+            assert_eq!(gba.interrupt_flags() & Interrupt::Timer0.bit(), 0);
+            gba.step();
+        }
+        assert_eq!(gba.interrupt_flags() & Interrupt::Timer0.bit(), Interrupt::Timer0.bit());
+        assert_eq!(gba.scheduler.scheduled_at(Event::TimerOverflow), Some(enabled_at + 512));
+    }
+
+    #[rstest]
+    #[case::contended(true, 1)]
+    #[case::not_modelled(false, 0)]
+    fn bitmap_framebuffer_writes_stall_during_hdraw(
+        #[case] contention: bool,
+        #[case] extra_cycles: CYCLES,
+    ) {
+        let program = [
+            0xe1c010b0, // strh r1, [r0]
+            0xeafffffe, // b .
+        ];
+        let mut gba = GBA::new_no_bios();
+        gba.memory.vram_contention().enabled = contention;
+        gba.memory.writeu16(IO_BASE + DISPCNT, 0x3 | 1 << 10); // Mode 3, BG2 on
+        gba.cpu.set_register(0, VRAM_BASE as u32 + 0x100);
+
+        load_arm_program(&mut gba, IWRAM_START, &program);
+        let hdraw_cycles = step_one_cycles(&mut gba);
+
+        while gba.ppu.y < 160 {
+            gba.step();
+        }
+        load_arm_program(&mut gba, IWRAM_START, &program);
+        let vblank_cycles = step_one_cycles(&mut gba);
+
+        assert_eq!(hdraw_cycles, vblank_cycles + extra_cycles);
     }
 }
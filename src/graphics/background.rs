@@ -0,0 +1,437 @@
+use std::ops::Range;
+
+use crate::{
+    memory::{
+        io_handlers::{
+            BG0CNT, BG0HOFS, BG0VOFS, BG2X_H, BG2X_L, BG2Y_H, BG2Y_L, DMX, DMY, DX, DY,
+        },
+        memory::MemoryBus,
+    },
+    utils::bits::sign_extend,
+};
+
+use super::ppu::SCREEN_WIDTH;
+
+pub const VRAM_BASE: usize = 0x6000000;
+pub const PALETTE_BASE: usize = 0x5000000;
+const BITMAP_FRAME_1_OFFSET: usize = 0xA000;
+const FRAME_SELECT: u16 = 1 << 4;
+const SCREEN_BLOCK_SIZE: usize = 0x800;
+const CHARACTER_BLOCK_SIZE: usize = 0x4000;
+const SCROLL_MASK: u16 = 0x1FF;
+const AFFINE_REGISTER_STRIDE: usize = 0x10;
+const AFFINE_WRAPAROUND: u16 = 1 << 13;
+
+/// An opaque background pixel. `palette_index` is the BG palette entry
+/// it was looked up from, None for the direct colour bitmaps.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LayerPixel {
+    pub color: u16,
+    pub palette_index: Option<u16>,
+}
+
+impl LayerPixel {
+    fn from_palette(palette_index: usize, memory: &dyn MemoryBus) -> Self {
+        Self {
+            color: memory.peeku16(PALETTE_BASE + palette_index * 2) & 0x7FFF,
+            palette_index: Some(palette_index as u16),
+        }
+    }
+}
+
+pub type LayerLine = [Option<LayerPixel>; SCREEN_WIDTH];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BitmapMode {
+    Mode3,
+    Mode4,
+    Mode5,
+}
+
+impl BitmapMode {
+    pub fn from_bg_mode(bg_mode: u16) -> Option<Self> {
+        match bg_mode {
+            3 => Some(Self::Mode3),
+            4 => Some(Self::Mode4),
+            5 => Some(Self::Mode5),
+            _ => None,
+        }
+    }
+
+    fn dimensions(self) -> (i32, i32) {
+        match self {
+            Self::Mode3 | Self::Mode4 => (240, 160),
+            Self::Mode5 => (160, 128),
+        }
+    }
+
+    fn has_frame_select(self) -> bool {
+        !matches!(self, Self::Mode3)
+    }
+
+    /// VRAM offsets of the page displayed with `disp_cnt`.
+    pub fn framebuffer(self, disp_cnt: u16) -> Range<usize> {
+        let (width, height) = self.dimensions();
+        let bytes_per_pixel = match self {
+            Self::Mode4 => 1,
+            Self::Mode3 | Self::Mode5 => 2,
+        };
+        let base = if self.has_frame_select() && disp_cnt & FRAME_SELECT > 0 {
+            BITMAP_FRAME_1_OFFSET
+        } else {
+            0
+        };
+        base..base + (width * height) as usize * bytes_per_pixel
+    }
+}
+
+/// Internal BG2/BG3 reference point, in 19.8 fixed point.
+/// Latched from BGxX/BGxY at the start of a frame and stepped by
+/// dmx/dmy after each scanline.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AffineReference {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl AffineReference {
+    pub fn latch_bg2(memory: &dyn MemoryBus) -> Self {
+        Self::latch(2, memory)
+    }
+
+    /// Latches the reference point of affine background `bg` (2 or 3).
+    pub fn latch(bg: usize, memory: &dyn MemoryBus) -> Self {
+        let offset = affine_register_offset(bg);
+        let read_reference = |low: usize, high: usize| {
+            let value = memory.ppu_io_read(low + offset) as u32
+                | (memory.ppu_io_read(high + offset) as u32) << 16;
+            sign_extend(value & 0x0FFF_FFFF, 27) as i32
+        };
+        Self {
+            x: read_reference(BG2X_L, BG2X_H),
+            y: read_reference(BG2Y_L, BG2Y_H),
+        }
+    }
+
+    pub fn advance_line(&mut self, parameters: &AffineParameters) {
+        self.x = self.x.wrapping_add(parameters.dmx as i32);
+        self.y = self.y.wrapping_add(parameters.dmy as i32);
+    }
+}
+
+/// BG2 rotation/scaling parameters, in 8.8 fixed point.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AffineParameters {
+    pub dx: i16,
+    pub dmx: i16,
+    pub dy: i16,
+    pub dmy: i16,
+}
+
+impl AffineParameters {
+    pub fn bg2(memory: &dyn MemoryBus) -> Self {
+        Self::for_background(2, memory)
+    }
+
+    /// Reads the parameters of affine background `bg` (2 or 3).
+    pub fn for_background(bg: usize, memory: &dyn MemoryBus) -> Self {
+        let offset = affine_register_offset(bg);
+        Self {
+            dx: memory.ppu_io_read(DX + offset) as i16,
+            dmx: memory.ppu_io_read(DMX + offset) as i16,
+            dy: memory.ppu_io_read(DY + offset) as i16,
+            dmy: memory.ppu_io_read(DMY + offset) as i16,
+        }
+    }
+}
+
+/// BG3's affine registers follow BG2's.
+fn affine_register_offset(bg: usize) -> usize {
+    assert!(bg == 2 || bg == 3);
+    (bg - 2) * AFFINE_REGISTER_STRIDE
+}
+
+/// Renders one scanline of the BG2 bitmap for modes 3-5. Screen pixels
+/// whose transformed coordinates fall outside the bitmap are left
+/// transparent so the backdrop shows through.
+pub fn render_bitmap_line(
+    mode: BitmapMode,
+    disp_cnt: u16,
+    reference: &AffineReference,
+    parameters: &AffineParameters,
+    memory: &dyn MemoryBus,
+    line: &mut LayerLine,
+) {
+    let (width, height) = mode.dimensions();
+    let frame_base = if mode.has_frame_select() && disp_cnt & FRAME_SELECT > 0 {
+        VRAM_BASE + BITMAP_FRAME_1_OFFSET
+    } else {
+        VRAM_BASE
+    };
+
+    for (screen_x, pixel) in line.iter_mut().enumerate() {
+        let texture_x = (reference.x + parameters.dx as i32 * screen_x as i32) >> 8;
+        let texture_y = (reference.y + parameters.dy as i32 * screen_x as i32) >> 8;
+
+        *pixel = None;
+        if texture_x < 0 || texture_x >= width || texture_y < 0 || texture_y >= height {
+            continue;
+        }
+
+        let pixel_index = (texture_y * width + texture_x) as usize;
+        *pixel = match mode {
+            BitmapMode::Mode3 | BitmapMode::Mode5 => Some(LayerPixel {
+                color: memory.peeku16(frame_base + pixel_index * 2) & 0x7FFF,
+                palette_index: None,
+            }),
+            BitmapMode::Mode4 => {
+                let palette_index = memory.peek(frame_base + pixel_index) as usize;
+                (palette_index != 0).then(|| LayerPixel::from_palette(palette_index, memory))
+            }
+        };
+    }
+}
+
+/// Control and scroll state of a text (regular) background.
+#[derive(Clone, Copy, Debug)]
+pub struct TextBackground {
+    pub priority: u8,
+    character_base: usize,
+    eight_bpp: bool,
+    screen_base: usize,
+    width: usize,
+    height: usize,
+    horizontal_offset: usize,
+    vertical_offset: usize,
+}
+
+impl TextBackground {
+    /// Reads BGxCNT and the scroll registers for background `bg`. The
+    /// scroll registers are write-only and only their low 9 bits matter.
+    pub fn from_registers(bg: usize, memory: &dyn MemoryBus) -> Self {
+        let control = memory.ppu_io_read(BG0CNT + bg * 2);
+        let (width, height) = match control >> 14 {
+            0 => (256, 256),
+            1 => (512, 256),
+            2 => (256, 512),
+            _ => (512, 512),
+        };
+        Self {
+            priority: (control & 0x3) as u8,
+            character_base: ((control >> 2) & 0x3) as usize * CHARACTER_BLOCK_SIZE,
+            eight_bpp: control & (1 << 7) > 0,
+            screen_base: ((control >> 8) & 0x1F) as usize * SCREEN_BLOCK_SIZE,
+            width,
+            height,
+            horizontal_offset: (memory.ppu_io_read(BG0HOFS + bg * 4) & SCROLL_MASK) as usize,
+            vertical_offset: (memory.ppu_io_read(BG0VOFS + bg * 4) & SCROLL_MASK) as usize,
+        }
+    }
+
+    /// Address of the screen entry covering map pixel (x, y). Maps wider or
+    /// taller than 256 pixels are made of consecutive 32x32 screen blocks.
+    fn screen_entry_address(&self, x: usize, y: usize) -> usize {
+        let blocks_across = self.width / 256;
+        let block = (y / 256) * blocks_across + x / 256;
+        let (tile_x, tile_y) = ((x % 256) / 8, (y % 256) / 8);
+        VRAM_BASE + self.screen_base + block * SCREEN_BLOCK_SIZE + (tile_y * 32 + tile_x) * 2
+    }
+
+    /// Renders one scanline. Pixels using palette index 0 are transparent.
+    pub fn render_line(&self, line: usize, memory: &dyn MemoryBus, pixels: &mut LayerLine) {
+        let y = (line + self.vertical_offset) % self.height;
+
+        for (screen_x, pixel) in pixels.iter_mut().enumerate() {
+            let x = (screen_x + self.horizontal_offset) % self.width;
+            let screen_entry = memory.peeku16(self.screen_entry_address(x, y));
+            let tile = (screen_entry & 0x3FF) as usize;
+            let mut pixel_x = x % 8;
+            let mut pixel_y = y % 8;
+            if screen_entry & (1 << 10) > 0 {
+                pixel_x = 7 - pixel_x;
+            }
+            if screen_entry & (1 << 11) > 0 {
+                pixel_y = 7 - pixel_y;
+            }
+
+            let palette_index = if self.eight_bpp {
+                let tile_address = VRAM_BASE + self.character_base + tile * 64;
+                memory.peek(tile_address + pixel_y * 8 + pixel_x) as usize
+            } else {
+                let tile_address = VRAM_BASE + self.character_base + tile * 32;
+                let pair = memory.peek(tile_address + pixel_y * 4 + pixel_x / 2);
+                ((pair >> (4 * (pixel_x & 1))) & 0xF) as usize
+            };
+
+            *pixel = if palette_index == 0 {
+                None
+            } else {
+                let palette_index = if self.eight_bpp {
+                    palette_index
+                } else {
+                    (screen_entry >> 12) as usize * 16 + palette_index
+                };
+                Some(LayerPixel::from_palette(palette_index, memory))
+            };
+        }
+    }
+}
+
+/// Control state of a rotation/scaling tiled background (BG2 in mode 1,
+/// BG2 and BG3 in mode 2). Maps are square, one byte per screen entry,
+/// and tiles are always 8bpp.
+#[derive(Clone, Copy, Debug)]
+pub struct AffineBackground {
+    pub priority: u8,
+    character_base: usize,
+    screen_base: usize,
+    size: i32,
+    wraparound: bool,
+}
+
+impl AffineBackground {
+    pub fn from_registers(bg: usize, memory: &dyn MemoryBus) -> Self {
+        let control = memory.ppu_io_read(BG0CNT + bg * 2);
+        Self {
+            priority: (control & 0x3) as u8,
+            character_base: ((control >> 2) & 0x3) as usize * CHARACTER_BLOCK_SIZE,
+            screen_base: ((control >> 8) & 0x1F) as usize * SCREEN_BLOCK_SIZE,
+            size: 128 << (control >> 14),
+            wraparound: control & AFFINE_WRAPAROUND > 0,
+        }
+    }
+
+    /// Renders one scanline. Samples outside the map either wrap around
+    /// or stay transparent, depending on the BGxCNT overflow bit.
+    pub fn render_line(
+        &self,
+        reference: &AffineReference,
+        parameters: &AffineParameters,
+        memory: &dyn MemoryBus,
+        pixels: &mut LayerLine,
+    ) {
+        for (screen_x, pixel) in pixels.iter_mut().enumerate() {
+            let mut x = (reference.x + parameters.dx as i32 * screen_x as i32) >> 8;
+            let mut y = (reference.y + parameters.dy as i32 * screen_x as i32) >> 8;
+
+            *pixel = None;
+            if self.wraparound {
+                x = x.rem_euclid(self.size);
+                y = y.rem_euclid(self.size);
+            } else if x < 0 || x >= self.size || y < 0 || y >= self.size {
+                continue;
+            }
+
+            let (x, y) = (x as usize, y as usize);
+            let tiles_across = self.size as usize / 8;
+            let entry_address = VRAM_BASE + self.screen_base + (y / 8) * tiles_across + x / 8;
+            let tile = memory.peek(entry_address) as usize;
+            let tile_address = VRAM_BASE + self.character_base + tile * 64;
+            let palette_index = memory.peek(tile_address + (y % 8) * 8 + x % 8) as usize;
+            if palette_index != 0 {
+                *pixel = Some(LayerPixel::from_palette(palette_index, memory));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        graphics::ppu::SCREEN_WIDTH,
+        memory::{
+            io_handlers::{BG0CNT, BG0HOFS, BG2CNT, DX, IO_BASE},
+            memory::{GBAMemory, MemoryBus},
+        },
+    };
+
+    use rstest::rstest;
+
+    use super::{
+        AffineBackground, AffineParameters, AffineReference, LayerLine, TextBackground,
+        PALETTE_BASE, VRAM_BASE,
+    };
+
+    fn memory_with_striped_background() -> Box<dyn MemoryBus> {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        // character base 0, screen base block 8, 4bpp, 256x256
+        memory.writeu16(IO_BASE + BG0CNT, 8 << 8);
+        for index in 0..16 {
+            memory.writeu16(PALETTE_BASE + index * 2, index as u16);
+        }
+        // tile 1: every row is palette indices 1..=8
+        for row in 0..8 {
+            memory.writeu32(VRAM_BASE + 32 + row * 4, 0x8765_4321);
+        }
+        for entry in 0..(32 * 32) {
+            memory.writeu16(VRAM_BASE + 0x4000 + entry * 2, 1);
+        }
+        memory
+    }
+
+    #[test]
+    fn scroll_register_writes_keep_only_low_9_bits() {
+        let mut memory = memory_with_striped_background();
+        memory.writeu16(IO_BASE + BG0HOFS, 0xFE05);
+
+        // write-only: the CPU reads nothing back
+        assert_eq!(memory.readu16(IO_BASE + BG0HOFS).data, 0);
+        assert_eq!(memory.ppu_io_read(BG0HOFS), 0x005);
+
+        let mut pixels: LayerLine = [None; SCREEN_WIDTH];
+        TextBackground::from_registers(0, memory.as_ref()).render_line(0, memory.as_ref(), &mut pixels);
+
+        assert_eq!(pixels[0].map(|pixel| pixel.color), Some(6));
+        assert_eq!(pixels[2].map(|pixel| pixel.color), Some(8));
+        assert_eq!(pixels[3].map(|pixel| pixel.color), Some(1));
+    }
+
+    #[test]
+    fn scroll_offset_wraps_around_the_map() {
+        let mut memory = memory_with_striped_background();
+        memory.writeu16(IO_BASE + BG0HOFS, 0x1FF);
+
+        let mut pixels: LayerLine = [None; SCREEN_WIDTH];
+        TextBackground::from_registers(0, memory.as_ref()).render_line(0, memory.as_ref(), &mut pixels);
+
+        // 0x1FF wraps to map x 255 on a 256 wide map
+        assert_eq!(pixels[0].map(|pixel| pixel.color), Some(8));
+        assert_eq!(pixels[1].map(|pixel| pixel.color), Some(1));
+    }
+
+    #[rstest]
+    #[case::transparent(false, None)]
+    #[case::wraparound(true, Some(0x001F))]
+    fn affine_samples_outside_the_map_follow_the_overflow_bit(
+        #[case] wraparound: bool,
+        #[case] expected_outside: Option<u16>,
+    ) {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        // 128x128 map at screen block 8, character base 0
+        let wraparound_bit = if wraparound { 1 << 13 } else { 0 };
+        memory.writeu16(IO_BASE + BG2CNT, 8 << 8 | wraparound_bit);
+        // each screen pixel steps two map pixels, so the map ends at x 64
+        memory.writeu16(IO_BASE + DX, 0x200);
+        memory.writeu16(PALETTE_BASE + 2, 0x001F);
+        // tile 1 is solid palette index 1 and fills the whole map
+        for byte in 0..64 {
+            memory.write(VRAM_BASE + 64 + byte, 1);
+        }
+        for entry in 0..16 * 16 {
+            memory.write(VRAM_BASE + 0x4000 + entry, 1);
+        }
+
+        let mut pixels: LayerLine = [None; SCREEN_WIDTH];
+        AffineBackground::from_registers(2, memory.as_ref()).render_line(
+            &AffineReference::latch(2, memory.as_ref()),
+            &AffineParameters::for_background(2, memory.as_ref()),
+            memory.as_ref(),
+            &mut pixels,
+        );
+
+        assert_eq!(pixels[0].map(|pixel| pixel.color), Some(0x001F));
+        assert_eq!(pixels[63].map(|pixel| pixel.color), Some(0x001F));
+        assert_eq!(pixels[64].map(|pixel| pixel.color), expected_outside);
+        assert_eq!(pixels[239].map(|pixel| pixel.color), expected_outside);
+    }
+}
@@ -0,0 +1,172 @@
+use std::fmt::Display;
+
+use crate::memory::{
+    io_handlers::{BLDALPHA, BLDCNT, BLDY},
+    memory::MemoryBus,
+};
+
+use super::layers::Layer;
+
+/// Coefficients are in 1/16ths and saturate at 16.
+const MAX_COEFFICIENT: u16 = 16;
+const MAX_CHANNEL: u16 = 0x1F;
+
+/// BLDCNT bits 6-7.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Effect {
+    #[default]
+    None,
+    AlphaBlend,
+    BrightnessIncrease,
+    BrightnessDecrease,
+}
+
+impl Effect {
+    pub fn from_bldcnt(bld_cnt: u16) -> Self {
+        match (bld_cnt >> 6) & 0x3 {
+            1 => Effect::AlphaBlend,
+            2 => Effect::BrightnessIncrease,
+            3 => Effect::BrightnessDecrease,
+            _ => Effect::None,
+        }
+    }
+}
+
+impl Display for Effect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Effect::None => "none".fmt(f),
+            Effect::AlphaBlend => "alpha blend".fmt(f),
+            Effect::BrightnessIncrease => "brighten".fmt(f),
+            Effect::BrightnessDecrease => "darken".fmt(f),
+        }
+    }
+}
+
+/// BLDCNT target bit of a layer, the same for the first and second
+/// target masks.
+pub(crate) fn target_bit(layer: Layer) -> u16 {
+    match layer {
+        Layer::Bg0 => 1 << 0,
+        Layer::Bg1 => 1 << 1,
+        Layer::Bg2 => 1 << 2,
+        Layer::Bg3 => 1 << 3,
+        Layer::Obj => 1 << 4,
+        Layer::Backdrop => 1 << 5,
+    }
+}
+
+/// The colour special effect set up by BLDCNT, BLDALPHA and BLDY.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ColorEffects {
+    pub effect: Effect,
+    first_targets: u16,
+    second_targets: u16,
+    eva: u16,
+    evb: u16,
+    evy: u16,
+}
+
+impl ColorEffects {
+    pub fn from_registers(memory: &dyn MemoryBus) -> Self {
+        let bld_cnt = memory.ppu_io_read(BLDCNT);
+        let bld_alpha = memory.ppu_io_read(BLDALPHA);
+        Self {
+            effect: Effect::from_bldcnt(bld_cnt),
+            first_targets: bld_cnt & 0x3F,
+            second_targets: (bld_cnt >> 8) & 0x3F,
+            eva: (bld_alpha & 0x1F).min(MAX_COEFFICIENT),
+            evb: ((bld_alpha >> 8) & 0x1F).min(MAX_COEFFICIENT),
+            evy: (memory.ppu_io_read(BLDY) & 0x1F).min(MAX_COEFFICIENT),
+        }
+    }
+
+    /// Applies the effect to the topmost pixel of a screen position, given
+    /// the layer below it for alpha blending. A semi-transparent OBJ
+    /// blends with a second target below it whatever the selected effect.
+    pub fn apply(
+        &self,
+        top: (Layer, u16),
+        below: Option<(Layer, u16)>,
+        semi_transparent: bool,
+    ) -> u16 {
+        let (top_layer, top_color) = top;
+        let blend_target = below.filter(|(layer, _)| self.second_targets & target_bit(*layer) > 0);
+        if let (true, Some((_, below_color))) = (semi_transparent, blend_target) {
+            return alpha_blend(top_color, below_color, self.eva, self.evb);
+        }
+        if self.first_targets & target_bit(top_layer) == 0 {
+            return top_color;
+        }
+        match self.effect {
+            Effect::None => top_color,
+            Effect::AlphaBlend => match blend_target {
+                Some((_, below_color)) => alpha_blend(top_color, below_color, self.eva, self.evb),
+                None => top_color,
+            },
+            Effect::BrightnessIncrease => brighten(top_color, self.evy),
+            Effect::BrightnessDecrease => darken(top_color, self.evy),
+        }
+    }
+}
+
+fn map_channels(color: u16, f: impl Fn(u16) -> u16) -> u16 {
+    (0..3).fold(0, |result, channel| {
+        let shift = channel * 5;
+        result | (f((color >> shift) & MAX_CHANNEL).min(MAX_CHANNEL) << shift)
+    })
+}
+
+/// `first * eva / 16 + second * evb / 16` per channel, saturating at 31.
+pub fn alpha_blend(first: u16, second: u16, eva: u16, evb: u16) -> u16 {
+    (0..3).fold(0, |result, channel| {
+        let shift = channel * 5;
+        let a = (first >> shift) & MAX_CHANNEL;
+        let b = (second >> shift) & MAX_CHANNEL;
+        result | (((a * eva + b * evb) >> 4).min(MAX_CHANNEL) << shift)
+    })
+}
+
+/// Moves each channel `evy` 16ths of the way towards white.
+pub fn brighten(color: u16, evy: u16) -> u16 {
+    map_channels(color, |channel| channel + (((MAX_CHANNEL - channel) * evy) >> 4))
+}
+
+/// Moves each channel `evy` 16ths of the way towards black.
+pub fn darken(color: u16, evy: u16) -> u16 {
+    map_channels(color, |channel| channel - ((channel * evy) >> 4))
+}
+
+#[cfg(test)]
+mod color_effects_tests {
+    use rstest::rstest;
+
+    use super::{alpha_blend, brighten, darken};
+
+    #[rstest]
+    #[case::half_and_half(0x001F, 0x7C00, 8, 8, 0x3C0F)]
+    #[case::saturates(0x7FFF, 0x7FFF, 16, 16, 0x7FFF)]
+    #[case::only_first(0x1234, 0x7FFF, 16, 0, 0x1234)]
+    fn alpha_blend_mixes_each_channel(
+        #[case] first: u16,
+        #[case] second: u16,
+        #[case] eva: u16,
+        #[case] evb: u16,
+        #[case] expected: u16,
+    ) {
+        assert_eq!(alpha_blend(first, second, eva, evb), expected);
+    }
+
+    #[rstest]
+    #[case::unchanged(0, 0x7FFF)]
+    #[case::half(8, 0x4210)]
+    #[case::black(16, 0x0000)]
+    fn brightness_decrease_fades_to_black(#[case] evy: u16, #[case] expected: u16) {
+        assert_eq!(darken(0x7FFF, evy), expected);
+    }
+
+    #[test]
+    fn full_brightness_increase_is_white() {
+        assert_eq!(brighten(0x0421, 16), 0x7FFF);
+    }
+}
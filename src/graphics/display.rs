@@ -1,9 +1,30 @@
 #![allow(unused)]
-use std::{sync::{Arc, Mutex}, time::Duration};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
-use sdl2::{event::Event, pixels::Color};
+use sdl2::{
+    event::Event,
+    keyboard::Keycode,
+    pixels::{Color, PixelFormatEnum},
+    render::Canvas,
+    video::Window,
+    Sdl,
+};
 
-use crate::memory::memory::GBAMemory;
+use crate::io::keypad::{Button, KeyInput, KeyState};
+
+use super::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// The GBA's refresh rate, 280896 cycles per frame at 2^24 Hz.
+pub const GBA_FRAME_RATE: f64 = 59.73;
+/// How close to a frame's deadline the limiter stops sleeping and spins,
+/// as a sleep can overshoot by a scheduler tick.
+const SPIN_THRESHOLD: Duration = Duration::from_millis(2);
+/// How long the display waits before checking again when no new frame
+/// has been finished.
+const NO_FRAME_BACKOFF: Duration = Duration::from_millis(1);
 
 #[repr(u32)]
 enum DisplayAddresses {
@@ -13,36 +34,302 @@ enum DisplayAddresses {
     BG0CNT = 0x4000_0008
 }
 
-pub fn start_display(memory: Arc<Mutex<GBAMemory>>) {
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-
-    let window = video_subsystem
-        .window("Gameboy Advance", 800, 600)
-        .position_centered()
-        .build()
-        .unwrap();
-
-    let mut canvas = window.into_canvas().build().unwrap();
-
-    canvas.set_draw_color(Color::RGB(0, 255, 255));
-    canvas.clear();
-    canvas.present();
-    let mut event_pump = sdl_context.event_pump().unwrap();
-    'running: loop {
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(sdl2::keyboard::Keycode::Escape),
-                    ..
-                } => {
-                    break 'running;
-                }
-                _ => {}
+fn button_for_key(keycode: Keycode) -> Option<Button> {
+    match keycode {
+        Keycode::Z => Some(Button::A),
+        Keycode::X => Some(Button::B),
+        Keycode::Backspace => Some(Button::Select),
+        Keycode::Return => Some(Button::Start),
+        Keycode::Right => Some(Button::Right),
+        Keycode::Left => Some(Button::Left),
+        Keycode::Up => Some(Button::Up),
+        Keycode::Down => Some(Button::Down),
+        Keycode::S => Some(Button::R),
+        Keycode::A => Some(Button::L),
+        _ => None,
+    }
+}
+
+/// A monotonic time source for `FrameLimiter`.
+pub trait Clock {
+    /// Time since a fixed starting point.
+    fn now(&self) -> Duration;
+    fn sleep(&mut self, duration: Duration);
+}
+
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self { start: Instant::now() }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// Passes finished frames from the emulator to the display. Only the
+/// latest is kept, so a display that falls behind shows the newest frame
+/// instead of working through a backlog. Clones share the same frame.
+#[derive(Clone, Debug, Default)]
+pub struct FrameHandoff {
+    latest: Arc<Mutex<Option<Vec<u16>>>>,
+}
+
+impl FrameHandoff {
+    /// Replaces any frame the display hasn't taken yet.
+    pub fn publish(&self, framebuffer: &[u16]) {
+        *self.latest.lock().unwrap() = Some(framebuffer.to_vec());
+    }
+
+    /// The frame published since the last call, if any.
+    pub fn take_latest(&self) -> Option<Vec<u16>> {
+        self.latest.lock().unwrap().take()
+    }
+}
+
+/// Paces the display to a frame rate, sleeping for most of each wait and
+/// spinning for the rest. A late frame is shown straight away, and once a
+/// whole frame behind the frames after it are paced from it rather than
+/// hurried to catch up.
+pub struct FrameLimiter<C: Clock> {
+    clock: C,
+    /// None when running unlimited.
+    frame_period: Option<Duration>,
+    next_frame: Option<Duration>,
+}
+
+impl<C: Clock> FrameLimiter<C> {
+    pub fn new(clock: C, frame_rate: Option<f64>) -> Self {
+        Self {
+            clock,
+            frame_period: frame_rate.map(|rate| Duration::from_secs_f64(1.0 / rate)),
+            next_frame: None,
+        }
+    }
+
+    /// Waits until the next frame is due, returning the time it is shown.
+    pub fn wait_for_next_frame(&mut self) -> Duration {
+        let Some(period) = self.frame_period else {
+            return self.clock.now();
+        };
+        let deadline = *self.next_frame.get_or_insert_with(|| self.clock.now());
+        loop {
+            let now = self.clock.now();
+            if now >= deadline {
+                break;
+            }
+            let remaining = deadline - now;
+            if remaining > SPIN_THRESHOLD {
+                self.clock.sleep(remaining - SPIN_THRESHOLD);
+            } else {
+                std::hint::spin_loop();
             }
         }
+        let now = self.clock.now();
+        let next_frame = deadline + period;
+        self.next_frame = Some(if now > next_frame { now + period } else { next_frame });
+        now
+    }
+}
+
+/// The window frames are shown in. SDL wants its window on the main
+/// thread, so it's opened there and the debugger runs beside it.
+pub struct Display {
+    sdl_context: Sdl,
+    canvas: Canvas<Window>,
+}
+
+impl Display {
+    /// Fails when there's nothing to show a window on, such as in a
+    /// headless session.
+    pub fn open() -> Result<Self, String> {
+        let sdl_context = sdl2::init()?;
+        let video_subsystem = sdl_context.video()?;
+
+        let window = video_subsystem
+            .window("Gameboy Advance", 3 * SCREEN_WIDTH as u32, 3 * SCREEN_HEIGHT as u32)
+            .position_centered()
+            .build()
+            .map_err(|error| error.to_string())?;
+        let canvas = window.into_canvas().build().map_err(|error| error.to_string())?;
+
+        Ok(Self { sdl_context, canvas })
+    }
+
+    /// Shows the frames handed off by the emulator, at most one per period
+    /// of `frame_rate`, or as often as it can when that is None. Keys
+    /// pressed in the window go to `key_input`. Returns once the window is
+    /// closed or `keep_running` is false.
+    pub fn run(
+        mut self,
+        frames: FrameHandoff,
+        key_input: KeyInput,
+        frame_rate: Option<f64>,
+        keep_running: impl Fn() -> bool,
+    ) -> Result<(), String> {
+        let canvas = &mut self.canvas;
+        let texture_creator = canvas.texture_creator();
+        // the PPU's colours are already laid out as BGR555
+        let mut texture = texture_creator
+            .create_texture_streaming(PixelFormatEnum::BGR555, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32)
+            .map_err(|error| error.to_string())?;
+
+        canvas.set_draw_color(Color::RGB(0, 255, 255));
+        canvas.clear();
         canvas.present();
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+        let mut event_pump = self.sdl_context.event_pump()?;
+        let mut keys = KeyState::default();
+        let mut frame_limiter = FrameLimiter::new(SystemClock::default(), frame_rate);
+        while keep_running() {
+            for event in event_pump.poll_iter() {
+                match event {
+                    Event::Quit { .. }
+                    | Event::KeyDown {
+                        keycode: Some(sdl2::keyboard::Keycode::Escape),
+                        ..
+                    } => {
+                        return Ok(());
+                    }
+                    Event::KeyDown {
+                        keycode: Some(keycode),
+                        ..
+                    } => {
+                        if let Some(button) = button_for_key(keycode) {
+                            keys = keys.with(button, true);
+                            key_input.set_keys(keys);
+                        }
+                    }
+                    Event::KeyUp {
+                        keycode: Some(keycode),
+                        ..
+                    } => {
+                        if let Some(button) = button_for_key(keycode) {
+                            keys = keys.with(button, false);
+                            key_input.set_keys(keys);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            frame_limiter.wait_for_next_frame();
+            // unlimited, the wait returns at once, so back off rather than
+            // spin redrawing the same frame until the emulator finishes one
+            let Some(frame) = frames.take_latest() else {
+                std::thread::sleep(NO_FRAME_BACKOFF);
+                continue;
+            };
+            let pixels: Vec<u8> = frame.iter().flat_map(|pixel| pixel.to_le_bytes()).collect();
+            texture
+                .update(None, &pixels, 2 * SCREEN_WIDTH)
+                .map_err(|error| error.to_string())?;
+            canvas.copy(&texture, None, None)?;
+            canvas.present();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod frame_limiter_tests {
+    use std::{cell::Cell, rc::Rc, time::Duration};
+
+    use super::{Clock, FrameHandoff, FrameLimiter, GBA_FRAME_RATE};
+
+    /// Each reading of the time takes a microsecond, so spinning moves on.
+    #[derive(Clone, Default)]
+    struct FakeClock {
+        now: Rc<Cell<Duration>>,
+    }
+
+    impl FakeClock {
+        fn advance(&self, duration: Duration) {
+            self.now.set(self.now.get() + duration);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Duration {
+            self.advance(Duration::from_micros(1));
+            self.now.get()
+        }
+
+        fn sleep(&mut self, duration: Duration) {
+            self.advance(duration);
+        }
+    }
+
+    const TOLERANCE: Duration = Duration::from_micros(10);
+
+    fn frame_period() -> Duration {
+        Duration::from_secs_f64(1.0 / GBA_FRAME_RATE)
+    }
+
+    #[test]
+    fn frames_are_shown_at_the_frame_rate_without_drifting() {
+        let clock = FakeClock::default();
+        let mut limiter = FrameLimiter::new(clock.clone(), Some(GBA_FRAME_RATE));
+
+        let first = limiter.wait_for_next_frame();
+        for frame in 1..=120 {
+            // drawing takes a few milliseconds of each frame
+            clock.advance(Duration::from_millis(3));
+            let shown = limiter.wait_for_next_frame() - first;
+            let due = frame_period() * frame;
+            assert!(shown.abs_diff(due) < TOLERANCE, "frame {frame} at {shown:?}");
+        }
+    }
+
+    #[test]
+    fn late_frame_is_shown_at_once_and_pacing_restarts_from_it() {
+        let clock = FakeClock::default();
+        let mut limiter = FrameLimiter::new(clock.clone(), Some(GBA_FRAME_RATE));
+        limiter.wait_for_next_frame();
+
+        clock.advance(frame_period() * 3);
+        let behind = clock.now();
+        let late = limiter.wait_for_next_frame();
+        assert!(late - behind < TOLERANCE);
+
+        let next = limiter.wait_for_next_frame();
+        assert!((next - late).abs_diff(frame_period()) < TOLERANCE);
+    }
+
+    #[test]
+    fn display_behind_the_emulator_shows_the_latest_frame() {
+        let clock = FakeClock::default();
+        let mut limiter = FrameLimiter::new(clock.clone(), Some(GBA_FRAME_RATE));
+        let frames = FrameHandoff::default();
+        limiter.wait_for_next_frame();
+
+        for frame in 0..3 {
+            frames.publish(&[frame; 4]);
+        }
+        clock.advance(frame_period() * 3);
+        let behind = clock.now();
+
+        assert!(limiter.wait_for_next_frame() - behind < TOLERANCE);
+        assert_eq!(frames.take_latest(), Some(vec![2; 4]));
+        assert_eq!(frames.take_latest(), None);
+    }
+
+    #[test]
+    fn unlimited_never_waits() {
+        let clock = FakeClock::default();
+        let mut limiter = FrameLimiter::new(clock.clone(), None);
+
+        let first = limiter.wait_for_next_frame();
+        let second = limiter.wait_for_next_frame();
+
+        assert!(second - first < TOLERANCE);
     }
 }
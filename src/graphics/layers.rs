@@ -0,0 +1,180 @@
+use std::str::FromStr;
+
+use super::{
+    background::LayerLine,
+    color_effects::ColorEffects,
+    objects::ObjLine,
+    ppu::SCREEN_WIDTH,
+    window::{effects_enabled, WindowLine},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layer {
+    Bg0,
+    Bg1,
+    Bg2,
+    Bg3,
+    Obj,
+    Backdrop,
+}
+
+impl Layer {
+    pub const BACKGROUNDS: [Layer; 4] = [Layer::Bg0, Layer::Bg1, Layer::Bg2, Layer::Bg3];
+
+    /// Tie-break between layers sharing a priority: OBJ sits above every
+    /// background and lower numbered backgrounds sit above higher ones.
+    fn order(self) -> u8 {
+        match self {
+            Layer::Obj => 0,
+            Layer::Bg0 => 1,
+            Layer::Bg1 => 2,
+            Layer::Bg2 => 3,
+            Layer::Bg3 => 4,
+            Layer::Backdrop => 5,
+        }
+    }
+}
+
+impl FromStr for Layer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bg0" => Ok(Layer::Bg0),
+            "bg1" => Ok(Layer::Bg1),
+            "bg2" => Ok(Layer::Bg2),
+            "bg3" => Ok(Layer::Bg3),
+            "obj" => Ok(Layer::Obj),
+            "backdrop" => Ok(Layer::Backdrop),
+            _ => Err(format!("Unknown layer {}", s)),
+        }
+    }
+}
+
+/// Sort key for a candidate pixel; the smallest value is drawn on top.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PixelPriority {
+    priority: u8,
+    order: u8,
+}
+
+impl PixelPriority {
+    pub fn new(priority: u8, layer: Layer) -> Self {
+        Self {
+            priority,
+            order: layer.order(),
+        }
+    }
+
+    pub fn backdrop() -> Self {
+        Self::new(4, Layer::Backdrop)
+    }
+}
+
+pub struct BackgroundLine {
+    pub layer: Layer,
+    pub priority: u8,
+    pub pixels: LayerLine,
+}
+
+/// Where a composed pixel came from, as reported by the pixel inspector.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PixelSource {
+    pub layer: Layer,
+    pub priority: u8,
+    /// Entry of the BG or OBJ palette, None for direct colour bitmaps.
+    pub palette_index: Option<u16>,
+}
+
+impl PixelSource {
+    /// The backdrop is BG palette entry 0 and sits below priority 3.
+    pub fn backdrop() -> Self {
+        Self {
+            layer: Layer::Backdrop,
+            priority: 4,
+            palette_index: Some(0),
+        }
+    }
+}
+
+/// A pixel competing to be drawn at one screen position.
+#[derive(Clone, Copy)]
+struct Candidate {
+    priority: PixelPriority,
+    color: u16,
+    source: PixelSource,
+    semi_transparent: bool,
+}
+
+/// Resolves each screen pixel to the topmost opaque layer, falling back to
+/// the backdrop colour, then applies the colour special effect using the
+/// layer beneath it where the window allows effects. With `sources`, also
+/// records which layer won each pixel.
+pub fn compose_scanline(
+    backgrounds: &[BackgroundLine],
+    objects: Option<&ObjLine>,
+    backdrop: u16,
+    effects: &ColorEffects,
+    window: Option<&WindowLine>,
+    output: &mut [u16],
+    mut sources: Option<&mut [PixelSource]>,
+) {
+    for x in 0..SCREEN_WIDTH {
+        let mut top = Candidate {
+            priority: PixelPriority::backdrop(),
+            color: backdrop,
+            source: PixelSource::backdrop(),
+            semi_transparent: false,
+        };
+        let mut below: Option<Candidate> = None;
+        let mut consider = |candidate: Candidate| {
+            if candidate.priority < top.priority {
+                below = Some(top);
+                top = candidate;
+            } else if below.is_none_or(|below| candidate.priority < below.priority) {
+                below = Some(candidate);
+            }
+        };
+
+        for background in backgrounds {
+            if let Some(pixel) = background.pixels[x] {
+                consider(Candidate {
+                    priority: PixelPriority::new(background.priority, background.layer),
+                    color: pixel.color,
+                    source: PixelSource {
+                        layer: background.layer,
+                        priority: background.priority,
+                        palette_index: pixel.palette_index,
+                    },
+                    semi_transparent: false,
+                });
+            }
+        }
+
+        if let Some(Some(obj_pixel)) = objects.map(|line| line[x]) {
+            consider(Candidate {
+                priority: PixelPriority::new(obj_pixel.priority, Layer::Obj),
+                color: obj_pixel.color,
+                source: PixelSource {
+                    layer: Layer::Obj,
+                    priority: obj_pixel.priority,
+                    palette_index: Some(obj_pixel.palette_index),
+                },
+                semi_transparent: obj_pixel.semi_transparent,
+            });
+        }
+
+        output[x] = if effects_enabled(window, x) {
+            effects.apply(
+                (top.source.layer, top.color),
+                below.map(|below| (below.source.layer, below.color)),
+                top.semi_transparent,
+            )
+        } else {
+            top.color
+        };
+        if let Some(sources) = sources.as_deref_mut() {
+            sources[x] = top.source;
+        }
+    }
+}
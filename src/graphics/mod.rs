@@ -1,2 +1,8 @@
 pub mod display;
+pub mod background;
+pub mod color_effects;
+pub mod layers;
+pub mod objects;
 pub mod ppu;
+pub mod screenshot;
+pub mod window;
@@ -0,0 +1,344 @@
+use crate::memory::{io_handlers::MOSAIC, memory::MemoryBus};
+
+use super::{
+    background::{PALETTE_BASE, VRAM_BASE},
+    ppu::SCREEN_WIDTH,
+};
+
+pub const OAM_BASE: usize = 0x7000000;
+const OBJ_TILE_BASE: usize = VRAM_BASE + 0x10000;
+const OBJ_PALETTE_BASE: usize = PALETTE_BASE + 0x200;
+const OAM_ENTRIES: usize = 128;
+/// In bitmap modes the lower half of OBJ VRAM holds the second frame, so
+/// only tiles 512-1023 are usable by sprites.
+const BITMAP_MODE_FIRST_OBJ_TILE: usize = 512;
+
+const OBJ_1D_MAPPING: u16 = 1 << 6;
+const HBLANK_INTERVAL_FREE: u16 = 1 << 5;
+/// OBJ rendering cycles available per scanline, fewer when the OBJ unit
+/// is kept off the bus during HBlank.
+const OBJ_CYCLES_PER_LINE: u32 = 1210;
+const OBJ_CYCLES_PER_LINE_HBLANK_FREE: u32 = 954;
+const AFFINE_OBJ_SETUP_CYCLES: u32 = 10;
+/// Each group of four affine parameters is spread over the unused fourth
+/// halfwords of four consecutive OAM entries.
+const AFFINE_GROUP_SIZE: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ObjPixel {
+    pub color: u16,
+    pub priority: u8,
+    /// Entry of the OBJ palette the colour came from.
+    pub palette_index: u16,
+    /// Alpha blends with the layer below whatever BLDCNT selects.
+    pub semi_transparent: bool,
+}
+
+pub type ObjLine = [Option<ObjPixel>; SCREEN_WIDTH];
+/// Pixels covered by OBJ window sprites.
+pub type ObjWindowLine = [bool; SCREEN_WIDTH];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ObjMode {
+    Normal,
+    SemiTransparent,
+    Window,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ObjAttributes {
+    pub y: u16,
+    pub affine: bool,
+    /// Affine sprites can be drawn in a bounding box twice their size so
+    /// rotated corners aren't clipped.
+    pub double_size: bool,
+    pub disabled: bool,
+    pub mode: ObjMode,
+    pub mosaic: bool,
+    pub eight_bpp: bool,
+    pub x: u16,
+    pub affine_group: usize,
+    pub horizontal_flip: bool,
+    pub vertical_flip: bool,
+    pub width: u16,
+    pub height: u16,
+    pub tile: usize,
+    pub priority: u8,
+    pub palette_bank: u16,
+}
+
+impl ObjAttributes {
+    pub fn from_oam(memory: &dyn MemoryBus, index: usize) -> Self {
+        let entry = OAM_BASE + index * 8;
+        let attribute0 = memory.peeku16(entry);
+        let attribute1 = memory.peeku16(entry + 2);
+        let attribute2 = memory.peeku16(entry + 4);
+
+        let affine = attribute0 & (1 << 8) > 0;
+        let shape = (attribute0 >> 14) as usize;
+        let size = (attribute1 >> 14) as usize;
+        let (width, height) = match (shape, size) {
+            (0, 0) => (8, 8),
+            (0, 1) => (16, 16),
+            (0, 2) => (32, 32),
+            (0, 3) => (64, 64),
+            (1, 0) => (16, 8),
+            (1, 1) => (32, 8),
+            (1, 2) => (32, 16),
+            (1, 3) => (64, 32),
+            (2, 0) => (8, 16),
+            (2, 1) => (8, 32),
+            (2, 2) => (16, 32),
+            (2, 3) => (32, 64),
+            // prohibited shape
+            _ => (8, 8),
+        };
+
+        Self {
+            y: attribute0 & 0xFF,
+            affine,
+            double_size: affine && attribute0 & (1 << 9) > 0,
+            disabled: !affine && attribute0 & (1 << 9) > 0,
+            mode: match (attribute0 >> 10) & 0x3 {
+                1 => ObjMode::SemiTransparent,
+                2 => ObjMode::Window,
+                // 3 is prohibited
+                _ => ObjMode::Normal,
+            },
+            mosaic: attribute0 & (1 << 12) > 0,
+            eight_bpp: attribute0 & (1 << 13) > 0,
+            x: attribute1 & 0x1FF,
+            affine_group: ((attribute1 >> 9) & 0x1F) as usize,
+            horizontal_flip: !affine && attribute1 & (1 << 12) > 0,
+            vertical_flip: !affine && attribute1 & (1 << 13) > 0,
+            width,
+            height,
+            tile: (attribute2 & 0x3FF) as usize,
+            priority: ((attribute2 >> 10) & 0x3) as u8,
+            palette_bank: attribute2 >> 12,
+        }
+    }
+
+    /// Size of the screen area the sprite covers.
+    fn bounds(&self) -> (u16, u16) {
+        if self.double_size {
+            (self.width * 2, self.height * 2)
+        } else {
+            (self.width, self.height)
+        }
+    }
+
+    /// Maps pixel (x, y) of a regular sprite's bounds to the texture.
+    fn regular_texel(&self, x: u16, y: u16) -> (u16, u16) {
+        let x = if self.horizontal_flip { self.width - 1 - x } else { x };
+        let y = if self.vertical_flip { self.height - 1 - y } else { y };
+        (x, y)
+    }
+
+    /// Maps pixel (x, y) of an affine sprite's bounds to the texture by
+    /// transforming its offset from the centre, or None when it lands
+    /// outside the texture.
+    fn affine_texel(&self, parameters: &ObjAffineParameters, x: u16, y: u16) -> Option<(u16, u16)> {
+        let (bounds_width, bounds_height) = self.bounds();
+        let dx = x as i32 - bounds_width as i32 / 2;
+        let dy = y as i32 - bounds_height as i32 / 2;
+        let texture_x = ((parameters.pa as i32 * dx + parameters.pb as i32 * dy) >> 8) + self.width as i32 / 2;
+        let texture_y = ((parameters.pc as i32 * dx + parameters.pd as i32 * dy) >> 8) + self.height as i32 / 2;
+        if texture_x < 0 || texture_x >= self.width as i32 || texture_y < 0 || texture_y >= self.height as i32 {
+            return None;
+        }
+        Some((texture_x as u16, texture_y as u16))
+    }
+
+    /// Returns the OBJ tile number holding sprite-local pixel (x, y).
+    fn tile_at(&self, x: u16, y: u16, one_dimensional: bool) -> usize {
+        let tile_step = if self.eight_bpp { 2 } else { 1 };
+        let row_stride = if one_dimensional {
+            (self.width as usize / 8) * tile_step
+        } else {
+            32
+        };
+        (self.tile + (y as usize / 8) * row_stride + (x as usize / 8) * tile_step) & 0x3FF
+    }
+}
+
+/// Rotation/scaling matrix of an affine sprite, in 8.8 fixed point.
+#[derive(Clone, Copy, Debug)]
+struct ObjAffineParameters {
+    pa: i16,
+    pb: i16,
+    pc: i16,
+    pd: i16,
+}
+
+impl ObjAffineParameters {
+    fn from_oam(memory: &dyn MemoryBus, group: usize) -> Self {
+        let base = OAM_BASE + group * AFFINE_GROUP_SIZE;
+        Self {
+            pa: memory.peeku16(base + 6) as i16,
+            pb: memory.peeku16(base + 14) as i16,
+            pc: memory.peeku16(base + 22) as i16,
+            pd: memory.peeku16(base + 30) as i16,
+        }
+    }
+}
+
+/// Returns the OBJ cycles available to each scanline under `disp_cnt`.
+pub fn obj_cycle_budget(disp_cnt: u16) -> u32 {
+    if disp_cnt & HBLANK_INTERVAL_FREE > 0 {
+        OBJ_CYCLES_PER_LINE_HBLANK_FREE
+    } else {
+        OBJ_CYCLES_PER_LINE
+    }
+}
+
+/// Returns the palette address of sprite-local pixel (x, y), or None when
+/// it is transparent.
+fn obj_color_address(
+    obj: &ObjAttributes,
+    x: u16,
+    y: u16,
+    one_dimensional: bool,
+    bitmap_mode: bool,
+    memory: &dyn MemoryBus,
+) -> Option<usize> {
+    let tile = obj.tile_at(x, y, one_dimensional);
+    if bitmap_mode && tile < BITMAP_MODE_FIRST_OBJ_TILE {
+        return None;
+    }
+
+    let tile_address = OBJ_TILE_BASE + tile * 32;
+    let (pixel_x, pixel_y) = ((x % 8) as usize, (y % 8) as usize);
+    if obj.eight_bpp {
+        let palette_index = memory.peek(tile_address + pixel_y * 8 + pixel_x);
+        (palette_index != 0).then(|| OBJ_PALETTE_BASE + palette_index as usize * 2)
+    } else {
+        let pair = memory.peek(tile_address + pixel_y * 4 + pixel_x / 2);
+        let palette_index = (pair >> (4 * (pixel_x & 1))) & 0xF;
+        (palette_index != 0).then(|| {
+            OBJ_PALETTE_BASE + (obj.palette_bank as usize * 16 + palette_index as usize) * 2
+        })
+    }
+}
+
+/// Calls `draw` with the screen x and texture coordinates of every pixel
+/// of the sprites in `mode` that cover `line`, in OAM order. Mosaic
+/// sprites repeat the texel at the top left of each mosaic block.
+/// With a `cycle_budget`, every sprite on the line spends rendering cycles
+/// and sprites past the point the budget runs out are dropped.
+fn for_each_obj_pixel(
+    line: u16,
+    mode: ObjMode,
+    cycle_budget: Option<u32>,
+    memory: &dyn MemoryBus,
+    mut draw: impl FnMut(&ObjAttributes, usize, u16, u16),
+) {
+    let mosaic = memory.ppu_io_read(MOSAIC);
+    let (mosaic_width, mosaic_height) = ((mosaic >> 8 & 0xF) + 1, (mosaic >> 12 & 0xF) + 1);
+    let mut cycles_used = 0;
+    for index in 0..OAM_ENTRIES {
+        let obj = ObjAttributes::from_oam(memory, index);
+        if obj.disabled {
+            continue;
+        }
+        let (bounds_width, bounds_height) = obj.bounds();
+        // Y is 8 bits and X is 9 bits, so sprites hanging off the bottom or
+        // right edge wrap around to the top or left
+        let mut sprite_y = line.wrapping_sub(obj.y) & 0xFF;
+        if sprite_y >= bounds_height {
+            continue;
+        }
+        if let Some(cycle_budget) = cycle_budget {
+            cycles_used += if obj.affine {
+                AFFINE_OBJ_SETUP_CYCLES + 2 * obj.width as u32
+            } else {
+                obj.width as u32
+            };
+            if cycles_used > cycle_budget {
+                return;
+            }
+        }
+        if (obj.mode == ObjMode::Window) != (mode == ObjMode::Window) {
+            continue;
+        }
+
+        if obj.mosaic {
+            sprite_y -= sprite_y.min(line % mosaic_height);
+        }
+        let affine_parameters = obj
+            .affine
+            .then(|| ObjAffineParameters::from_oam(memory, obj.affine_group));
+
+        for bounds_x in 0..bounds_width {
+            let screen_x = ((obj.x + bounds_x) & 0x1FF) as usize;
+            if screen_x >= SCREEN_WIDTH {
+                continue;
+            }
+            let mut sprite_x = bounds_x;
+            if obj.mosaic {
+                sprite_x -= sprite_x.min(screen_x as u16 % mosaic_width);
+            }
+            let texel = match &affine_parameters {
+                Some(parameters) => obj.affine_texel(parameters, sprite_x, sprite_y),
+                None => Some(obj.regular_texel(sprite_x, sprite_y)),
+            };
+            if let Some((texture_x, texture_y)) = texel {
+                draw(&obj, screen_x, texture_x, texture_y);
+            }
+        }
+    }
+}
+
+/// Draws the regular and affine sprites that cover `line`. Among
+/// overlapping sprites the lowest priority value wins, then the lowest
+/// OAM index. OBJ window sprites are never drawn, see
+/// `render_obj_window_line`.
+pub fn render_obj_line(
+    line: u16,
+    disp_cnt: u16,
+    bitmap_mode: bool,
+    cycle_budget: Option<u32>,
+    memory: &dyn MemoryBus,
+) -> ObjLine {
+    let mut obj_line: ObjLine = [None; SCREEN_WIDTH];
+    let one_dimensional = disp_cnt & OBJ_1D_MAPPING > 0;
+
+    for_each_obj_pixel(line, ObjMode::Normal, cycle_budget, memory, |obj, screen_x, x, y| {
+        if let Some(existing) = obj_line[screen_x] {
+            if existing.priority <= obj.priority {
+                return;
+            }
+        }
+        if let Some(color_address) = obj_color_address(obj, x, y, one_dimensional, bitmap_mode, memory) {
+            obj_line[screen_x] = Some(ObjPixel {
+                color: memory.peeku16(color_address) & 0x7FFF,
+                priority: obj.priority,
+                palette_index: ((color_address - OBJ_PALETTE_BASE) / 2) as u16,
+                semi_transparent: obj.mode == ObjMode::SemiTransparent,
+            });
+        }
+    });
+
+    obj_line
+}
+
+/// Marks the opaque pixels of OBJ window sprites on `line`; their colour
+/// is never displayed.
+pub fn render_obj_window_line(
+    line: u16,
+    disp_cnt: u16,
+    bitmap_mode: bool,
+    cycle_budget: Option<u32>,
+    memory: &dyn MemoryBus,
+) -> ObjWindowLine {
+    let mut window_line: ObjWindowLine = [false; SCREEN_WIDTH];
+    let one_dimensional = disp_cnt & OBJ_1D_MAPPING > 0;
+
+    for_each_obj_pixel(line, ObjMode::Window, cycle_budget, memory, |obj, screen_x, x, y| {
+        if obj_color_address(obj, x, y, one_dimensional, bitmap_mode, memory).is_some() {
+            window_line[screen_x] = true;
+        }
+    });
+
+    window_line
+}
@@ -1,9 +1,32 @@
-use crate::memory::{io_handlers::{DISPSTAT, IF, IO_BASE, VCOUNT}, memory::MemoryBus};
+use std::ops::Range;
+
+use crate::arm7tdmi::interrupts::{request_interrupt, Interrupt};
+use crate::memory::{io_handlers::{BG2CNT, DISPCNT, DISPSTAT, VCOUNT}, memory::MemoryBus};
+use crate::state::savestate::{StateError, StateReader, StateWriter};
+use crate::types::WORD;
+
+use super::{
+    background::{
+        render_bitmap_line, AffineBackground, AffineParameters, AffineReference, BitmapMode,
+        LayerLine, TextBackground, PALETTE_BASE,
+    },
+    color_effects::ColorEffects,
+    layers::{compose_scanline, BackgroundLine, Layer, PixelSource},
+    objects::{obj_cycle_budget, render_obj_line, render_obj_window_line},
+    window::{apply_window, window_line},
+};
+
+pub const SCREEN_WIDTH: usize = 240;
+pub const SCREEN_HEIGHT: usize = 160;
 
 const HDRAW: u64 = 240;
 const HBLANK: u64 = 68;
 const VDRAW: u64 = 160;
 const VBLANK: u64 = 68;
+/// DMA3 video capture runs on the HBlanks of lines 2-161, two lines
+/// behind the display.
+const VIDEO_CAPTURE_START: u64 = 2;
+const VIDEO_CAPTURE_END: u64 = VDRAW + 2;
 
 const VBLANK_FLAG: u16 = 1 << 0;
 const HBLANK_FLAG: u16 = 1 << 1;
@@ -11,61 +34,860 @@ const VCOUNTER_FLAG: u16 = 1 << 2;
 const VBLANK_ENABLE: u16 = 1 << 3;
 const HBLANK_ENABLE: u16 = 1 << 4;
 
-#[derive(Default, Debug)]
+const BG_MODE_MASK: u16 = 0x7;
+const FORCED_BLANK: u16 = 1 << 7;
+const BG0_ENABLE: u16 = 1 << 8;
+const BG2_ENABLE: u16 = 1 << 10;
+const OBJ_ENABLE: u16 = 1 << 12;
+const WHITE: u16 = 0x7FFF;
+
+/// Display periods entered during a call to `advance_ppu`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PPUEvents {
+    pub hblank: bool,
+    pub vblank: bool,
+    /// HBlank of a line on which a DMA3 video capture transfer runs.
+    pub video_capture: bool,
+    /// Line where video capture stops and DMA3 disables itself.
+    pub video_capture_end: bool,
+}
+
+#[derive(Debug)]
 pub struct PPU {
-    usable_cycles: u64,
+    pub(crate) usable_cycles: u64,
     pub x: u64,
     pub y: u64,
+    pub framebuffer: Vec<u16>,
+    pub frame_count: u64,
+    /// Drops sprites that don't fit in the scanline's OBJ cycle budget,
+    /// like hardware does.
+    pub limit_obj_cycles: bool,
+    /// Records which layer drew each pixel for `pixel_source`. Off by
+    /// default, since it costs a write per pixel.
+    pub record_pixel_sources: bool,
+    pixel_sources: Vec<PixelSource>,
+    /// Set while `render_isolated_frame` draws a single layer.
+    isolated_layer: Option<Layer>,
+    bg2_reference: AffineReference,
+    bg3_reference: AffineReference,
+}
+
+impl Default for PPU {
+    fn default() -> Self {
+        Self {
+            usable_cycles: 0,
+            x: 0,
+            y: 0,
+            framebuffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_count: 0,
+            limit_obj_cycles: false,
+            record_pixel_sources: false,
+            pixel_sources: vec![PixelSource::backdrop(); SCREEN_WIDTH * SCREEN_HEIGHT],
+            isolated_layer: None,
+            bg2_reference: AffineReference::default(),
+            bg3_reference: AffineReference::default(),
+        }
+    }
 }
 
 impl PPU {
-    pub fn advance_ppu(&mut self, cycles: u8, memory: &mut Box<dyn MemoryBus>) {
+    /// The layer that drew screen pixel (x, y) when it was last rendered,
+    /// if pixel sources are being recorded.
+    pub fn pixel_source(&self, x: usize, y: usize) -> Option<PixelSource> {
+        if !self.record_pixel_sources || x >= SCREEN_WIDTH || y >= SCREEN_HEIGHT {
+            return None;
+        }
+        Some(self.pixel_sources[y * SCREEN_WIDTH + x])
+    }
+
+    /// Writes the PPU's position in the frame and its affine reference
+    /// points. The framebuffer is redrawn by the next frame anyway.
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.u64(self.usable_cycles);
+        writer.word(self.x as WORD);
+        writer.word(self.y as WORD);
+        writer.u64(self.frame_count);
+        for reference in [self.bg2_reference, self.bg3_reference] {
+            writer.word(reference.x as WORD);
+            writer.word(reference.y as WORD);
+        }
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let offset = reader.offset();
+        self.usable_cycles = match reader.u64()? {
+            cycles if cycles < 4 => cycles,
+            _ => return Err(StateError::InvalidValue { offset }),
+        };
+        self.x = reader.index((HDRAW + HBLANK) as usize)? as u64;
+        self.y = reader.index((VDRAW + VBLANK) as usize)? as u64;
+        self.frame_count = reader.u64()?;
+        for reference in [&mut self.bg2_reference, &mut self.bg3_reference] {
+            reference.x = reader.word()? as i32;
+            reference.y = reader.word()? as i32;
+        }
+        Ok(())
+    }
+
+    /// Takes the position from a PPU loaded with `load_state`, keeping the
+    /// framebuffer and the rendering options.
+    pub fn restore(&mut self, loaded: PPU) {
+        self.usable_cycles = loaded.usable_cycles;
+        self.x = loaded.x;
+        self.y = loaded.y;
+        self.frame_count = loaded.frame_count;
+        self.bg2_reference = loaded.bg2_reference;
+        self.bg3_reference = loaded.bg3_reference;
+    }
+
+    pub fn advance_ppu(&mut self, cycles: u8, memory: &mut Box<dyn MemoryBus>) -> PPUEvents {
+        let mut events = PPUEvents::default();
         self.usable_cycles += cycles as u64;
         let dots = self.usable_cycles / 4;
         if dots < 1 {
-            return;
+            return events;
         }
         self.usable_cycles %= 4;
+        let previous_x = self.x;
         self.x += dots;
-        let mut disp_stat = memory.readu16(IO_BASE + DISPSTAT).data;
-        let mut interrupt_flags_register = memory.readu16(IO_BASE + IF).data;
+        let mut disp_stat = memory.ppu_io_read(DISPSTAT);
+        if previous_x < HDRAW && self.x >= HDRAW {
+            if self.y < VDRAW {
+                self.render_scanline(self.y as usize, memory.as_ref());
+                events.hblank = true;
+            }
+            events.video_capture = (VIDEO_CAPTURE_START..VIDEO_CAPTURE_END).contains(&self.y);
+            // unlike HBlank DMAs, the flag and IRQ come on VBlank lines too
+            disp_stat |= HBLANK_FLAG;
+            if disp_stat & HBLANK_ENABLE > 0 {
+                request_interrupt(memory.as_mut(), Interrupt::HBlank);
+            }
+        }
         if self.x >= (HDRAW + HBLANK) {
             self.y += 1;
             self.x %= HDRAW + HBLANK;
+            disp_stat &= !HBLANK_FLAG;
 
-            if self.y >= VDRAW && (disp_stat & VBLANK_ENABLE) > 0 {
-                disp_stat |= VBLANK_FLAG;
-                interrupt_flags_register |= VBLANK_FLAG;
+            if self.y == VDRAW {
+                events.vblank = true;
+                self.frame_count += 1;
+                self.latch_affine_references(memory.as_ref());
             }
+            events.video_capture_end = self.y == VIDEO_CAPTURE_END;
 
             if self.y >= (VDRAW + VBLANK) {
                 self.y %= VDRAW + VBLANK;
             }
+
+            // the flag is already clear on the last line of VBlank
+            if (VDRAW..VDRAW + VBLANK - 1).contains(&self.y) {
+                disp_stat |= VBLANK_FLAG;
+            } else {
+                disp_stat &= !VBLANK_FLAG;
+            }
+            if self.y == VDRAW && (disp_stat & VBLANK_ENABLE) > 0 {
+                request_interrupt(memory.as_mut(), Interrupt::VBlank);
+            }
             memory.ppu_io_write(VCOUNT, self.y as u16);
         }
         memory.ppu_io_write(DISPSTAT, disp_stat);
-        memory.ppu_io_write(IF, interrupt_flags_register);
+        events
+    }
+
+    /// The cycles into the current dot and the dot the PPU would be at
+    /// after `cycles` more, as long as that doesn't reach a boundary.
+    pub fn position_after(&self, cycles: u64) -> (u64, u64) {
+        let usable_cycles = self.usable_cycles + cycles;
+        (usable_cycles % 4, self.x + usable_cycles / 4)
+    }
+
+    pub fn in_hblank(&self) -> bool {
+        self.x >= HDRAW
+    }
+
+    /// Cycles until the PPU next enters HBlank or starts a new line.
+    pub fn cycles_until_next_boundary(&self) -> u64 {
+        let boundary = if self.in_hblank() { HDRAW + HBLANK } else { HDRAW };
+        (boundary - self.x) * 4 - self.usable_cycles
+    }
+
+    /// The bitmap page the PPU is fetching from, while it is drawing one.
+    pub fn scanned_out_framebuffer(&self, memory: &dyn MemoryBus) -> Option<Range<usize>> {
+        if self.x >= HDRAW || self.y >= VDRAW {
+            return None;
+        }
+        let disp_cnt = memory.ppu_io_read(DISPCNT);
+        if disp_cnt & FORCED_BLANK > 0 || disp_cnt & BG2_ENABLE == 0 {
+            return None;
+        }
+        BitmapMode::from_bg_mode(disp_cnt & BG_MODE_MASK).map(|mode| mode.framebuffer(disp_cnt))
+    }
+
+    /// Shows a white screen, as when the LCD is off.
+    pub fn blank_screen(&mut self) {
+        self.framebuffer.fill(WHITE);
+    }
+
+    pub fn latch_affine_references(&mut self, memory: &dyn MemoryBus) {
+        self.bg2_reference = AffineReference::latch_bg2(memory);
+        self.bg3_reference = AffineReference::latch(3, memory);
+    }
+
+    /// Draws every line of the current memory state with only `layer`
+    /// over the backdrop, leaving out windows and colour effects so the
+    /// frame shows exactly what the layer contributes. The framebuffer and
+    /// the running frame are left untouched.
+    pub fn render_isolated_frame(&mut self, layer: Layer, memory: &dyn MemoryBus) -> Vec<u16> {
+        let framebuffer = self.framebuffer.clone();
+        let references = (self.bg2_reference, self.bg3_reference);
+        let record_pixel_sources = std::mem::take(&mut self.record_pixel_sources);
+        self.isolated_layer = Some(layer);
+
+        self.latch_affine_references(memory);
+        for line in 0..SCREEN_HEIGHT {
+            self.render_scanline(line, memory);
+        }
+
+        self.isolated_layer = None;
+        self.record_pixel_sources = record_pixel_sources;
+        (self.bg2_reference, self.bg3_reference) = references;
+        std::mem::replace(&mut self.framebuffer, framebuffer)
+    }
+
+    pub fn render_scanline(&mut self, line: usize, memory: &dyn MemoryBus) {
+        let disp_cnt = memory.ppu_io_read(DISPCNT);
+        let line_pixels = line * SCREEN_WIDTH..(line + 1) * SCREEN_WIDTH;
+        let output = &mut self.framebuffer[line_pixels.clone()];
+        let sources = self
+            .record_pixel_sources
+            .then(|| &mut self.pixel_sources[line_pixels]);
+        if disp_cnt & FORCED_BLANK > 0 {
+            output.fill(WHITE);
+            if let Some(sources) = sources {
+                // white isn't a palette colour
+                sources.fill(PixelSource {
+                    palette_index: None,
+                    ..PixelSource::backdrop()
+                });
+            }
+            return;
+        }
+
+        let backdrop = memory.peeku16(PALETTE_BASE) & 0x7FFF;
+        let mut backgrounds = Vec::new();
+        let bg_mode = disp_cnt & BG_MODE_MASK;
+        let text_backgrounds = match bg_mode {
+            0 => 0..4,
+            1 => 0..2,
+            _ => 0..0,
+        };
+        for bg in text_backgrounds {
+            if disp_cnt & (BG0_ENABLE << bg) == 0 {
+                continue;
+            }
+            let background = TextBackground::from_registers(bg, memory);
+            let mut pixels: LayerLine = [None; SCREEN_WIDTH];
+            background.render_line(line, memory, &mut pixels);
+            backgrounds.push(BackgroundLine {
+                layer: Layer::BACKGROUNDS[bg],
+                priority: background.priority,
+                pixels,
+            });
+        }
+
+        let affine_backgrounds = match bg_mode {
+            1 => 2..3,
+            2 => 2..4,
+            _ => 0..0,
+        };
+        for bg in affine_backgrounds {
+            let parameters = AffineParameters::for_background(bg, memory);
+            let reference = if bg == 2 {
+                &mut self.bg2_reference
+            } else {
+                &mut self.bg3_reference
+            };
+            if disp_cnt & (BG0_ENABLE << bg) > 0 {
+                let background = AffineBackground::from_registers(bg, memory);
+                let mut pixels: LayerLine = [None; SCREEN_WIDTH];
+                background.render_line(reference, &parameters, memory, &mut pixels);
+                backgrounds.push(BackgroundLine {
+                    layer: Layer::BACKGROUNDS[bg],
+                    priority: background.priority,
+                    pixels,
+                });
+            }
+            reference.advance_line(&parameters);
+        }
+
+        let bitmap_mode = BitmapMode::from_bg_mode(bg_mode);
+        if let Some(mode) = bitmap_mode {
+            let parameters = AffineParameters::bg2(memory);
+            if disp_cnt & BG2_ENABLE > 0 {
+                let mut pixels: LayerLine = [None; SCREEN_WIDTH];
+                render_bitmap_line(
+                    mode,
+                    disp_cnt,
+                    &self.bg2_reference,
+                    &parameters,
+                    memory,
+                    &mut pixels,
+                );
+                backgrounds.push(BackgroundLine {
+                    layer: Layer::Bg2,
+                    priority: (memory.ppu_io_read(BG2CNT) & 0x3) as u8,
+                    pixels,
+                });
+            }
+            self.bg2_reference.advance_line(&parameters);
+        }
+
+        let obj_enabled = disp_cnt & OBJ_ENABLE > 0;
+        let cycle_budget = self.limit_obj_cycles.then(|| obj_cycle_budget(disp_cnt));
+        let mut objects = obj_enabled.then(|| {
+            render_obj_line(line as u16, disp_cnt, bitmap_mode.is_some(), cycle_budget, memory)
+        });
+        let obj_window = if obj_enabled {
+            render_obj_window_line(line as u16, disp_cnt, bitmap_mode.is_some(), cycle_budget, memory)
+        } else {
+            [false; SCREEN_WIDTH]
+        };
+        if let Some(layer) = self.isolated_layer {
+            backgrounds.retain(|background| background.layer == layer);
+            if layer != Layer::Obj {
+                objects = None;
+            }
+        }
+        let window = match self.isolated_layer {
+            None => window_line(disp_cnt, line, &obj_window, memory),
+            Some(_) => None,
+        };
+        if let Some(window) = &window {
+            apply_window(window, &mut backgrounds, objects.as_mut());
+        }
+
+        let effects = match self.isolated_layer {
+            None => ColorEffects::from_registers(memory),
+            Some(_) => ColorEffects::default(),
+        };
+        compose_scanline(
+            &backgrounds,
+            objects.as_ref(),
+            backdrop,
+            &effects,
+            window.as_ref(),
+            output,
+            sources,
+        );
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{arm7tdmi::cpu::CPU, graphics::ppu::{HBLANK, HDRAW, VDRAW}, memory::{io_handlers::{DISPSTAT, IO_BASE}, memory::GBAMemory}};
+    use rstest::rstest;
+
+    use crate::{arm7tdmi::interrupts::Interrupt, gba::GBA, graphics::{background::{PALETTE_BASE, VRAM_BASE}, objects::OAM_BASE, ppu::{HBLANK, HDRAW, VDRAW}}, memory::io_handlers::{BG0CNT, BG1CNT, BG2CNT, BLDALPHA, BLDCNT, BLDY, DISPCNT, DISPSTAT, DMY, DX, IF, IO_BASE, MOSAIC, WIN0H, WIN0V, WININ, WINOUT}};
+    use crate::graphics::{layers::Layer, window::{effects_enabled, window_line}};
 
-    use super::VBLANK_ENABLE;
+    use super::{HBLANK_ENABLE, HBLANK_FLAG, SCREEN_HEIGHT, SCREEN_WIDTH, VBLANK_ENABLE, VBLANK_FLAG};
 
     #[test]
     fn ppu_sets_vblank_flag_when_in_vblank() {
-        let memory = GBAMemory::new();
-        let mut cpu = CPU::new(memory);
-        cpu.memory.writeu16(IO_BASE + DISPSTAT, VBLANK_ENABLE); // Enable VBLANK
-        assert_eq!(cpu.memory.readu16(IO_BASE + DISPSTAT).data, 0x8);
+        let mut gba = GBA::new_no_bios();
+        gba.memory.writeu16(IO_BASE + DISPSTAT, VBLANK_ENABLE); // Enable VBLANK
+        assert_eq!(gba.memory.readu16(IO_BASE + DISPSTAT).data, 0x8);
 
         for _ in 0..(VDRAW * (HDRAW + HBLANK) * 4) {
-            cpu.execute_cpu_cycle();
+            gba.step();
+        }
+
+        assert_eq!(gba.memory.readu16(IO_BASE + DISPSTAT).data, 0x9);
+
+    }
+
+    #[rstest]
+    #[case(159, 0)]
+    #[case(160, 1)]
+    #[case(226, 1)]
+    #[case(227, 0)]
+    fn vblank_flag_is_set_from_line_160_to_226(#[case] line: u64, #[case] flag: u16) {
+        let mut gba = GBA::new_no_bios();
+        while gba.ppu.y != line {
+            gba.step();
+        }
+
+        assert_eq!(gba.memory.readu16(IO_BASE + DISPSTAT).data & VBLANK_FLAG, flag);
+    }
+
+    #[rstest]
+    #[case(10)]
+    #[case(200)]
+    fn hblank_flag_and_irq_come_on_visible_and_vblank_lines(#[case] line: u64) {
+        let mut gba = GBA::new_no_bios();
+        gba.memory.writeu16(IO_BASE + DISPSTAT, HBLANK_ENABLE);
+        while gba.ppu.y != line {
+            gba.step();
+        }
+        gba.memory.writeu16(IO_BASE + IF, 0xFFFF);
+        assert_eq!(gba.memory.readu16(IO_BASE + DISPSTAT).data & HBLANK_FLAG, 0);
+
+        while !gba.ppu.in_hblank() {
+            gba.step();
+        }
+        assert_eq!(gba.memory.readu16(IO_BASE + DISPSTAT).data & HBLANK_FLAG, HBLANK_FLAG);
+        assert_eq!(Interrupt::from_flags(gba.interrupt_flags()), vec![Interrupt::HBlank]);
+
+        while gba.ppu.y == line {
+            gba.step();
+        }
+        assert_eq!(gba.memory.readu16(IO_BASE + DISPSTAT).data & HBLANK_FLAG, 0);
+    }
+
+    #[test]
+    fn mode_5_shows_backdrop_outside_160_by_128() {
+        let mut gba = GBA::new_no_bios();
+        let backdrop = 0x1234;
+        gba.memory.writeu16(PALETTE_BASE, backdrop);
+        gba.memory.writeu16(IO_BASE + DISPCNT, 0x5 | 1 << 10); // Mode 5, BG2 on
+        gba.memory.writeu16(IO_BASE + DX, 0x100);
+        gba.memory.writeu16(IO_BASE + DMY, 0x100);
+        for y in 0..128 {
+            for x in 0..160 {
+                gba.memory.writeu16(VRAM_BASE + (y * 160 + x) * 2, (x + y) as u16);
+            }
+        }
+
+        gba.ppu.latch_affine_references(gba.memory.as_ref());
+        for line in 0..SCREEN_HEIGHT {
+            gba.ppu.render_scanline(line, gba.memory.as_ref());
+        }
+
+        let framebuffer = &gba.ppu.framebuffer;
+        assert_eq!(framebuffer[0], 0);
+        assert_eq!(framebuffer[10 * SCREEN_WIDTH + 20], 30);
+        assert_eq!(framebuffer[127 * SCREEN_WIDTH + 159], 127 + 159);
+        assert_eq!(framebuffer[127 * SCREEN_WIDTH + 160], backdrop);
+        assert_eq!(framebuffer[128 * SCREEN_WIDTH + 10], backdrop);
+        assert_eq!(framebuffer[159 * SCREEN_WIDTH + 239], backdrop);
+    }
+
+    #[test]
+    fn mode_4_renders_framebuffer_and_sprites_from_their_own_vram() {
+        let mut gba = GBA::new_no_bios();
+        gba.memory.writeu16(IO_BASE + DISPCNT, 0x4 | 1 << 6 | 1 << 10 | 1 << 12); // Mode 4, 1D OBJ, BG2 + OBJ on
+        gba.memory.writeu16(IO_BASE + DX, 0x100);
+        gba.memory.writeu16(IO_BASE + DMY, 0x100);
+        gba.memory.writeu16(PALETTE_BASE + 2, 0x001F); // BG colour 1
+        gba.memory.writeu16(PALETTE_BASE + 0x200 + 2, 0x03E0); // OBJ colour 1
+        gba.memory.writeu16(PALETTE_BASE + 0x200 + 4, 0x7C00); // OBJ colour 2
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                gba.memory.write(VRAM_BASE + y * SCREEN_WIDTH + x, 1);
+                // second page lives on top of OBJ tiles 0-431
+                gba.memory.write(VRAM_BASE + 0xA000 + y * SCREEN_WIDTH + x, 0x22);
+            }
+        }
+        // tile 512 is the first OBJ tile outside the bitmap frames
+        for byte in 0..32 {
+            gba.memory.write(VRAM_BASE + 0x14000 + byte, 0x11);
+        }
+        // 8x8 4bpp sprite at (16, 8) using tile 512
+        gba.memory.writeu16(OAM_BASE, 8);
+        gba.memory.writeu16(OAM_BASE + 2, 16);
+        gba.memory.writeu16(OAM_BASE + 4, 512);
+        // sprite pointing at tile 0 overlaps the second frame and must not draw
+        gba.memory.writeu16(OAM_BASE + 8, 40);
+        gba.memory.writeu16(OAM_BASE + 10, 40);
+        gba.memory.writeu16(OAM_BASE + 12, 0);
+        for i in 2..128 {
+            gba.memory.writeu16(OAM_BASE + i * 8, 1 << 9); // disabled
+        }
+
+        gba.ppu.latch_affine_references(gba.memory.as_ref());
+        for line in 0..SCREEN_HEIGHT {
+            gba.ppu.render_scanline(line, gba.memory.as_ref());
+        }
+
+        let framebuffer = &gba.ppu.framebuffer;
+        assert_eq!(framebuffer[0], 0x001F);
+        assert_eq!(framebuffer[8 * SCREEN_WIDTH + 16], 0x03E0);
+        assert_eq!(framebuffer[15 * SCREEN_WIDTH + 23], 0x03E0);
+        assert_eq!(framebuffer[15 * SCREEN_WIDTH + 24], 0x001F);
+        assert_eq!(framebuffer[40 * SCREEN_WIDTH + 40], 0x001F);
+        assert_eq!(gba.memory.read(VRAM_BASE + 0xA000).data, 0x22);
+        assert_eq!(gba.memory.read(VRAM_BASE + 0x14000).data, 0x11);
+    }
+
+    #[test]
+    fn transparent_background_pixels_reveal_lower_priority_background() {
+        let mut gba = GBA::new_no_bios();
+        gba.memory.writeu16(IO_BASE + DISPCNT, 1 << 8 | 1 << 9); // Mode 0, BG0 + BG1 on
+        gba.memory.writeu16(IO_BASE + BG0CNT, 8 << 8); // priority 0, screen block 8
+        gba.memory.writeu16(IO_BASE + BG1CNT, 9 << 8 | 1); // priority 1, screen block 9
+        gba.memory.writeu16(PALETTE_BASE, 0x7C00); // backdrop
+        gba.memory.writeu16(PALETTE_BASE + 2, 0x03E0);
+        gba.memory.writeu16(PALETTE_BASE + 4, 0x03E0);
+        gba.memory.writeu16(PALETTE_BASE + 0x20 + 2, 0x001F);
+        for row in 0..8 {
+            // tile 1 alternates between palette index 1 and transparent index 0
+            gba.memory.writeu32(VRAM_BASE + 32 + row * 4, 0x0101_0101);
+            // tile 2 is solid palette index 2
+            gba.memory.writeu32(VRAM_BASE + 64 + row * 4, 0x2222_2222);
+        }
+        for column in 0..32 {
+            // BG0 uses palette bank 1, whose index 0 must still be transparent
+            gba.memory.writeu16(VRAM_BASE + 0x4000 + column * 2, 1 | 1 << 12);
+            gba.memory.writeu16(VRAM_BASE + 0x4800 + column * 2, 2);
+        }
+
+        gba.ppu.render_scanline(0, gba.memory.as_ref());
+        gba.ppu.render_scanline(8, gba.memory.as_ref());
+
+        let framebuffer = &gba.ppu.framebuffer;
+        assert_eq!(framebuffer[0], 0x001F);
+        assert_eq!(framebuffer[1], 0x03E0);
+        assert_eq!(framebuffer[2], 0x001F);
+        assert_eq!(framebuffer[239], 0x03E0);
+        // both backgrounds are transparent here, the backdrop is drawn
+        assert_eq!(framebuffer[8 * SCREEN_WIDTH], 0x7C00);
+    }
+
+    #[test]
+    fn obj_window_sprite_masks_background_without_drawing() {
+        let mut gba = GBA::new_no_bios();
+        // Mode 0, 1D OBJ, BG0 + OBJ on, OBJ window on
+        gba.memory.writeu16(IO_BASE + DISPCNT, 1 << 6 | 1 << 8 | 1 << 12 | 1 << 15);
+        gba.memory.writeu16(IO_BASE + BG0CNT, 8 << 8);
+        // BG0 and OBJ outside every window, only OBJ inside the OBJ window
+        gba.memory.writeu16(IO_BASE + WINOUT, 0x10 << 8 | 0x11);
+        gba.memory.writeu16(PALETTE_BASE, 0x7C00); // backdrop
+        gba.memory.writeu16(PALETTE_BASE + 2, 0x001F); // BG colour 1
+        gba.memory.writeu16(PALETTE_BASE + 0x200 + 2, 0x03E0); // OBJ colour 1
+        for row in 0..8 {
+            gba.memory.writeu32(VRAM_BASE + 32 + row * 4, 0x1111_1111);
+            gba.memory.writeu32(VRAM_BASE + 0x10000 + row * 4, 0x1111_1111);
+        }
+        for entry in 0..32 * 32 {
+            gba.memory.writeu16(VRAM_BASE + 0x4000 + entry * 2, 1);
+        }
+        // 8x8 OBJ window sprite at (16, 8) using tile 0
+        gba.memory.writeu16(OAM_BASE, 8 | 2 << 10);
+        gba.memory.writeu16(OAM_BASE + 2, 16);
+        gba.memory.writeu16(OAM_BASE + 4, 0);
+        for i in 1..128 {
+            gba.memory.writeu16(OAM_BASE + i * 8, 1 << 9); // disabled
+        }
+
+        gba.ppu.render_scanline(8, gba.memory.as_ref());
+
+        let framebuffer = &gba.ppu.framebuffer;
+        assert_eq!(framebuffer[8 * SCREEN_WIDTH + 15], 0x001F);
+        // BG0 is masked inside the window and the sprite itself is not drawn
+        assert_eq!(framebuffer[8 * SCREEN_WIDTH + 16], 0x7C00);
+        assert_eq!(framebuffer[8 * SCREEN_WIDTH + 23], 0x7C00);
+        assert_eq!(framebuffer[8 * SCREEN_WIDTH + 24], 0x001F);
+    }
+
+    #[rstest]
+    #[case::win0_enabled(1 << 13, [0x7C00, 0x001F, 0x001F, 0x7C00], 0x7C00)]
+    #[case::no_window_enabled(0, [0x001F; 4], 0x001F)]
+    fn win0_restricts_layers_only_when_enabled(
+        #[case] window_enable: u16,
+        #[case] expected_line_8: [u16; 4],
+        #[case] expected_line_20: u16,
+    ) {
+        let mut gba = GBA::new_no_bios();
+        // Mode 0, BG0 on
+        gba.memory.writeu16(IO_BASE + DISPCNT, 1 << 8 | window_enable);
+        gba.memory.writeu16(IO_BASE + BG0CNT, 8 << 8);
+        // WIN0 covers x 16-31 and y 8-15 and shows BG0, nothing shows outside
+        gba.memory.writeu16(IO_BASE + WIN0H, 16 << 8 | 32);
+        gba.memory.writeu16(IO_BASE + WIN0V, 8 << 8 | 16);
+        gba.memory.writeu16(IO_BASE + WININ, 0x01);
+        gba.memory.writeu16(IO_BASE + WINOUT, 0x00);
+        gba.memory.writeu16(PALETTE_BASE, 0x7C00); // backdrop
+        gba.memory.writeu16(PALETTE_BASE + 2, 0x001F); // BG colour 1
+        for row in 0..8 {
+            gba.memory.writeu32(VRAM_BASE + 32 + row * 4, 0x1111_1111);
+        }
+        for entry in 0..32 * 32 {
+            gba.memory.writeu16(VRAM_BASE + 0x4000 + entry * 2, 1);
+        }
+
+        gba.ppu.render_scanline(8, gba.memory.as_ref());
+        gba.ppu.render_scanline(20, gba.memory.as_ref());
+
+        let framebuffer = &gba.ppu.framebuffer;
+        let line_8 = [15, 16, 31, 32].map(|x| framebuffer[8 * SCREEN_WIDTH + x]);
+        assert_eq!(line_8, expected_line_8);
+        assert_eq!(framebuffer[20 * SCREEN_WIDTH + 16], expected_line_20);
+    }
+
+    #[test]
+    fn win0_uncovers_the_lower_background_inside_its_rectangle() {
+        let mut gba = GBA::new_no_bios();
+        // Mode 0, BG0 and BG1 on, WIN0 on
+        gba.memory.writeu16(IO_BASE + DISPCNT, 1 << 8 | 1 << 9 | 1 << 13);
+        gba.memory.writeu16(IO_BASE + BG0CNT, 8 << 8);
+        gba.memory.writeu16(IO_BASE + BG1CNT, 9 << 8 | 1);
+        // WIN0 covers x 16-31 and y 8-15 and hides BG0 there but allows
+        // color effects, outside both backgrounds show without effects
+        gba.memory.writeu16(IO_BASE + WIN0H, 16 << 8 | 32);
+        gba.memory.writeu16(IO_BASE + WIN0V, 8 << 8 | 16);
+        gba.memory.writeu16(IO_BASE + WININ, 0x22);
+        gba.memory.writeu16(IO_BASE + WINOUT, 0x03);
+        gba.memory.writeu16(PALETTE_BASE, 0x7C00); // backdrop
+        gba.memory.writeu16(PALETTE_BASE + 2, 0x001F); // BG0 colour 1
+        gba.memory.writeu16(PALETTE_BASE + 4, 0x03E0); // BG1 colour 2
+        for row in 0..8 {
+            gba.memory.writeu32(VRAM_BASE + 32 + row * 4, 0x1111_1111);
+            gba.memory.writeu32(VRAM_BASE + 64 + row * 4, 0x2222_2222);
+        }
+        for entry in 0..32 * 32 {
+            gba.memory.writeu16(VRAM_BASE + 0x4000 + entry * 2, 1);
+            gba.memory.writeu16(VRAM_BASE + 0x4800 + entry * 2, 2);
+        }
+
+        gba.ppu.render_scanline(7, gba.memory.as_ref());
+        gba.ppu.render_scanline(8, gba.memory.as_ref());
+        gba.ppu.render_scanline(16, gba.memory.as_ref());
+
+        let framebuffer = &gba.ppu.framebuffer;
+        let line = |y: usize| [15, 16, 31, 32].map(|x| framebuffer[y * SCREEN_WIDTH + x]);
+        assert_eq!(line(7), [0x001F; 4]);
+        assert_eq!(line(8), [0x001F, 0x03E0, 0x03E0, 0x001F]);
+        assert_eq!(line(16), [0x001F; 4]);
+
+        let disp_cnt = gba.memory.readu16(IO_BASE + DISPCNT).data;
+        let window = window_line(disp_cnt, 8, &[false; SCREEN_WIDTH], gba.memory.as_ref());
+        assert!(!effects_enabled(window.as_ref(), 15));
+        assert!(effects_enabled(window.as_ref(), 16));
+        assert!(effects_enabled(None, 15));
+    }
+
+    #[rstest]
+    #[case::no_effect(0x0201, 0x0808, 0, 0x001F)]
+    #[case::half_alpha_blend(1 << 6 | 0x0201, 0x0808, 0, 0x3C0F)]
+    #[case::second_target_missing(1 << 6 | 0x0001, 0x0808, 0, 0x001F)]
+    #[case::fade_to_black(3 << 6 | 0x0001, 0, 16, 0x0000)]
+    #[case::fade_to_white(2 << 6 | 0x0001, 0, 16, 0x7FFF)]
+    fn color_effects_apply_to_the_top_layer(
+        #[case] bld_cnt: u16,
+        #[case] bld_alpha: u16,
+        #[case] bld_y: u16,
+        #[case] expected: u16,
+    ) {
+        let mut gba = GBA::new_no_bios();
+        // Mode 0, solid red BG0 over solid blue BG1
+        gba.memory.writeu16(IO_BASE + DISPCNT, 1 << 8 | 1 << 9);
+        gba.memory.writeu16(IO_BASE + BG0CNT, 8 << 8);
+        gba.memory.writeu16(IO_BASE + BG1CNT, 9 << 8 | 1);
+        gba.memory.writeu16(IO_BASE + BLDCNT, bld_cnt);
+        gba.memory.writeu16(IO_BASE + BLDALPHA, bld_alpha);
+        gba.memory.writeu16(IO_BASE + BLDY, bld_y);
+        gba.memory.writeu16(PALETTE_BASE + 2, 0x001F); // BG0 colour 1
+        gba.memory.writeu16(PALETTE_BASE + 4, 0x7C00); // BG1 colour 2
+        for row in 0..8 {
+            gba.memory.writeu32(VRAM_BASE + 32 + row * 4, 0x1111_1111);
+            gba.memory.writeu32(VRAM_BASE + 64 + row * 4, 0x2222_2222);
+        }
+        for entry in 0..32 * 32 {
+            gba.memory.writeu16(VRAM_BASE + 0x4000 + entry * 2, 1);
+            gba.memory.writeu16(VRAM_BASE + 0x4800 + entry * 2, 2);
+        }
+
+        gba.ppu.render_scanline(0, gba.memory.as_ref());
+
+        assert_eq!(gba.ppu.framebuffer[..SCREEN_WIDTH], [expected; SCREEN_WIDTH]);
+    }
+
+    #[test]
+    fn isolated_bg2_frame_shows_only_bg2_over_the_backdrop() {
+        let mut gba = GBA::new_no_bios();
+        // Mode 0, solid red BG0 over BG2, which only covers the left half
+        gba.memory.writeu16(IO_BASE + DISPCNT, 1 << 8 | 1 << 10);
+        gba.memory.writeu16(IO_BASE + BG0CNT, 8 << 8);
+        gba.memory.writeu16(IO_BASE + BG2CNT, 10 << 8 | 1);
+        gba.memory.writeu16(PALETTE_BASE, 0x03E0); // backdrop
+        gba.memory.writeu16(PALETTE_BASE + 2, 0x001F); // BG0 colour 1
+        gba.memory.writeu16(PALETTE_BASE + 4, 0x7C00); // BG2 colour 2
+        for row in 0..8 {
+            gba.memory.writeu32(VRAM_BASE + 32 + row * 4, 0x1111_1111);
+            gba.memory.writeu32(VRAM_BASE + 64 + row * 4, 0x2222_2222);
+        }
+        for entry in 0..32 * 32 {
+            gba.memory.writeu16(VRAM_BASE + 0x4000 + entry * 2, 1);
+            let bg2_tile = if entry % 32 < 15 { 2 } else { 0 };
+            gba.memory.writeu16(VRAM_BASE + 0x5000 + entry * 2, bg2_tile);
+        }
+        gba.ppu.render_scanline(0, gba.memory.as_ref());
+
+        let frame = gba.ppu.render_isolated_frame(Layer::Bg2, gba.memory.as_ref());
+
+        for y in [0, SCREEN_HEIGHT - 1] {
+            assert_eq!(frame[y * SCREEN_WIDTH..y * SCREEN_WIDTH + 120], [0x7C00; 120]);
+            assert_eq!(frame[y * SCREEN_WIDTH + 120..(y + 1) * SCREEN_WIDTH], [0x03E0; 120]);
+        }
+        // the emulated frame is left as it was
+        assert_eq!(gba.ppu.framebuffer[..SCREEN_WIDTH], [0x001F; SCREEN_WIDTH]);
+    }
+
+    /// Mode 0 with 1D OBJ mapping and only OBJ on, every OAM entry
+    /// after the first disabled and OBJ palette entry i set to colour i.
+    fn gba_with_one_sprite(attribute0: u16, attribute1: u16, attribute2: u16) -> GBA {
+        let mut gba = GBA::new_no_bios();
+        gba.memory.writeu16(IO_BASE + DISPCNT, 1 << 6 | 1 << 12);
+        gba.memory.writeu16(PALETTE_BASE, 0x7C00); // backdrop
+        for index in 1..16 {
+            gba.memory.writeu16(PALETTE_BASE + 0x200 + index * 2, index as u16);
+        }
+        gba.memory.writeu16(OAM_BASE, attribute0);
+        gba.memory.writeu16(OAM_BASE + 2, attribute1);
+        gba.memory.writeu16(OAM_BASE + 4, attribute2);
+        for i in 1..128 {
+            gba.memory.writeu16(OAM_BASE + i * 8, 1 << 9); // disabled
+        }
+        gba
+    }
+
+    #[test]
+    fn sixteen_by_sixteen_sprite_is_drawn_from_four_tiles() {
+        // square 16x16 4bpp sprite at (40, 30) starting at tile 1
+        let mut gba = gba_with_one_sprite(30, 1 << 14 | 40, 1);
+        // tiles 1-4 are solid colours 1-4, laid out left to right, top to bottom
+        for tile in 1..5 {
+            for byte in 0..32 {
+                gba.memory.write(VRAM_BASE + 0x10000 + tile * 32 + byte, (tile * 0x11) as u8);
+            }
+        }
+
+        gba.ppu.render_scanline(30, gba.memory.as_ref());
+        gba.ppu.render_scanline(45, gba.memory.as_ref());
+        gba.ppu.render_scanline(46, gba.memory.as_ref());
+
+        let framebuffer = &gba.ppu.framebuffer;
+        let top = [39, 40, 47, 48, 55, 56].map(|x| framebuffer[30 * SCREEN_WIDTH + x]);
+        assert_eq!(top, [0x7C00, 1, 1, 2, 2, 0x7C00]);
+        let bottom = [40, 47, 48, 55].map(|x| framebuffer[45 * SCREEN_WIDTH + x]);
+        assert_eq!(bottom, [3, 3, 4, 4]);
+        assert_eq!(framebuffer[46 * SCREEN_WIDTH + 40], 0x7C00);
+    }
+
+    #[rstest]
+    // mirroring about the centre lands column 0 on texel 8, just outside
+    #[case::mirrored(1 << 8, -0x100, vec![(16, 0x7C00), (17, 2), (20, 2), (21, 1), (23, 1), (24, 0x7C00)])]
+    #[case::double_size(1 << 8 | 1 << 9, 0x100, vec![(19, 0x7C00), (20, 1), (23, 1), (24, 2), (27, 2), (28, 0x7C00)])]
+    fn affine_sprites_sample_through_their_matrix(
+        #[case] affine_bits: u16,
+        #[case] pa: i16,
+        #[case] expected: Vec<(usize, u16)>,
+    ) {
+        // 8x8 affine sprite at (16, 8) using matrix 0 and tile 1
+        let mut gba = gba_with_one_sprite(8 | affine_bits, 16, 1);
+        gba.memory.writeu16(OAM_BASE + 6, pa as u16);
+        gba.memory.writeu16(OAM_BASE + 30, 0x100);
+        // left half colour 1, right half colour 2
+        for row in 0..8 {
+            gba.memory.writeu32(VRAM_BASE + 0x10000 + 32 + row * 4, 0x2222_1111);
+        }
+
+        gba.ppu.render_scanline(12, gba.memory.as_ref());
+
+        for (x, color) in expected {
+            assert_eq!(gba.ppu.framebuffer[12 * SCREEN_WIDTH + x], color, "x = {}", x);
+        }
+    }
+
+    #[test]
+    fn mosaic_sprite_repeats_the_first_texel_of_each_block() {
+        // 8x8 mosaic sprite at (16, 8), columns use colours 1-8
+        let mut gba = gba_with_one_sprite(8 | 1 << 12, 16, 1);
+        gba.memory.writeu16(IO_BASE + MOSAIC, 3 << 8); // 4 pixel wide OBJ blocks
+        for row in 0..8 {
+            gba.memory.writeu32(VRAM_BASE + 0x10000 + 32 + row * 4, 0x8765_4321);
+        }
+
+        gba.ppu.render_scanline(8, gba.memory.as_ref());
+
+        let line = &gba.ppu.framebuffer[8 * SCREEN_WIDTH + 16..8 * SCREEN_WIDTH + 24];
+        assert_eq!(line, [1, 1, 1, 1, 5, 5, 5, 5]);
+    }
+
+    #[test]
+    fn sprites_past_the_screen_edges_wrap_around() {
+        let mut gba = GBA::new_no_bios();
+        gba.memory.writeu16(IO_BASE + DISPCNT, 1 << 6 | 1 << 12); // Mode 0, 1D OBJ, OBJ on
+        gba.memory.writeu16(PALETTE_BASE, 0x7C00); // backdrop
+        gba.memory.writeu16(PALETTE_BASE + 0x200 + 2, 0x03E0); // OBJ colour 1
+        for word in 0..32 {
+            gba.memory.writeu32(VRAM_BASE + 0x10000 + word * 4, 0x1111_1111);
+        }
+        // 16x16 sprite at (507, 250) covers x 507-522 and y 250-265
+        gba.memory.writeu16(OAM_BASE, 250);
+        gba.memory.writeu16(OAM_BASE + 2, 507 | 1 << 14);
+        gba.memory.writeu16(OAM_BASE + 4, 0);
+        for i in 1..128 {
+            gba.memory.writeu16(OAM_BASE + i * 8, 1 << 9); // disabled
+        }
+
+        gba.ppu.render_scanline(0, gba.memory.as_ref());
+        gba.ppu.render_scanline(9, gba.memory.as_ref());
+        gba.ppu.render_scanline(10, gba.memory.as_ref());
+
+        let framebuffer = &gba.ppu.framebuffer;
+        // x 512-522 wrap to the left edge of the screen
+        assert_eq!(framebuffer[0], 0x03E0);
+        assert_eq!(framebuffer[10], 0x03E0);
+        assert_eq!(framebuffer[11], 0x7C00);
+        assert_eq!(framebuffer[239], 0x7C00);
+        // y 256-265 wrap to the top of the screen
+        assert_eq!(framebuffer[9 * SCREEN_WIDTH], 0x03E0);
+        assert_eq!(framebuffer[10 * SCREEN_WIDTH], 0x7C00);
+    }
+
+    #[rstest]
+    #[case::no_limit(false, 0, true, true)]
+    #[case::over_budget(true, 0, true, false)]
+    #[case::hblank_interval_free(true, 1 << 5, false, false)]
+    fn sprites_past_the_obj_cycle_budget_are_dropped(
+        #[case] limit_obj_cycles: bool,
+        #[case] hblank_interval_free: u16,
+        #[case] small_sprite_drawn: bool,
+        #[case] last_sprite_drawn: bool,
+    ) {
+        let mut gba = GBA::new_no_bios();
+        gba.ppu.limit_obj_cycles = limit_obj_cycles;
+        // Mode 0, 1D OBJ, OBJ on
+        gba.memory.writeu16(IO_BASE + DISPCNT, 1 << 6 | 1 << 12 | hblank_interval_free);
+        gba.memory.writeu16(PALETTE_BASE, 0x7C00); // backdrop
+        gba.memory.writeu16(PALETTE_BASE + 0x200 + 2, 0x03E0); // OBJ colour 1
+        for word in 0..8 * 64 {
+            gba.memory.writeu32(VRAM_BASE + 0x10000 + word * 4, 0x1111_1111);
+        }
+        // 18 64x64 sprites at x 0 spend 1152 of the 1210 cycles
+        for i in 0..18 {
+            gba.memory.writeu16(OAM_BASE + i * 8, 0);
+            gba.memory.writeu16(OAM_BASE + i * 8 + 2, 3 << 14);
+            gba.memory.writeu16(OAM_BASE + i * 8 + 4, 0);
+        }
+        // an 8x8 sprite at x 100 still fits
+        gba.memory.writeu16(OAM_BASE + 18 * 8, 0);
+        gba.memory.writeu16(OAM_BASE + 18 * 8 + 2, 100);
+        gba.memory.writeu16(OAM_BASE + 18 * 8 + 4, 0);
+        // a 64x64 sprite at x 160 does not
+        gba.memory.writeu16(OAM_BASE + 19 * 8, 0);
+        gba.memory.writeu16(OAM_BASE + 19 * 8 + 2, 160 | 3 << 14);
+        gba.memory.writeu16(OAM_BASE + 19 * 8 + 4, 0);
+        for i in 20..128 {
+            gba.memory.writeu16(OAM_BASE + i * 8, 1 << 9); // disabled
         }
 
-        assert_eq!(cpu.memory.readu16(IO_BASE + DISPSTAT).data, 0x9);
+        gba.ppu.render_scanline(0, gba.memory.as_ref());
 
+        let color = |drawn: bool| if drawn { 0x03E0 } else { 0x7C00 };
+        let framebuffer = &gba.ppu.framebuffer;
+        assert_eq!(framebuffer[0], 0x03E0);
+        assert_eq!(framebuffer[100], color(small_sprite_drawn));
+        assert_eq!(framebuffer[160], color(last_sprite_drawn));
     }
 }
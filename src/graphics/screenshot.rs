@@ -0,0 +1,30 @@
+use std::{fs::File, io::Write};
+
+use super::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Widens a 5-bit channel to 8 bits, so 0x1F becomes 0xFF.
+fn expand_channel(channel: u16) -> u8 {
+    let channel = (channel & 0x1F) as u8;
+    channel << 3 | channel >> 2
+}
+
+/// The 15-bit BGR colours of a frame as RGB bytes in screen order.
+pub fn frame_to_rgb(frame: &[u16]) -> Vec<u8> {
+    frame
+        .iter()
+        .flat_map(|color| [*color, color >> 5, color >> 10].map(expand_channel))
+        .collect()
+}
+
+/// A 15-bit BGR colour as 32-bit 0x00RRGGBB.
+pub fn color_to_rgb32(color: u16) -> u32 {
+    let [red, green, blue] = [color, color >> 5, color >> 10].map(expand_channel);
+    u32::from_be_bytes([0, red, green, blue])
+}
+
+/// Writes a frame as a binary PPM, which most image viewers open.
+pub fn export_ppm(frame: &[u16], path: &str) -> Result<(), std::io::Error> {
+    let mut file = File::create(path)?;
+    write!(file, "P6\n{} {}\n255\n", SCREEN_WIDTH, SCREEN_HEIGHT)?;
+    file.write_all(&frame_to_rgb(frame))
+}
@@ -0,0 +1,116 @@
+use crate::memory::{
+    io_handlers::{WIN0H, WIN0V, WININ, WINOUT},
+    memory::MemoryBus,
+};
+
+use super::{
+    layers::{BackgroundLine, Layer},
+    objects::{ObjLine, ObjWindowLine},
+    ppu::SCREEN_WIDTH,
+};
+
+const WIN0_ENABLE: u16 = 1 << 13;
+const OBJ_WINDOW_ENABLE: u16 = 1 << 15;
+const WINDOW_ENABLES: u16 = 0x7 << 13;
+/// WININ/WINOUT bit that lets color special effects apply in a region.
+const EFFECTS_ENABLE: u8 = 1 << 5;
+
+/// WININ/WINOUT layer and color effect enable bits for every pixel of a
+/// scanline.
+pub type WindowLine = [u8; SCREEN_WIDTH];
+
+fn layer_bit(layer: Layer) -> u8 {
+    match layer {
+        Layer::Bg0 => 1 << 0,
+        Layer::Bg1 => 1 << 1,
+        Layer::Bg2 => 1 << 2,
+        Layer::Bg3 => 1 << 3,
+        Layer::Obj => 1 << 4,
+        Layer::Backdrop => 0,
+    }
+}
+
+/// Whether `position` lies between window edges packed as
+/// `start << 8 | end` in WINxH or WINxV. The end is exclusive and a start
+/// past the end wraps around the screen.
+fn in_window_span(edges: u16, position: usize) -> bool {
+    let (start, end) = ((edges >> 8) as usize, (edges & 0xFF) as usize);
+    if start <= end {
+        (start..end).contains(&position)
+    } else {
+        position >= start || position < end
+    }
+}
+
+/// Resolves which layers are visible at each pixel of line `y`, or None
+/// when DISPCNT enables no window and every layer shows. WIN0 takes
+/// precedence over WIN1, which takes precedence over the OBJ window;
+/// pixels in none of the enabled windows use the lower byte of WINOUT.
+pub fn window_line(
+    disp_cnt: u16,
+    y: usize,
+    obj_window: &ObjWindowLine,
+    memory: &dyn MemoryBus,
+) -> Option<WindowLine> {
+    if disp_cnt & WINDOW_ENABLES == 0 {
+        return None;
+    }
+    let winin = memory.ppu_io_read(WININ);
+    let winout = memory.ppu_io_read(WINOUT);
+    let outside = (winout & 0x3F) as u8;
+    let inside_obj_window = (winout >> 8 & 0x3F) as u8;
+
+    let mut line = [outside; SCREEN_WIDTH];
+    if disp_cnt & OBJ_WINDOW_ENABLE > 0 {
+        for (enabled_layers, in_obj_window) in line.iter_mut().zip(obj_window) {
+            if *in_obj_window {
+                *enabled_layers = inside_obj_window;
+            }
+        }
+    }
+    // WIN1 first so WIN0 overwrites it where they overlap
+    for window in [1, 0] {
+        let vertical = memory.ppu_io_read(WIN0V + window * 2);
+        if disp_cnt & (WIN0_ENABLE << window) == 0 || !in_window_span(vertical, y) {
+            continue;
+        }
+        let horizontal = memory.ppu_io_read(WIN0H + window * 2);
+        let inside = (winin >> (8 * window) & 0x3F) as u8;
+        for (x, enabled_layers) in line.iter_mut().enumerate() {
+            if in_window_span(horizontal, x) {
+                *enabled_layers = inside;
+            }
+        }
+    }
+    Some(line)
+}
+
+/// Whether color special effects may apply at `x`. Without any window
+/// every pixel may be blended.
+pub fn effects_enabled(window: Option<&WindowLine>, x: usize) -> bool {
+    window.is_none_or(|window| window[x] & EFFECTS_ENABLE > 0)
+}
+
+/// Removes the pixels of layers that are hidden by the window at their
+/// position, letting lower layers or the backdrop show through.
+pub fn apply_window(
+    window: &WindowLine,
+    backgrounds: &mut [BackgroundLine],
+    objects: Option<&mut ObjLine>,
+) {
+    for background in backgrounds.iter_mut() {
+        let bit = layer_bit(background.layer);
+        for (pixel, enabled_layers) in background.pixels.iter_mut().zip(window) {
+            if enabled_layers & bit == 0 {
+                *pixel = None;
+            }
+        }
+    }
+    if let Some(objects) = objects {
+        for (pixel, enabled_layers) in objects.iter_mut().zip(window) {
+            if enabled_layers & layer_bit(Layer::Obj) == 0 {
+                *pixel = None;
+            }
+        }
+    }
+}
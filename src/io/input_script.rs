@@ -0,0 +1,154 @@
+use std::{fmt::Display, fs, str::FromStr};
+
+use crate::memory::memory::MemoryBus;
+
+use super::keypad::{set_button, Button};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InputEvent {
+    pub frame: u64,
+    pub button: Button,
+    pub pressed: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum InputScriptError {
+    InvalidLine(usize, String),
+    CouldNotRead(String),
+}
+
+impl Display for InputScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InputScriptError::InvalidLine(line, contents) => {
+                write!(f, "Invalid input script line {}: {}", line, contents)
+            }
+            InputScriptError::CouldNotRead(path) => write!(f, "Could not read input script {}", path),
+        }
+    }
+}
+
+/// Scripted keypad input, one `<frame> <button> <press|release>` event per
+/// line. Blank lines and lines starting with `#` are ignored.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InputScript {
+    events: Vec<InputEvent>,
+}
+
+impl FromStr for InputScript {
+    type Err = InputScriptError;
+
+    fn from_str(script: &str) -> Result<Self, Self::Err> {
+        let mut events = Vec::new();
+        for (index, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid_line = || InputScriptError::InvalidLine(index + 1, line.to_string());
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [frame, button, action] = fields[..] else {
+                return Err(invalid_line());
+            };
+            let frame = frame.parse::<u64>().map_err(|_| invalid_line())?;
+            let button = button.parse::<Button>().map_err(|_| invalid_line())?;
+            let pressed = match action {
+                "press" => true,
+                "release" => false,
+                _ => return Err(invalid_line()),
+            };
+            events.push(InputEvent {
+                frame,
+                button,
+                pressed,
+            });
+        }
+        events.sort_by_key(|event| event.frame);
+        Ok(Self { events })
+    }
+}
+
+impl InputScript {
+    pub fn load(path: &str) -> Result<Self, InputScriptError> {
+        let script =
+            fs::read_to_string(path).map_err(|_| InputScriptError::CouldNotRead(path.to_string()))?;
+        script.parse()
+    }
+
+    pub fn events(&self) -> &[InputEvent] {
+        &self.events
+    }
+
+    /// Applies every event scheduled for `frame` to KEYINPUT.
+    pub fn apply_frame(&self, frame: u64, memory: &mut dyn MemoryBus) {
+        for event in self.events.iter().filter(|event| event.frame == frame) {
+            set_button(memory, event.button, event.pressed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod input_script_tests {
+    use crate::{
+        gba::GBA,
+        io::keypad::Button,
+        memory::io_handlers::{IO_BASE, KEYINPUT},
+        utils::testing::load_arm_program,
+    };
+
+    use super::{InputEvent, InputScript, InputScriptError};
+
+    #[test]
+    fn parses_events() {
+        let script: InputScript = "# comment\n10 A press\n\n12 a release\n".parse().unwrap();
+
+        assert_eq!(
+            script.events(),
+            &[
+                InputEvent {
+                    frame: 10,
+                    button: Button::A,
+                    pressed: true
+                },
+                InputEvent {
+                    frame: 12,
+                    button: Button::A,
+                    pressed: false
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        assert_eq!(
+            "10 A hold".parse::<InputScript>(),
+            Err(InputScriptError::InvalidLine(1, String::from("10 A hold")))
+        );
+        assert!("10 X press".parse::<InputScript>().is_err());
+        assert!("A press".parse::<InputScript>().is_err());
+    }
+
+    #[test]
+    fn presses_and_releases_a_on_scheduled_frames() {
+        let script: InputScript = "10 A press\n12 A release".parse().unwrap();
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, 0x3000000, &[
+            0xeafffffe, // b .
+        ]);
+        gba.set_input_script(script);
+
+        let mut keys = Vec::new();
+        for frame in 0..=13 {
+            assert_eq!(gba.ppu.frame_count, frame);
+            keys.push(gba.memory.readu16(IO_BASE + KEYINPUT).data);
+            gba.step_frame();
+        }
+
+        assert_eq!(keys[9], 0x3FF);
+        assert_eq!(keys[10], 0x3FE);
+        assert_eq!(keys[11], 0x3FE);
+        assert_eq!(keys[12], 0x3FF);
+        assert_eq!(keys[13], 0x3FF);
+    }
+}
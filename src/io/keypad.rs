@@ -0,0 +1,197 @@
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+use crate::{
+    arm7tdmi::interrupts::{request_interrupt, Interrupt},
+    memory::{
+        io_handlers::{KEYCNT, KEYINPUT},
+        memory::MemoryBus,
+    },
+};
+
+const BUTTONS_MASK: u16 = 0x3FF;
+const KEYCNT_IRQ_ENABLE: u16 = 1 << 14;
+/// Set for the keypad interrupt to need every selected button, clear for
+/// any of them.
+const KEYCNT_AND: u16 = 1 << 15;
+/// Marks keys the emulator hasn't latched into KEYINPUT yet.
+const KEYS_PENDING: u32 = 1 << 16;
+
+/// Buttons in KEYINPUT bit order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    A = 0,
+    B = 1,
+    Select = 2,
+    Start = 3,
+    Right = 4,
+    Left = 5,
+    Up = 6,
+    Down = 7,
+    R = 8,
+    L = 9,
+}
+
+impl Button {
+    pub const ALL: [Button; 10] = [
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+        Button::Right,
+        Button::Left,
+        Button::Up,
+        Button::Down,
+        Button::R,
+        Button::L,
+    ];
+
+    pub fn mask(&self) -> u16 {
+        1 << (*self as u16)
+    }
+}
+
+impl FromStr for Button {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "a" => Ok(Button::A),
+            "b" => Ok(Button::B),
+            "select" => Ok(Button::Select),
+            "start" => Ok(Button::Start),
+            "right" => Ok(Button::Right),
+            "left" => Ok(Button::Left),
+            "up" => Ok(Button::Up),
+            "down" => Ok(Button::Down),
+            "r" => Ok(Button::R),
+            "l" => Ok(Button::L),
+            _ => Err(s.to_string()),
+        }
+    }
+}
+
+/// KEYINPUT is active low, a pressed button reads as 0.
+pub fn set_button(memory: &mut dyn MemoryBus, button: Button, pressed: bool) {
+    let keys = memory.ppu_io_read(KEYINPUT);
+    let keys = if pressed {
+        keys & !button.mask()
+    } else {
+        keys | button.mask()
+    };
+    memory.ppu_io_write(KEYINPUT, keys);
+}
+
+/// Pressed buttons, one bit per `Button` in KEYINPUT order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KeyState(pub u16);
+
+impl KeyState {
+    pub fn with(self, button: Button, pressed: bool) -> Self {
+        if pressed {
+            Self(self.0 | button.mask())
+        } else {
+            Self(self.0 & !button.mask())
+        }
+    }
+
+    /// The KEYINPUT value, where a pressed button reads as 0.
+    pub fn keyinput(&self) -> u16 {
+        !self.0 & BUTTONS_MASK
+    }
+}
+
+/// Keys set from another thread, such as the display's event loop, and
+/// latched into KEYINPUT by the emulator on its next step. Clones share
+/// the same keys.
+#[derive(Clone, Debug, Default)]
+pub struct KeyInput {
+    keys: Arc<AtomicU32>,
+}
+
+impl KeyInput {
+    pub fn set_keys(&self, keys: KeyState) {
+        self.keys.store(keys.0 as u32 | KEYS_PENDING, Ordering::Relaxed);
+    }
+
+    /// The keys set since the last call, if any.
+    pub fn take_keys(&self) -> Option<KeyState> {
+        let keys = self.keys.fetch_and(!KEYS_PENDING, Ordering::Relaxed);
+        (keys & KEYS_PENDING > 0).then_some(KeyState(keys as u16))
+    }
+}
+
+pub fn set_keys(memory: &mut dyn MemoryBus, keys: KeyState) {
+    memory.ppu_io_write(KEYINPUT, keys.keyinput());
+}
+
+/// Requests the keypad interrupt when KEYCNT enables it and its buttons
+/// are held: any of them, or all of them with the AND condition.
+pub fn check_keypad_interrupt(memory: &mut dyn MemoryBus) {
+    let control = memory.ppu_io_read(KEYCNT);
+    let selected = control & BUTTONS_MASK;
+    if control & KEYCNT_IRQ_ENABLE == 0 || selected == 0 {
+        return;
+    }
+    let pressed = !memory.ppu_io_read(KEYINPUT) & selected;
+    let condition_met = if control & KEYCNT_AND > 0 {
+        pressed == selected
+    } else {
+        pressed != 0
+    };
+    if condition_met {
+        request_interrupt(memory, Interrupt::Keypad);
+    }
+}
+
+#[cfg(test)]
+mod keypad_tests {
+    use rstest::rstest;
+
+    use crate::{
+        arm7tdmi::interrupts::Interrupt,
+        memory::{
+            io_handlers::{IF, IO_BASE, KEYCNT, KEYINPUT},
+            memory::{GBAMemory, MemoryBus},
+        },
+    };
+
+    use super::{check_keypad_interrupt, set_keys, Button, KeyInput, KeyState};
+
+    #[test]
+    fn a_and_start_read_back_active_low() {
+        let mut memory = GBAMemory::new();
+        let input = KeyInput::default();
+        let display_side = input.clone();
+
+        display_side.set_keys(KeyState::default().with(Button::A, true).with(Button::Start, true));
+        set_keys(memory.as_mut(), input.take_keys().unwrap());
+
+        assert_eq!(memory.readu16(IO_BASE + KEYINPUT).data, 0x3F6);
+        assert_eq!(input.take_keys(), None);
+    }
+
+    #[rstest]
+    #[case::or_with_one_held(1 << 14 | 0x9, KeyState(0x1), true)]
+    #[case::and_with_one_held(1 << 15 | 1 << 14 | 0x9, KeyState(0x1), false)]
+    #[case::and_with_both_held(1 << 15 | 1 << 14 | 0x9, KeyState(0x9), true)]
+    #[case::irq_disabled(0x9, KeyState(0x9), false)]
+    fn keycnt_conditions_request_the_keypad_interrupt(
+        #[case] keycnt: u16,
+        #[case] keys: KeyState,
+        #[case] requested: bool,
+    ) {
+        let mut memory = GBAMemory::new();
+        memory.writeu16(IO_BASE + KEYCNT, keycnt);
+        set_keys(memory.as_mut(), keys);
+
+        check_keypad_interrupt(memory.as_mut());
+
+        assert_eq!(memory.ppu_io_read(IF) & Interrupt::Keypad.bit() > 0, requested);
+    }
+}
@@ -1 +1,4 @@
-pub mod io_handlers;
+pub mod keypad;
+pub mod input_script;
+pub mod timers;
+pub mod sound;
@@ -0,0 +1,529 @@
+pub mod psg;
+
+use std::{collections::VecDeque, fmt::Display};
+
+use crate::{
+    memory::{
+        io_handlers::{SOUNDCNT_H, SOUNDCNT_L, SOUNDCNT_X},
+        memory::MemoryBus,
+    },
+    state::savestate::{StateError, StateReader, StateWriter},
+};
+
+use psg::{NoiseChannel, SquareChannel, WaveChannel};
+
+/// System clock, 2^24 Hz.
+const CLOCK_RATE: u64 = 1 << 24;
+/// Cycles per step of the 512 Hz frame sequencer that clocks the lengths,
+/// sweep and envelopes.
+const FRAME_SEQUENCER_PERIOD: u32 = 32768;
+const MASTER_ENABLE: u16 = 1 << 7;
+/// SOUNDCNT_H bits for FIFO_A, FIFO_B is 4 bits higher: full volume,
+/// enabled on the right, on the left, played on timer 1, and reset.
+const DIRECT_SOUND_FULL_VOLUME: u16 = 1 << 2;
+const DIRECT_SOUND_RIGHT: u16 = 1 << 8;
+const DIRECT_SOUND_LEFT: u16 = 1 << 9;
+const DIRECT_SOUND_TIMER: u16 = 10;
+const DIRECT_SOUND_RESET: u16 = 1 << 11;
+/// Scales the loudest mix, four PSG channels at 15 with the volume at 8
+/// and both FIFOs at full volume, to fit in an i16.
+const OUTPUT_SCALE: i32 = 16;
+pub const DEFAULT_SAMPLE_RATE: u32 = 32768;
+/// One second at the default rate.
+pub const DEFAULT_BUFFER_LENGTH: usize = 32768;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SoundConfigError {
+    ZeroSampleRate,
+    /// Above the system clock, which samples are taken from.
+    SampleRateAboveClock,
+    ZeroBufferLength,
+}
+
+impl Display for SoundConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SoundConfigError::ZeroSampleRate => write!(f, "The sample rate must be above 0"),
+            SoundConfigError::SampleRateAboveClock => {
+                write!(f, "The sample rate can't be above the {} Hz system clock", CLOCK_RATE)
+            }
+            SoundConfigError::ZeroBufferLength => write!(f, "The sound buffer length must be above 0"),
+        }
+    }
+}
+
+/// The output format the host asks for: the rate samples are produced at,
+/// and how many stereo samples are kept for it before the oldest are
+/// dropped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SoundConfig {
+    sample_rate: u32,
+    buffer_length: usize,
+}
+
+impl Default for SoundConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            buffer_length: DEFAULT_BUFFER_LENGTH,
+        }
+    }
+}
+
+impl SoundConfig {
+    pub fn new(sample_rate: u32, buffer_length: usize) -> Result<Self, SoundConfigError> {
+        if sample_rate == 0 {
+            return Err(SoundConfigError::ZeroSampleRate);
+        }
+        if sample_rate as u64 > CLOCK_RATE {
+            return Err(SoundConfigError::SampleRateAboveClock);
+        }
+        if buffer_length == 0 {
+            return Err(SoundConfigError::ZeroBufferLength);
+        }
+        Ok(Self {
+            sample_rate,
+            buffer_length,
+        })
+    }
+}
+
+/// The four PSG channels and the two Direct Sound FIFOs, mixed into
+/// stereo samples at the configured sample rate.
+///
+/// The channels are driven by polling the sound registers, the trigger
+/// bits of SOUNDxCNT and the FIFO reset bits of SOUNDCNT_H are cleared
+/// once they have been seen.
+#[derive(Debug)]
+pub struct Sound {
+    config: SoundConfig,
+    pub square1: SquareChannel,
+    pub square2: SquareChannel,
+    pub wave: WaveChannel,
+    pub noise: NoiseChannel,
+    /// The last sample taken from FIFO_A and FIFO_B, played until the
+    /// next overflow of their timer.
+    pub fifo_samples: [i8; 2],
+    frame_sequencer_step: u8,
+    frame_sequencer_cycles: u32,
+    /// System cycles towards the next sample, in units of 1/sample_rate.
+    sample_clock: u64,
+    /// Interleaved left and right samples not yet taken by the host.
+    samples: VecDeque<i16>,
+}
+
+impl Default for Sound {
+    fn default() -> Self {
+        Self::new(SoundConfig::default())
+    }
+}
+
+impl Sound {
+    pub fn new(config: SoundConfig) -> Self {
+        Self {
+            config,
+            square1: SquareChannel::channel1(),
+            square2: SquareChannel::channel2(),
+            wave: WaveChannel::default(),
+            noise: NoiseChannel::default(),
+            fifo_samples: [0; 2],
+            frame_sequencer_step: 0,
+            frame_sequencer_cycles: 0,
+            sample_clock: 0,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Switches to a new output format. Samples already produced are kept,
+    /// up to the new buffer length.
+    pub fn set_config(&mut self, config: SoundConfig) {
+        self.config = config;
+        let excess = self.samples.len().saturating_sub(2 * config.buffer_length);
+        self.samples.drain(..excess);
+    }
+
+    /// Writes the channels and the FIFO samples playing. The FIFOs
+    /// themselves are in memory.
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        self.square1.save_state(writer);
+        self.square2.save_state(writer);
+        self.wave.save_state(writer);
+        self.noise.save_state(writer);
+        for sample in self.fifo_samples {
+            writer.byte(sample as u8);
+        }
+        writer.byte(self.frame_sequencer_step);
+        writer.word(self.frame_sequencer_cycles);
+        writer.u64(self.sample_clock);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.square1.load_state(reader)?;
+        self.square2.load_state(reader)?;
+        self.wave.load_state(reader)?;
+        self.noise.load_state(reader)?;
+        for sample in self.fifo_samples.iter_mut() {
+            *sample = reader.byte()? as i8;
+        }
+        let offset = reader.offset();
+        self.frame_sequencer_step = match reader.byte()? {
+            step if step < 8 => step,
+            _ => return Err(StateError::InvalidValue { offset }),
+        };
+        self.frame_sequencer_cycles = reader.word()?;
+        let offset = reader.offset();
+        self.sample_clock = match reader.u64()? {
+            clock if clock < CLOCK_RATE => clock,
+            _ => return Err(StateError::InvalidValue { offset }),
+        };
+        Ok(())
+    }
+
+    /// Takes the channels from a sound loaded with `load_state`, keeping
+    /// the output format and the samples the host hasn't taken yet.
+    pub fn restore(&mut self, loaded: Sound) {
+        *self = Sound {
+            config: self.config,
+            samples: std::mem::take(&mut self.samples),
+            ..loaded
+        };
+    }
+
+    /// Takes up to `count` stereo samples, left then right, padding with
+    /// silence if the emulator hasn't produced that many yet.
+    pub fn generate_samples(&mut self, count: usize) -> Vec<i16> {
+        let available = self.samples.len().min(2 * count);
+        let mut samples: Vec<i16> = self.samples.drain(..available).collect();
+        samples.resize(2 * count, 0);
+        samples
+    }
+
+    /// Advances the channels by `cycles` system cycles, producing a sample
+    /// every time a sample period passes.
+    pub fn tick(&mut self, cycles: u32, memory: &mut Box<dyn MemoryBus>) {
+        let memory = memory.as_mut();
+        let master_enabled = memory.ppu_io_read(SOUNDCNT_X) & MASTER_ENABLE > 0;
+        if master_enabled {
+            self.square1.poll(memory);
+            self.square2.poll(memory);
+            self.wave.poll(memory);
+            self.noise.poll(memory);
+        }
+
+        let mut cycles = cycles;
+        while cycles > 0 {
+            let rate = self.config.sample_rate as u64;
+            let until_sample = (CLOCK_RATE - self.sample_clock).div_ceil(rate) as u32;
+            let chunk = cycles.min(until_sample);
+            if master_enabled {
+                self.advance_channels(chunk, memory);
+            }
+            cycles -= chunk;
+            self.sample_clock += chunk as u64 * rate;
+            if self.sample_clock >= CLOCK_RATE {
+                self.sample_clock -= CLOCK_RATE;
+                let [left, right] = if master_enabled { self.mix(memory) } else { [0, 0] };
+                self.push_sample(left, right);
+            }
+        }
+
+        if master_enabled {
+            self.update_channel_flags(memory);
+        }
+    }
+
+    /// Moves each FIFO on by a sample per overflow of the timer it plays
+    /// on. Returns which FIFOs have run low and need a DMA refill.
+    pub fn timer_overflows(&mut self, overflows: [u32; 4], memory: &mut Box<dyn MemoryBus>) -> [bool; 2] {
+        let memory = memory.as_mut();
+        let control = memory.ppu_io_read(SOUNDCNT_H);
+        let resets = DIRECT_SOUND_RESET | DIRECT_SOUND_RESET << 4;
+        if control & resets > 0 {
+            memory.ppu_io_write(SOUNDCNT_H, control & !resets);
+        }
+
+        let mut refill = [false; 2];
+        let master_enabled = memory.ppu_io_read(SOUNDCNT_X) & MASTER_ENABLE > 0;
+        for (fifo, needs_refill) in refill.iter_mut().enumerate() {
+            let shift = 4 * fifo as u16;
+            if control & (DIRECT_SOUND_RESET << shift) > 0 {
+                memory.sound_fifos()[fifo].clear();
+                self.fifo_samples[fifo] = 0;
+            }
+            let timer = (control >> (DIRECT_SOUND_TIMER + shift)) & 1;
+            let overflows = overflows[timer as usize];
+            if !master_enabled || overflows == 0 {
+                continue;
+            }
+            let queue = &mut memory.sound_fifos()[fifo];
+            for _ in 0..overflows {
+                if let Some(sample) = queue.pop() {
+                    self.fifo_samples[fifo] = sample;
+                }
+            }
+            *needs_refill = queue.needs_refill();
+        }
+        refill
+    }
+
+    fn advance_channels(&mut self, cycles: u32, memory: &mut dyn MemoryBus) {
+        self.square1.advance(cycles, memory);
+        self.square2.advance(cycles, memory);
+        self.wave.advance(cycles, memory);
+        self.noise.advance(cycles, memory);
+
+        self.frame_sequencer_cycles += cycles;
+        while self.frame_sequencer_cycles >= FRAME_SEQUENCER_PERIOD {
+            self.frame_sequencer_cycles -= FRAME_SEQUENCER_PERIOD;
+            self.clock_frame_sequencer(memory);
+        }
+    }
+
+    /// Lengths are clocked at 256 Hz, the sweep at 128 Hz and the
+    /// envelopes at 64 Hz.
+    fn clock_frame_sequencer(&mut self, memory: &mut dyn MemoryBus) {
+        let step = self.frame_sequencer_step;
+        self.frame_sequencer_step = (step + 1) % 8;
+        if step.is_multiple_of(2) {
+            self.square1.clock_length();
+            self.square2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+        if step == 2 || step == 6 {
+            self.square1.clock_sweep(memory);
+        }
+        if step == 7 {
+            self.square1.clock_envelope();
+            self.square2.clock_envelope();
+            self.noise.clock_envelope();
+        }
+    }
+
+    /// Mixes the channels enabled on each side of SOUNDCNT_L at that
+    /// side's volume, then applies the PSG volume in SOUNDCNT_H and adds
+    /// the FIFOs enabled on that side.
+    fn mix(&self, memory: &dyn MemoryBus) -> [i16; 2] {
+        let outputs = [
+            self.square1.output(memory),
+            self.square2.output(memory),
+            self.wave.output(memory),
+            self.noise.output(),
+        ];
+        let control = memory.ppu_io_read(SOUNDCNT_L);
+        let direct_control = memory.ppu_io_read(SOUNDCNT_H);
+        let psg_shift = match direct_control & 0x3 {
+            0 => 2,
+            1 => 1,
+            _ => 0,
+        };
+        let direct_sound = |direct_enable: u16| -> i32 {
+            (0..2)
+                .filter(|fifo| direct_control & (direct_enable << (4 * fifo)) > 0)
+                .map(|fifo| {
+                    let volume = match direct_control & (DIRECT_SOUND_FULL_VOLUME << fifo) {
+                        0 => 2,
+                        _ => 4,
+                    };
+                    self.fifo_samples[fifo as usize] as i32 * volume
+                })
+                .sum()
+        };
+        let side = |volume_shift: u16, enable_shift: u16, direct_enable: u16| {
+            let mixed: i32 = outputs
+                .iter()
+                .enumerate()
+                .filter(|(channel, _)| control >> (enable_shift + *channel as u16) & 1 > 0)
+                .map(|(_, output)| output)
+                .sum();
+            let volume = ((control >> volume_shift) & 0x7) as i32 + 1;
+            let psg = (mixed * volume * OUTPUT_SCALE) >> psg_shift;
+            let direct = direct_sound(direct_enable) * OUTPUT_SCALE;
+            (psg + direct).clamp(i16::MIN as i32, i16::MAX as i32) as i16
+        };
+        [side(4, 12, DIRECT_SOUND_LEFT), side(0, 8, DIRECT_SOUND_RIGHT)]
+    }
+
+    fn push_sample(&mut self, left: i16, right: i16) {
+        if self.samples.len() >= 2 * self.config.buffer_length {
+            self.samples.drain(..2);
+        }
+        self.samples.push_back(left);
+        self.samples.push_back(right);
+    }
+
+    /// Reflects which channels are playing in the low bits of SOUNDCNT_X.
+    fn update_channel_flags(&self, memory: &mut dyn MemoryBus) {
+        let flags = self.square1.enabled as u16
+            | (self.square2.enabled as u16) << 1
+            | (self.wave.enabled as u16) << 2
+            | (self.noise.enabled as u16) << 3;
+        let control = memory.ppu_io_read(SOUNDCNT_X);
+        memory.ppu_io_write(SOUNDCNT_X, (control & !0xF) | flags);
+    }
+}
+
+#[cfg(test)]
+mod sound_tests {
+    use rstest::rstest;
+
+    use crate::memory::{
+        io_handlers::{
+            FIFO_A, IO_BASE, SOUND1CNT_H, SOUND1CNT_X, SOUND2CNT_H, SOUND2CNT_L, SOUND3CNT_H, SOUND3CNT_L,
+            SOUND3CNT_X, SOUNDCNT_H, SOUNDCNT_L, SOUNDCNT_X, WAVE_RAM,
+        },
+        memory::{GBAMemory, MemoryBus},
+    };
+
+    use super::{Sound, SoundConfig, SoundConfigError, OUTPUT_SCALE};
+
+    const CYCLES_PER_SAMPLE: u32 = 512;
+    const CYCLES_PER_FRAME: u32 = 280896;
+
+    /// Sound on at full volume, with `channels` on both sides.
+    fn enable_channels(memory: &mut Box<dyn MemoryBus>, channels: u16) {
+        memory.writeu16(IO_BASE + SOUNDCNT_X, 0x80);
+        memory.writeu16(IO_BASE + SOUNDCNT_L, channels << 12 | channels << 8 | 0x77);
+        memory.writeu16(IO_BASE + SOUNDCNT_H, 0x2);
+    }
+
+    fn left(samples: &[i16]) -> Vec<i16> {
+        samples.iter().step_by(2).copied().collect()
+    }
+
+    #[rstest]
+    #[case::f512hz(1792, 64)]
+    #[case::f1024hz(1920, 32)]
+    fn square_wave_repeats_at_the_channel_frequency(#[case] frequency: u16, #[case] period: usize) {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        let mut sound = Sound::default();
+        enable_channels(&mut memory, 0b0001);
+        memory.writeu16(IO_BASE + SOUND1CNT_H, 0xF080); // volume 15, 50% duty
+        memory.writeu16(IO_BASE + SOUND1CNT_X, 0x8000 | frequency);
+
+        sound.tick(256 * CYCLES_PER_SAMPLE, &mut memory);
+        let samples = left(&sound.generate_samples(256));
+
+        let rising_edges: Vec<usize> = (1..samples.len())
+            .filter(|&i| samples[i - 1] < 0 && samples[i] > 0)
+            .collect();
+        assert!(rising_edges.len() >= 3);
+        assert!(rising_edges.windows(2).all(|edges| edges[1] - edges[0] == period));
+        let high = samples[rising_edges[0]..rising_edges[1]].iter().filter(|&&s| s > 0).count();
+        assert_eq!(high, period / 2);
+    }
+
+    #[test]
+    fn channel_stops_when_its_length_runs_out() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        let mut sound = Sound::default();
+        enable_channels(&mut memory, 0b0010);
+        memory.writeu16(IO_BASE + SOUND2CNT_L, 0xF080 | 62); // 2 length steps
+        memory.writeu16(IO_BASE + SOUND2CNT_H, 0xC000 | 1792);
+
+        sound.tick(1, &mut memory);
+        assert_eq!(memory.ppu_io_read(SOUNDCNT_X) & 0xF, 0b0010);
+        // lengths are clocked every other 512 Hz step
+        sound.tick(3 * 32768, &mut memory);
+
+        assert!(!sound.square2.enabled);
+        assert_eq!(memory.ppu_io_read(SOUNDCNT_X) & 0xF, 0);
+        let samples = sound.generate_samples(256);
+        assert!(samples[samples.len() - 64..].iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn wave_channel_plays_the_selected_bank() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        let mut sound = Sound::default();
+        enable_channels(&mut memory, 0b0100);
+        // the CPU writes the bank that isn't playing, then selects it
+        for i in 0..8 {
+            memory.writeu16(IO_BASE + WAVE_RAM + 2 * i, 0x2301 + 0x4444 * (i as u16 % 4));
+        }
+        sound.tick(1, &mut memory);
+        memory.writeu16(IO_BASE + SOUND3CNT_L, 0xC0);
+        memory.writeu16(IO_BASE + SOUND3CNT_H, 0x2000); // 100% volume
+        // one sample per output sample
+        memory.writeu16(IO_BASE + SOUND3CNT_X, 0x8000 | (2048 - CYCLES_PER_SAMPLE as u16 / 8));
+
+        sound.tick(64 * CYCLES_PER_SAMPLE, &mut memory);
+        let samples = left(&sound.generate_samples(64));
+
+        let step = |level: i32| (level * 8 * OUTPUT_SCALE) as i16;
+        let rising = samples.windows(2).filter(|pair| pair[1] - pair[0] == step(2)).count();
+        let wrapping = samples.windows(2).filter(|pair| pair[1] - pair[0] == step(-30)).count();
+        assert_eq!(rising + wrapping, samples.len() - 1);
+        assert_eq!(memory.ppu_io_read(WAVE_RAM), 0);
+    }
+
+    #[test]
+    fn fifo_a_plays_a_sample_per_timer_overflow() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        let mut sound = Sound::default();
+        memory.writeu16(IO_BASE + SOUNDCNT_X, 0x80);
+        // FIFO_A at full volume on both sides, played on timer 0
+        memory.writeu16(IO_BASE + SOUNDCNT_H, 0x0304);
+        memory.writeu32(IO_BASE + FIFO_A, 0x807F_F010);
+        memory.writeu16(IO_BASE + FIFO_A, 0x0201);
+        memory.write(IO_BASE + FIFO_A, 0x03);
+
+        let mut played = vec![];
+        for overflows in [1, 1, 1, 1, 0, 2] {
+            let refill = sound.timer_overflows([overflows, 0, 0, 0], &mut memory);
+            assert!(refill[0] || overflows == 0);
+            sound.tick(CYCLES_PER_SAMPLE, &mut memory);
+            played.push(sound.generate_samples(1)[0] as i32 / OUTPUT_SCALE);
+        }
+        assert_eq!(played, [0x10 * 4, -0x10 * 4, 0x7F * 4, -0x80 * 4, -0x80 * 4, 2 * 4]);
+
+        memory.writeu16(IO_BASE + SOUNDCNT_H, 0x0B04);
+        sound.timer_overflows([0; 4], &mut memory);
+        sound.tick(CYCLES_PER_SAMPLE, &mut memory);
+        assert_eq!(sound.generate_samples(1), [0, 0]);
+        assert_eq!(memory.ppu_io_read(SOUNDCNT_H), 0x0304);
+    }
+
+    #[rstest]
+    #[case::f44100hz(44100, 738)]
+    #[case::f48000hz(48000, 803)]
+    fn samples_per_frame_follow_the_sample_rate(#[case] sample_rate: u32, #[case] expected: usize) {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        let mut sound = Sound::new(SoundConfig::new(sample_rate, 4096).unwrap());
+
+        sound.tick(CYCLES_PER_FRAME, &mut memory);
+
+        assert_eq!(sound.samples.len(), 2 * expected);
+    }
+
+    #[test]
+    fn buffer_length_caps_the_samples_kept_for_the_host() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        let mut sound = Sound::new(SoundConfig::new(48000, 512).unwrap());
+
+        sound.tick(CYCLES_PER_FRAME, &mut memory);
+        assert_eq!(sound.samples.len(), 2 * 512);
+
+        sound.set_config(SoundConfig::new(48000, 100).unwrap());
+        assert_eq!(sound.samples.len(), 2 * 100);
+    }
+
+    #[test]
+    fn zero_sample_rate_or_buffer_length_is_rejected() {
+        assert_eq!(SoundConfig::new(0, 512), Err(SoundConfigError::ZeroSampleRate));
+        assert_eq!(SoundConfig::new(44100, 0), Err(SoundConfigError::ZeroBufferLength));
+    }
+
+    #[test]
+    fn sample_rate_is_capped_at_the_system_clock() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        let mut sound = Sound::new(SoundConfig::new(1 << 24, 4096).unwrap());
+
+        sound.tick(100, &mut memory);
+        assert_eq!(sound.samples.len(), 2 * 100);
+        assert_eq!(
+            SoundConfig::new((1 << 24) + 1, 4096),
+            Err(SoundConfigError::SampleRateAboveClock)
+        );
+    }
+}
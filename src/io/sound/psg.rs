@@ -0,0 +1,544 @@
+use crate::{
+    memory::{
+        io_handlers::{
+            SOUND1CNT_H, SOUND1CNT_L, SOUND1CNT_X, SOUND2CNT_H, SOUND2CNT_L, SOUND3CNT_H,
+            SOUND3CNT_L, SOUND3CNT_X, SOUND4CNT_H, SOUND4CNT_L, WAVE_RAM,
+        },
+        memory::MemoryBus,
+    },
+    state::savestate::{StateError, StateReader, StateWriter},
+};
+
+const TRIGGER: u16 = 1 << 15;
+const LENGTH_ENABLE: u16 = 1 << 14;
+const FREQUENCY_MASK: u16 = 0x7FF;
+const WAVE_BANK_BYTES: usize = 16;
+
+/// Duty cycles of 12.5%, 25%, 50% and 75%, one bit per eighth of a period.
+const DUTY_PATTERNS: [[bool; 8]; 4] = [
+    [false, false, false, false, false, false, false, true],
+    [true, false, false, false, false, false, false, true],
+    [true, false, false, false, false, true, true, true],
+    [false, true, true, true, true, true, true, false],
+];
+
+/// Takes the trigger bit out of `register`, so the next poll only sees a
+/// new write. Returns the register as it was written.
+fn take_trigger(memory: &mut dyn MemoryBus, register: usize) -> u16 {
+    let value = memory.ppu_io_read(register);
+    if value & TRIGGER > 0 {
+        memory.ppu_io_write(register, value & !TRIGGER);
+    }
+    value
+}
+
+/// Counts a channel down to silence when its length flag is set.
+#[derive(Clone, Copy, Debug, Default)]
+struct LengthCounter {
+    remaining: u32,
+    enabled: bool,
+}
+
+impl LengthCounter {
+    fn trigger(&mut self, length: u32, control: u16) {
+        self.remaining = length;
+        self.enabled = control & LENGTH_ENABLE > 0;
+    }
+
+    /// Returns false once the length has run out.
+    fn clock(&mut self) -> bool {
+        if !self.enabled || self.remaining == 0 {
+            return true;
+        }
+        self.remaining -= 1;
+        self.remaining > 0
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.word(self.remaining);
+        writer.flag(self.enabled);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.remaining = reader.word()?;
+        self.enabled = reader.flag()?;
+        Ok(())
+    }
+}
+
+/// Volume envelope in bits 8-15 of SOUNDxCNT_H, SOUND2CNT_L and
+/// SOUND4CNT_L.
+#[derive(Clone, Copy, Debug, Default)]
+struct Envelope {
+    volume: u8,
+    increase: bool,
+    step: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    /// The channel is silent while its envelope starts at zero and falls.
+    fn dac_enabled(register: u16) -> bool {
+        register & 0xF800 > 0
+    }
+
+    fn trigger(&mut self, register: u16) {
+        self.volume = (register >> 12) as u8;
+        self.increase = register & (1 << 11) > 0;
+        self.step = ((register >> 8) & 0x7) as u8;
+        self.timer = self.step;
+    }
+
+    fn clock(&mut self) {
+        if self.step == 0 {
+            return;
+        }
+        self.timer -= 1;
+        if self.timer > 0 {
+            return;
+        }
+        self.timer = self.step;
+        if self.increase && self.volume < 15 {
+            self.volume += 1;
+        } else if !self.increase && self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.byte(self.volume);
+        writer.flag(self.increase);
+        writer.byte(self.step);
+        writer.byte(self.timer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.volume = reader.byte()?;
+        self.increase = reader.flag()?;
+        self.step = reader.byte()?;
+        self.timer = reader.byte()?;
+        Ok(())
+    }
+}
+
+/// Channel 1's frequency sweep, which writes the swept frequency back to
+/// SOUND1CNT_X.
+#[derive(Clone, Copy, Debug, Default)]
+struct Sweep {
+    enabled: bool,
+    shadow_frequency: u16,
+    timer: u8,
+}
+
+impl Sweep {
+    fn period(register: u16) -> u8 {
+        match (register >> 4) & 0x7 {
+            0 => 8,
+            period => period as u8,
+        }
+    }
+
+    /// The next frequency, which can be past the 11 bit range.
+    fn next_frequency(&self, register: u16) -> u16 {
+        let delta = self.shadow_frequency >> (register & 0x7);
+        if register & (1 << 3) > 0 {
+            self.shadow_frequency - delta
+        } else {
+            self.shadow_frequency + delta
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.flag(self.enabled);
+        writer.hword(self.shadow_frequency);
+        writer.byte(self.timer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = reader.flag()?;
+        self.shadow_frequency = reader.hword()?;
+        self.timer = reader.byte()?;
+        Ok(())
+    }
+}
+
+/// Channels 1 and 2. Only channel 1 has the sweep register.
+#[derive(Debug)]
+pub struct SquareChannel {
+    sweep_register: Option<usize>,
+    duty_register: usize,
+    control_register: usize,
+    pub enabled: bool,
+    length: LengthCounter,
+    envelope: Envelope,
+    sweep: Sweep,
+    duty_position: usize,
+    /// Cycles until the next eighth of the period.
+    timer: u32,
+}
+
+impl SquareChannel {
+    pub fn channel1() -> Self {
+        Self::new(Some(SOUND1CNT_L), SOUND1CNT_H, SOUND1CNT_X)
+    }
+
+    pub fn channel2() -> Self {
+        Self::new(None, SOUND2CNT_L, SOUND2CNT_H)
+    }
+
+    fn new(sweep_register: Option<usize>, duty_register: usize, control_register: usize) -> Self {
+        Self {
+            sweep_register,
+            duty_register,
+            control_register,
+            enabled: false,
+            length: LengthCounter::default(),
+            envelope: Envelope::default(),
+            sweep: Sweep::default(),
+            duty_position: 0,
+            timer: 0,
+        }
+    }
+
+    /// Cycles per eighth of the period, 16 for each step below 2048.
+    fn step_period(&self, memory: &dyn MemoryBus) -> u32 {
+        let frequency = memory.ppu_io_read(self.control_register) & FREQUENCY_MASK;
+        16 * (2048 - frequency as u32)
+    }
+
+    pub fn poll(&mut self, memory: &mut dyn MemoryBus) {
+        let control = take_trigger(memory, self.control_register);
+        let duty = memory.ppu_io_read(self.duty_register);
+        if control & TRIGGER == 0 {
+            self.length.enabled = control & LENGTH_ENABLE > 0;
+            return;
+        }
+        self.enabled = Envelope::dac_enabled(duty);
+        self.length.trigger(64 - (duty & 0x3F) as u32, control);
+        self.envelope.trigger(duty);
+        self.timer = self.step_period(memory);
+        if let Some(sweep_register) = self.sweep_register {
+            let sweep = memory.ppu_io_read(sweep_register);
+            self.sweep = Sweep {
+                enabled: sweep & 0x77 > 0,
+                shadow_frequency: control & FREQUENCY_MASK,
+                timer: Sweep::period(sweep),
+            };
+            if sweep & 0x7 > 0 && self.sweep.next_frequency(sweep) > FREQUENCY_MASK {
+                self.enabled = false;
+            }
+        }
+    }
+
+    pub fn advance(&mut self, cycles: u32, memory: &dyn MemoryBus) {
+        if !self.enabled {
+            return;
+        }
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.step_period(memory);
+            self.duty_position = (self.duty_position + 1) % 8;
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        self.enabled &= self.length.clock();
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self, memory: &mut dyn MemoryBus) {
+        let Some(sweep_register) = self.sweep_register.filter(|_| self.enabled) else {
+            return;
+        };
+        let register = memory.ppu_io_read(sweep_register);
+        self.sweep.timer -= 1;
+        if self.sweep.timer > 0 {
+            return;
+        }
+        self.sweep.timer = Sweep::period(register);
+        if !self.sweep.enabled || register & 0x70 == 0 {
+            return;
+        }
+        let frequency = self.sweep.next_frequency(register);
+        if frequency > FREQUENCY_MASK {
+            self.enabled = false;
+            return;
+        }
+        if register & 0x7 > 0 {
+            self.sweep.shadow_frequency = frequency;
+            let control = memory.ppu_io_read(self.control_register);
+            memory.ppu_io_write(self.control_register, (control & !FREQUENCY_MASK) | frequency);
+            if self.sweep.next_frequency(register) > FREQUENCY_MASK {
+                self.enabled = false;
+            }
+        }
+    }
+
+    pub fn output(&self, memory: &dyn MemoryBus) -> i32 {
+        if !self.enabled {
+            return 0;
+        }
+        let duty = (memory.ppu_io_read(self.duty_register) >> 6) & 0x3;
+        let volume = self.envelope.volume as i32;
+        if DUTY_PATTERNS[duty as usize][self.duty_position] {
+            volume
+        } else {
+            -volume
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.flag(self.enabled);
+        self.length.save_state(writer);
+        self.envelope.save_state(writer);
+        self.sweep.save_state(writer);
+        writer.word(self.duty_position as u32);
+        writer.word(self.timer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = reader.flag()?;
+        self.length.load_state(reader)?;
+        self.envelope.load_state(reader)?;
+        self.sweep.load_state(reader)?;
+        self.duty_position = reader.index(DUTY_PATTERNS[0].len())?;
+        self.timer = reader.word()?;
+        Ok(())
+    }
+}
+
+/// Channel 3, which plays 4-bit samples from one of two 32 sample banks.
+/// The CPU sees the bank that isn't selected for playback at WAVE_RAM.
+#[derive(Debug, Default)]
+pub struct WaveChannel {
+    pub enabled: bool,
+    length: LengthCounter,
+    banks: [[u8; WAVE_BANK_BYTES]; 2],
+    /// The bank selected for playback when the banks were last synced.
+    selected_bank: usize,
+    position: usize,
+    timer: u32,
+}
+
+impl WaveChannel {
+    fn step_period(memory: &dyn MemoryBus) -> u32 {
+        let frequency = memory.ppu_io_read(SOUND3CNT_X) & FREQUENCY_MASK;
+        8 * (2048 - frequency as u32)
+    }
+
+    fn read_wave_ram(&mut self, bank: usize, memory: &dyn MemoryBus) {
+        for i in (0..WAVE_BANK_BYTES).step_by(2) {
+            let value = memory.ppu_io_read(WAVE_RAM + i).to_le_bytes();
+            self.banks[bank][i..i + 2].copy_from_slice(&value);
+        }
+    }
+
+    fn write_wave_ram(&self, bank: usize, memory: &mut dyn MemoryBus) {
+        for i in (0..WAVE_BANK_BYTES).step_by(2) {
+            let value = u16::from_le_bytes([self.banks[bank][i], self.banks[bank][i + 1]]);
+            memory.ppu_io_write(WAVE_RAM + i, value);
+        }
+    }
+
+    /// Keeps the CPU's writes to WAVE_RAM in the bank they went to, and
+    /// swaps the other bank in when the selection changes.
+    fn sync_banks(&mut self, select: u16, memory: &mut dyn MemoryBus) {
+        let selected_bank = ((select >> 6) & 0x1) as usize;
+        self.read_wave_ram(self.selected_bank ^ 1, memory);
+        if selected_bank != self.selected_bank {
+            self.selected_bank = selected_bank;
+            self.write_wave_ram(selected_bank ^ 1, memory);
+        }
+    }
+
+    pub fn poll(&mut self, memory: &mut dyn MemoryBus) {
+        let select = memory.ppu_io_read(SOUND3CNT_L);
+        self.sync_banks(select, memory);
+        let control = take_trigger(memory, SOUND3CNT_X);
+        let dac_enabled = select & (1 << 7) > 0;
+        if control & TRIGGER == 0 {
+            self.length.enabled = control & LENGTH_ENABLE > 0;
+            self.enabled &= dac_enabled;
+            return;
+        }
+        let length = memory.ppu_io_read(SOUND3CNT_H) & 0xFF;
+        self.enabled = dac_enabled;
+        self.length.trigger(256 - length as u32, control);
+        self.position = 0;
+        self.timer = Self::step_period(memory);
+    }
+
+    pub fn advance(&mut self, cycles: u32, memory: &dyn MemoryBus) {
+        if !self.enabled {
+            return;
+        }
+        // two banks are played one after the other in 64 sample mode
+        let samples = if memory.ppu_io_read(SOUND3CNT_L) & (1 << 5) > 0 { 64 } else { 32 };
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = Self::step_period(memory);
+            self.position = (self.position + 1) % samples;
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        self.enabled &= self.length.clock();
+    }
+
+    pub fn output(&self, memory: &dyn MemoryBus) -> i32 {
+        if !self.enabled {
+            return 0;
+        }
+        let bank = (self.selected_bank + self.position / 32) % 2;
+        let byte = self.banks[bank][(self.position % 32) / 2];
+        // the high nibble plays first
+        let sample = if self.position.is_multiple_of(2) { byte >> 4 } else { byte & 0xF };
+        let sample = 2 * sample as i32 - 15;
+        let volume = memory.ppu_io_read(SOUND3CNT_H);
+        if volume & (1 << 15) > 0 {
+            return sample * 3 / 4;
+        }
+        match (volume >> 13) & 0x3 {
+            0 => 0,
+            1 => sample,
+            2 => sample / 2,
+            _ => sample / 4,
+        }
+    }
+
+    /// Both banks are written, the one the CPU sees is also in WAVE_RAM.
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.flag(self.enabled);
+        self.length.save_state(writer);
+        for bank in &self.banks {
+            writer.bytes(bank);
+        }
+        writer.word(self.selected_bank as u32);
+        writer.word(self.position as u32);
+        writer.word(self.timer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = reader.flag()?;
+        self.length.load_state(reader)?;
+        for bank in self.banks.iter_mut() {
+            reader.bytes_into(bank)?;
+        }
+        self.selected_bank = reader.index(self.banks.len())?;
+        self.position = reader.index(2 * 2 * WAVE_BANK_BYTES)?;
+        self.timer = reader.word()?;
+        Ok(())
+    }
+}
+
+/// Channel 4, a linear feedback shift register clocked at a configurable
+/// rate.
+#[derive(Debug)]
+pub struct NoiseChannel {
+    pub enabled: bool,
+    length: LengthCounter,
+    envelope: Envelope,
+    lfsr: u16,
+    timer: u32,
+}
+
+impl Default for NoiseChannel {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            length: LengthCounter::default(),
+            envelope: Envelope::default(),
+            lfsr: 0x7FFF,
+            timer: 0,
+        }
+    }
+}
+
+impl NoiseChannel {
+    /// Cycles per shift, from the dividing ratio in bits 0-2 and the
+    /// shift in bits 4-7 of SOUND4CNT_H. A ratio of 0 counts as 0.5.
+    fn shift_period(control: u16) -> u32 {
+        let ratio = match control & 0x7 {
+            0 => 16,
+            ratio => 32 * ratio as u32,
+        };
+        ratio << (((control >> 4) & 0xF) + 1)
+    }
+
+    pub fn poll(&mut self, memory: &mut dyn MemoryBus) {
+        let control = take_trigger(memory, SOUND4CNT_H);
+        let envelope = memory.ppu_io_read(SOUND4CNT_L);
+        if control & TRIGGER == 0 {
+            self.length.enabled = control & LENGTH_ENABLE > 0;
+            return;
+        }
+        self.enabled = Envelope::dac_enabled(envelope);
+        self.length.trigger(64 - (envelope & 0x3F) as u32, control);
+        self.envelope.trigger(envelope);
+        self.lfsr = 0x7FFF;
+        self.timer = Self::shift_period(control);
+    }
+
+    pub fn advance(&mut self, cycles: u32, memory: &dyn MemoryBus) {
+        if !self.enabled {
+            return;
+        }
+        let control = memory.ppu_io_read(SOUND4CNT_H);
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = Self::shift_period(control);
+            let bit = (self.lfsr ^ (self.lfsr >> 1)) & 0x1;
+            self.lfsr = (self.lfsr >> 1) | (bit << 14);
+            // the 7 bit mode also feeds the bit back in below bit 7
+            if control & (1 << 3) > 0 {
+                self.lfsr = (self.lfsr & !(1 << 6)) | (bit << 6);
+            }
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        self.enabled &= self.length.clock();
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn output(&self) -> i32 {
+        if !self.enabled {
+            return 0;
+        }
+        let volume = self.envelope.volume as i32;
+        if self.lfsr & 0x1 == 0 {
+            volume
+        } else {
+            -volume
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.flag(self.enabled);
+        self.length.save_state(writer);
+        self.envelope.save_state(writer);
+        writer.hword(self.lfsr);
+        writer.word(self.timer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = reader.flag()?;
+        self.length.load_state(reader)?;
+        self.envelope.load_state(reader)?;
+        self.lfsr = reader.hword()?;
+        self.timer = reader.word()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,301 @@
+use crate::{
+    arm7tdmi::interrupts::{request_interrupt, Interrupt},
+    memory::{
+        io_handlers::{TM0CNT_H, TM0CNT_L},
+        memory::MemoryBus,
+    },
+    state::savestate::{StateError, StateReader, StateWriter},
+};
+
+/// Each timer's registers are 4 bytes after the previous timer's.
+pub(crate) const TIMER_STRIDE: usize = 0x4;
+const PRESCALER_MASK: u16 = 0x3;
+const COUNT_UP: u16 = 1 << 2;
+const TIMER_IRQ: u16 = 1 << 6;
+const TIMER_ENABLE: u16 = 1 << 7;
+const COUNTER_RANGE: u32 = 0x10000;
+
+/// System cycles per increment with the prescaler in TMxCNT_H `control`:
+/// 1, 64, 256 or 1024.
+pub(crate) fn prescaler_period(control: u16) -> u32 {
+    match control & PRESCALER_MASK {
+        0 => 1,
+        1 => 64,
+        2 => 256,
+        _ => 1024,
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Timer {
+    index: usize,
+    /// Set once the enable bit has been seen and the reload latched.
+    running: bool,
+    /// TMxCNT_H as of the last `latch`.
+    control: u16,
+    counter: u16,
+    /// System cycles not yet making up a prescaler period.
+    prescaler_cycles: u32,
+}
+
+impl Timer {
+    fn register(&self, offset: usize) -> usize {
+        offset + self.index * TIMER_STRIDE
+    }
+
+    /// Picks up the control register, reloading the counter if the timer
+    /// was just enabled.
+    fn latch(&mut self, memory: &dyn MemoryBus) {
+        self.control = memory.ppu_io_read(self.register(TM0CNT_H));
+        if self.control & TIMER_ENABLE == 0 {
+            self.running = false;
+        } else if !self.running {
+            self.running = true;
+            self.counter = self.reload(memory);
+            self.prescaler_cycles = 0;
+        }
+    }
+
+    fn counts_up(&self) -> bool {
+        // timer 0 has nothing below it to count up from
+        self.control & COUNT_UP > 0 && self.index > 0
+    }
+
+    fn reload(&self, memory: &dyn MemoryBus) -> u16 {
+        memory.ppu_io_read(self.register(TM0CNT_L))
+    }
+
+    /// Adds `increments` to the counter, reloading on every overflow, and
+    /// returns how many overflows happened.
+    fn count(&mut self, increments: u32, reload: u16) -> u32 {
+        let total = self.counter as u32 + increments;
+        if total < COUNTER_RANGE {
+            self.counter = total as u16;
+            return 0;
+        }
+        let period = COUNTER_RANGE - reload as u32;
+        let excess = total - COUNTER_RANGE;
+        self.counter = (reload as u32 + excess % period) as u16;
+        1 + excess / period
+    }
+}
+
+/// The four hardware timers. A timer with the count-up bit set ignores
+/// its prescaler and increments once per overflow of the timer below it,
+/// so the timers are ticked in order.
+///
+/// TMxCNT_L holds the reload value the CPU wrote. Reads of it see the
+/// running counter through the `TimerReadout` in memory.
+#[derive(Clone, Debug)]
+pub struct Timers {
+    timers: [Timer; 4],
+}
+
+impl Default for Timers {
+    fn default() -> Self {
+        let mut timers = [Timer::default(); 4];
+        for (index, timer) in timers.iter_mut().enumerate() {
+            timer.index = index;
+        }
+        Self { timers }
+    }
+}
+
+impl Timers {
+    /// The counter as of the last time the timers ran, see
+    /// `GBA::read_timer_counter` for the running one.
+    pub fn read_counter(&self, timer: usize) -> u16 {
+        self.timers[timer].counter
+    }
+
+    /// Reads the control registers. A timer that was enabled starts from
+    /// its reload value and one that was disabled stops.
+    pub fn latch(&mut self, memory: &dyn MemoryBus) {
+        for timer in self.timers.iter_mut() {
+            timer.latch(memory);
+        }
+    }
+
+    /// Advances the timers by `cycles` system cycles with the settings
+    /// last latched, raising the overflow interrupt of every timer that
+    /// overflows with its IRQ bit set. Returns how many times each timer
+    /// overflowed.
+    pub fn advance(&mut self, cycles: u32, memory: &mut Box<dyn MemoryBus>) -> [u32; 4] {
+        let overflows = self.count(cycles, memory.as_ref());
+        for (timer, &overflowed) in self.timers.iter().zip(overflows.iter()) {
+            if overflowed > 0 && timer.control & TIMER_IRQ > 0 {
+                request_interrupt(memory.as_mut(), Interrupt::timer(timer.index));
+            }
+        }
+        overflows
+    }
+
+    /// Runs the counters on by `cycles`, returning the overflows.
+    fn count(&mut self, cycles: u32, memory: &dyn MemoryBus) -> [u32; 4] {
+        let mut overflows = [0; 4];
+        let mut previous_overflows = 0;
+        for timer in self.timers.iter_mut() {
+            let control = timer.control;
+            if !timer.running {
+                previous_overflows = 0;
+                continue;
+            }
+            let reload = timer.reload(memory);
+
+            let increments = if timer.counts_up() {
+                previous_overflows
+            } else {
+                let period = prescaler_period(control);
+                timer.prescaler_cycles += cycles;
+                let increments = timer.prescaler_cycles / period;
+                timer.prescaler_cycles %= period;
+                increments
+            };
+
+            previous_overflows = timer.count(increments, reload);
+            overflows[timer.index] = previous_overflows;
+        }
+        overflows
+    }
+
+    /// Writes the counters and prescalers, which don't live in IO memory.
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        for timer in &self.timers {
+            writer.flag(timer.running);
+            writer.hword(timer.control);
+            writer.hword(timer.counter);
+            writer.word(timer.prescaler_cycles);
+        }
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        for timer in self.timers.iter_mut() {
+            timer.running = reader.flag()?;
+            timer.control = reader.hword()?;
+            timer.counter = reader.hword()?;
+            timer.prescaler_cycles = reader.word()?;
+        }
+        Ok(())
+    }
+
+    /// Cycles until the next overflow of a timer driven by the clock.
+    /// Timers counting up only overflow along with the timer below them.
+    pub fn cycles_until_overflow(&self) -> Option<u64> {
+        self.timers
+            .iter()
+            .filter(|timer| timer.running && !timer.counts_up())
+            .map(|timer| {
+                let increments = (COUNTER_RANGE - timer.counter as u32) as u64;
+                let period = prescaler_period(timer.control) as u64;
+                increments * period - timer.prescaler_cycles as u64
+            })
+            .min()
+    }
+}
+
+/// The timers as of their last sync, for reads of TMxCNT_L. The timers
+/// only run when the scheduler gets to them, so a read works out how far
+/// the counters have moved on since.
+#[derive(Debug, Default)]
+pub struct TimerReadout {
+    timers: Timers,
+    synced_at: u64,
+    /// The system time the current instruction started at.
+    pub now: u64,
+}
+
+impl TimerReadout {
+    pub fn sync(&mut self, timers: &Timers, synced_at: u64) {
+        self.timers.clone_from(timers);
+        self.synced_at = synced_at;
+    }
+
+    pub fn counter(&self, timer: usize, memory: &dyn MemoryBus) -> u16 {
+        let mut timers = self.timers.clone();
+        // a running timer is synced at least once per overflow
+        let elapsed = self.now.saturating_sub(self.synced_at).min(u32::MAX as u64);
+        timers.count(elapsed as u32, memory);
+        timers.timers[timer].counter
+    }
+}
+
+#[cfg(test)]
+mod timers_tests {
+    use rstest::rstest;
+
+    use crate::memory::{
+        io_handlers::{IF, IO_BASE, TM0CNT_H, TM0CNT_L},
+        memory::{GBAMemory, MemoryBus},
+    };
+
+    use super::Timers;
+
+    const TM1CNT_L: usize = TM0CNT_L + 4;
+    const TM1CNT_H: usize = TM0CNT_H + 4;
+
+    /// Picks up control register writes like the scheduler does, then runs
+    /// the timers for `cycles`.
+    fn tick(timers: &mut Timers, cycles: u32, memory: &mut Box<dyn MemoryBus>) -> [u32; 4] {
+        timers.latch(memory.as_ref());
+        timers.advance(cycles, memory)
+    }
+
+    #[rstest]
+    #[case::f1(0, 1)]
+    #[case::f64(1, 64)]
+    #[case::f256(2, 256)]
+    #[case::f1024(3, 1024)]
+    fn prescaled_timer_overflows_and_requests_an_irq(#[case] prescaler: u16, #[case] period: u32) {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        let mut timers = Timers::default();
+        memory.writeu16(IO_BASE + TM0CNT_L, 0xFFF0);
+        memory.writeu16(IO_BASE + TM0CNT_H, 1 << 7 | 1 << 6 | prescaler);
+
+        tick(&mut timers, 15 * period, &mut memory);
+        assert_eq!(timers.read_counter(0), 0xFFFF);
+        assert_eq!(memory.ppu_io_read(IF), 0);
+
+        tick(&mut timers, period, &mut memory);
+        assert_eq!(timers.read_counter(0), 0xFFF0);
+        assert_eq!(memory.ppu_io_read(IF), 1 << 3);
+    }
+
+    #[test]
+    fn cascaded_timer_counts_overflows_of_the_timer_below() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        let mut timers = Timers::default();
+        memory.writeu16(IO_BASE + TM0CNT_L, 0xFF00);
+        memory.writeu16(IO_BASE + TM0CNT_H, 1 << 7);
+        memory.writeu16(IO_BASE + TM1CNT_L, 0xFFFE);
+        memory.writeu16(IO_BASE + TM1CNT_H, 1 << 7 | 1 << 6 | 1 << 2 | 3);
+
+        tick(&mut timers, 0x100, &mut memory);
+        assert_eq!(timers.read_counter(0), 0xFF00);
+        assert_eq!(timers.read_counter(1), 0xFFFF);
+        assert_eq!(memory.ppu_io_read(IF), 0);
+
+        // two overflows of timer 0 in one tick carry through to timer 1
+        let overflows = tick(&mut timers, 0x200, &mut memory);
+        assert_eq!(overflows, [2, 1, 0, 0]);
+        assert_eq!(timers.read_counter(1), 0xFFFF);
+        assert_eq!(memory.ppu_io_read(IF), 1 << 4);
+    }
+
+    #[test]
+    fn disabled_timer_reloads_when_enabled_again() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        let mut timers = Timers::default();
+        memory.writeu16(IO_BASE + TM0CNT_L, 0x1000);
+        memory.writeu16(IO_BASE + TM0CNT_H, 1 << 7);
+        tick(&mut timers, 0x20, &mut memory);
+        assert_eq!(timers.read_counter(0), 0x1020);
+
+        memory.writeu16(IO_BASE + TM0CNT_H, 0);
+        tick(&mut timers, 0x20, &mut memory);
+        assert_eq!(timers.read_counter(0), 0x1020);
+
+        memory.writeu16(IO_BASE + TM0CNT_H, 1 << 7);
+        tick(&mut timers, 0x1, &mut memory);
+        assert_eq!(timers.read_counter(0), 0x1001);
+    }
+}
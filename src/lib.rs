@@ -5,3 +5,7 @@ pub mod graphics;
 pub(crate) mod utils;
 pub(crate) mod types;
 pub mod gba;
+pub mod scheduler;
+pub mod cheats;
+pub mod state;
+pub mod io;
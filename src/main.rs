@@ -1,16 +1,20 @@
-use std::panic;
 use std::thread;
 
-use debugger::debugger::start_debugger;
+use gameboy_advance::debugger::debugger::start_debugger;
+use gameboy_advance::graphics::display::{Display, FrameHandoff, GBA_FRAME_RATE};
+use gameboy_advance::io::keypad::KeyInput;
+use gameboy_advance::memory::backup::BackupType;
 use getopts::Options;
 use std::env;
-mod arm7tdmi;
-mod debugger;
-mod graphics;
-mod memory;
-mod types;
-mod utils;
-mod gba;
+use std::path::Path;
+use std::process;
+
+/// Prints `message` and the usage text, then exits with a failure status.
+fn exit_with_usage(opts: &Options, program: &str, message: &str) -> ! {
+    eprintln!("{}", message);
+    eprint!("{}", opts.usage(&format!("Usage: {} -g ROM [options]", program)));
+    process::exit(1);
+}
 
 fn main() -> Result<(), std::io::Error> {
     let args: Vec<String> = env::args().collect();
@@ -18,22 +22,65 @@ fn main() -> Result<(), std::io::Error> {
     let mut opts = Options::new();
     opts.optopt("b", "bios", "set bios", "BIOS");
     opts.optopt("g", "game", "set game rom", "ROM");
+    opts.optopt(
+        "s",
+        "save-type",
+        "force the backup type: none, sram, eeprom512, eeprom8k, flash64k or flash128k",
+        "TYPE",
+    );
+    opts.optflag(
+        "p",
+        "debug-on-panic",
+        "keep the debugger open with a backtrace when the emulator panics",
+    );
+    opts.optflag(
+        "u",
+        "unlimited",
+        "run the display as fast as possible instead of at the GBA's frame rate",
+    );
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
-        Err(_) => {
-            panic!("Invalid Argument")
-        }
+        Err(error) => exit_with_usage(&opts, &args[0], &error.to_string()),
     };
 
     let bios = matches.opt_str("b").unwrap_or(String::from("gba_bios.bin"));
     let rom = matches.opt_str("g").unwrap();
-
-    //let display_memory = memory.clone();
-
-    thread::scope(move |scope| {
-        scope.spawn(move || start_debugger(bios, rom));
-        //start_display(display_memory);
+    // kept next to the ROM, as other emulators do
+    let save = Path::new(&rom).with_extension("sav");
+    let save_type = matches.opt_str("s").map(|save_type| {
+        save_type
+            .parse::<BackupType>()
+            .unwrap_or_else(|error| exit_with_usage(&opts, &args[0], &error))
     });
+    let open_on_panic = matches.opt_present("p");
+    if open_on_panic && cfg!(panic = "abort") {
+        exit_with_usage(
+            &opts,
+            &args[0],
+            "--debug-on-panic needs a build that unwinds on panic, such as --release",
+        );
+    }
+    let frame_rate = (!matches.opt_present("u")).then_some(GBA_FRAME_RATE);
 
-    Ok(())
+    let key_input = KeyInput::default();
+    let frames = FrameHandoff::default();
+    // opened before the debugger takes over the terminal, so a failure
+    // is still readable once it exits
+    let display = Display::open()
+        .inspect_err(|error| eprintln!("No display, running without a window: {}", error))
+        .ok();
+    let debugger = {
+        let key_input = key_input.clone();
+        let frames = frames.clone();
+        thread::spawn(move || {
+            start_debugger(bios, rom, save_type, save, open_on_panic, key_input, frames)
+        })
+    };
+    let display_error = display
+        .and_then(|display| display.run(frames, key_input, frame_rate, || !debugger.is_finished()).err());
+    let result = debugger.join().expect("debugger thread panicked");
+    if let Some(error) = display_error {
+        eprintln!("Display closed: {}", error);
+    }
+    result
 }
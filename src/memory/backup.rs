@@ -0,0 +1,136 @@
+use std::{fmt::Display, str::FromStr};
+
+/// Save chip on the cartridge.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BackupType {
+    #[default]
+    None,
+    Sram,
+    Eeprom512,
+    Eeprom8k,
+    Flash64k,
+    Flash128k,
+}
+
+/// ID strings the Nintendo save libraries leave in the ROM. EEPROM_V is
+/// used by both EEPROM sizes, so it detects as the smaller one.
+const SIGNATURES: [(&[u8], BackupType); 6] = [
+    (b"EEPROM_V", BackupType::Eeprom512),
+    (b"SRAM_V", BackupType::Sram),
+    (b"SRAM_F_V", BackupType::Sram),
+    (b"FLASH_V", BackupType::Flash64k),
+    (b"FLASH512_V", BackupType::Flash64k),
+    (b"FLASH1M_V", BackupType::Flash128k),
+];
+
+impl BackupType {
+    pub fn size(&self) -> usize {
+        match self {
+            BackupType::None => 0,
+            BackupType::Sram => 0x8000,
+            BackupType::Eeprom512 => 0x200,
+            BackupType::Eeprom8k => 0x2000,
+            BackupType::Flash64k => 0x10000,
+            BackupType::Flash128k => 0x20000,
+        }
+    }
+
+    /// Guesses the save chip from the library ID strings in `rom`.
+    pub fn detect(rom: &[u8]) -> Self {
+        // the signatures are word aligned
+        for window in (0..rom.len()).step_by(4).map(|offset| &rom[offset..]) {
+            for (signature, backup_type) in SIGNATURES {
+                if window.starts_with(signature) {
+                    return backup_type;
+                }
+            }
+        }
+        BackupType::None
+    }
+}
+
+impl Display for BackupType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackupType::None => "none".fmt(f),
+            BackupType::Sram => "sram".fmt(f),
+            BackupType::Eeprom512 => "eeprom512".fmt(f),
+            BackupType::Eeprom8k => "eeprom8k".fmt(f),
+            BackupType::Flash64k => "flash64k".fmt(f),
+            BackupType::Flash128k => "flash128k".fmt(f),
+        }
+    }
+}
+
+impl FromStr for BackupType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(BackupType::None),
+            "sram" => Ok(BackupType::Sram),
+            "eeprom512" => Ok(BackupType::Eeprom512),
+            "eeprom8k" => Ok(BackupType::Eeprom8k),
+            "flash64k" => Ok(BackupType::Flash64k),
+            "flash128k" => Ok(BackupType::Flash128k),
+            _ => Err(format!("Unknown backup type {}", s)),
+        }
+    }
+}
+
+/// The cartridge's save chip: detected from the ROM, unless the user has
+/// forced a type for a game whose signature misleads detection.
+#[derive(Default, Debug)]
+pub struct BackupConfig {
+    pub forced: Option<BackupType>,
+    detected: BackupType,
+}
+
+impl BackupConfig {
+    pub fn detect(&mut self, rom: &[u8]) {
+        self.detected = BackupType::detect(rom);
+    }
+
+    pub fn backup_type(&self) -> BackupType {
+        self.forced.unwrap_or(self.detected)
+    }
+}
+
+#[cfg(test)]
+mod backup_tests {
+    use rstest::rstest;
+
+    use super::{BackupConfig, BackupType};
+
+    fn rom_with_signature(signature: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0; 0x100];
+        rom.extend_from_slice(signature);
+        rom.resize(0x200, 0);
+        rom
+    }
+
+    #[rstest]
+    #[case(b"SRAM_V113", BackupType::Sram)]
+    #[case(b"EEPROM_V124", BackupType::Eeprom512)]
+    #[case(b"FLASH512_V131", BackupType::Flash64k)]
+    #[case(b"FLASH1M_V103", BackupType::Flash128k)]
+    #[case(b"NOTHING", BackupType::None)]
+    fn detects_backup_from_library_signature(
+        #[case] signature: &[u8],
+        #[case] expected: BackupType,
+    ) {
+        assert_eq!(BackupType::detect(&rom_with_signature(signature)), expected);
+    }
+
+    #[test]
+    fn forced_type_overrides_detection() {
+        let mut config = BackupConfig::default();
+        config.detect(&rom_with_signature(b"SRAM_V113"));
+        assert_eq!(config.backup_type(), BackupType::Sram);
+
+        config.forced = Some(BackupType::Eeprom8k);
+
+        assert_eq!(config.backup_type(), BackupType::Eeprom8k);
+        assert_eq!(config.backup_type().size(), 0x2000);
+    }
+}
@@ -0,0 +1,27 @@
+use std::ops::Range;
+
+/// Address ranges of RAM written since the CPU last looked, so it can
+/// drop the decoded instructions that were overwritten.
+#[derive(Debug, Default)]
+pub struct CodeWrites {
+    ranges: Vec<Range<usize>>,
+}
+
+impl CodeWrites {
+    /// Records a write of `size` bytes, extending the last range when the
+    /// write carries on from it as a copy or fill does.
+    pub fn record(&mut self, address: usize, size: usize) {
+        let address = address & !(size - 1);
+        if let Some(last) = self.ranges.last_mut() {
+            if last.end == address {
+                last.end += size;
+                return;
+            }
+        }
+        self.ranges.push(address..address + size);
+    }
+
+    pub fn drain(&mut self) -> impl Iterator<Item = Range<usize>> + '_ {
+        self.ranges.drain(..)
+    }
+}
@@ -1,8 +1,37 @@
-use super::memory::{DebuggerMemoryBus, MemoryBus, MemoryBusNoPanic, MemoryError, MemoryFetch};
+use std::{cell::RefCell, fmt::Display, path::Path};
+
+use crate::{io::timers::TimerReadout, state::MemoryRegion};
+
+use super::{
+    backup::BackupConfig,
+    code_writes::CodeWrites,
+    io_handlers::{HaltMode, RegisterChanges},
+    io_trace::IOTrace,
+    rom_write_guard::RomWriteGuard,
+    sound_fifo::SoundFifo,
+    vram_contention::VramContention,
+    memory::{BusState, DebuggerMemoryBus, MemoryBus, MemoryBusNoPanic, MemoryError, MemoryFetch},
+};
+
+/// Whether an access reported to the breakpoint checker reads or writes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemoryAccess {
+    Read,
+    Write,
+}
+
+impl Display for MemoryAccess {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MemoryAccess::Read => "read".fmt(f),
+            MemoryAccess::Write => "write".fmt(f),
+        }
+    }
+}
 
 pub struct DebuggerMemory {
     catch_memory_error: Box<dyn Fn(MemoryError) -> ()>,
-    breakpoint_checker: Box<dyn Fn(usize) -> ()>,
+    breakpoint_checker: Box<dyn Fn(usize, MemoryAccess)>,
     pub memory: Box<dyn DebuggerMemoryBus>,
 }
 
@@ -10,7 +39,7 @@ pub struct DebuggerMemory {
 impl DebuggerMemory {
     pub fn new(
         memory: Box<dyn DebuggerMemoryBus>,
-        breakpoint_checker: Box<dyn Fn(usize) -> ()>,
+        breakpoint_checker: Box<dyn Fn(usize, MemoryAccess)>,
         catch_memory_error: Box<dyn Fn(MemoryError) -> ()>,
     ) -> Box<DebuggerMemory> {
         Box::new(Self {
@@ -23,26 +52,26 @@ impl DebuggerMemory {
 
 impl MemoryBusNoPanic for DebuggerMemory {
     fn try_read(
-        &self,
+        &mut self,
         address: usize,
     ) -> Result<super::memory::MemoryFetch<u8>, super::memory::MemoryError> {
-        (self.breakpoint_checker)(address);
+        (self.breakpoint_checker)(address, MemoryAccess::Read);
         self.memory.try_read(address)
     }
 
     fn try_readu16(
-        &self,
+        &mut self,
         address: usize,
     ) -> Result<super::memory::MemoryFetch<u16>, super::memory::MemoryError> {
-        (self.breakpoint_checker)(address);
+        (self.breakpoint_checker)(address, MemoryAccess::Read);
         self.memory.try_readu16(address)
     }
 
     fn try_readu32(
-        &self,
+        &mut self,
         address: usize,
     ) -> Result<super::memory::MemoryFetch<u32>, super::memory::MemoryError> {
-        (self.breakpoint_checker)(address);
+        (self.breakpoint_checker)(address, MemoryAccess::Read);
         self.memory.try_readu32(address)
     }
 
@@ -51,7 +80,7 @@ impl MemoryBusNoPanic for DebuggerMemory {
         address: usize,
         value: u8,
     ) -> Result<crate::types::CYCLES, super::memory::MemoryError> {
-        (self.breakpoint_checker)(address);
+        (self.breakpoint_checker)(address, MemoryAccess::Write);
         self.memory.try_write(address, value)
     }
 
@@ -60,7 +89,7 @@ impl MemoryBusNoPanic for DebuggerMemory {
         address: usize,
         value: u16,
     ) -> Result<crate::types::CYCLES, super::memory::MemoryError> {
-        (self.breakpoint_checker)(address);
+        (self.breakpoint_checker)(address, MemoryAccess::Write);
         self.memory.try_writeu16(address, value)
     }
 
@@ -69,14 +98,14 @@ impl MemoryBusNoPanic for DebuggerMemory {
         address: usize,
         value: u32,
     ) -> Result<crate::types::CYCLES, super::memory::MemoryError> {
-        (self.breakpoint_checker)(address);
+        (self.breakpoint_checker)(address, MemoryAccess::Write);
         self.memory.try_writeu32(address, value)
     }
 }
 
 impl MemoryBus for DebuggerMemory {
-    fn read(&self, address: usize) -> super::memory::MemoryFetch<u8> {
-        (self.breakpoint_checker)(address);
+    fn read(&mut self, address: usize) -> super::memory::MemoryFetch<u8> {
+        (self.breakpoint_checker)(address, MemoryAccess::Read);
         self.memory.try_read(address).unwrap_or_else(|err| {
             (self.catch_memory_error)(err);
             MemoryFetch {
@@ -86,8 +115,8 @@ impl MemoryBus for DebuggerMemory {
         })
     }
 
-    fn readu16(&self, address: usize) -> super::memory::MemoryFetch<u16> {
-        (self.breakpoint_checker)(address);
+    fn readu16(&mut self, address: usize) -> super::memory::MemoryFetch<u16> {
+        (self.breakpoint_checker)(address, MemoryAccess::Read);
         self.memory.try_readu16(address).unwrap_or_else(|err| {
             (self.catch_memory_error)(err);
             MemoryFetch {
@@ -97,8 +126,8 @@ impl MemoryBus for DebuggerMemory {
         })
     }
 
-    fn readu32(&self, address: usize) -> super::memory::MemoryFetch<u32> {
-        (self.breakpoint_checker)(address);
+    fn readu32(&mut self, address: usize) -> super::memory::MemoryFetch<u32> {
+        (self.breakpoint_checker)(address, MemoryAccess::Read);
         self.memory.try_readu32(address).unwrap_or_else(|err| {
             (self.catch_memory_error)(err);
             MemoryFetch {
@@ -110,7 +139,7 @@ impl MemoryBus for DebuggerMemory {
     }
 
     fn write(&mut self, address: usize, value: u8) -> crate::types::CYCLES {
-        (self.breakpoint_checker)(address);
+        (self.breakpoint_checker)(address, MemoryAccess::Write);
         self.memory.try_write(address, value).unwrap_or_else(|err| {
             (self.catch_memory_error)(err);
             0
@@ -119,7 +148,7 @@ impl MemoryBus for DebuggerMemory {
     }
 
     fn writeu16(&mut self, address: usize, value: u16) -> crate::types::CYCLES {
-        (self.breakpoint_checker)(address);
+        (self.breakpoint_checker)(address, MemoryAccess::Write);
         self.memory.try_writeu16(address, value).unwrap_or_else(|err| {
             (self.catch_memory_error)(err);
             0
@@ -128,7 +157,7 @@ impl MemoryBus for DebuggerMemory {
     }
 
     fn writeu32(&mut self, address: usize, value: u32) -> crate::types::CYCLES {
-        (self.breakpoint_checker)(address);
+        (self.breakpoint_checker)(address, MemoryAccess::Write);
         self.memory.try_writeu32(address, value).unwrap_or_else(|err| {
             (self.catch_memory_error)(err);
             0
@@ -136,7 +165,101 @@ impl MemoryBus for DebuggerMemory {
 
     }
     
+    fn peek(&self, address: usize) -> u8 {
+        self.memory.peek(address)
+    }
+
+    fn peeku16(&self, address: usize) -> u16 {
+        self.memory.peeku16(address)
+    }
+
+    fn peeku32(&self, address: usize) -> u32 {
+        self.memory.peeku32(address)
+    }
+
     fn ppu_io_write(&mut self, address: usize, value: u16) {
         self.memory.ppu_io_write(address, value)
     }
+
+    fn ppu_io_read(&self, address: usize) -> u16 {
+        self.memory.ppu_io_read(address)
+    }
+
+    fn io_trace(&self) -> &RefCell<IOTrace> {
+        self.memory.io_trace()
+    }
+
+    fn patch_rom(&mut self, address: usize, value: u16) {
+        self.memory.patch_rom(address, value)
+    }
+
+    fn take_halt_request(&mut self) -> Option<HaltMode> {
+        self.memory.take_halt_request()
+    }
+
+    fn take_register_changes(&mut self) -> RegisterChanges {
+        self.memory.take_register_changes()
+    }
+
+    fn rom_write_guard(&mut self) -> &mut RomWriteGuard {
+        self.memory.rom_write_guard()
+    }
+
+    fn vram_contention(&mut self) -> &mut VramContention {
+        self.memory.vram_contention()
+    }
+
+    fn backup_config(&mut self) -> &mut BackupConfig {
+        self.memory.backup_config()
+    }
+
+    fn load_save(&mut self, path: &Path) -> Result<(), std::io::Error> {
+        self.memory.load_save(path)
+    }
+
+    fn save_to(&mut self, path: &Path) -> Result<(), std::io::Error> {
+        self.memory.save_to(path)
+    }
+
+    fn sound_fifos(&mut self) -> &mut [SoundFifo; 2] {
+        self.memory.sound_fifos()
+    }
+
+    fn timer_readout(&mut self) -> &mut TimerReadout {
+        self.memory.timer_readout()
+    }
+
+    fn code_writes(&mut self) -> &mut CodeWrites {
+        self.memory.code_writes()
+    }
+
+    fn region_snapshot(&self, region: MemoryRegion) -> Vec<u8> {
+        self.memory.region_snapshot(region)
+    }
+
+    fn fetch_u32(&mut self, address: usize) -> MemoryFetch<u32> {
+        (self.breakpoint_checker)(address, MemoryAccess::Read);
+        self.memory.fetch_u32(address)
+    }
+
+    fn fetch_u16(&mut self, address: usize) -> MemoryFetch<u16> {
+        (self.breakpoint_checker)(address, MemoryAccess::Read);
+        self.memory.fetch_u16(address)
+    }
+
+    fn run_prefetcher(&mut self, elapsed: u64) {
+        self.memory.run_prefetcher(elapsed)
+    }
+
+    fn restore_region(&mut self, region: MemoryRegion, bytes: &[u8]) {
+        self.memory.restore_region(region, bytes)
+    }
+
+    fn bus_state(&self) -> BusState {
+        self.memory.bus_state()
+    }
+
+    fn restore_bus_state(&mut self, state: BusState) {
+        self.memory.restore_bus_state(state)
+    }
 }
@@ -0,0 +1,526 @@
+use std::fmt::Display;
+
+use crate::{
+    arm7tdmi::interrupts::{request_interrupt, Interrupt},
+    memory::{
+        io_handlers::{DMA0CNT_H, DMA0CNT_L, DMA0DAD, DMA0SAD, FIFO_A, FIFO_B, IO_BASE},
+        memory::MemoryBus,
+    },
+    state::savestate::{StateError, StateReader, StateWriter},
+};
+
+/// Each channel's registers are 12 bytes after the previous channel's.
+pub(crate) const CHANNEL_STRIDE: usize = 0xC;
+const DMA_ENABLE: u16 = 1 << 15;
+const DMA_IRQ: u16 = 1 << 14;
+const DMA_REPEAT: u16 = 1 << 9;
+const DMA_WORD: u16 = 1 << 10;
+/// Only channel 3 supports video capture; special timing on channels 1
+/// and 2 is for the sound FIFOs.
+const VIDEO_CAPTURE_CHANNEL: usize = 3;
+/// A sound FIFO refill always moves four words.
+const SOUND_FIFO_WORDS: u32 = 4;
+/// Two internal cycles before the first transfer.
+const DMA_STARTUP_CYCLES: u32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DmaTiming {
+    Immediate,
+    VBlank,
+    HBlank,
+    Special,
+}
+
+impl DmaTiming {
+    /// From DMAxCNT_H bits 12-13.
+    pub(crate) fn from_control(control: u16) -> Self {
+        match (control >> 12) & 0x3 {
+            0 => DmaTiming::Immediate,
+            1 => DmaTiming::VBlank,
+            2 => DmaTiming::HBlank,
+            _ => DmaTiming::Special,
+        }
+    }
+}
+
+impl Display for DmaTiming {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DmaTiming::Immediate => "immediate".fmt(f),
+            DmaTiming::VBlank => "vblank".fmt(f),
+            DmaTiming::HBlank => "hblank".fmt(f),
+            DmaTiming::Special => "special".fmt(f),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum AddressControl {
+    Increment,
+    Decrement,
+    Fixed,
+    IncrementReload,
+}
+
+impl Display for AddressControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AddressControl::Increment => "increment".fmt(f),
+            AddressControl::Decrement => "decrement".fmt(f),
+            AddressControl::Fixed => "fixed".fmt(f),
+            AddressControl::IncrementReload => "reload".fmt(f),
+        }
+    }
+}
+
+impl AddressControl {
+    pub(crate) fn from_bits(bits: u16) -> Self {
+        match bits & 0x3 {
+            0 => AddressControl::Increment,
+            1 => AddressControl::Decrement,
+            2 => AddressControl::Fixed,
+            _ => AddressControl::IncrementReload,
+        }
+    }
+
+    fn step(&self, address: u32, unit_size: u32) -> u32 {
+        match self {
+            AddressControl::Increment | AddressControl::IncrementReload => {
+                address.wrapping_add(unit_size)
+            }
+            AddressControl::Decrement => address.wrapping_sub(unit_size),
+            AddressControl::Fixed => address,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct DmaChannel {
+    index: usize,
+    /// Set once the enable bit has been seen and the addresses latched.
+    active: bool,
+    source: u32,
+    destination: u32,
+    count: u32,
+}
+
+impl DmaChannel {
+    fn register(&self, offset: usize) -> usize {
+        offset + self.index * CHANNEL_STRIDE
+    }
+
+    fn control(&self, memory: &dyn MemoryBus) -> u16 {
+        memory.ppu_io_read(self.register(DMA0CNT_H))
+    }
+
+    fn timing(&self, memory: &dyn MemoryBus) -> DmaTiming {
+        DmaTiming::from_control(self.control(memory))
+    }
+
+    fn read_address(&self, offset: usize, memory: &dyn MemoryBus) -> u32 {
+        let register = self.register(offset);
+        (memory.ppu_io_read(register + 2) as u32) << 16 | memory.ppu_io_read(register) as u32
+    }
+
+    fn latch_count(&mut self, memory: &dyn MemoryBus) {
+        let count = memory.ppu_io_read(self.register(DMA0CNT_L)) as u32;
+        let (count_mask, max_count) = if self.index == 3 {
+            (0xFFFF, 0x10000)
+        } else {
+            (0x3FFF, 0x4000)
+        };
+        self.count = match count & count_mask {
+            0 => max_count,
+            count => count,
+        };
+    }
+
+    fn is_video_capture(&self, memory: &dyn MemoryBus) -> bool {
+        self.index == VIDEO_CAPTURE_CHANNEL
+            && self.active
+            && self.timing(memory) == DmaTiming::Special
+    }
+
+    fn is_sound_fifo(&self, memory: &dyn MemoryBus) -> bool {
+        (self.index == 1 || self.index == 2) && self.timing(memory) == DmaTiming::Special
+    }
+
+    fn unit_size(&self, memory: &dyn MemoryBus) -> u32 {
+        if self.control(memory) & DMA_WORD > 0 {
+            4
+        } else {
+            2
+        }
+    }
+
+    fn alignment_mask(&self, memory: &dyn MemoryBus) -> u32 {
+        // the low bits of both addresses are ignored, aligning them to the unit size
+        !(self.unit_size(memory) - 1)
+    }
+
+    fn latch_destination(&mut self, memory: &dyn MemoryBus) {
+        let destination_mask = if self.index == 3 { 0x0FFF_FFFF } else { 0x07FF_FFFF };
+        self.destination =
+            self.read_address(DMA0DAD, memory) & destination_mask & self.alignment_mask(memory);
+    }
+
+    fn latch(&mut self, memory: &dyn MemoryBus) {
+        let source_mask = if self.index == 0 { 0x07FF_FFFF } else { 0x0FFF_FFFF };
+        self.source = self.read_address(DMA0SAD, memory) & source_mask & self.alignment_mask(memory);
+        self.latch_destination(memory);
+        self.latch_count(memory);
+        self.active = true;
+    }
+
+    /// Copies `count` units and returns the cycles the bus was held for.
+    /// Sound FIFO transfers ignore the count, size and destination control.
+    fn transfer(&mut self, memory: &mut Box<dyn MemoryBus>) -> u32 {
+        let control = self.control(memory.as_ref());
+        let sound_fifo = self.is_sound_fifo(memory.as_ref());
+        let destination_control = match sound_fifo {
+            true => AddressControl::Fixed,
+            false => AddressControl::from_bits(control >> 5),
+        };
+        let source_control = AddressControl::from_bits(control >> 7);
+        let (unit_size, count) = match sound_fifo {
+            true => (4, SOUND_FIFO_WORDS),
+            false => (self.unit_size(memory.as_ref()), self.count),
+        };
+        let mut cycles = DMA_STARTUP_CYCLES;
+
+        for _ in 0..count {
+            if unit_size == 4 {
+                let fetch = memory.readu32(self.source as usize);
+                cycles += fetch.cycles as u32;
+                cycles += memory.writeu32(self.destination as usize, fetch.data) as u32;
+            } else {
+                let fetch = memory.readu16(self.source as usize);
+                cycles += fetch.cycles as u32;
+                cycles += memory.writeu16(self.destination as usize, fetch.data) as u32;
+            }
+            self.source = source_control.step(self.source, unit_size);
+            self.destination = destination_control.step(self.destination, unit_size);
+        }
+
+        if control & DMA_IRQ > 0 {
+            request_interrupt(memory.as_mut(), Interrupt::dma(self.index));
+        }
+
+        let timing = self.timing(memory.as_ref());
+        if control & DMA_REPEAT > 0 && timing != DmaTiming::Immediate {
+            // each repeat starts over at the original destination, while
+            // the source carries on from where it stopped
+            if destination_control == AddressControl::IncrementReload {
+                self.latch_destination(memory.as_ref());
+            }
+            self.latch_count(memory.as_ref());
+        } else {
+            self.active = false;
+            memory.ppu_io_write(self.register(DMA0CNT_H), control & !DMA_ENABLE);
+        }
+
+        cycles
+    }
+}
+
+/// The four DMA channels. Channels are serviced in order, so a lower
+/// numbered channel always finishes before a higher numbered one starts.
+#[derive(Debug)]
+pub struct DmaController {
+    channels: [DmaChannel; 4],
+}
+
+impl Default for DmaController {
+    fn default() -> Self {
+        let mut channels = [DmaChannel::default(); 4];
+        for (index, channel) in channels.iter_mut().enumerate() {
+            channel.index = index;
+        }
+        Self { channels }
+    }
+}
+
+impl DmaController {
+    /// Latches channels whose enable bit was just set and runs the ones
+    /// with immediate timing. Returns the cycles the CPU was stalled for.
+    pub fn step(&mut self, memory: &mut Box<dyn MemoryBus>) -> u32 {
+        let mut cycles = 0;
+        for channel in self.channels.iter_mut() {
+            let enabled = channel.control(memory.as_ref()) & DMA_ENABLE > 0;
+            if !enabled {
+                channel.active = false;
+                continue;
+            }
+            if channel.active {
+                continue;
+            }
+            channel.latch(memory.as_ref());
+            if channel.timing(memory.as_ref()) == DmaTiming::Immediate {
+                cycles += channel.transfer(memory);
+            }
+        }
+        cycles
+    }
+
+    /// Writes the addresses and count each channel latched, which carry on
+    /// from one repeat to the next.
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        for channel in &self.channels {
+            writer.flag(channel.active);
+            writer.word(channel.source);
+            writer.word(channel.destination);
+            writer.word(channel.count);
+        }
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        for channel in self.channels.iter_mut() {
+            channel.active = reader.flag()?;
+            channel.source = reader.word()?;
+            channel.destination = reader.word()?;
+            channel.count = reader.word()?;
+        }
+        Ok(())
+    }
+
+    /// Runs every active channel waiting on `timing`.
+    pub fn trigger(&mut self, timing: DmaTiming, memory: &mut Box<dyn MemoryBus>) -> u32 {
+        let mut cycles = 0;
+        for channel in self.channels.iter_mut() {
+            if channel.active && channel.timing(memory.as_ref()) == timing {
+                cycles += channel.transfer(memory);
+            }
+        }
+        cycles
+    }
+
+    /// Refills FIFO_A (`fifo` 0) or FIFO_B from the sound channel pointed
+    /// at it, if there is one.
+    pub fn trigger_sound_fifo(&mut self, fifo: usize, memory: &mut Box<dyn MemoryBus>) -> u32 {
+        let address = (IO_BASE + [FIFO_A, FIFO_B][fifo]) as u32;
+        let mut cycles = 0;
+        for channel in self.channels[1..=2].iter_mut() {
+            if channel.active
+                && channel.is_sound_fifo(memory.as_ref())
+                && channel.destination == address
+            {
+                cycles += channel.transfer(memory);
+            }
+        }
+        cycles
+    }
+
+    /// Runs channel 3's video capture transfer for the current scanline.
+    pub fn trigger_video_capture(&mut self, memory: &mut Box<dyn MemoryBus>) -> u32 {
+        let channel = &mut self.channels[VIDEO_CAPTURE_CHANNEL];
+        if channel.is_video_capture(memory.as_ref()) {
+            channel.transfer(memory)
+        } else {
+            0
+        }
+    }
+
+    /// Video capture stops by itself after the last captured line, even
+    /// with the repeat bit set.
+    pub fn stop_video_capture(&mut self, memory: &mut Box<dyn MemoryBus>) {
+        let channel = &mut self.channels[VIDEO_CAPTURE_CHANNEL];
+        if channel.is_video_capture(memory.as_ref()) {
+            channel.active = false;
+            let control = channel.control(memory.as_ref());
+            memory.ppu_io_write(channel.register(DMA0CNT_H), control & !DMA_ENABLE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod dma_tests {
+    use crate::{
+        arm7tdmi::interrupts::Interrupt,
+        gba::GBA,
+        memory::io_handlers::{
+            DMA1CNT_H, DMA1DAD, DMA1SAD, DMA3CNT_H, DMA3CNT_L, DMA3DAD, DMA3SAD, FIFO_A, IF, IO_BASE,
+            SOUNDCNT_H, SOUNDCNT_X, TM0CNT_H, TM0CNT_L,
+        },
+        utils::testing::load_arm_program,
+    };
+
+    fn start_dma3(gba: &mut GBA, source: u32, destination: u32, count: u16, control: u16) {
+        gba.memory.writeu32(IO_BASE + DMA3SAD, source);
+        gba.memory.writeu32(IO_BASE + DMA3DAD, destination);
+        gba.memory.writeu16(IO_BASE + DMA3CNT_L, count);
+        gba.memory.writeu16(IO_BASE + DMA3CNT_H, control);
+    }
+
+    #[test]
+    fn immediate_dma_copies_words_and_clears_enable() {
+        let mut gba = GBA::new_no_bios();
+        for i in 0..4 {
+            gba.memory.writeu32(0x3000100 + i * 4, 0xA0 + i as u32);
+        }
+
+        start_dma3(&mut gba, 0x3000100, 0x3000200, 4, 0x8400 | 0x4000);
+        gba.step();
+
+        for i in 0..4 {
+            assert_eq!(gba.memory.readu32(0x3000200 + i * 4).data, 0xA0 + i as u32);
+        }
+        assert_eq!(gba.memory.ppu_io_read(DMA3CNT_H) & 0x8000, 0);
+        assert_eq!(gba.memory.ppu_io_read(IF) & Interrupt::Dma3.bit(), Interrupt::Dma3.bit());
+    }
+
+    #[test]
+    fn word_dma_aligns_misaligned_addresses() {
+        let mut gba = GBA::new_no_bios();
+        gba.memory.writeu32(0x3000100, 0x1122_3344);
+        gba.memory.writeu32(0x3000104, 0x5566_7788);
+
+        start_dma3(&mut gba, 0x3000102, 0x3000203, 2, 0x8400);
+        gba.step();
+
+        assert_eq!(gba.memory.readu32(0x3000200).data, 0x1122_3344);
+        assert_eq!(gba.memory.readu32(0x3000204).data, 0x5566_7788);
+    }
+
+    #[test]
+    fn halfword_dma_with_fixed_source_fills_destination() {
+        let mut gba = GBA::new_no_bios();
+        gba.memory.writeu16(0x3000100, 0x1234);
+
+        start_dma3(&mut gba, 0x3000100, 0x3000200, 3, 0x8000 | 2 << 7);
+        gba.step();
+
+        assert_eq!(gba.memory.readu16(0x3000200).data, 0x1234);
+        assert_eq!(gba.memory.readu16(0x3000204).data, 0x1234);
+        assert_eq!(gba.memory.readu16(0x3000206).data, 0);
+    }
+
+    #[test]
+    fn vblank_dma_waits_for_vblank() {
+        let mut gba = GBA::new_no_bios();
+        gba.memory.writeu32(0x3000100, 0xCAFE);
+
+        start_dma3(&mut gba, 0x3000100, 0x3000200, 1, 0x8400 | 1 << 12);
+        gba.step();
+        assert_eq!(gba.memory.readu32(0x3000200).data, 0);
+
+        let frame = gba.ppu.frame_count;
+        while gba.ppu.frame_count == frame {
+            gba.step();
+        }
+        assert_eq!(gba.memory.readu32(0x3000200).data, 0xCAFE);
+    }
+
+    #[test]
+    fn repeating_dma_reloads_the_destination_each_repeat() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, 0x3000000, &[0xeafffffe]); // b .
+        for i in 0..8 {
+            gba.memory.writeu32(0x3000100 + i * 4, 0xA0 + i as u32);
+        }
+
+        // words, increment/reload destination, repeat, HBlank timing
+        start_dma3(&mut gba, 0x3000100, 0x3000200, 2, 0x8400 | 3 << 5 | 1 << 9 | 2 << 12);
+        while gba.ppu.y != 1 {
+            gba.step();
+        }
+        assert_eq!(gba.memory.readu32(0x3000200).data, 0xA0);
+        assert_eq!(gba.memory.readu32(0x3000204).data, 0xA1);
+
+        while gba.ppu.y != 2 {
+            gba.step();
+        }
+        assert_eq!(gba.memory.readu32(0x3000200).data, 0xA2);
+        assert_eq!(gba.memory.readu32(0x3000204).data, 0xA3);
+        assert_eq!(gba.memory.readu32(0x3000208).data, 0);
+    }
+
+    #[test]
+    fn video_capture_dma_transfers_once_per_visible_scanline() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, 0x3000000, &[0xeafffffe]); // b .
+        gba.memory.writeu16(0x3000100, 0xABCD);
+
+        // fixed source, repeat, special timing
+        start_dma3(&mut gba, 0x3000100, 0x2000000, 1, 0x8000 | 2 << 7 | 1 << 9 | 3 << 12);
+        while gba.ppu.y != 170 {
+            gba.step();
+        }
+
+        let transfers = (0..200)
+            .take_while(|i| gba.memory.readu16(0x2000000 + i * 2).data == 0xABCD)
+            .count();
+        assert_eq!(transfers, 160);
+        assert_eq!(gba.memory.ppu_io_read(DMA3CNT_H) & 0x8000, 0);
+    }
+
+    #[test]
+    fn cpu_writes_and_dma_to_overlapping_memory_apply_in_program_order() {
+        let mut gba = GBA::new_no_bios();
+        let source = 0x3000100;
+        let destination = 0x3000200;
+        for i in 0..4 {
+            gba.memory.writeu32(source + i * 4, 0xA0 + i as u32);
+        }
+        gba.cpu.set_register(0, (IO_BASE + DMA3SAD) as u32);
+        gba.cpu.set_register(1, source as u32);
+        gba.cpu.set_register(2, destination as u32);
+        gba.cpu.set_register(3, 0x8400_0004); // 4 words, immediate, enabled
+        gba.cpu.set_register(4, 0x1111_1111);
+        gba.cpu.set_register(5, 0x2222_2222);
+        load_arm_program(
+            &mut gba,
+            0x3000000,
+            &[
+                0xE5814000, // str r4, [r1]      CPU writes the source first
+                0xE5801000, // str r1, [r0]      DMA3SAD
+                0xE5802004, // str r2, [r0, #4]  DMA3DAD
+                0xE5803008, // str r3, [r0, #8]  DMA3CNT, starts the transfer
+                0xE5825004, // str r5, [r2, #4]  CPU overwrites the copied data
+            ],
+        );
+
+        for _ in 0..5 {
+            gba.step();
+        }
+
+        // the DMA sees the CPU's earlier write and runs to completion
+        // before the next instruction, which then overwrites its result
+        let copied: Vec<u32> = (0..4)
+            .map(|i| gba.memory.readu32(destination + i * 4).data)
+            .collect();
+        assert_eq!(copied, vec![0x1111_1111, 0x2222_2222, 0xA2, 0xA3]);
+    }
+
+    #[test]
+    fn sound_dma_refills_fifo_a_as_timer_0_drains_it() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, 0x3000000, &[0xeafffffe]); // b .
+        for i in 0..64 {
+            gba.memory.write(0x2000000 + i, i as u8 + 1);
+        }
+        gba.memory.writeu16(IO_BASE + SOUNDCNT_X, 0x80);
+        gba.memory.writeu16(IO_BASE + SOUNDCNT_H, 0x0304);
+        // repeating 32-bit special timing DMA with an incrementing source;
+        // the count is ignored
+        gba.memory.writeu32(IO_BASE + DMA1SAD, 0x2000000);
+        gba.memory.writeu32(IO_BASE + DMA1DAD, (IO_BASE + FIFO_A) as u32);
+        gba.memory.writeu16(IO_BASE + DMA1CNT_H, 0x8000 | 0x3000 | 0x0400 | 0x0200);
+        gba.memory.writeu16(IO_BASE + TM0CNT_L, 0xFF00);
+        gba.memory.writeu16(IO_BASE + TM0CNT_H, 0x80);
+
+        let mut played = vec![];
+        // FIFO_A starts empty, the first overflow plays nothing and
+        // requests the first refill
+        while played.len() < 41 {
+            gba.step();
+            let sample = gba.sound.fifo_samples[0];
+            if played.last() != Some(&sample) {
+                played.push(sample);
+            }
+        }
+
+        assert_eq!(played, (0..=40).collect::<Vec<i8>>());
+        // the DMA keeps going without re-enabling
+        assert_eq!(gba.memory.ppu_io_read(DMA1CNT_H) & 0x8000, 0x8000);
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+
+use crate::state::savestate::{StateError, StateReader, StateWriter};
+
+const LARGE_EEPROM_SIZE: usize = 0x2000;
+const SMALL_EEPROM_SIZE: usize = 0x200;
+/// 512 byte EEPROMs take 6 address bits, 8KB ones 14 of which only the
+/// low 10 are used.
+const SMALL_ADDRESS_BITS: usize = 6;
+const LARGE_ADDRESS_BITS: usize = 14;
+const BLOCK_BITS: usize = 64;
+/// Bits a read sends before the block: 4 dummy bits.
+const READ_DUMMY_BITS: usize = 4;
+
+/// Serial EEPROM save chip, accessed one bit per halfword over the
+/// 0x0D000000 region, normally by DMA3.
+///
+/// A command is `11`, an address and a stop bit to request a read, or
+/// `10`, an address, 64 data bits and a stop bit to write a block. The
+/// command is only known to be complete once the game starts reading, so
+/// it is buffered until then. Until the size is known it is inferred from
+/// the length of the first command.
+#[derive(Clone, Debug)]
+pub struct Eeprom {
+    data: Vec<u8>,
+    /// 512 or 8KB, once known.
+    size: Option<usize>,
+    command: Vec<u8>,
+    output: VecDeque<u8>,
+    dirty: bool,
+}
+
+impl Default for Eeprom {
+    fn default() -> Self {
+        Self {
+            data: vec![0xFF; LARGE_EEPROM_SIZE],
+            size: None,
+            command: Vec::new(),
+            output: VecDeque::new(),
+            dirty: false,
+        }
+    }
+}
+
+impl Eeprom {
+    pub fn size(&self) -> Option<usize> {
+        self.size
+    }
+
+    /// Fixes the size instead of inferring it from the first command.
+    pub fn set_size(&mut self, size: usize) {
+        self.size = Some(size);
+    }
+
+    /// Receives bit 0 of a halfword written to the EEPROM.
+    pub fn write_bit(&mut self, value: u16) {
+        if !self.output.is_empty() {
+            // a new command ends whatever was being read out
+            self.output.clear();
+        }
+        self.command.push((value & 1) as u8);
+    }
+
+    /// The next bit of a read, or 1 (ready) when there is nothing to send.
+    pub fn read_bit(&mut self) -> u16 {
+        if !self.command.is_empty() {
+            self.run_command();
+        }
+        self.output.pop_front().unwrap_or(1) as u16
+    }
+
+    fn run_command(&mut self) {
+        let command = std::mem::take(&mut self.command);
+        match command.as_slice() {
+            [1, 1, address @ .., _stop] => {
+                let Some(offset) = self.block_offset(address) else {
+                    return;
+                };
+                self.output.extend([0; READ_DUMMY_BITS]);
+                for byte in &self.data[offset..offset + 8] {
+                    self.output.extend((0..8).rev().map(|bit| byte >> bit & 1));
+                }
+            }
+            [1, 0, rest @ ..] if rest.len() > BLOCK_BITS => {
+                let (address, block) = rest[..rest.len() - 1].split_at(rest.len() - 1 - BLOCK_BITS);
+                let Some(offset) = self.block_offset(address) else {
+                    return;
+                };
+                for (byte, bits) in self.data[offset..offset + 8].iter_mut().zip(block.chunks(8)) {
+                    *byte = bits.iter().fold(0, |byte, bit| byte << 1 | bit);
+                }
+                self.dirty = true;
+            }
+            _ => {}
+        }
+    }
+
+    /// Byte offset of the block an address selects, sizing the EEPROM
+    /// from the address width if it isn't known yet.
+    fn block_offset(&mut self, address: &[u8]) -> Option<usize> {
+        let size = match (self.size, address.len()) {
+            (Some(size), _) => size,
+            (None, SMALL_ADDRESS_BITS) => SMALL_EEPROM_SIZE,
+            (None, LARGE_ADDRESS_BITS) => LARGE_EEPROM_SIZE,
+            _ => return None,
+        };
+        self.size = Some(size);
+        let block = address.iter().fold(0, |block, bit| block << 1 | *bit as usize);
+        Some(block * 8 % size)
+    }
+
+    /// The save bytes, sized to the EEPROM if known.
+    pub fn bytes(&self) -> &[u8] {
+        &self.data[..self.size.unwrap_or(LARGE_EEPROM_SIZE)]
+    }
+
+    /// Loads a save, which also tells the size of the EEPROM.
+    pub fn load(&mut self, save: &[u8]) {
+        self.data.fill(0xFF);
+        let length = save.len().min(LARGE_EEPROM_SIZE);
+        self.data[..length].copy_from_slice(&save[..length]);
+        self.size = Some(if length <= SMALL_EEPROM_SIZE {
+            SMALL_EEPROM_SIZE
+        } else {
+            LARGE_EEPROM_SIZE
+        });
+        self.dirty = false;
+    }
+
+    /// Whether a block was written since the last `load` or `take_dirty`.
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    /// Writes the contents and any command in progress.
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.bytes(&self.data);
+        writer.optional_word(self.size.map(|size| size as u32));
+        writer.bytes(&self.command);
+        let output: Vec<u8> = self.output.iter().copied().collect();
+        writer.bytes(&output);
+    }
+
+    /// Loading counts as a change, as the contents needn't match the save
+    /// file any more.
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.bytes_into(&mut self.data)?;
+        let offset = reader.offset();
+        self.size = match reader.optional_word()? {
+            None => None,
+            Some(size) if matches!(size as usize, SMALL_EEPROM_SIZE | LARGE_EEPROM_SIZE) => {
+                Some(size as usize)
+            }
+            Some(_) => return Err(StateError::InvalidValue { offset }),
+        };
+        self.command = reader.bytes()?.to_vec();
+        self.output = reader.bytes()?.iter().copied().collect();
+        self.dirty = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod eeprom_tests {
+    use rstest::rstest;
+
+    use super::Eeprom;
+
+    fn send(eeprom: &mut Eeprom, bits: &[u8]) {
+        for bit in bits {
+            eeprom.write_bit(*bit as u16);
+        }
+    }
+
+    fn address_bits(block: usize, width: usize) -> Vec<u8> {
+        (0..width).rev().map(|bit| (block >> bit & 1) as u8).collect()
+    }
+
+    fn data_bits(data: u64) -> Vec<u8> {
+        (0..64).rev().map(|bit| (data >> bit & 1) as u8).collect()
+    }
+
+    #[rstest]
+    #[case::small(6, 0x200)]
+    #[case::large(14, 0x2000)]
+    fn written_block_reads_back_after_dummy_bits(#[case] width: usize, #[case] size: usize) {
+        let mut eeprom = Eeprom::default();
+        let block = 5;
+        let data = 0x0123_4567_89AB_CDEF;
+
+        send(&mut eeprom, &[1, 0]);
+        send(&mut eeprom, &address_bits(block, width));
+        send(&mut eeprom, &data_bits(data));
+        send(&mut eeprom, &[0]);
+        assert_eq!(eeprom.read_bit(), 1); // ready
+
+        send(&mut eeprom, &[1, 1]);
+        send(&mut eeprom, &address_bits(block, width));
+        send(&mut eeprom, &[0]);
+        let stream: Vec<u8> = (0..68).map(|_| eeprom.read_bit() as u8).collect();
+
+        assert_eq!(stream[..4], [0, 0, 0, 0]);
+        assert_eq!(stream[4..], data_bits(data));
+        assert_eq!(eeprom.size(), Some(size));
+        assert_eq!(eeprom.bytes()[block * 8..block * 8 + 8], data.to_be_bytes());
+        assert!(eeprom.take_dirty());
+    }
+
+    #[test]
+    fn unwritten_eeprom_reads_as_erased() {
+        let mut eeprom = Eeprom::default();
+
+        send(&mut eeprom, &[1, 1]);
+        send(&mut eeprom, &address_bits(0, 6));
+        send(&mut eeprom, &[0]);
+        let stream: Vec<u8> = (0..68).map(|_| eeprom.read_bit() as u8).collect();
+
+        assert_eq!(stream[4..], [1; 64]);
+    }
+}
@@ -1,29 +1,32 @@
-use super::memory::{GBAMemory, MemoryError};
+use super::{
+    io_trace::IOAccessKind,
+    memory::{GBAMemory, MemoryError},
+};
 
 pub const IO_BASE: usize = 0x4000000;
-const DISPCNT: usize = 0x000;
+pub const DISPCNT: usize = 0x000;
 pub const DISPSTAT: usize = 0x004;
 pub const VCOUNT: usize = 0x006;
-const BG0CNT: usize = 0x008;
-const BG1CNT: usize = 0x00A;
-const BG2CNT: usize = 0x00C;
-const BG3CNT: usize = 0x00E;
-const BG0HOFS: usize = 0x010;
-const BG0VOFS: usize = 0x012;
-const BG1HOFS: usize = 0x014;
-const BG1VOFS: usize = 0x016;
-const BG2HOFS: usize = 0x018;
-const BG2VOFS: usize = 0x01A;
-const BG3HOFS: usize = 0x01C;
-const BG3VOFS: usize = 0x01E;
-const DX: usize = 0x020;
-const DMX: usize = 0x022;
-const DY: usize = 0x024;
-const DMY: usize = 0x026;
-const BG2X_L: usize = 0x028;
-const BG2X_H: usize = 0x02A;
-const BG2Y_L: usize = 0x02C;
-const BG2Y_H: usize = 0x02E;
+pub const BG0CNT: usize = 0x008;
+pub const BG1CNT: usize = 0x00A;
+pub const BG2CNT: usize = 0x00C;
+pub const BG3CNT: usize = 0x00E;
+pub const BG0HOFS: usize = 0x010;
+pub const BG0VOFS: usize = 0x012;
+pub const BG1HOFS: usize = 0x014;
+pub const BG1VOFS: usize = 0x016;
+pub const BG2HOFS: usize = 0x018;
+pub const BG2VOFS: usize = 0x01A;
+pub const BG3HOFS: usize = 0x01C;
+pub const BG3VOFS: usize = 0x01E;
+pub const DX: usize = 0x020;
+pub const DMX: usize = 0x022;
+pub const DY: usize = 0x024;
+pub const DMY: usize = 0x026;
+pub const BG2X_L: usize = 0x028;
+pub const BG2X_H: usize = 0x02A;
+pub const BG2Y_L: usize = 0x02C;
+pub const BG2Y_H: usize = 0x02E;
 const BG3_DX: usize = 0x030;
 const BG3_DMX: usize = 0x032;
 const BG3_DY: usize = 0x034;
@@ -32,52 +35,86 @@ const BG3X_L: usize = 0x038;
 const BG3X_H: usize = 0x03A;
 const BG3Y_L: usize = 0x03C;
 const BG3Y_H: usize = 0x03E;
-const WIN0H: usize = 0x040;
+pub const WIN0H: usize = 0x040;
 const WIN1H: usize = 0x042;
-const WIN0V: usize = 0x044;
+pub const WIN0V: usize = 0x044;
 const WIN1V: usize = 0x046;
-const WININ: usize = 0x048;
-const WINOUT: usize = 0x04A;
-const MOSAIC: usize = 0x04C;
-const BLDCNT: usize = 0x050;
-const BLDALPHA: usize = 0x052;
-const BLDY: usize = 0x054;
-
-const DMA0SAD: usize = 0x0B0;
-const DMA0DAD: usize = 0x0B4;
-const DMA0CNT_L: usize = 0x0B8;
-const DMA0CNT_H: usize = 0x0BA;
-const DMA1SAD: usize = 0x0BC;
-const DMA1DAD: usize = 0x0C0;
-const DMA1CNT_L: usize = 0x0C4;
-const DMA1CNT_H: usize = 0x0C6;
+pub const WININ: usize = 0x048;
+pub const WINOUT: usize = 0x04A;
+pub const MOSAIC: usize = 0x04C;
+pub const BLDCNT: usize = 0x050;
+pub const BLDALPHA: usize = 0x052;
+pub const BLDY: usize = 0x054;
+
+pub const DMA0SAD: usize = 0x0B0;
+pub const DMA0DAD: usize = 0x0B4;
+pub const DMA0CNT_L: usize = 0x0B8;
+pub const DMA0CNT_H: usize = 0x0BA;
+pub const DMA1SAD: usize = 0x0BC;
+pub const DMA1DAD: usize = 0x0C0;
+pub const DMA1CNT_L: usize = 0x0C4;
+pub const DMA1CNT_H: usize = 0x0C6;
 const DMA2SAD: usize = 0x0C8;
 const DMA2DAD: usize = 0x0CC;
 const DMA2CNT_L: usize = 0x0D0;
 const DMA2CNT_H: usize = 0x0D2;
-const DMA3SAD: usize = 0x0D4;
-const DMA3DAD: usize = 0x0D8;
-const DMA3CNT_L: usize = 0x0DC;
-const DMA3CNT_H: usize = 0x0DE;
-const TM0CNT_L: usize = 0x100;
-const TM0CNT_H: usize = 0x102;
+pub const DMA3SAD: usize = 0x0D4;
+pub const DMA3DAD: usize = 0x0D8;
+pub const DMA3CNT_L: usize = 0x0DC;
+pub const DMA3CNT_H: usize = 0x0DE;
+pub const TM0CNT_L: usize = 0x100;
+pub const TM0CNT_H: usize = 0x102;
 const TM1CNT_L: usize = 0x104;
 const TM1CNT_H: usize = 0x106;
 const TM2CNT_L: usize = 0x108;
 const TM2CNT_H: usize = 0x10A;
 const TM3CNT_L: usize = 0x10C;
-const TM3CNT_H: usize = 0x10E;
+pub const TM3CNT_H: usize = 0x10E;
 pub const KEYINPUT: usize = 0x130;
-const KEYCNT: usize = 0x132;
-
-const SOUNDBIAS: usize = 0x088;
+pub const KEYCNT: usize = 0x132;
+
+pub const SOUND1CNT_L: usize = 0x060;
+pub const SOUND1CNT_H: usize = 0x062;
+pub const SOUND1CNT_X: usize = 0x064;
+pub const SOUND2CNT_L: usize = 0x068;
+pub const SOUND2CNT_H: usize = 0x06C;
+pub const SOUND3CNT_L: usize = 0x070;
+pub const SOUND3CNT_H: usize = 0x072;
+pub const SOUND3CNT_X: usize = 0x074;
+pub const SOUND4CNT_L: usize = 0x078;
+pub const SOUND4CNT_H: usize = 0x07C;
+pub const SOUNDCNT_L: usize = 0x080;
+pub const SOUNDCNT_H: usize = 0x082;
+pub const SOUNDCNT_X: usize = 0x084;
+pub const SOUNDBIAS: usize = 0x088;
+pub const WAVE_RAM: usize = 0x090;
+pub const FIFO_A: usize = 0x0A0;
+pub const FIFO_B: usize = 0x0A4;
 
 pub const IME: usize = 0x208;
 pub const IE: usize = 0x200;
 pub const IF: usize = 0x202;
-const WAITCNT: usize = 0x204;
+pub const WAITCNT: usize = 0x204;
 const POSTFLG: usize = 0x300;
-const HALTCNT: usize = 0x301;
+pub const HALTCNT: usize = 0x301;
+
+const HALTCNT_STOP: u8 = 1 << 7;
+
+/// Low power mode requested by a write to HALTCNT.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HaltMode {
+    /// Wakes on any enabled interrupt.
+    Halt,
+    /// Wakes only on a keypad, serial or cartridge interrupt.
+    Stop,
+}
+
+/// Registers written since the last step that move scheduled events.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RegisterChanges {
+    pub timers: bool,
+    pub dma: bool,
+}
 
 #[derive(Copy, Clone)]
 struct IORegisterDefinition {
@@ -259,7 +296,7 @@ const IO_REGISTER_DEFINITIONS: [Option<IORegisterDefinition>; 0x412] = {
         false,
     ));
     definitions[MOSAIC] = Some(IORegisterDefinition::new(
-        BitMask::SIXTEEN(0xFFFF, 0xFFFF),
+        BitMask::SIXTEEN(0x0000, 0xFFFF),
         false,
     ));
     definitions[BLDCNT] = Some(IORegisterDefinition::new(
@@ -271,7 +308,79 @@ const IO_REGISTER_DEFINITIONS: [Option<IORegisterDefinition>; 0x412] = {
         false,
     ));
     definitions[BLDY] = Some(IORegisterDefinition::new(
-        BitMask::SIXTEEN(0x001F, 0x001F),
+        BitMask::SIXTEEN(0x0000, 0x001F),
+        false,
+    ));
+    // sound lengths, frequencies and the FIFOs are write-only, the
+    // envelope, duty and sweep settings read back
+    definitions[SOUND1CNT_L] = Some(IORegisterDefinition::new(
+        BitMask::SIXTEEN(0x007F, 0x007F),
+        false,
+    ));
+    definitions[SOUND1CNT_H] = Some(IORegisterDefinition::new(
+        BitMask::SIXTEEN(0xFFC0, 0xFFFF),
+        false,
+    ));
+    definitions[SOUND1CNT_X] = Some(IORegisterDefinition::new(
+        BitMask::SIXTEEN(0x4000, 0xC7FF),
+        false,
+    ));
+    definitions[SOUND2CNT_L] = Some(IORegisterDefinition::new(
+        BitMask::SIXTEEN(0xFFC0, 0xFFFF),
+        false,
+    ));
+    definitions[SOUND2CNT_H] = Some(IORegisterDefinition::new(
+        BitMask::SIXTEEN(0x4000, 0xC7FF),
+        false,
+    ));
+    definitions[SOUND3CNT_L] = Some(IORegisterDefinition::new(
+        BitMask::SIXTEEN(0x00E0, 0x00E0),
+        false,
+    ));
+    definitions[SOUND3CNT_H] = Some(IORegisterDefinition::new(
+        BitMask::SIXTEEN(0xE000, 0xE0FF),
+        false,
+    ));
+    definitions[SOUND3CNT_X] = Some(IORegisterDefinition::new(
+        BitMask::SIXTEEN(0x4000, 0xC7FF),
+        false,
+    ));
+    definitions[SOUND4CNT_L] = Some(IORegisterDefinition::new(
+        BitMask::SIXTEEN(0xFF00, 0xFF3F),
+        false,
+    ));
+    definitions[SOUND4CNT_H] = Some(IORegisterDefinition::new(
+        BitMask::SIXTEEN(0x40FF, 0xC0FF),
+        false,
+    ));
+    definitions[SOUNDCNT_L] = Some(IORegisterDefinition::new(
+        BitMask::SIXTEEN(0xFF77, 0xFF77),
+        false,
+    ));
+    // the FIFO reset bits are write-only
+    definitions[SOUNDCNT_H] = Some(IORegisterDefinition::new(
+        BitMask::SIXTEEN(0x770F, 0xFF0F),
+        false,
+    ));
+    // channel on flags are read-only
+    definitions[SOUNDCNT_X] = Some(IORegisterDefinition::new(
+        BitMask::SIXTEEN(0x008F, 0x0080),
+        false,
+    ));
+    let mut i = WAVE_RAM;
+    while i != FIFO_A {
+        definitions[i] = Some(IORegisterDefinition::new(
+            BitMask::SIXTEEN(0xFFFF, 0xFFFF),
+            false,
+        ));
+        i += 2;
+    }
+    definitions[FIFO_A] = Some(IORegisterDefinition::new(
+        BitMask::THIRTYTWO(0, 0xFFFFFFFF),
+        false,
+    ));
+    definitions[FIFO_B] = Some(IORegisterDefinition::new(
+        BitMask::THIRTYTWO(0, 0xFFFFFFFF),
         false,
     ));
     definitions[DMA0SAD] = Some(IORegisterDefinition::new(
@@ -528,7 +637,7 @@ fn masked_io_load(region: &Vec<u16>, address: usize) -> Result<u16, MemoryError>
         }
         BitMask::SIXTEEN(mask, _) => mask & data,
         BitMask::THIRTYTWO(mask, _) => {
-            let shifted_mask = (mask >> (8 * address & 0b10)) as u16;
+            let shifted_mask = (mask >> (8 * (address & 0b10))) as u16;
             data & shifted_mask
         }
     })
@@ -572,7 +681,7 @@ fn masked_io_store(region: &mut Vec<u16>, address: usize, value: u16) -> Result<
         }
         BitMask::SIXTEEN(_, mask) => value & mask,
         BitMask::THIRTYTWO(_, mask) => {
-            let shifted_mask = (mask >> (8 * address & 0b10)) as u16;
+            let shifted_mask = (mask >> (8 * (address & 0b10))) as u16;
             value & shifted_mask
         }
     };
@@ -589,40 +698,132 @@ fn get_io_definition(offset: usize) -> Result<IORegisterDefinition, MemoryError>
     if let Some(io_definition) = IO_REGISTER_DEFINITIONS[offset & 0xFFE] {
         return Ok(io_definition);
     };
+    // the upper half of a 32-bit register shares its definition
+    if let Some(io_definition) = IO_REGISTER_DEFINITIONS[offset & 0xFFC] {
+        if let BitMask::THIRTYTWO(..) = io_definition.mask {
+            return Ok(io_definition);
+        }
+    };
     return Err(MemoryError::NoIODefinition(offset));
 }
 
 impl GBAMemory {
+    fn trace_io(&self, kind: IOAccessKind, address: usize, value: u32) {
+        self.io_trace
+            .borrow_mut()
+            .record(kind, IO_BASE | (address & 0xFFF), value);
+    }
+
+    /// Reads of TMxCNT_L see the running counter, writes set the reload.
+    fn io_load_register(&self, offset: usize) -> Result<u16, MemoryError> {
+        match offset {
+            TM0CNT_L | TM1CNT_L | TM2CNT_L | TM3CNT_L => {
+                Ok(self.timer_readout.counter((offset - TM0CNT_L) / 4, self))
+            }
+            _ => masked_io_load(&self.ioram, offset),
+        }
+    }
+
     pub(super) fn io_readu8(&self, address: usize) -> Result<u8, MemoryError> {
-        let load_value = masked_io_load(&self.ioram, address & 0xFFE)?;
-        Ok((load_value >> (8 * (address & 0b1))) as u8)
+        let load_value = self.io_load_register(address & 0xFFE)?;
+        let value = (load_value >> (8 * (address & 0b1))) as u8;
+        self.trace_io(IOAccessKind::Read, address, value as u32);
+        Ok(value)
     }
 
     pub(super) fn io_readu16(&self, address: usize) -> Result<u16, MemoryError> {
-        Ok(masked_io_load(&self.ioram, address & 0xFFE)?)
+        let value = self.io_load_register(address & 0xFFE)?;
+        self.trace_io(IOAccessKind::Read, address & !0b1, value as u32);
+        Ok(value)
     }
 
     pub(super) fn io_readu32(&self, address: usize) -> Result<u32, MemoryError> {
+        let value = self.io_load_word(address);
+        self.trace_io(IOAccessKind::Read, address & 0xFFC, value);
+        Ok(value)
+    }
+
+    /// The register pair holding `address`, without tracing the read.
+    pub(super) fn io_load_word(&self, address: usize) -> u32 {
         let word_aligned_offset = address & 0xFFC;
-        let lower = masked_io_load(&self.ioram, word_aligned_offset).unwrap_or(0) as u32;
-        let upper = masked_io_load(&self.ioram, word_aligned_offset + 2).unwrap_or(0) as u32;
+        let lower = self.io_load_register(word_aligned_offset).unwrap_or(0) as u32;
+        let upper = self.io_load_register(word_aligned_offset + 2).unwrap_or(0) as u32;
+        upper << 16 | lower
+    }
 
-        Ok(upper << 16 | lower)
+    fn request_halt(&mut self, haltcnt: u8) {
+        self.halt_request = Some(if haltcnt & HALTCNT_STOP > 0 {
+            HaltMode::Stop
+        } else {
+            HaltMode::Halt
+        });
+    }
+
+    fn note_register_change(&mut self, address: usize) {
+        match address & 0xFFE {
+            DMA0SAD..=DMA3CNT_H => self.register_changes.dma = true,
+            TM0CNT_L..=TM3CNT_H => self.register_changes.timers = true,
+            _ => {}
+        }
+    }
+
+    /// Queues the bytes of a write to FIFO_A or FIFO_B. Returns false for
+    /// any other register.
+    fn push_sound_fifo(&mut self, address: usize, bytes: &[u8]) -> bool {
+        let fifo = match address & 0xFFC {
+            FIFO_A => 0,
+            FIFO_B => 1,
+            _ => return false,
+        };
+        self.sound_fifos[fifo].push(bytes);
+        true
     }
 
     pub(super) fn io_writeu8(&mut self, address: usize, value: u8) -> Result<(), MemoryError> {
+        self.trace_io(IOAccessKind::Write, address, value as u32);
+        self.note_register_change(address);
+        if self.push_sound_fifo(address, &[value]) {
+            return Ok(());
+        }
+        if address & 0xFFF == HALTCNT {
+            self.request_halt(value);
+        }
         let mut current_value = io_load(&self.ioram, address & 0xFFE);
         current_value &= 0xFF << (8 * !(address & 0b1));
         current_value |= (value as u16) << (8 * (address & 0b1));
-        masked_io_store(&mut self.ioram, address & 0xFFF, current_value)
+        masked_io_store(&mut self.ioram, address & 0xFFF, current_value)?;
+        if address & 0xFFE == WAITCNT {
+            self.configure_wait_states();
+        }
+        Ok(())
     }
 
     pub(super) fn io_writeu16(&mut self, address: usize, value: u16) -> Result<(), MemoryError> {
-        masked_io_store(&mut self.ioram, address & 0xFFE, value)
+        self.trace_io(IOAccessKind::Write, address & !0b1, value as u32);
+        self.note_register_change(address);
+        if self.push_sound_fifo(address, &value.to_le_bytes()) {
+            return Ok(());
+        }
+        if address & 0xFFE == POSTFLG {
+            self.request_halt((value >> 8) as u8);
+        }
+        masked_io_store(&mut self.ioram, address & 0xFFE, value)?;
+        if address & 0xFFE == WAITCNT {
+            self.configure_wait_states();
+        }
+        Ok(())
     }
 
     pub(super) fn io_writeu32(&mut self, address: usize, value: u32) -> Result<(), MemoryError> {
         let offset = address & 0xFFC;
+        self.trace_io(IOAccessKind::Write, offset, value);
+        self.note_register_change(offset);
+        if self.push_sound_fifo(offset, &value.to_le_bytes()) {
+            return Ok(());
+        }
+        if offset == POSTFLG {
+            self.request_halt((value >> 8) as u8);
+        }
         let Ok(io_definition) = get_io_definition(offset) else {
             return Ok(());
         };
@@ -633,12 +834,15 @@ impl GBAMemory {
                     todo!();
                 }
                 let store_value = mask & value;
-                io_store(&mut self.ioram, offset, (store_value >> 16) as u16);
+                io_store(&mut self.ioram, offset + 2, (store_value >> 16) as u16);
                 io_store(&mut self.ioram, offset, (store_value & 0xFFFF) as u16);
             }
             _ => {
-                self.io_writeu16(offset + 2, (value >> 16) as u16)?;
-                self.io_writeu16(offset, (value & 0xFFFF) as u16)?;
+                masked_io_store(&mut self.ioram, offset + 2, (value >> 16) as u16)?;
+                masked_io_store(&mut self.ioram, offset, (value & 0xFFFF) as u16)?;
+                if offset == WAITCNT {
+                    self.configure_wait_states();
+                }
 
                 return Ok(());
             }
@@ -730,6 +934,39 @@ mod tests {
         assert_eq!(io_load(&memory.ioram, address), expected_value);
     }
 
+    #[test]
+    fn test_write_io32_register_stores_both_halves() {
+        let mut memory = GBAMemory::new();
+        memory.io_writeu32(DMA0SAD, 0xFFFF_1234).unwrap();
+        memory.io_writeu16(DMA0DAD + 2, 0xFFFF).unwrap();
+
+        assert_eq!(io_load(&memory.ioram, DMA0SAD), 0x1234);
+        assert_eq!(io_load(&memory.ioram, DMA0SAD + 2), 0x07FF);
+        assert_eq!(io_load(&memory.ioram, DMA0DAD + 2), 0x07FF);
+    }
+
+    #[rstest]
+    #[case::bg_offset(BG0HOFS, 0x0123, 0)]
+    #[case::mosaic(MOSAIC, 0x1234, 0)]
+    #[case::blend_brightness(BLDY, 0x0010, 0)]
+    #[case::dma_source(DMA1SAD, 0x1234, 0)]
+    #[case::dma_count(DMA3CNT_L, 0x0040, 0)]
+    #[case::sound_length(SOUND1CNT_H, 0xF23F, 0xF200)]
+    #[case::sound_frequency(SOUND1CNT_X, 0x47FF, 0x4000)]
+    #[case::wave_length(SOUND3CNT_H, 0x20FF, 0x2000)]
+    #[case::fifo_reset(SOUNDCNT_H, 0x8B0F, 0x030F)]
+    #[case::fifo(FIFO_A, 0x1234, 0)]
+    fn test_write_only_bits_read_as_zero(
+        #[case] address: usize,
+        #[case] write_value: u16,
+        #[case] expected_value: u16,
+    ) {
+        let mut memory = GBAMemory::new();
+        memory.io_writeu16(address, write_value).unwrap();
+
+        assert_eq!(memory.io_readu16(address).unwrap(), expected_value);
+    }
+
     #[rstest]
     #[case(0x3FFF, 0x3FFF, 0)]
     #[case(0x3FF0, 0x0FF0, 0x3000)]
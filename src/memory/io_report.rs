@@ -0,0 +1,341 @@
+use crate::{
+    arm7tdmi::interrupts::Interrupt,
+    graphics::{
+        color_effects::{target_bit, Effect},
+        layers::Layer,
+    },
+    io::{
+        keypad::Button,
+        timers::{prescaler_period, TIMER_STRIDE},
+    },
+};
+
+use super::{
+    dma::{AddressControl, DmaTiming, CHANNEL_STRIDE},
+    io_handlers::{
+        BG0CNT, BG0HOFS, BLDALPHA, BLDCNT, BLDY, DISPCNT, DISPSTAT, DMA0CNT_H, DMA0CNT_L,
+        DMA0DAD, DMA0SAD, IE, IF, IME, IO_BASE, KEYCNT, KEYINPUT, MOSAIC, SOUND1CNT_H,
+        SOUND1CNT_L, SOUND1CNT_X, SOUND2CNT_H, SOUND2CNT_L, SOUND3CNT_H, SOUND3CNT_L,
+        SOUND3CNT_X, SOUND4CNT_H, SOUND4CNT_L, SOUNDBIAS, SOUNDCNT_H, SOUNDCNT_L, SOUNDCNT_X,
+        TM0CNT_H, TM0CNT_L, VCOUNT, WAITCNT, WIN0H, WIN0V, WININ, WINOUT,
+    },
+    memory::MemoryBus,
+};
+
+const BLEND_TARGETS: [(Layer, &str); 6] = [
+    (Layer::Bg0, "BG0"),
+    (Layer::Bg1, "BG1"),
+    (Layer::Bg2, "BG2"),
+    (Layer::Bg3, "BG3"),
+    (Layer::Obj, "OBJ"),
+    (Layer::Backdrop, "BD"),
+];
+
+type Decoder = fn(u16) -> String;
+
+/// Names of the set bits of `value`, one name per bit from bit 0.
+fn flag_names(value: u16, names: &[&str]) -> String {
+    let set: Vec<&str> = names
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| value & (1 << bit) > 0)
+        .map(|(_, name)| *name)
+        .collect();
+    if set.is_empty() {
+        String::from("none")
+    } else {
+        set.join(" ")
+    }
+}
+
+fn on_off(value: u16, bit: u16) -> &'static str {
+    if value & (1 << bit) > 0 {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+fn decode_dispcnt(value: u16) -> String {
+    format!(
+        "mode {}, frame {}, OBJ {}D, forced blank {}, enabled: {}",
+        value & 0x7,
+        value >> 4 & 1,
+        if value & (1 << 6) > 0 { 1 } else { 2 },
+        on_off(value, 7),
+        flag_names(value >> 8, &["BG0", "BG1", "BG2", "BG3", "OBJ", "WIN0", "WIN1", "OBJWIN"])
+    )
+}
+
+fn decode_dispstat(value: u16) -> String {
+    format!(
+        "flags: {}, irqs: {}, vcount target {}",
+        flag_names(value, &["vblank", "hblank", "vcount"]),
+        flag_names(value >> 3, &["vblank", "hblank", "vcount"]),
+        value >> 8
+    )
+}
+
+fn decode_bgcnt(value: u16) -> String {
+    format!(
+        "priority {}, tiles {:#X}, map {:#X}, size {}, {}bpp, mosaic {}, wrap {}",
+        value & 0x3,
+        (value >> 2 & 0x3) as usize * 0x4000,
+        (value >> 8 & 0x1F) as usize * 0x800,
+        value >> 14,
+        if value & (1 << 7) > 0 { 8 } else { 4 },
+        on_off(value, 6),
+        on_off(value, 13)
+    )
+}
+
+fn decode_window_span(value: u16) -> String {
+    format!("{}..{}", value >> 8, value & 0xFF)
+}
+
+fn decode_window_control(value: u16) -> String {
+    const WINDOW_FLAGS: [&str; 6] = ["BG0", "BG1", "BG2", "BG3", "OBJ", "effects"];
+    format!(
+        "low: {}, high: {}",
+        flag_names(value & 0x3F, &WINDOW_FLAGS),
+        flag_names(value >> 8 & 0x3F, &WINDOW_FLAGS)
+    )
+}
+
+/// Names of the layers whose BLDCNT target bit is set in `targets`.
+fn blend_target_names(targets: u16) -> String {
+    let set: Vec<&str> = BLEND_TARGETS
+        .iter()
+        .filter(|(layer, _)| targets & target_bit(*layer) > 0)
+        .map(|(_, name)| *name)
+        .collect();
+    if set.is_empty() {
+        String::from("none")
+    } else {
+        set.join(" ")
+    }
+}
+
+fn decode_bldcnt(value: u16) -> String {
+    format!(
+        "{}, first: {}, second: {}",
+        Effect::from_bldcnt(value),
+        blend_target_names(value),
+        blend_target_names(value >> 8)
+    )
+}
+
+fn decode_bldalpha(value: u16) -> String {
+    format!("eva {}/16, evb {}/16", value & 0x1F, value >> 8 & 0x1F)
+}
+
+fn decode_soundcnt_x(value: u16) -> String {
+    format!(
+        "master {}, playing: {}",
+        on_off(value, 7),
+        flag_names(value, &["ch1", "ch2", "ch3", "ch4"])
+    )
+}
+
+fn decode_dmacnt(value: u16) -> String {
+    format!(
+        "{}, {}-bit, dest {}, source {}, repeat {}, irq {}, enabled {}",
+        DmaTiming::from_control(value),
+        if value & (1 << 10) > 0 { 32 } else { 16 },
+        AddressControl::from_bits(value >> 5),
+        AddressControl::from_bits(value >> 7),
+        on_off(value, 9),
+        on_off(value, 14),
+        on_off(value, 15)
+    )
+}
+
+fn decode_tmcnt(value: u16) -> String {
+    format!(
+        "prescaler {}, cascade {}, irq {}, enabled {}",
+        prescaler_period(value),
+        on_off(value, 2),
+        on_off(value, 6),
+        on_off(value, 7)
+    )
+}
+
+fn button_names(mask: u16) -> String {
+    let pressed: Vec<String> = Button::ALL
+        .iter()
+        .filter(|button| mask & button.mask() > 0)
+        .map(|button| format!("{:?}", button))
+        .collect();
+    if pressed.is_empty() {
+        String::from("none")
+    } else {
+        pressed.join(" ")
+    }
+}
+
+fn decode_keyinput(value: u16) -> String {
+    // active low
+    format!("pressed: {}", button_names(!value & 0x3FF))
+}
+
+fn decode_keycnt(value: u16) -> String {
+    format!(
+        "irq {} when {} of: {}",
+        on_off(value, 14),
+        if value & (1 << 15) > 0 { "all" } else { "any" },
+        button_names(value & 0x3FF)
+    )
+}
+
+fn decode_interrupts(value: u16) -> String {
+    let interrupts: Vec<String> = Interrupt::from_flags(value)
+        .iter()
+        .map(|interrupt| interrupt.to_string())
+        .collect();
+    if interrupts.is_empty() {
+        String::from("none")
+    } else {
+        interrupts.join(" ")
+    }
+}
+
+fn decode_ime(value: u16) -> String {
+    String::from(on_off(value, 0))
+}
+
+/// Every register in the report, in address order, with its decoder if
+/// its fields need one.
+fn registers() -> Vec<(String, usize, Option<Decoder>)> {
+    let mut registers: Vec<(String, usize, Option<Decoder>)> = vec![
+        (String::from("DISPCNT"), DISPCNT, Some(decode_dispcnt)),
+        (String::from("DISPSTAT"), DISPSTAT, Some(decode_dispstat)),
+        (String::from("VCOUNT"), VCOUNT, None),
+    ];
+    for bg in 0..4 {
+        registers.push((format!("BG{}CNT", bg), BG0CNT + bg * 2, Some(decode_bgcnt)));
+    }
+    for bg in 0..4 {
+        registers.push((format!("BG{}HOFS", bg), BG0HOFS + bg * 4, None));
+        registers.push((format!("BG{}VOFS", bg), BG0HOFS + bg * 4 + 2, None));
+    }
+    for window in 0..2 {
+        registers.push((format!("WIN{}H", window), WIN0H + window * 2, Some(decode_window_span)));
+    }
+    for window in 0..2 {
+        registers.push((format!("WIN{}V", window), WIN0V + window * 2, Some(decode_window_span)));
+    }
+    registers.extend([
+        (String::from("WININ"), WININ, Some(decode_window_control as Decoder)),
+        (String::from("WINOUT"), WINOUT, Some(decode_window_control)),
+        (String::from("MOSAIC"), MOSAIC, None),
+        (String::from("BLDCNT"), BLDCNT, Some(decode_bldcnt)),
+        (String::from("BLDALPHA"), BLDALPHA, Some(decode_bldalpha)),
+        (String::from("BLDY"), BLDY, None),
+        (String::from("SOUND1CNT_L"), SOUND1CNT_L, None),
+        (String::from("SOUND1CNT_H"), SOUND1CNT_H, None),
+        (String::from("SOUND1CNT_X"), SOUND1CNT_X, None),
+        (String::from("SOUND2CNT_L"), SOUND2CNT_L, None),
+        (String::from("SOUND2CNT_H"), SOUND2CNT_H, None),
+        (String::from("SOUND3CNT_L"), SOUND3CNT_L, None),
+        (String::from("SOUND3CNT_H"), SOUND3CNT_H, None),
+        (String::from("SOUND3CNT_X"), SOUND3CNT_X, None),
+        (String::from("SOUND4CNT_L"), SOUND4CNT_L, None),
+        (String::from("SOUND4CNT_H"), SOUND4CNT_H, None),
+        (String::from("SOUNDCNT_L"), SOUNDCNT_L, None),
+        (String::from("SOUNDCNT_H"), SOUNDCNT_H, None),
+        (String::from("SOUNDCNT_X"), SOUNDCNT_X, Some(decode_soundcnt_x)),
+        (String::from("SOUNDBIAS"), SOUNDBIAS, None),
+    ]);
+    for channel in 0..4 {
+        let offset = channel * CHANNEL_STRIDE;
+        registers.extend([
+            (format!("DMA{}SAD_L", channel), DMA0SAD + offset, None),
+            (format!("DMA{}SAD_H", channel), DMA0SAD + offset + 2, None),
+            (format!("DMA{}DAD_L", channel), DMA0DAD + offset, None),
+            (format!("DMA{}DAD_H", channel), DMA0DAD + offset + 2, None),
+            (format!("DMA{}CNT_L", channel), DMA0CNT_L + offset, None),
+            (format!("DMA{}CNT_H", channel), DMA0CNT_H + offset, Some(decode_dmacnt as Decoder)),
+        ]);
+    }
+    for timer in 0..4 {
+        let offset = timer * TIMER_STRIDE;
+        // TMxCNT_L keeps the reload, the running counter lives in the timers
+        registers.push((format!("TM{}CNT_L", timer), TM0CNT_L + offset, None));
+        registers.push((format!("TM{}CNT_H", timer), TM0CNT_H + offset, Some(decode_tmcnt)));
+    }
+    registers.extend([
+        (String::from("KEYINPUT"), KEYINPUT, Some(decode_keyinput as Decoder)),
+        (String::from("KEYCNT"), KEYCNT, Some(decode_keycnt)),
+        (String::from("IE"), IE, Some(decode_interrupts)),
+        (String::from("IF"), IF, Some(decode_interrupts)),
+        (String::from("WAITCNT"), WAITCNT, None),
+        (String::from("IME"), IME, Some(decode_ime)),
+    ]);
+    registers
+}
+
+/// One line per IO register from DISPCNT to IME with its raw value and,
+/// where it has one, its decoded fields. Values are read straight from
+/// the register file, so write-only registers show what was written.
+pub fn io_report(memory: &dyn MemoryBus) -> String {
+    let mut report = String::new();
+    for (name, offset, decoder) in registers() {
+        let value = memory.ppu_io_read(offset);
+        report.push_str(&format!("{:<12}{:#010X} = {:04X}", name, IO_BASE + offset, value));
+        if let Some(decoder) = decoder {
+            report.push_str(&format!("  {}", decoder(value)));
+        }
+        report.push('\n');
+    }
+    report
+}
+
+#[cfg(test)]
+mod io_report_tests {
+    use crate::memory::{
+        io_handlers::{BLDCNT, DISPCNT, DMA0CNT_H, IO_BASE, TM0CNT_H},
+        memory::{GBAMemory, MemoryBus},
+    };
+
+    use super::io_report;
+
+    #[test]
+    fn report_decodes_dispcnt_and_timer_control() {
+        let mut memory = GBAMemory::new();
+        memory.writeu16(IO_BASE + DISPCNT, 0x3 | 1 << 10);
+        memory.writeu16(IO_BASE + TM0CNT_H + 4, 1 << 7 | 1 << 6 | 1 << 2 | 2);
+
+        let report = io_report(memory.as_ref());
+
+        let line = |name: &str| {
+            report
+                .lines()
+                .find(|line| line.starts_with(name))
+                .unwrap_or_else(|| panic!("{name} missing from report"))
+                .to_string()
+        };
+        assert_eq!(
+            line("DISPCNT "),
+            "DISPCNT     0x04000000 = 0403  mode 3, frame 0, OBJ 2D, forced blank off, enabled: BG2"
+        );
+        assert_eq!(
+            line("TM1CNT_H "),
+            "TM1CNT_H    0x04000106 = 00C6  prescaler 256, cascade on, irq on, enabled on"
+        );
+        assert!(line("IME ").ends_with("off"));
+    }
+
+    #[test]
+    fn report_decodes_dma_and_blend_control() {
+        let mut memory = GBAMemory::new();
+        memory.writeu16(IO_BASE + DMA0CNT_H + 0xC, 2 << 12 | 1 << 10 | 1 << 9 | 2 << 7 | 3 << 5);
+        memory.writeu16(IO_BASE + BLDCNT, 1 << 12 | 1 << 6 | 1 << 5 | 1 << 2);
+
+        let report = io_report(memory.as_ref());
+
+        assert!(report.contains(
+            "DMA1CNT_H   0x040000C6 = 2760  hblank, 32-bit, dest reload, source fixed, repeat on, irq off, enabled off"
+        ));
+        assert!(report.contains("BLDCNT      0x04000050 = 1064  alpha blend, first: BG2 BD, second: OBJ"));
+    }
+}
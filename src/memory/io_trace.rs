@@ -0,0 +1,140 @@
+use std::{collections::VecDeque, fmt::Display};
+
+const MAX_TRACE_ENTRIES: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IOAccessKind {
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IOAccess {
+    pub kind: IOAccessKind,
+    pub address: usize,
+    pub value: u32,
+}
+
+impl Display for IOAccess {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.kind {
+            IOAccessKind::Read => "R",
+            IOAccessKind::Write => "W",
+        };
+        write!(f, "{kind} {:#010X} = {:#X}", self.address, self.value)
+    }
+}
+
+/// Log of CPU accesses to the IO region. Only accesses inside the
+/// inclusive `filter` range are kept when one is set.
+#[derive(Default, Debug)]
+pub struct IOTrace {
+    pub enabled: bool,
+    filter: Option<(usize, usize)>,
+    entries: VecDeque<IOAccess>,
+}
+
+impl IOTrace {
+    pub fn set_filter(&mut self, start: usize, end: usize) {
+        self.filter = Some((start.min(end), start.max(end)));
+    }
+
+    pub fn clear_filter(&mut self) {
+        self.filter = None;
+    }
+
+    pub fn filter(&self) -> Option<(usize, usize)> {
+        self.filter
+    }
+
+    pub fn record(&mut self, kind: IOAccessKind, address: usize, value: u32) {
+        if !self.enabled {
+            return;
+        }
+        if let Some((start, end)) = self.filter {
+            if address < start || address > end {
+                return;
+            }
+        }
+        if self.entries.len() >= MAX_TRACE_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(IOAccess {
+            kind,
+            address,
+            value,
+        });
+    }
+
+    pub fn entries(&self) -> &VecDeque<IOAccess> {
+        &self.entries
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        debugger::{debugger::Debugger, terminal_commands::parse_command},
+        memory::{
+            io_handlers::{DISPCNT, IO_BASE, TM0CNT_L, TM3CNT_H},
+            memory::{GBAMemory, MemoryBus},
+        },
+    };
+
+    use super::{IOAccess, IOAccessKind};
+
+    #[test]
+    fn filter_only_logs_accesses_in_range() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        {
+            let mut trace = memory.io_trace().borrow_mut();
+            trace.enabled = true;
+            trace.set_filter(IO_BASE + TM0CNT_L, IO_BASE + TM3CNT_H);
+        }
+
+        memory.writeu16(IO_BASE + DISPCNT, 0x403);
+        memory.writeu16(IO_BASE + TM0CNT_L, 0xFF00);
+        memory.readu16(IO_BASE + DISPCNT);
+        memory.readu16(IO_BASE + TM0CNT_L);
+        memory.write(IO_BASE + TM3CNT_H, 0x80);
+
+        let trace = memory.io_trace().borrow();
+        let entries: Vec<IOAccess> = trace.entries().iter().copied().collect();
+        assert_eq!(
+            entries,
+            vec![
+                IOAccess { kind: IOAccessKind::Write, address: IO_BASE + TM0CNT_L, value: 0xFF00 },
+                // reads see the counter of the timer, which hasn't started
+                IOAccess { kind: IOAccessKind::Read, address: IO_BASE + TM0CNT_L, value: 0 },
+                IOAccess { kind: IOAccessKind::Write, address: IO_BASE + TM3CNT_H, value: 0x80 },
+            ]
+        );
+    }
+
+    #[test]
+    fn show_lists_the_active_filter_first() {
+        let mut debugger = Debugger::with_memory(GBAMemory::new());
+        let mut run_command = |command: &str| {
+            debugger.terminal_buffer = String::from(command);
+            parse_command(&mut debugger).unwrap_or_else(|err| err.to_string())
+        };
+        run_command("iotrace on");
+        run_command("iotrace filter 0x4000100 0x400010E");
+
+        assert_eq!(run_command("iotrace show"), "Filtered to 0x4000100-0x400010E\n");
+        run_command("iotrace nofilter");
+        assert_eq!(run_command("iotrace show"), "");
+    }
+
+    #[test]
+    fn disabled_trace_logs_nothing() {
+        let mut memory: Box<dyn MemoryBus> = GBAMemory::new();
+        memory.writeu16(IO_BASE + DISPCNT, 0x403);
+
+        assert!(memory.io_trace().borrow().entries().is_empty());
+    }
+}
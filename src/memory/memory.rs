@@ -1,11 +1,28 @@
+use crate::io::timers::TimerReadout;
+use crate::state::{
+    savestate::{StateError, StateReader, StateWriter},
+    MemoryRegion,
+};
 use crate::types::{BYTE, CYCLES, HWORD, WORD};
 use std::{
+    cell::RefCell,
     fmt::Display,
-    fs::File,
+    fs::{self, File},
     io::{Read, Seek},
+    path::Path,
 };
 
-use super::io_handlers::{io_store, KEYINPUT};
+use super::{
+    backup::{BackupConfig, BackupType},
+    eeprom::Eeprom,
+    io_handlers::{io_load, io_store, HaltMode, RegisterChanges, KEYINPUT, WAITCNT},
+    io_trace::IOTrace,
+    rom_write_guard::RomWriteGuard,
+    code_writes::CodeWrites,
+    prefetch::PrefetchBuffer,
+    sound_fifo::SoundFifo,
+    vram_contention::VramContention,
+};
 
 pub struct MemoryFetch<T> {
     pub cycles: CYCLES,
@@ -50,6 +67,7 @@ const ROM1B_REGION: usize = 0xB;
 const ROM2A_REGION: usize = 0xC;
 const ROM2B_REGION: usize = 0xD;
 const SRAM_REGION: usize = 0xE;
+const SRAM_MIRROR_REGION: usize = 0xF;
 
 const BIOS_SIZE: usize = 0x4000;
 const EXWRAM_SIZE: usize = 0x40000;
@@ -59,7 +77,22 @@ const BGRAM_SIZE: usize = 0x400;
 const VRAM_SIZE: usize = 0x18000;
 const OAM_SIZE: usize = 0x400;
 const ROM_SIZE: usize = 0x1000000;
-const SRAM_SIZE: usize = 0x10000;
+const SRAM_SIZE: usize = 0x8000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BusWidth {
+    Eight,
+    Sixteen,
+    ThirtyTwo,
+}
+
+fn bus_width(region: usize) -> BusWidth {
+    match region {
+        BIOS_REGION | IWRAM_REGION | IORAM_REGION | OAM_REGION => BusWidth::ThirtyTwo,
+        SRAM_REGION | SRAM_MIRROR_REGION => BusWidth::Eight,
+        _ => BusWidth::Sixteen,
+    }
+}
 
 pub struct GBAMemory {
     bios: Vec<u32>,
@@ -71,8 +104,87 @@ pub struct GBAMemory {
     oam: Vec<u32>,
     rom: Vec<u32>,
     sram: Vec<u32>,
-    wait_cycles_u16: [u8; 15],
-    wait_cycles_u32: [u8; 15],
+    /// Set by writes to SRAM since it was last loaded or saved.
+    sram_dirty: bool,
+    /// Driven through halfword accesses to 0x0DXXXXXX.
+    eeprom: Eeprom,
+    /// Cycles for a halfword access to each region, the cartridge ones
+    /// follow WAITCNT.
+    nonsequential_cycles: [CYCLES; 16],
+    sequential_cycles: [CYCLES; 16],
+    /// Where a halfword or word access has to be to continue the last one.
+    next_sequential: Option<usize>,
+    pub(super) io_trace: RefCell<IOTrace>,
+    pub(super) halt_request: Option<HaltMode>,
+    pub(super) register_changes: RegisterChanges,
+    rom_write_guard: RomWriteGuard,
+    vram_contention: VramContention,
+    backup: BackupConfig,
+    /// The last instruction fetched, which reads of unmapped memory see.
+    open_bus: WORD,
+    /// The BIOS can only be read while executing from it, reads from
+    /// elsewhere see the last opcode it fetched.
+    executing_bios: bool,
+    bios_opcode: WORD,
+    prefetch: PrefetchBuffer,
+    /// Set while fetching an instruction, which the prefetch buffer can
+    /// serve. Any other access to the cartridge flushes it.
+    instruction_fetch: bool,
+    /// Cycles spent on cartridge accesses since the prefetcher last ran.
+    cartridge_busy: u64,
+    pub(super) sound_fifos: [SoundFifo; 2],
+    pub(super) timer_readout: TimerReadout,
+    code_writes: CodeWrites,
+}
+
+/// What a save state keeps of memory besides the RAM regions: the save
+/// chip, the sound FIFOs and what the bus was last doing.
+#[derive(Debug, Default)]
+pub struct BusState {
+    sram: Vec<u8>,
+    eeprom: Eeprom,
+    sound_fifos: [SoundFifo; 2],
+    prefetch: PrefetchBuffer,
+    next_sequential: Option<usize>,
+    cartridge_busy: u64,
+    open_bus: WORD,
+    executing_bios: bool,
+    bios_opcode: WORD,
+    halt_request: Option<HaltMode>,
+}
+
+impl BusState {
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.bytes(&self.sram);
+        self.eeprom.save_state(writer);
+        for fifo in &self.sound_fifos {
+            fifo.save_state(writer);
+        }
+        self.prefetch.save_state(writer);
+        writer.optional_word(self.next_sequential.map(|address| address as u32));
+        writer.u64(self.cartridge_busy);
+        writer.word(self.open_bus);
+        writer.flag(self.executing_bios);
+        writer.word(self.bios_opcode);
+        writer.halt_mode(self.halt_request);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.sram = vec![0; SRAM_SIZE];
+        reader.bytes_into(&mut self.sram)?;
+        self.eeprom.load_state(reader)?;
+        for fifo in self.sound_fifos.iter_mut() {
+            fifo.load_state(reader)?;
+        }
+        self.prefetch.load_state(reader)?;
+        self.next_sequential = reader.optional_word()?.map(|address| address as usize);
+        self.cartridge_busy = reader.u64()?;
+        self.open_bus = reader.word()?;
+        self.executing_bios = reader.flag()?;
+        self.bios_opcode = reader.word()?;
+        self.halt_request = reader.halt_mode()?;
+        Ok(())
+    }
 }
 
 #[inline(always)]
@@ -93,6 +205,7 @@ pub enum MemoryError {
     NoIODefinition(usize),
     ReadError(usize),
     WriteError(usize, u32),
+    RomWrite(usize, u32),
 }
 
 impl Display for MemoryError {
@@ -102,6 +215,9 @@ impl Display for MemoryError {
             MemoryError::WriteError(address, value) => {
                 write!(f, "Write Error: {:#X} <- {:#X}", address, value)
             }
+            MemoryError::RomWrite(address, value) => {
+                write!(f, "ROM Write: {:#X} <- {:#X}", address, value)
+            }
             MemoryError::NoIODefinition(address) => {
                 write!(f, "No IO Definition Provided: {:#X}", address)
             }
@@ -112,11 +228,11 @@ impl Display for MemoryError {
 pub trait DebuggerMemoryBus: MemoryBus + MemoryBusNoPanic {}
 
 pub trait MemoryBusNoPanic {
-    fn try_read(&self, address: usize) -> Result<MemoryFetch<u8>, MemoryError>;
+    fn try_read(&mut self, address: usize) -> Result<MemoryFetch<u8>, MemoryError>;
 
-    fn try_readu16(&self, address: usize) -> Result<MemoryFetch<u16>, MemoryError>;
+    fn try_readu16(&mut self, address: usize) -> Result<MemoryFetch<u16>, MemoryError>;
 
-    fn try_readu32(&self, address: usize) -> Result<MemoryFetch<u32>, MemoryError>;
+    fn try_readu32(&mut self, address: usize) -> Result<MemoryFetch<u32>, MemoryError>;
 
     fn try_write(&mut self, address: usize, value: u8) -> Result<CYCLES, MemoryError>;
 
@@ -126,11 +242,20 @@ pub trait MemoryBusNoPanic {
 }
 
 pub trait MemoryBus {
-    fn read(&self, address: usize) -> MemoryFetch<u8>;
+    fn read(&mut self, address: usize) -> MemoryFetch<u8>;
+
+    fn readu16(&mut self, address: usize) -> MemoryFetch<u16>;
+
+    fn readu32(&mut self, address: usize) -> MemoryFetch<u32>;
 
-    fn readu16(&self, address: usize) -> MemoryFetch<u16>;
+    /// Reads for the PPU and the debugger, which see the same data as the
+    /// CPU without counting as bus accesses, so they leave the sequential
+    /// timing alone.
+    fn peek(&self, address: usize) -> u8;
 
-    fn readu32(&self, address: usize) -> MemoryFetch<u32>;
+    fn peeku16(&self, address: usize) -> u16;
+
+    fn peeku32(&self, address: usize) -> u32;
 
     fn write(&mut self, address: usize, value: u8) -> CYCLES;
 
@@ -139,48 +264,85 @@ pub trait MemoryBus {
     fn writeu32(&mut self, address: usize, value: u32) -> CYCLES;
 
     fn ppu_io_write(&mut self, address: usize, value: u16);
+
+    fn ppu_io_read(&self, address: usize) -> u16;
+
+    fn io_trace(&self) -> &RefCell<IOTrace>;
+
+    fn patch_rom(&mut self, address: usize, value: u16);
+
+    /// Returns and clears the low power mode requested through HALTCNT.
+    fn take_halt_request(&mut self) -> Option<HaltMode>;
+
+    /// Returns and clears the timer and DMA registers written since the
+    /// last call.
+    fn take_register_changes(&mut self) -> RegisterChanges;
+
+    fn rom_write_guard(&mut self) -> &mut RomWriteGuard;
+
+    fn vram_contention(&mut self) -> &mut VramContention;
+
+    fn backup_config(&mut self) -> &mut BackupConfig;
+
+    /// Loads a raw save, as written by `save_to`, into SRAM or the EEPROM.
+    fn load_save(&mut self, path: &Path) -> Result<(), std::io::Error>;
+
+    /// Writes the save out as raw bytes, unless it is unchanged since it
+    /// was last loaded or saved.
+    fn save_to(&mut self, path: &Path) -> Result<(), std::io::Error>;
+
+    /// FIFO_A and FIFO_B.
+    fn sound_fifos(&mut self) -> &mut [SoundFifo; 2];
+
+    /// What reads of TMxCNT_L work the running counters out from.
+    fn timer_readout(&mut self) -> &mut TimerReadout;
+
+    /// Writes to memory that code can run from, for the instruction cache.
+    fn code_writes(&mut self) -> &mut CodeWrites;
+
+    fn region_snapshot(&self, region: MemoryRegion) -> Vec<u8>;
+
+    /// Overwrites a region with bytes laid out as `region_snapshot` returns
+    /// them. Bytes past the end of the region are ignored.
+    fn restore_region(&mut self, region: MemoryRegion, bytes: &[u8]);
+
+    fn bus_state(&self) -> BusState;
+
+    /// Restores a `bus_state`. Goes after restoring the IO region, which
+    /// flushes the prefetch buffer as it sets WAITCNT.
+    fn restore_bus_state(&mut self, state: BusState);
+
+    /// Reads an ARM instruction, latching it as the open bus value.
+    fn fetch_u32(&mut self, address: usize) -> MemoryFetch<u32>;
+
+    /// Reads a Thumb instruction, latching it as the open bus value.
+    fn fetch_u16(&mut self, address: usize) -> MemoryFetch<u16>;
+
+    /// Lets the cartridge prefetcher read ahead for the part of `elapsed`
+    /// cycles the cartridge bus was idle.
+    fn run_prefetcher(&mut self, elapsed: u64);
 }
 
 impl DebuggerMemoryBus for GBAMemory {}
 
 impl GBAMemory {
     pub fn new() -> Box<Self> {
-        let mut wait_cycles_u16 = [0; 15];
-        wait_cycles_u16[BIOS_REGION] = 1;
-        wait_cycles_u16[IWRAM_REGION] = 1;
-        wait_cycles_u16[EXWRAM_REGION] = 3;
-        wait_cycles_u16[IORAM_REGION] = 1;
-        wait_cycles_u16[OAM_REGION] = 1;
-        wait_cycles_u16[BGRAM_REGION] = 1;
-        wait_cycles_u16[VRAM_REGION] = 1;
-        wait_cycles_u16[ROM0A_REGION] = 5;
-        wait_cycles_u16[ROM0B_REGION] = 5;
-        wait_cycles_u16[ROM1A_REGION] = 5;
-        wait_cycles_u16[ROM1B_REGION] = 5;
-        wait_cycles_u16[ROM2A_REGION] = 5;
-        wait_cycles_u16[ROM2B_REGION] = 5;
-        wait_cycles_u16[SRAM_REGION] = 5;
-
-        let mut wait_cycles_u32 = [0; 15];
-        wait_cycles_u32[BIOS_REGION] = 1;
-        wait_cycles_u32[IWRAM_REGION] = 1;
-        wait_cycles_u32[EXWRAM_REGION] = 6;
-        wait_cycles_u32[IORAM_REGION] = 1;
-        wait_cycles_u32[OAM_REGION] = 1;
-        wait_cycles_u32[BGRAM_REGION] = 2;
-        wait_cycles_u32[VRAM_REGION] = 2;
-        wait_cycles_u32[ROM0A_REGION] = 8;
-        wait_cycles_u32[ROM0B_REGION] = 8;
-        wait_cycles_u32[ROM1A_REGION] = 8;
-        wait_cycles_u32[ROM1B_REGION] = 8;
-        wait_cycles_u32[ROM2A_REGION] = 8;
-        wait_cycles_u32[ROM2B_REGION] = 8;
+        let mut nonsequential_cycles = [0; 16];
+        nonsequential_cycles[BIOS_REGION] = 1;
+        nonsequential_cycles[IWRAM_REGION] = 1;
+        nonsequential_cycles[EXWRAM_REGION] = 3;
+        nonsequential_cycles[IORAM_REGION] = 1;
+        nonsequential_cycles[OAM_REGION] = 1;
+        nonsequential_cycles[BGRAM_REGION] = 1;
+        nonsequential_cycles[VRAM_REGION] = 1;
+        // only the cartridge is faster in sequence, configured below
+        let sequential_cycles = nonsequential_cycles;
 
         let mut ioram = vec![0; IORAM_SIZE >> 1];
         io_store(&mut ioram, 0x088, 0x200);
         io_store(&mut ioram, KEYINPUT, 0x03FF);
 
-        Box::new(Self {
+        let mut memory = Box::new(Self {
             bios: vec![0; BIOS_SIZE >> 2],
             exwram: vec![0; EXWRAM_SIZE >> 2],
             iwram: vec![0; IWRAM_SIZE >> 2],
@@ -190,11 +352,148 @@ impl GBAMemory {
             oam: vec![0; OAM_SIZE >> 2],
             rom: vec![0; ROM_SIZE >> 2],
             sram: vec![0; SRAM_SIZE >> 2],
-            wait_cycles_u16,
-            wait_cycles_u32,
+            sram_dirty: false,
+            eeprom: Eeprom::default(),
+            nonsequential_cycles,
+            sequential_cycles,
+            next_sequential: None,
+            io_trace: RefCell::new(IOTrace::default()),
+            halt_request: None,
+            register_changes: RegisterChanges::default(),
+            rom_write_guard: RomWriteGuard::default(),
+            vram_contention: VramContention::default(),
+            backup: BackupConfig::default(),
+            open_bus: 0,
+            // execution starts at the reset vector
+            executing_bios: true,
+            bios_opcode: 0,
+            prefetch: PrefetchBuffer::default(),
+            instruction_fetch: false,
+            cartridge_busy: 0,
+            sound_fifos: Default::default(),
+            timer_readout: TimerReadout::default(),
+            code_writes: CodeWrites::default(),
+        });
+        memory.configure_wait_states();
+        memory
+    }
+
+    /// The word seen by a CPU or DMA read that nothing answers: unmapped
+    /// addresses, and the BIOS while executing outside it.
+    fn undriven_word(&self, address: usize) -> Option<WORD> {
+        match address >> 24 {
+            BIOS_REGION if address < BIOS_SIZE => (!self.executing_bios).then_some(self.bios_opcode),
+            BIOS_REGION | UNMAPPED_REGION | 0x10.. => Some(self.open_bus),
+            _ => None,
+        }
+    }
+
+    /// The word holding `address` as it is stored, with IO registers read
+    /// the way the CPU sees them but left out of the IO trace.
+    fn load_word(&self, address: usize) -> Result<WORD, MemoryError> {
+        Ok(match address >> 24 {
+            BIOS_REGION if address < BIOS_SIZE => memory_load(&self.bios, address),
+            EXWRAM_REGION => memory_load(&self.exwram, address & EX_WRAM_MIRROR_MASK),
+            IWRAM_REGION => memory_load(&self.iwram, address & IW_WRAM_MIRROR_MASK),
+            IORAM_REGION => self.io_load_word(address),
+            BGRAM_REGION => memory_load(&self.bgram, address & BGRAM_MIRROR_MASK),
+            VRAM_REGION => memory_load(&self.vram, vram_offset(address)),
+            OAM_REGION => memory_load(&self.oam, address & OAM_MIRROR_MASK),
+            ROM0A_REGION..=ROM2B_REGION => memory_load(&self.rom, address & 0xFFFFFF),
+            SRAM_REGION | SRAM_MIRROR_REGION => memory_load(&self.sram, address & SRAM_MIRROR_MASK),
+            _ => return Err(MemoryError::ReadError(address)),
         })
     }
 
+    /// Peeks see the BIOS wherever the CPU is executing, only unmapped
+    /// addresses show the open bus. Reading the EEPROM shifts bits out of
+    /// it, so peeks see the ROM underneath instead.
+    fn peek_word(&self, address: usize) -> WORD {
+        self.load_word(address)
+            .ok()
+            .or_else(|| self.undriven_word(address))
+            .unwrap_or(0)
+    }
+
+    fn begin_fetch(&mut self, address: usize) {
+        self.instruction_fetch = true;
+        self.executing_bios = address < BIOS_SIZE;
+        if self.executing_bios {
+            self.bios_opcode = memory_load(&self.bios, address);
+        }
+    }
+
+    /// Sets the cartridge timings from WAITCNT. SRAM has no sequential
+    /// accesses, and each ROM wait state has its own sequential timing.
+    pub(super) fn configure_wait_states(&mut self) {
+        const NONSEQUENTIAL_WAITS: [CYCLES; 4] = [4, 3, 2, 8];
+        let waitcnt = io_load(&self.ioram, WAITCNT);
+        let nonsequential = |shift: u16| NONSEQUENTIAL_WAITS[(waitcnt >> shift) as usize & 0b11] + 1;
+        let sequential = |bit: u16, waits: CYCLES| if waitcnt >> bit & 1 == 1 { 2 } else { waits + 1 };
+
+        for region in [SRAM_REGION, SRAM_MIRROR_REGION] {
+            self.nonsequential_cycles[region] = nonsequential(0);
+            self.sequential_cycles[region] = nonsequential(0);
+        }
+        for (regions, nonsequential, sequential) in [
+            (ROM0A_REGION..=ROM0B_REGION, nonsequential(2), sequential(4, 2)),
+            (ROM1A_REGION..=ROM1B_REGION, nonsequential(5), sequential(7, 4)),
+            (ROM2A_REGION..=ROM2B_REGION, nonsequential(8), sequential(10, 8)),
+        ] {
+            for region in regions {
+                self.nonsequential_cycles[region] = nonsequential;
+                self.sequential_cycles[region] = sequential;
+            }
+        }
+
+        self.prefetch.enabled = waitcnt & 0x4000 != 0;
+        self.prefetch.flush();
+    }
+
+    /// Cycles for an access of `size` bytes. A halfword or word access is
+    /// sequential when it follows on from the last one in the same region,
+    /// a byte access never is.
+    fn access_cycles(&mut self, address: usize, size: usize) -> CYCLES {
+        let region = address >> 24;
+        let address = address & !(size - 1);
+        let sequential = size > 1 && self.next_sequential == Some(address);
+        let next = address + size;
+        self.next_sequential = (size > 1 && next >> 24 == region).then_some(next);
+
+        let cycles = if sequential {
+            self.sequential_cycles[region]
+        } else {
+            self.nonsequential_cycles[region]
+        };
+        // a 32-bit access on a 16-bit bus is split into two halfword
+        // accesses, the second of which is sequential
+        let cycles = match bus_width(region) {
+            BusWidth::Sixteen if size == 4 => cycles + self.sequential_cycles[region],
+            _ => cycles,
+        };
+        if !(ROM0A_REGION..=ROM2B_REGION).contains(&region) {
+            return cycles + self.vram_stall(address);
+        }
+
+        let cycles = match self.instruction_fetch {
+            true if self.prefetch.take(address, size) => 1,
+            true => cycles,
+            false => {
+                self.prefetch.flush();
+                cycles
+            }
+        };
+        self.cartridge_busy += cycles as u64;
+        cycles
+    }
+
+    fn vram_stall(&self, address: usize) -> CYCLES {
+        if address >> 24 != VRAM_REGION {
+            return 0;
+        }
+        self.vram_contention.stall_cycles(vram_offset(address))
+    }
+
     pub fn initialize_bios(&mut self, filename: String) -> Result<(), std::io::Error> {
         let mut index = 0;
         let mut bios_file = File::options().read(true).open(filename)?;
@@ -222,91 +521,102 @@ impl GBAMemory {
             self.rom[index] = u32::from_le_bytes(buffer.clone());
             index += 1;
         }
+        let rom_bytes: Vec<u8> = self.rom[..index]
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect();
+        self.backup.detect(&rom_bytes);
 
         Ok(())
     }
+
+    fn eeprom_selected(&self) -> bool {
+        matches!(
+            self.backup.backup_type(),
+            BackupType::Eeprom512 | BackupType::Eeprom8k
+        )
+    }
+
+    /// The EEPROM, sized by the forced backup type if there is one.
+    fn eeprom(&mut self) -> &mut Eeprom {
+        if let Some(forced @ (BackupType::Eeprom512 | BackupType::Eeprom8k)) = self.backup.forced {
+            self.eeprom.set_size(forced.size());
+        }
+        &mut self.eeprom
+    }
 }
 
 const EX_WRAM_MIRROR_MASK: usize = 0x3FFFF;
 const IW_WRAM_MIRROR_MASK: usize = 0x7FFF;
 const BGRAM_MIRROR_MASK: usize = 0x3FF;
 const OAM_MIRROR_MASK: usize = 0x3FF;
+const SRAM_MIRROR_MASK: usize = 0x7FFF;
+const UNMAPPED_REGION: usize = 0x1;
+const VRAM_MIRROR_MASK: usize = 0x1FFFF;
+
+/// VRAM repeats every 128KB, and the 32KB past its 96KB in each repeat
+/// mirrors the OBJ tiles at 0x10000-0x17FFF.
+fn vram_offset(address: usize) -> usize {
+    let offset = address & VRAM_MIRROR_MASK;
+    if offset >= VRAM_SIZE {
+        offset - 0x8000
+    } else {
+        offset
+    }
+}
+
+/// Where a write to work RAM lands without its mirroring, as code that
+/// runs from there is fetched from the first copy.
+fn unmirrored_ram_address(address: usize) -> Option<usize> {
+    match address >> 24 {
+        EXWRAM_REGION => Some(EXWRAM_REGION << 24 | address & EX_WRAM_MIRROR_MASK),
+        IWRAM_REGION => Some(IWRAM_REGION << 24 | address & IW_WRAM_MIRROR_MASK),
+        _ => None,
+    }
+}
 
 impl MemoryBusNoPanic for GBAMemory {
-    fn try_read(&self, address: usize) -> Result<MemoryFetch<u8>, MemoryError> {
-        let region = address >> 24;
-        let data = match region {
-            BIOS_REGION => memory_load(&self.bios, address).to_le_bytes()[address & 0b11],
-            EXWRAM_REGION => memory_load(&self.exwram, address & EX_WRAM_MIRROR_MASK).to_le_bytes()
-                [address & 0b11],
-            IWRAM_REGION => memory_load(&self.iwram, address & IW_WRAM_MIRROR_MASK).to_le_bytes()
-                [address & 0b11],
+    fn try_read(&mut self, address: usize) -> Result<MemoryFetch<u8>, MemoryError> {
+        if let Some(word) = self.undriven_word(address) {
+            return Ok(MemoryFetch::new(word.to_le_bytes()[address & 0b11], 1));
+        }
+        let data = match address >> 24 {
             IORAM_REGION => self.io_readu8(address)?,
-            BGRAM_REGION => {
-                memory_load(&self.bgram, address & BGRAM_MIRROR_MASK).to_le_bytes()[address & 0b11]
-            }
-            VRAM_REGION => {
-                memory_load(&self.vram, address & 0xFFFFFF).to_le_bytes()[address & 0b11]
-            }
-            OAM_REGION => {
-                memory_load(&self.oam, address & OAM_MIRROR_MASK).to_le_bytes()[address & 0b11]
-            }
-            ROM0A_REGION..=ROM2B_REGION => {
-                memory_load(&self.rom, address & 0xFFFFFF).to_le_bytes()[address & 0b11]
-            }
-            SRAM_REGION => {
-                memory_load(&self.sram, address & 0xFFFFFF).to_le_bytes()[address & 0b11]
-            }
-            _ => return Err(MemoryError::ReadError(address)),
+            _ => self.load_word(address)?.to_le_bytes()[address & 0b11],
         };
 
-        Ok(MemoryFetch::new(data, self.wait_cycles_u16[region]))
+        Ok(MemoryFetch::new(data, self.access_cycles(address, 1)))
     }
 
-    fn try_readu16(&self, address: usize) -> Result<MemoryFetch<u16>, MemoryError> {
+    fn try_readu16(&mut self, address: usize) -> Result<MemoryFetch<u16>, MemoryError> {
         let region = address >> 24;
+        if let Some(word) = self.undriven_word(address) {
+            return Ok(MemoryFetch::new((word >> (16 * ((address >> 1) & 0x1))) as u16, 1));
+        }
+        if region == ROM2B_REGION && self.eeprom_selected() {
+            let bit = self.eeprom().read_bit();
+            return Ok(MemoryFetch::new(bit, self.access_cycles(address, 2)));
+        }
         let data = match region {
-            BIOS_REGION => memory_load(&self.bios, address),
-            EXWRAM_REGION => memory_load(&self.exwram, address & EX_WRAM_MIRROR_MASK),
-            IWRAM_REGION => memory_load(&self.iwram, address & IW_WRAM_MIRROR_MASK),
-            IORAM_REGION => {
-                return Ok(MemoryFetch {
-                    data: self.io_readu16(address)?,
-                    cycles: self.wait_cycles_u16[region],
-                })
-            }
-            BGRAM_REGION => memory_load(&self.bgram, address & BGRAM_MIRROR_MASK),
-            VRAM_REGION => memory_load(&self.vram, address & 0xFFFFFF),
-            OAM_REGION => memory_load(&self.oam, address & OAM_MIRROR_MASK),
-            ROM0A_REGION..=ROM2B_REGION => memory_load(&self.rom, address & 0xFFFFFF),
-            SRAM_REGION => memory_load(&self.sram, address & 0xFFFFFF),
-            _ => return Err(MemoryError::ReadError(address)),
+            IORAM_REGION => self.io_readu16(address)?,
+            _ => (self.load_word(address)? >> (16 * ((address >> 1) & 0x1))) as u16,
         };
 
-        let shift_amount = 16 * ((address >> 1) & 0x1);
-        let data = data >> shift_amount;
-
-        Ok(MemoryFetch::new(data as u16, self.wait_cycles_u16[region]))
+        Ok(MemoryFetch::new(data, self.access_cycles(address, 2)))
     }
 
-    fn try_readu32(&self, address: usize) -> Result<MemoryFetch<u32>, MemoryError> {
-        let region = address >> 24;
-        let data = match region {
-            BIOS_REGION => memory_load(&self.bios, address),
-            EXWRAM_REGION => memory_load(&self.exwram, address & EX_WRAM_MIRROR_MASK),
-            IWRAM_REGION => memory_load(&self.iwram, address & IW_WRAM_MIRROR_MASK),
+    fn try_readu32(&mut self, address: usize) -> Result<MemoryFetch<u32>, MemoryError> {
+        if let Some(word) = self.undriven_word(address) {
+            return Ok(MemoryFetch::new(word.rotate_right(8 * (address as u32 & 0b11)), 1));
+        }
+        let data = match address >> 24 {
             IORAM_REGION => self.io_readu32(address)?,
-            BGRAM_REGION => memory_load(&self.bgram, address & BGRAM_MIRROR_MASK),
-            VRAM_REGION => memory_load(&self.vram, address & 0xFFFFFF),
-            OAM_REGION => memory_load(&self.oam, address & OAM_MIRROR_MASK),
-            ROM0A_REGION..=ROM2B_REGION => memory_load(&self.rom, address & 0xFFFFFF),
-            SRAM_REGION => memory_load(&self.sram, address & 0xFFFFFF),
-            _ => return Err(MemoryError::ReadError(address)),
+            _ => self.load_word(address)?,
         };
 
         Ok(MemoryFetch::new(
             data.rotate_right(8 * (address as u32 & 0b11)),
-            self.wait_cycles_u32[region],
+            self.access_cycles(address, 4),
         ))
     }
 
@@ -339,10 +649,11 @@ impl MemoryBusNoPanic for GBAMemory {
                 memory_store(&mut self.bgram, mirror_masked_address, value);
             }
             VRAM_REGION => {
-                let mut current_value = memory_load(&self.vram, address & 0xFFFFFF);
+                let mirror_masked_address = vram_offset(address);
+                let mut current_value = memory_load(&self.vram, mirror_masked_address);
                 current_value &= !(0xFF << 8 * (address & 0b11));
                 let value = current_value | ((value as u32) << (8 * (address & 0b11)));
-                memory_store(&mut self.vram, address & 0xFFFFFF, value);
+                memory_store(&mut self.vram, mirror_masked_address, value);
             }
             OAM_REGION => {
                 let mirror_masked_address = address & OAM_MIRROR_MASK;
@@ -352,17 +663,22 @@ impl MemoryBusNoPanic for GBAMemory {
                     current_value | ((value as u32) << (8 * (mirror_masked_address & 0b11)));
                 memory_store(&mut self.oam, mirror_masked_address, value);
             }
-            ROM0A_REGION..=ROM2B_REGION => {}
-            SRAM_REGION => {
-                let mut current_value = memory_load(&self.sram, address & 0xFFFFFF);
+            ROM0A_REGION..=ROM2B_REGION => self.rom_write_guard.check(address, value as u32)?,
+            SRAM_REGION | SRAM_MIRROR_REGION => {
+                let mut current_value = memory_load(&self.sram, address & SRAM_MIRROR_MASK);
                 current_value &= !(0xFF << 8 * (address & 0b11));
                 let value = current_value | ((value as u32) << (8 * (address & 0b11)));
-                memory_store(&mut self.sram, address & 0xFFFFFF, value);
+                memory_store(&mut self.sram, address & SRAM_MIRROR_MASK, value);
+                self.sram_dirty = true;
             }
             _ => return Err(MemoryError::WriteError(address, value as u32)),
         };
 
-        Ok(self.wait_cycles_u16[region])
+        if let Some(code_address) = unmirrored_ram_address(address) {
+            self.code_writes.record(code_address, 1);
+        }
+
+        Ok(self.access_cycles(address, 1))
     }
 
     fn try_writeu16(&mut self, address: usize, value: u16) -> Result<CYCLES, MemoryError> {
@@ -392,10 +708,11 @@ impl MemoryBusNoPanic for GBAMemory {
                 memory_store(&mut self.bgram, mirror_masked_address & 0xFFFFFF, value);
             }
             VRAM_REGION => {
-                let mut current_value = memory_load(&self.vram, address & 0xFFFFFE);
+                let mirror_masked_address = vram_offset(address);
+                let mut current_value = memory_load(&self.vram, mirror_masked_address);
                 current_value &= !(0xFFFFu32 << (16 * ((address >> 1) & 0b1)));
                 let value = current_value | ((value as u32) << (16 * ((address >> 1) & 0b1)));
-                memory_store(&mut self.vram, address & 0xFFFFFF, value);
+                memory_store(&mut self.vram, mirror_masked_address, value);
             }
             OAM_REGION => {
                 let mirror_masked_address = address & OAM_MIRROR_MASK;
@@ -404,17 +721,23 @@ impl MemoryBusNoPanic for GBAMemory {
                 let value = current_value | ((value as u32) << (16 * ((mirror_masked_address >> 1) & 0b1)));
                 memory_store(&mut self.oam, mirror_masked_address & 0xFFFFFF, value);
             }
-            ROM0A_REGION..=ROM2B_REGION => {}
-            SRAM_REGION => {
-                let mut current_value = memory_load(&self.sram, address & 0xFFFFFE);
+            ROM2B_REGION if self.eeprom_selected() => self.eeprom().write_bit(value),
+            ROM0A_REGION..=ROM2B_REGION => self.rom_write_guard.check(address, value as u32)?,
+            SRAM_REGION | SRAM_MIRROR_REGION => {
+                let mut current_value = memory_load(&self.sram, address & SRAM_MIRROR_MASK);
                 current_value &= !(0xFFFFu32 << (16 * ((address >> 1) & 0b1)));
                 let value = current_value | ((value as u32) << (16 * ((address >> 1) & 0b1)));
-                memory_store(&mut self.sram, address & 0xFFFFFF, value);
+                memory_store(&mut self.sram, address & SRAM_MIRROR_MASK, value);
+                self.sram_dirty = true;
             }
             _ => return Err(MemoryError::WriteError(address, value as u32)),
         };
 
-        Ok(self.wait_cycles_u16[region])
+        if let Some(code_address) = unmirrored_ram_address(address) {
+            self.code_writes.record(code_address, 2);
+        }
+
+        Ok(self.access_cycles(address, 2))
     }
 
     fn try_writeu32(&mut self, address: usize, value: u32) -> Result<CYCLES, MemoryError> {
@@ -435,36 +758,53 @@ impl MemoryBusNoPanic for GBAMemory {
                 memory_store(&mut self.bgram, mirror_masked_address & 0xFFFFFF, value);
             }
             VRAM_REGION => {
-                memory_store(&mut self.vram, address & 0xFFFFFF, value);
+                memory_store(&mut self.vram, vram_offset(address), value);
             }
             OAM_REGION => {
                 let mirror_masked_address = address & OAM_MIRROR_MASK;
                 memory_store(&mut self.oam, mirror_masked_address & 0xFFFFFF, value);
             }
-            ROM0A_REGION..=ROM2B_REGION => {}
-            SRAM_REGION => {
-                memory_store(&mut self.sram, address & 0xFFFFFF, value);
+            ROM0A_REGION..=ROM2B_REGION => self.rom_write_guard.check(address, value)?,
+            SRAM_REGION | SRAM_MIRROR_REGION => {
+                memory_store(&mut self.sram, address & SRAM_MIRROR_MASK, value);
+                self.sram_dirty = true;
             }
             _ => return Err(MemoryError::WriteError(address, value as u32)),
         };
 
-        Ok(self.wait_cycles_u32[region])
+        if let Some(code_address) = unmirrored_ram_address(address) {
+            self.code_writes.record(code_address, 4);
+        }
+
+        Ok(self.access_cycles(address, 4))
     }
 }
 
 impl MemoryBus for GBAMemory {
-    fn read(&self, address: usize) -> MemoryFetch<u8> {
+    fn read(&mut self, address: usize) -> MemoryFetch<u8> {
         self.try_read(address).unwrap()
     }
 
-    fn readu16(&self, address: usize) -> MemoryFetch<u16> {
+    fn readu16(&mut self, address: usize) -> MemoryFetch<u16> {
         self.try_readu16(address).unwrap()
     }
 
-    fn readu32(&self, address: usize) -> MemoryFetch<u32> {
+    fn readu32(&mut self, address: usize) -> MemoryFetch<u32> {
         self.try_readu32(address).unwrap()
     }
 
+    fn peek(&self, address: usize) -> u8 {
+        self.peek_word(address).to_le_bytes()[address & 0b11]
+    }
+
+    fn peeku16(&self, address: usize) -> u16 {
+        (self.peek_word(address) >> (16 * ((address >> 1) & 0x1))) as u16
+    }
+
+    fn peeku32(&self, address: usize) -> u32 {
+        self.peek_word(address).rotate_right(8 * (address as u32 & 0b11))
+    }
+
     fn write(&mut self, address: usize, value: u8) -> CYCLES {
         self.try_write(address, value).unwrap()
     }
@@ -480,14 +820,277 @@ impl MemoryBus for GBAMemory {
     fn ppu_io_write(&mut self, address: usize, value: u16) {
         self.ioram[(address & 0xFFF) >> 1] = value;
     }
+
+    fn ppu_io_read(&self, address: usize) -> u16 {
+        io_load(&self.ioram, address & 0xFFF)
+    }
+
+    fn io_trace(&self) -> &RefCell<IOTrace> {
+        &self.io_trace
+    }
+
+    fn take_halt_request(&mut self) -> Option<HaltMode> {
+        self.halt_request.take()
+    }
+
+    fn take_register_changes(&mut self) -> RegisterChanges {
+        std::mem::take(&mut self.register_changes)
+    }
+
+    fn rom_write_guard(&mut self) -> &mut RomWriteGuard {
+        &mut self.rom_write_guard
+    }
+
+    fn vram_contention(&mut self) -> &mut VramContention {
+        &mut self.vram_contention
+    }
+
+    fn backup_config(&mut self) -> &mut BackupConfig {
+        &mut self.backup
+    }
+
+    fn load_save(&mut self, path: &Path) -> Result<(), std::io::Error> {
+        let save = fs::read(path)?;
+        if self.eeprom_selected() {
+            self.eeprom.load(&save);
+            return Ok(());
+        }
+        self.sram.fill(0);
+        for (word, bytes) in self.sram.iter_mut().zip(save.chunks(4)) {
+            let mut buffer = [0; 4];
+            buffer[..bytes.len()].copy_from_slice(bytes);
+            *word = u32::from_le_bytes(buffer);
+        }
+        self.sram_dirty = false;
+        Ok(())
+    }
+
+    fn save_to(&mut self, path: &Path) -> Result<(), std::io::Error> {
+        if self.eeprom_selected() {
+            let eeprom = &mut self.eeprom;
+            if eeprom.take_dirty() {
+                fs::write(path, eeprom.bytes())?;
+            }
+            return Ok(());
+        }
+        if !self.sram_dirty {
+            return Ok(());
+        }
+        let save: Vec<u8> = self.sram.iter().flat_map(|word| word.to_le_bytes()).collect();
+        fs::write(path, save)?;
+        self.sram_dirty = false;
+        Ok(())
+    }
+
+    fn sound_fifos(&mut self) -> &mut [SoundFifo; 2] {
+        &mut self.sound_fifos
+    }
+
+    fn timer_readout(&mut self) -> &mut TimerReadout {
+        &mut self.timer_readout
+    }
+
+    fn code_writes(&mut self) -> &mut CodeWrites {
+        &mut self.code_writes
+    }
+
+    fn region_snapshot(&self, region: MemoryRegion) -> Vec<u8> {
+        let words = match region {
+            MemoryRegion::EWRAM => &self.exwram,
+            MemoryRegion::IWRAM => &self.iwram,
+            MemoryRegion::IO => {
+                return self.ioram.iter().flat_map(|hword| hword.to_le_bytes()).collect();
+            }
+            MemoryRegion::Palette => &self.bgram,
+            MemoryRegion::VRAM => &self.vram,
+            MemoryRegion::OAM => &self.oam,
+        };
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    fn restore_region(&mut self, region: MemoryRegion, bytes: &[u8]) {
+        let words = match region {
+            MemoryRegion::EWRAM => &mut self.exwram,
+            MemoryRegion::IWRAM => &mut self.iwram,
+            MemoryRegion::IO => {
+                for (hword, bytes) in self.ioram.iter_mut().zip(bytes.chunks_exact(2)) {
+                    *hword = u16::from_le_bytes([bytes[0], bytes[1]]);
+                }
+                self.configure_wait_states();
+                return;
+            }
+            MemoryRegion::Palette => &mut self.bgram,
+            MemoryRegion::VRAM => &mut self.vram,
+            MemoryRegion::OAM => &mut self.oam,
+        };
+        for (word, bytes) in words.iter_mut().zip(bytes.chunks_exact(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+    }
+
+    fn bus_state(&self) -> BusState {
+        BusState {
+            sram: self.sram.iter().flat_map(|word| word.to_le_bytes()).collect(),
+            eeprom: self.eeprom.clone(),
+            sound_fifos: self.sound_fifos.clone(),
+            prefetch: self.prefetch.clone(),
+            next_sequential: self.next_sequential,
+            cartridge_busy: self.cartridge_busy,
+            open_bus: self.open_bus,
+            executing_bios: self.executing_bios,
+            bios_opcode: self.bios_opcode,
+            halt_request: self.halt_request,
+        }
+    }
+
+    fn restore_bus_state(&mut self, state: BusState) {
+        for (word, bytes) in self.sram.iter_mut().zip(state.sram.chunks_exact(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        // like the EEPROM, the contents needn't match the save file now
+        self.sram_dirty = true;
+        self.eeprom = state.eeprom;
+        self.sound_fifos = state.sound_fifos;
+        self.prefetch = state.prefetch;
+        self.next_sequential = state.next_sequential;
+        self.cartridge_busy = state.cartridge_busy;
+        self.open_bus = state.open_bus;
+        self.executing_bios = state.executing_bios;
+        self.bios_opcode = state.bios_opcode;
+        self.halt_request = state.halt_request;
+    }
+
+    fn fetch_u32(&mut self, address: usize) -> MemoryFetch<u32> {
+        self.begin_fetch(address);
+        let memory_fetch = self.readu32(address);
+        self.instruction_fetch = false;
+        self.open_bus = memory_fetch.data;
+        memory_fetch
+    }
+
+    fn fetch_u16(&mut self, address: usize) -> MemoryFetch<u16> {
+        self.begin_fetch(address);
+        let memory_fetch = self.readu16(address);
+        self.instruction_fetch = false;
+        let value = memory_fetch.data as WORD;
+        self.open_bus = match bus_width(address >> 24) {
+            // a 32 bit bus only drives the half that was fetched, the other
+            // keeps whatever was last on it
+            BusWidth::ThirtyTwo if address & 0b10 == 0 => (self.open_bus & 0xFFFF_0000) | value,
+            BusWidth::ThirtyTwo => (self.open_bus & 0x0000_FFFF) | (value << 16),
+            _ => value | (value << 16),
+        };
+        memory_fetch
+    }
+
+    fn run_prefetcher(&mut self, elapsed: u64) {
+        let idle_cycles = elapsed.saturating_sub(std::mem::take(&mut self.cartridge_busy));
+        if let Some(head) = self.prefetch.head() {
+            self.prefetch.run(idle_cycles, self.sequential_cycles[head >> 24]);
+        }
+    }
+
+    fn patch_rom(&mut self, address: usize, value: u16) {
+        let offset = address & 0xFFFFFE;
+        let shift = 16 * ((offset >> 1) & 0b1);
+        let mut current_value = memory_load(&self.rom, offset);
+        current_value &= !(0xFFFFu32 << shift);
+        memory_store(&mut self.rom, offset, current_value | (value as u32) << shift);
+        self.code_writes.record(address, 2);
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::memory::memory::MemoryBus;
+    use crate::{
+        arm7tdmi::cpu::InstructionMode,
+        gba::GBA,
+        memory::{backup::BackupType, memory::MemoryBus},
+        types::CYCLES,
+        utils::testing::load_arm_program,
+    };
+    use rstest::rstest;
 
     use super::GBAMemory;
 
+    #[test]
+    fn unmapped_reads_return_the_last_fetched_instruction() {
+        let mut memory = GBAMemory::new();
+        memory.writeu32(0x3000000, 0x1234_5678);
+        memory.fetch_u32(0x3000000);
+
+        assert_eq!(memory.readu32(0x1000000).data, 0x1234_5678);
+        assert_eq!(memory.readu16(0x1000002).data, 0x1234);
+        assert_eq!(memory.read(0x10000001).data, 0x56);
+    }
+
+    #[test]
+    fn writes_through_ram_mirrors_are_recorded_unmirrored() {
+        let mut memory = GBAMemory::new();
+        memory.writeu32(0x3008000, 0);
+        memory.writeu16(0x2040004, 0);
+
+        let ranges: Vec<_> = memory.code_writes().drain().collect();
+        assert_eq!(ranges, [0x3000000..0x3000004, 0x2000004..0x2000006]);
+    }
+
+    #[test]
+    fn bios_reads_from_outside_return_the_last_bios_opcode() {
+        let mut memory = GBAMemory::new();
+        memory.bios[0] = 0xAAAA_AAAA;
+        memory.bios[0x10 >> 2] = 0xE3A0_0001;
+
+        memory.fetch_u32(0x10);
+        assert_eq!(memory.readu32(0x0).data, 0xAAAA_AAAA);
+
+        memory.fetch_u32(0x3000000);
+        assert_eq!(memory.readu32(0x0).data, 0xE3A0_0001);
+    }
+
+    #[test]
+    fn peeks_see_the_bios_from_outside_it() {
+        let mut memory = GBAMemory::new();
+        memory.bios[0] = 0xAAAA_AAAA;
+        memory.bios[0x10 >> 2] = 0xE3A0_0001;
+        memory.fetch_u32(0x10);
+        memory.fetch_u32(0x3000000);
+
+        assert_eq!(memory.peeku32(0x0), 0xAAAA_AAAA);
+        assert_eq!(memory.peeku16(0x2), 0xAAAA);
+        assert_eq!(memory.peek(0x12), 0xA0);
+    }
+
+    #[test]
+    fn arm_ldr_from_unmapped_memory_sees_the_prefetched_instruction() {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, 0x3000000, &[
+            0xe5910000, // ldr r0, [r1]
+            0xe1a00000, // nop
+            0xe3a02001, // mov r2, #1
+        ]);
+        gba.cpu.set_register(1, 0x1000000);
+
+        gba.step();
+
+        assert_eq!(gba.cpu.get_register(0), 0xe3a02001);
+    }
+
+    /// A Thumb fetch drives both halves of a 16 bit bus, but only its own
+    /// half of IWRAM's 32 bit bus, the other half keeps the previous fetch.
+    #[rstest]
+    #[case::iwram(0x3000000, 0x2101_2202)]
+    #[case::ewram(0x2000000, 0x2202_2202)]
+    fn thumb_fetches_set_the_open_bus_by_bus_width(#[case] base: usize, #[case] expected: u32) {
+        let mut memory = GBAMemory::new();
+        memory.writeu16(base + 2, 0x2101);
+        memory.writeu16(base + 4, 0x2202);
+
+        memory.fetch_u16(base + 2);
+        memory.fetch_u16(base + 4);
+
+        assert_eq!(memory.readu32(0x1000000).data, expected);
+    }
+
     #[test]
     fn can_read_byte_from_bios() {
         let mut memory = GBAMemory::new();
@@ -594,6 +1197,84 @@ mod tests {
         }
     }
 
+    #[test]
+    fn sram_survives_a_save_and_reload() {
+        let path = std::env::temp_dir().join(format!("gba_sram_{}.sav", std::process::id()));
+        let mut memory = GBAMemory::new();
+        memory.write(0x0E000005, 0xAB);
+        memory.save_to(&path).unwrap();
+
+        let mut reloaded = GBAMemory::new();
+        reloaded.load_save(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(reloaded.read(0x0E000005).data, 0xAB);
+        assert_eq!(reloaded.read(0x0E008005).data, 0xAB); // mirrored every 32KB
+    }
+
+    #[test]
+    fn eeprom_is_driven_through_the_0d_region_and_saved() {
+        let path = std::env::temp_dir().join(format!("gba_eeprom_{}.sav", std::process::id()));
+        let mut memory = GBAMemory::new();
+        memory.backup_config().forced = Some(BackupType::Eeprom512);
+        let send = |memory: &mut GBAMemory, bits: &[u16]| {
+            for (i, bit) in bits.iter().enumerate() {
+                memory.writeu16(0x0D000000 + i * 2, *bit);
+            }
+        };
+        // write 0xFF00FF00FF00FF00 to block 1
+        let block = (0..64).map(|bit| (bit / 8 % 2 == 0) as u16);
+        let write: Vec<u16> = [1, 0, 0, 0, 0, 0, 0, 1].into_iter().chain(block).chain([0]).collect();
+        send(&mut memory, &write);
+        assert_eq!(memory.readu16(0x0D000000).data, 1);
+        memory.save_to(&path).unwrap();
+
+        let mut reloaded = GBAMemory::new();
+        reloaded.backup_config().forced = Some(BackupType::Eeprom512);
+        reloaded.load_save(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        send(&mut reloaded, &[1, 1, 0, 0, 0, 0, 0, 1, 0]);
+        let stream: Vec<u16> = (0..68).map(|i| reloaded.readu16(0x0D000000 + i * 2).data).collect();
+
+        assert_eq!(stream[4..12], [1; 8]);
+        assert_eq!(stream[12..20], [0; 8]);
+    }
+
+    #[test]
+    fn peeks_leave_a_pending_eeprom_read_alone() {
+        let mut memory = GBAMemory::new();
+        memory.backup_config().forced = Some(BackupType::Eeprom512);
+        let send = |memory: &mut GBAMemory, bits: &[u16]| {
+            for (i, bit) in bits.iter().enumerate() {
+                memory.writeu16(0x0D000000 + i * 2, *bit);
+            }
+        };
+        // write 0xFF00FF00FF00FF00 to block 1, then request it back
+        let block = (0..64).map(|bit| (bit / 8 % 2 == 0) as u16);
+        let write: Vec<u16> = [1, 0, 0, 0, 0, 0, 0, 1].into_iter().chain(block).chain([0]).collect();
+        send(&mut memory, &write);
+        memory.readu16(0x0D000000);
+        send(&mut memory, &[1, 1, 0, 0, 0, 0, 0, 1, 0]);
+
+        for i in 0..68 {
+            memory.peeku16(0x0D000000 + i * 2);
+        }
+        let stream: Vec<u16> = (0..68).map(|i| memory.readu16(0x0D000000 + i * 2).data).collect();
+
+        assert_eq!(stream[4..12], [1; 8]);
+        assert_eq!(stream[12..20], [0; 8]);
+    }
+
+    #[test]
+    fn unchanged_sram_is_not_saved() {
+        let path = std::env::temp_dir().join(format!("gba_sram_clean_{}.sav", std::process::id()));
+        let mut memory = GBAMemory::new();
+
+        memory.save_to(&path).unwrap();
+
+        assert!(!path.exists());
+    }
+
     #[test]
     fn can_read_byte_from_sram() {
         let mut memory = GBAMemory::new();
@@ -682,4 +1363,197 @@ mod tests {
         assert_eq!(cycles, 6);
         assert_eq!(fetch.data, 0xabcdef12);
     }
+
+    #[test]
+    fn word_access_costs_twice_a_halfword_on_ewram() {
+        let mut memory = GBAMemory::new();
+
+        let byte_cycles = memory.read(0x2000000).cycles;
+        let hword_cycles = memory.readu16(0x2000000).cycles;
+        let word_cycles = memory.readu32(0x2000000).cycles;
+
+        assert_eq!(byte_cycles, hword_cycles);
+        assert_eq!(word_cycles, 2 * hword_cycles);
+    }
+
+    #[rstest]
+    #[case(0x3000000)]
+    #[case(0x7000000)]
+    fn word_access_costs_the_same_as_a_halfword_on_32_bit_bus(#[case] address: usize) {
+        let mut memory = GBAMemory::new();
+
+        assert_eq!(memory.readu32(address).cycles, memory.readu16(address).cycles);
+        assert_eq!(memory.writeu32(address, 0), memory.writeu16(address, 0));
+        assert_eq!(memory.readu32(address).cycles, 1);
+    }
+
+    #[test]
+    fn ewram_word_access_is_slower_than_iwram_and_oam() {
+        let mut memory = GBAMemory::new();
+
+        let ewram_cycles = memory.writeu32(0x2000000, 0);
+        let iwram_cycles = memory.writeu32(0x3000000, 0);
+        let oam_cycles = memory.writeu32(0x7000000, 0);
+
+        assert_eq!(ewram_cycles, 6);
+        assert!(ewram_cycles > iwram_cycles);
+        assert!(ewram_cycles > oam_cycles);
+    }
+
+    #[rstest]
+    #[case::obj_tiles_mirror(0x6018000, 0x6010000)]
+    #[case::end_of_obj_tiles_mirror(0x601FFFC, 0x6017FFC)]
+    #[case::next_128kb(0x6020000, 0x6000000)]
+    #[case::obj_tiles_mirror_in_next_128kb(0x6038004, 0x6010004)]
+    fn vram_mirror_writes_are_visible_at_the_base_address(
+        #[case] mirror: usize,
+        #[case] base: usize,
+    ) {
+        let mut memory = GBAMemory::new();
+
+        memory.writeu32(mirror, 0x1234_5678);
+        assert_eq!(memory.readu32(base).data, 0x1234_5678);
+
+        memory.writeu16(mirror + 2, 0xBEEF);
+        assert_eq!(memory.readu32(base).data, 0xBEEF_5678);
+        assert_eq!(memory.readu16(mirror + 2).data, 0xBEEF);
+    }
+
+    #[rstest]
+    #[case::reset(0x0000, 5, 3)]
+    #[case::ws0_3_1(0x0014, 4, 2)]
+    #[case::ws0_2_1(0x0018, 3, 2)]
+    #[case::ws0_8_2(0x000C, 9, 3)]
+    fn waitcnt_sets_the_rom_access_timing(
+        #[case] waitcnt: u16,
+        #[case] nonsequential: CYCLES,
+        #[case] sequential: CYCLES,
+    ) {
+        let mut memory = GBAMemory::new();
+        memory.writeu16(0x4000204, waitcnt);
+
+        assert_eq!(memory.readu16(0x8000100).cycles, nonsequential);
+        assert_eq!(memory.readu16(0x8000102).cycles, sequential);
+        assert_eq!(memory.readu32(0x8000104).cycles, 2 * sequential);
+        assert_eq!(memory.readu32(0x8000000).cycles, nonsequential + sequential);
+    }
+
+    #[test]
+    fn rom_accesses_are_only_sequential_in_the_same_region() {
+        let mut memory = GBAMemory::new();
+        // WS1 sequential accesses take 1 wait state
+        memory.writeu16(0x4000204, 0x0080);
+
+        memory.readu16(0x8000100);
+        assert_eq!(memory.readu16(0x0A000102).cycles, 5);
+        assert_eq!(memory.readu16(0x0A000104).cycles, 2);
+        memory.readu32(0x3000000);
+        assert_eq!(memory.readu16(0x0A000106).cycles, 5);
+        assert_eq!(memory.read(0x0A000108).cycles, 5);
+        assert_eq!(memory.read(0x0A000109).cycles, 5);
+    }
+
+    #[test]
+    fn ppu_and_debugger_reads_leave_the_sequential_timing_alone() {
+        let mut gba = GBA::new_no_bios();
+        gba.memory.writeu16(0x4000000, 0x0100);
+
+        gba.memory.readu16(0x8000100);
+        gba.ppu.render_scanline(0, gba.memory.as_ref());
+        gba.memory.peeku32(0x8001000);
+
+        assert_eq!(gba.memory.readu16(0x8000102).cycles, 3);
+    }
+
+    #[rstest]
+    #[case(0x0000, 5)]
+    #[case(0x0003, 9)]
+    fn sram_timing_follows_waitcnt(#[case] waitcnt: u16, #[case] cycles: CYCLES) {
+        let mut memory = GBAMemory::new();
+        memory.write(0x4000204, waitcnt as u8);
+
+        assert_eq!(memory.read(0x0E000000).cycles, cycles);
+        assert_eq!(memory.read(0x0E000001).cycles, cycles);
+    }
+
+    #[test]
+    fn sram_is_mirrored_at_0x0f000000() {
+        let mut memory = GBAMemory::new();
+        memory.write(0x0E000010, 0x5A);
+
+        assert_eq!(memory.read(0x0F000010).data, 0x5A);
+        assert_eq!(memory.readu32(0x0F000000).data, 0);
+        memory.write(0x0F000020, 0xA5);
+        assert_eq!(memory.read(0x0E000020).data, 0xA5);
+    }
+
+    #[rstest]
+    #[case::disabled(0x0000, 3)]
+    #[case::enabled(0x4000, 1)]
+    fn prefetched_fetches_take_a_cycle(#[case] waitcnt: u16, #[case] buffered_cycles: CYCLES) {
+        let mut memory = GBAMemory::new();
+        memory.writeu16(0x4000204, waitcnt);
+
+        assert_eq!(memory.fetch_u16(0x8000000).cycles, 5);
+        // 6 idle cycles read ahead two halfwords at 3 cycles each
+        memory.run_prefetcher(5 + 6);
+        assert_eq!(memory.fetch_u16(0x8000002).cycles, buffered_cycles);
+        assert_eq!(memory.fetch_u16(0x8000004).cycles, buffered_cycles);
+        assert_eq!(memory.fetch_u16(0x8000006).cycles, 3);
+    }
+
+    #[test]
+    fn data_reads_from_rom_flush_the_prefetch_buffer() {
+        let mut memory = GBAMemory::new();
+        memory.writeu16(0x4000204, 0x4000);
+
+        memory.fetch_u16(0x8000000);
+        memory.run_prefetcher(5 + 6);
+        memory.readu16(0x8001000);
+
+        assert_eq!(memory.fetch_u16(0x8000002).cycles, 5);
+    }
+
+    #[test]
+    fn peeking_at_rom_leaves_the_prefetch_buffer_alone() {
+        let mut memory = GBAMemory::new();
+        memory.writeu16(0x4000204, 0x4000);
+
+        memory.fetch_u16(0x8000000);
+        memory.run_prefetcher(5);
+        memory.peeku16(0x8001000);
+        // the peek didn't keep the cartridge busy, so all 6 cycles are idle
+        memory.run_prefetcher(6);
+
+        assert_eq!(memory.fetch_u16(0x8000002).cycles, 1);
+        assert_eq!(memory.fetch_u16(0x8000004).cycles, 1);
+    }
+
+    #[test]
+    fn prefetch_speeds_up_a_run_of_multiplies_from_rom() {
+        let run = |waitcnt: u16| {
+            let mut gba = GBA::new_no_bios();
+            gba.memory.writeu16(0x4000204, waitcnt);
+            for i in 0..8 {
+                gba.memory.patch_rom(0x8000000 + i * 2, 0x4348); // mul r0, r1
+            }
+            gba.cpu.set_register(0, 0x1234_5678);
+            gba.cpu.set_register(1, 0x1234_5678);
+            gba.cpu.set_instruction_mode(InstructionMode::THUMB);
+            gba.cpu.set_pc(0x8000000);
+            gba.cpu.flush_pipeline(&mut gba.memory);
+            (0..6).map(|_| gba.step().cycles as u32).sum::<u32>()
+        };
+
+        assert!(run(0x4000) < run(0x0000));
+    }
+
+    #[rstest]
+    #[case(0x5000000)]
+    #[case(0x6000000)]
+    fn word_access_is_split_on_palette_and_vram(#[case] address: usize) {
+        let mut memory = GBAMemory::new();
+
+        assert_eq!(memory.readu32(address).cycles, 2 * memory.readu16(address).cycles);
+    }
 }
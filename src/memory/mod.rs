@@ -1,4 +1,14 @@
 pub mod memory;
+pub mod backup;
+pub mod code_writes;
+pub mod eeprom;
 pub mod io_handlers;
+pub mod io_report;
+pub mod io_trace;
+pub mod prefetch;
+pub mod rom_write_guard;
+pub mod sound_fifo;
+pub mod vram_contention;
 pub mod debugger_memory;
+pub mod dma;
 
@@ -0,0 +1,81 @@
+use crate::{
+    state::savestate::{StateError, StateReader, StateWriter},
+    types::CYCLES,
+};
+
+/// Halfwords the buffer holds before it stops reading ahead.
+const CAPACITY: usize = 8;
+
+/// The cartridge prefetcher, which reads the halfwords after the last
+/// instruction fetch while the cartridge bus is idle. Turned on by WAITCNT.
+#[derive(Clone, Default, Debug)]
+pub struct PrefetchBuffer {
+    pub enabled: bool,
+    /// Address of the first buffered halfword, while the prefetcher runs.
+    head: Option<usize>,
+    halfwords: usize,
+    /// Idle cycles spent towards the next halfword.
+    progress: u64,
+}
+
+impl PrefetchBuffer {
+    pub fn head(&self) -> Option<usize> {
+        self.head
+    }
+
+    pub fn flush(&mut self) {
+        *self = Self {
+            enabled: self.enabled,
+            ..Self::default()
+        };
+    }
+
+    /// Serves an instruction fetch of `size` bytes if it is already
+    /// buffered. Otherwise the fetch goes to the cartridge and the buffer
+    /// starts over from the instruction after it.
+    pub fn take(&mut self, address: usize, size: usize) -> bool {
+        let halfwords = size / 2;
+        if self.enabled && self.head == Some(address) && self.halfwords >= halfwords {
+            self.halfwords -= halfwords;
+            self.head = Some(address + size);
+            return true;
+        }
+        self.flush();
+        self.head = self.enabled.then_some(address + size);
+        false
+    }
+
+    /// Reads ahead for `idle_cycles`, each halfword taking a sequential
+    /// access of `sequential_cycles`.
+    pub fn run(&mut self, idle_cycles: u64, sequential_cycles: CYCLES) {
+        if self.head.is_none() || self.halfwords == CAPACITY {
+            return;
+        }
+        self.progress += idle_cycles;
+        let fetched = (self.progress / sequential_cycles as u64) as usize;
+        self.progress %= sequential_cycles as u64;
+        self.halfwords = (self.halfwords + fetched).min(CAPACITY);
+        if self.halfwords == CAPACITY {
+            self.progress = 0;
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.flag(self.enabled);
+        writer.optional_word(self.head.map(|head| head as u32));
+        writer.byte(self.halfwords as u8);
+        writer.u64(self.progress);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = reader.flag()?;
+        self.head = reader.optional_word()?.map(|head| head as usize);
+        let offset = reader.offset();
+        self.halfwords = match reader.byte()? as usize {
+            halfwords if halfwords <= CAPACITY => halfwords,
+            _ => return Err(StateError::InvalidValue { offset }),
+        };
+        self.progress = reader.u64()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+use std::{collections::VecDeque, fmt::Display};
+
+use super::memory::MemoryError;
+
+const MAX_LOGGED_WRITES: usize = 1_000;
+
+/// What to do when the CPU or a DMA writes to cartridge ROM.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RomWriteAction {
+    /// Drop the write, as hardware does.
+    #[default]
+    Ignore,
+    /// Drop the write and record it.
+    Log,
+    /// Fail the write with `MemoryError::RomWrite`, which stops the
+    /// debugger like any other memory error.
+    Break,
+}
+
+impl Display for RomWriteAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RomWriteAction::Ignore => "ignore".fmt(f),
+            RomWriteAction::Log => "log".fmt(f),
+            RomWriteAction::Break => "break".fmt(f),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RomWrite {
+    pub address: usize,
+    pub value: u32,
+}
+
+impl Display for RomWrite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#010X} <- {:#X}", self.address, self.value)
+    }
+}
+
+/// Catches accidental writes to read-only cartridge ROM.
+#[derive(Default, Debug)]
+pub struct RomWriteGuard {
+    pub action: RomWriteAction,
+    writes: VecDeque<RomWrite>,
+}
+
+impl RomWriteGuard {
+    /// Applies the configured action to a write that the ROM ignores.
+    pub fn check(&mut self, address: usize, value: u32) -> Result<(), MemoryError> {
+        match self.action {
+            RomWriteAction::Ignore => Ok(()),
+            RomWriteAction::Log => {
+                if self.writes.len() >= MAX_LOGGED_WRITES {
+                    self.writes.pop_front();
+                }
+                self.writes.push_back(RomWrite { address, value });
+                Ok(())
+            }
+            RomWriteAction::Break => Err(MemoryError::RomWrite(address, value)),
+        }
+    }
+
+    /// Logged writes, oldest first.
+    pub fn writes(&self) -> impl Iterator<Item = &RomWrite> {
+        self.writes.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.writes.clear();
+    }
+}
+
+#[cfg(test)]
+mod rom_write_guard_tests {
+    use crate::memory::memory::{GBAMemory, MemoryBus, MemoryBusNoPanic, MemoryError};
+
+    use super::{RomWrite, RomWriteAction};
+
+    const ROM_ADDRESS: usize = 0x8000100;
+
+    #[test]
+    fn rom_writes_are_silently_ignored_by_default() {
+        let mut memory = GBAMemory::new();
+
+        assert!(memory.try_writeu32(ROM_ADDRESS, 0x1234).is_ok());
+
+        assert_eq!(memory.readu32(ROM_ADDRESS).data, 0);
+        assert_eq!(memory.rom_write_guard().writes().count(), 0);
+    }
+
+    #[test]
+    fn logged_rom_writes_are_recorded_and_dropped() {
+        let mut memory = GBAMemory::new();
+        memory.rom_write_guard().action = RomWriteAction::Log;
+
+        memory.writeu16(ROM_ADDRESS, 0xBEEF);
+
+        assert_eq!(memory.readu16(ROM_ADDRESS).data, 0);
+        let writes: Vec<RomWrite> = memory.rom_write_guard().writes().copied().collect();
+        assert_eq!(
+            writes,
+            vec![RomWrite {
+                address: ROM_ADDRESS,
+                value: 0xBEEF
+            }]
+        );
+    }
+
+    #[test]
+    fn rom_writes_fail_when_set_to_break() {
+        let mut memory = GBAMemory::new();
+        memory.rom_write_guard().action = RomWriteAction::Break;
+
+        let result = memory.try_write(ROM_ADDRESS, 0x12);
+
+        assert!(matches!(result, Err(MemoryError::RomWrite(ROM_ADDRESS, 0x12))));
+        assert_eq!(memory.read(ROM_ADDRESS).data, 0);
+    }
+}
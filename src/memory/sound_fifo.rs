@@ -0,0 +1,54 @@
+use std::collections::VecDeque;
+
+use crate::state::savestate::{StateError, StateReader, StateWriter};
+
+/// Bytes a Direct Sound FIFO holds.
+const FIFO_CAPACITY: usize = 32;
+/// A FIFO asks its DMA channel for more once it is down to this many.
+const FIFO_REFILL_LEVEL: usize = 16;
+
+/// One of the two Direct Sound FIFOs, filled with signed 8-bit samples by
+/// writes to FIFO_A or FIFO_B and drained on timer overflows.
+#[derive(Clone, Debug, Default)]
+pub struct SoundFifo {
+    samples: VecDeque<i8>,
+}
+
+impl SoundFifo {
+    /// Queues the bytes of a write, lowest address first. Bytes written
+    /// while the FIFO is full are lost.
+    pub fn push(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if self.samples.len() < FIFO_CAPACITY {
+                self.samples.push_back(byte as i8);
+            }
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<i8> {
+        self.samples.pop_front()
+    }
+
+    pub fn needs_refill(&self) -> bool {
+        self.samples.len() <= FIFO_REFILL_LEVEL
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        let bytes: Vec<u8> = self.samples.iter().map(|&sample| sample as u8).collect();
+        writer.bytes(&bytes);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let offset = reader.offset();
+        let bytes = reader.bytes()?;
+        if bytes.len() > FIFO_CAPACITY {
+            return Err(StateError::InvalidValue { offset });
+        }
+        self.samples = bytes.iter().map(|&byte| byte as i8).collect();
+        Ok(())
+    }
+}
@@ -0,0 +1,28 @@
+use std::ops::Range;
+
+use crate::types::CYCLES;
+
+/// Stall for CPU or DMA accesses to the bitmap framebuffer while the PPU
+/// is fetching it during HDraw. Off by default, since it slows down every
+/// VRAM access.
+#[derive(Default, Debug)]
+pub struct VramContention {
+    pub enabled: bool,
+    /// VRAM offsets of the page being scanned out, if one is.
+    framebuffer: Option<Range<usize>>,
+}
+
+impl VramContention {
+    pub fn set_framebuffer(&mut self, framebuffer: Option<Range<usize>>) {
+        self.framebuffer = framebuffer;
+    }
+
+    /// The CPU waits one cycle for the PPU's fetch when both want the
+    /// framebuffer at once.
+    pub fn stall_cycles(&self, vram_offset: usize) -> CYCLES {
+        match &self.framebuffer {
+            Some(framebuffer) if self.enabled && framebuffer.contains(&vram_offset) => 1,
+            _ => 0,
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+/// Points in time where the hardware outside the CPU changes state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Event {
+    /// The PPU reaches HBlank, where it draws the line and HBlank DMAs run.
+    HBlank,
+    /// The PPU starts the next line, and VBlank when that is line 160.
+    LineEnd,
+    /// The first timer driven by the clock overflows.
+    TimerOverflow,
+    /// A DMA channel was enabled or disabled.
+    DmaStart,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct ScheduledEvent {
+    at: u64,
+    /// Events due on the same cycle fire in the order they were scheduled.
+    sequence: u64,
+    event: Event,
+}
+
+/// Keeps the system time in cycles and the events due in the future, so
+/// the rest of the hardware only has to run when one of them is reached.
+/// Each event is pending at most once.
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    now: u64,
+    sequence: u64,
+    events: BinaryHeap<Reverse<ScheduledEvent>>,
+}
+
+impl Scheduler {
+    /// A scheduler with no events pending whose clock reads `now`.
+    pub fn starting_at(now: u64) -> Self {
+        Self {
+            now,
+            ..Self::default()
+        }
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn advance(&mut self, cycles: u64) {
+        self.now += cycles;
+    }
+
+    /// Cycles from `*since` to now, moving `*since` up to now.
+    pub fn elapsed_since(&self, since: &mut u64) -> u64 {
+        let elapsed = self.now - *since;
+        *since = self.now;
+        elapsed
+    }
+
+    /// Schedules `event` `cycles` from now, replacing it if it's pending.
+    pub fn schedule(&mut self, event: Event, cycles: u64) {
+        let at = self.now + cycles;
+        if self.scheduled_at(event) == Some(at) {
+            return;
+        }
+        self.cancel(event);
+        self.sequence += 1;
+        self.events.push(Reverse(ScheduledEvent {
+            at,
+            sequence: self.sequence,
+            event,
+        }));
+    }
+
+    pub fn cancel(&mut self, event: Event) {
+        self.events.retain(|Reverse(scheduled)| scheduled.event != event);
+    }
+
+    pub fn scheduled_at(&self, event: Event) -> Option<u64> {
+        self.events
+            .iter()
+            .find(|Reverse(scheduled)| scheduled.event == event)
+            .map(|Reverse(scheduled)| scheduled.at)
+    }
+
+    pub fn cycles_until_next_event(&self) -> Option<u64> {
+        self.events
+            .peek()
+            .map(|Reverse(scheduled)| scheduled.at.saturating_sub(self.now))
+    }
+
+    /// Removes the earliest event that is due, returning it with the cycle
+    /// it was due on.
+    pub fn pop_due(&mut self) -> Option<(u64, Event)> {
+        let Reverse(next) = self.events.peek()?;
+        if next.at > self.now {
+            return None;
+        }
+        self.events.pop().map(|Reverse(scheduled)| (scheduled.at, scheduled.event))
+    }
+}
+
+#[cfg(test)]
+mod scheduler_tests {
+    use super::{Event, Scheduler};
+
+    #[test]
+    fn events_fire_in_time_order_once_due() {
+        let mut scheduler = Scheduler::default();
+        scheduler.schedule(Event::LineEnd, 1232);
+        scheduler.schedule(Event::HBlank, 960);
+        scheduler.schedule(Event::TimerOverflow, 1000);
+
+        scheduler.advance(959);
+        assert_eq!(scheduler.pop_due(), None);
+        assert_eq!(scheduler.cycles_until_next_event(), Some(1));
+
+        scheduler.advance(1041);
+        assert_eq!(scheduler.pop_due(), Some((960, Event::HBlank)));
+        assert_eq!(scheduler.pop_due(), Some((1000, Event::TimerOverflow)));
+        assert_eq!(scheduler.pop_due(), Some((1232, Event::LineEnd)));
+        assert_eq!(scheduler.pop_due(), None);
+    }
+
+    #[test]
+    fn rescheduling_replaces_the_pending_event() {
+        let mut scheduler = Scheduler::default();
+        scheduler.schedule(Event::TimerOverflow, 100);
+        scheduler.schedule(Event::DmaStart, 0);
+        scheduler.schedule(Event::TimerOverflow, 50);
+
+        assert_eq!(scheduler.scheduled_at(Event::TimerOverflow), Some(50));
+        scheduler.cancel(Event::DmaStart);
+        scheduler.advance(100);
+        assert_eq!(scheduler.pop_due(), Some((50, Event::TimerOverflow)));
+        assert_eq!(scheduler.pop_due(), None);
+    }
+}
@@ -0,0 +1,260 @@
+use std::{fmt::Display, fs};
+
+use crate::{arm7tdmi::cpu::CPUMode, types::WORD, utils::bits::Bits};
+
+use super::{CpuState, MachineState, MemoryRegion, RegionSnapshot};
+
+/// Upper byte of mGBA's version magic, the low bytes count format revisions.
+const MGBA_MAGIC: u32 = 0x0100_0000;
+const STATE_SIZE: usize = 0x61000;
+
+const GPRS: usize = 0x20;
+const CPSR: usize = 0x60;
+const SPSR: usize = 0x64;
+/// `int32_t bankedRegisters[6][7]`, r8-r14 for each bank.
+const BANKED_REGISTERS: usize = 0x70;
+const BANKED_SPSRS: usize = 0x118;
+
+/// mGBA's register banks, in the order of its banked register arrays.
+#[derive(Clone, Copy, PartialEq)]
+enum Bank {
+    User,
+    Fiq,
+    Irq,
+    Svc,
+    Abt,
+    Und,
+}
+
+impl Bank {
+    fn from_cpsr(cpsr: WORD) -> Self {
+        match (cpsr & 0x1F) as u8 {
+            x if x == CPUMode::FIQ as u8 => Bank::Fiq,
+            x if x == CPUMode::IRQ as u8 => Bank::Irq,
+            x if x == CPUMode::SVC as u8 => Bank::Svc,
+            x if x == CPUMode::ABT as u8 => Bank::Abt,
+            x if x == CPUMode::UND as u8 => Bank::Und,
+            _ => Bank::User,
+        }
+    }
+
+    /// Only FIQ has its own r8-r12, every other mode shares the user ones.
+    fn for_register(self, register: usize) -> Self {
+        if register < 13 && self != Bank::Fiq {
+            Bank::User
+        } else {
+            self
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MgbaImportError {
+    TooShort(usize),
+    UnknownVersion(u32),
+    CouldNotRead(String),
+}
+
+impl Display for MgbaImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MgbaImportError::TooShort(len) => {
+                write!(f, "State is {:#X} bytes, expected {:#X}", len, STATE_SIZE)
+            }
+            MgbaImportError::UnknownVersion(magic) => {
+                write!(f, "Not an mGBA state, magic is {:#010X}", magic)
+            }
+            MgbaImportError::CouldNotRead(err) => write!(f, "Could not read state: {}", err),
+        }
+    }
+}
+
+fn region_location(region: MemoryRegion) -> (usize, usize) {
+    match region {
+        MemoryRegion::IO => (0x400, 0x400),
+        MemoryRegion::Palette => (0x800, 0x400),
+        MemoryRegion::OAM => (0xC00, 0x400),
+        MemoryRegion::VRAM => (0x1000, 0x18000),
+        MemoryRegion::IWRAM => (0x19000, 0x8000),
+        MemoryRegion::EWRAM => (0x21000, 0x40000),
+    }
+}
+
+/// Maps an uncompressed mGBA savestate onto a `MachineState`. Only the
+/// CPU registers and memory are imported; PPU, timer and audio state is
+/// left out, and the pipeline is left empty to be refetched from PC.
+/// mGBA keeps PC one instruction ahead of the next one between steps, it
+/// is moved to two ahead as this emulator holds it.
+pub fn import_mgba_state(bytes: &[u8]) -> Result<MachineState, MgbaImportError> {
+    if bytes.len() < STATE_SIZE {
+        return Err(MgbaImportError::TooShort(bytes.len()));
+    }
+    let word = |offset: usize| {
+        WORD::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+    };
+    let magic = word(0);
+    if magic & 0xFF00_0000 != MGBA_MAGIC {
+        return Err(MgbaImportError::UnknownVersion(magic));
+    }
+
+    let cpsr = word(CPSR);
+    let instruction_size = if cpsr.bit_is_set(5) { 2 } else { 4 };
+    let current_bank = Bank::from_cpsr(cpsr);
+    // the active mode's registers live in gprs, the rest are banked
+    let register = |bank: Bank, register: usize| {
+        let bank = bank.for_register(register);
+        if bank == current_bank.for_register(register) {
+            word(GPRS + register * 4)
+        } else {
+            word(BANKED_REGISTERS + (bank as usize * 7 + register - 8) * 4)
+        }
+    };
+    let spsr = |bank: Bank| {
+        if bank == current_bank {
+            word(SPSR)
+        } else {
+            word(BANKED_SPSRS + bank as usize * 4)
+        }
+    };
+
+    let mut registers = [0; 16];
+    for (i, value) in registers.iter_mut().enumerate() {
+        *value = match i {
+            8..=14 => register(Bank::User, i),
+            15 => word(GPRS + i * 4).wrapping_add(instruction_size),
+            _ => word(GPRS + i * 4),
+        };
+    }
+    let mut registers_fiq = [0; 8];
+    for (i, value) in registers_fiq.iter_mut().take(7).enumerate() {
+        *value = register(Bank::Fiq, i + 8);
+    }
+    let banked_pair = |bank: Bank| [register(bank, 13), register(bank, 14)];
+
+    let cpu = CpuState {
+        registers,
+        registers_fiq,
+        registers_svc: banked_pair(Bank::Svc),
+        registers_abt: banked_pair(Bank::Abt),
+        registers_irq: banked_pair(Bank::Irq),
+        registers_und: banked_pair(Bank::Und),
+        cpsr,
+        spsr: [Bank::Fiq, Bank::Svc, Bank::Abt, Bank::Irq, Bank::Und].map(spsr),
+        prefetch: [None; 2],
+    };
+    let memory = MemoryRegion::ALL
+        .iter()
+        .map(|&region| {
+            let (offset, len) = region_location(region);
+            RegionSnapshot {
+                region,
+                bytes: bytes[offset..offset + len].to_vec(),
+            }
+        })
+        .collect();
+
+    Ok(MachineState { cpu, memory })
+}
+
+pub fn load_mgba_state(path: &str) -> Result<MachineState, MgbaImportError> {
+    let bytes = fs::read(path).map_err(|err| MgbaImportError::CouldNotRead(err.to_string()))?;
+    import_mgba_state(&bytes)
+}
+
+#[cfg(test)]
+mod mgba_tests {
+    use crate::{
+        debugger::{debugger::Debugger, terminal_commands::parse_command},
+        memory::memory::GBAMemory,
+        state::MemoryRegion,
+    };
+
+    use super::{import_mgba_state, MgbaImportError, STATE_SIZE};
+
+    fn put_word(state: &mut [u8], offset: usize, value: u32) {
+        state[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// bankedRegisters[bank][register - 8]
+    fn banked_register(bank: usize, register: usize) -> usize {
+        0x70 + (bank * 7 + register - 8) * 4
+    }
+
+    #[test]
+    fn imports_registers_from_every_bank() {
+        let mut state = vec![0; STATE_SIZE];
+        put_word(&mut state, 0, 0x0100_0009);
+        for register in 0..16 {
+            put_word(&mut state, 0x20 + register * 4, 0x1111_1111 * register as u32);
+        }
+        put_word(&mut state, 0x5C, 0x0800_0104); // pc
+        put_word(&mut state, 0x60, 0x6000_0092); // IRQ mode
+        put_word(&mut state, 0x64, 0x0000_001F); // SPSR_irq
+        put_word(&mut state, banked_register(0, 13), 0x0300_7F00); // user sp
+        put_word(&mut state, banked_register(1, 8), 0x0000_00F8); // r8_fiq
+        put_word(&mut state, banked_register(3, 13), 0x0300_7FE0); // sp_svc
+        put_word(&mut state, banked_register(3, 14), 0x0800_0100); // lr_svc
+        put_word(&mut state, 0x118 + 3 * 4, 0x0000_00D3); // SPSR_svc
+        put_word(&mut state, 0x19000, 0xDEAD_BEEF); // IWRAM
+
+        let imported = import_mgba_state(&state).unwrap();
+
+        let cpu = &imported.cpu;
+        assert_eq!(cpu.cpsr, 0x6000_0092);
+        assert_eq!(cpu.registers[0], 0);
+        assert_eq!(cpu.registers[8], 0x8888_8888);
+        assert_eq!(cpu.registers[13], 0x0300_7F00);
+        // one instruction further ahead than mGBA keeps it
+        assert_eq!(cpu.registers[15], 0x0800_0108);
+        assert_eq!(cpu.registers_irq, [0xDDDD_DDDD, 0xEEEE_EEEE]);
+        assert_eq!(cpu.registers_svc, [0x0300_7FE0, 0x0800_0100]);
+        assert_eq!(cpu.registers_fiq[0], 0xF8);
+        // spsr is ordered fiq, svc, abt, irq, und
+        assert_eq!(cpu.spsr, [0, 0xD3, 0, 0x1F, 0]);
+
+        let iwram = imported
+            .memory
+            .iter()
+            .find(|snapshot| snapshot.region == MemoryRegion::IWRAM)
+            .unwrap();
+        assert_eq!(iwram.bytes[..4], [0xEF, 0xBE, 0xAD, 0xDE]);
+    }
+
+    #[test]
+    fn rejects_data_without_the_mgba_magic() {
+        let state = vec![0; STATE_SIZE];
+
+        assert_eq!(import_mgba_state(&state), Err(MgbaImportError::UnknownVersion(0)));
+        assert_eq!(import_mgba_state(&state[..0x100]), Err(MgbaImportError::TooShort(0x100)));
+    }
+
+    #[test]
+    fn loaded_state_resumes_from_its_registers_and_memory() {
+        let mut state = vec![0; STATE_SIZE];
+        put_word(&mut state, 0, 0x0100_0009);
+        put_word(&mut state, 0x20 + 4, 0x0300_0100); // r1
+        put_word(&mut state, 0x20 + 13 * 4, 0x0300_7F00); // sp
+        put_word(&mut state, 0x5C, 0x0300_0004); // pc, next is 0x3000000
+        put_word(&mut state, 0x60, 0x0000_001F); // system mode, ARM
+        put_word(&mut state, 0x19000, 0xE281_0002); // add r0, r1, #2
+        put_word(&mut state, 0x19004, 0xE581_0000); // str r0, [r1]
+        put_word(&mut state, 0x21000, 0x1234_5678); // EWRAM
+        let path = std::env::temp_dir().join(format!("gba_mgba_{}.ss0", std::process::id()));
+        std::fs::write(&path, &state).unwrap();
+
+        let mut debugger = Debugger::with_memory(GBAMemory::new());
+        debugger.terminal_buffer = format!("mgbastate {}", path.display());
+        let loaded = parse_command(&mut debugger);
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.is_ok());
+
+        let gba = &mut debugger.cpu;
+        assert_eq!(gba.cpu.get_sp(), 0x0300_7F00);
+        assert_eq!(gba.cpu.next_executed_pc(), Some(0x0300_0000));
+        assert_eq!(gba.memory.readu32(0x0200_0000).data, 0x1234_5678);
+        gba.step();
+        gba.step();
+        assert_eq!(gba.cpu.get_register(0), 0x0300_0102);
+        assert_eq!(gba.memory.readu32(0x0300_0100).data, 0x0300_0102);
+    }
+}
@@ -0,0 +1,267 @@
+pub mod mgba;
+pub mod savestate;
+pub mod trace;
+
+use std::fmt::Display;
+
+use crate::{arm7tdmi::cpu::CPUMode, types::WORD};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryRegion {
+    EWRAM,
+    IWRAM,
+    IO,
+    Palette,
+    VRAM,
+    OAM,
+}
+
+impl MemoryRegion {
+    pub const ALL: [MemoryRegion; 6] = [
+        MemoryRegion::EWRAM,
+        MemoryRegion::IWRAM,
+        MemoryRegion::IO,
+        MemoryRegion::Palette,
+        MemoryRegion::VRAM,
+        MemoryRegion::OAM,
+    ];
+
+    pub fn base_address(&self) -> usize {
+        match self {
+            MemoryRegion::EWRAM => 0x2000000,
+            MemoryRegion::IWRAM => 0x3000000,
+            MemoryRegion::IO => 0x4000000,
+            MemoryRegion::Palette => 0x5000000,
+            MemoryRegion::VRAM => 0x6000000,
+            MemoryRegion::OAM => 0x7000000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CpuState {
+    pub registers: [WORD; 16],
+    pub registers_fiq: [WORD; 8],
+    pub registers_svc: [WORD; 2],
+    pub registers_abt: [WORD; 2],
+    pub registers_irq: [WORD; 2],
+    pub registers_und: [WORD; 2],
+    pub cpsr: WORD,
+    pub spsr: [WORD; 5],
+    pub prefetch: [Option<WORD>; 2],
+}
+
+impl CpuState {
+    /// The register as the mode in CPSR sees it, banked registers included.
+    pub fn register(&self, register: usize) -> WORD {
+        if register < 8 || register == 15 {
+            return self.registers[register];
+        }
+        match (self.cpsr & 0x1F) as u8 {
+            x if x == CPUMode::FIQ as u8 => self.registers_fiq[register - 8],
+            _ if register < 13 => self.registers[register],
+            x if x == CPUMode::SVC as u8 => self.registers_svc[register - 13],
+            x if x == CPUMode::ABT as u8 => self.registers_abt[register - 13],
+            x if x == CPUMode::IRQ as u8 => self.registers_irq[register - 13],
+            x if x == CPUMode::UND as u8 => self.registers_und[register - 13],
+            _ => self.registers[register],
+        }
+    }
+
+    /// The SPSR of the mode in CPSR, or None in user and system mode.
+    pub fn current_spsr(&self) -> Option<WORD> {
+        match (self.cpsr & 0x1F) as u8 {
+            x if x == CPUMode::FIQ as u8 => Some(self.spsr[0]),
+            x if x == CPUMode::SVC as u8 => Some(self.spsr[1]),
+            x if x == CPUMode::ABT as u8 => Some(self.spsr[2]),
+            x if x == CPUMode::IRQ as u8 => Some(self.spsr[3]),
+            x if x == CPUMode::UND as u8 => Some(self.spsr[4]),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegionSnapshot {
+    pub region: MemoryRegion,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MachineState {
+    pub cpu: CpuState,
+    pub memory: Vec<RegionSnapshot>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StateDifference {
+    Register {
+        name: String,
+        left: Option<WORD>,
+        right: Option<WORD>,
+    },
+    /// Differing bytes in `start..end`, addresses are absolute.
+    Memory {
+        region: MemoryRegion,
+        start: usize,
+        end: usize,
+    },
+    MissingRegion(MemoryRegion),
+}
+
+impl Display for StateDifference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let format_value = |value: &Option<WORD>| match value {
+            Some(value) => format!("{:#010X}", value),
+            None => String::from("empty"),
+        };
+        match self {
+            StateDifference::Register { name, left, right } => {
+                write!(f, "{}: {} != {}", name, format_value(left), format_value(right))
+            }
+            StateDifference::Memory { region, start, end } => {
+                write!(f, "{:?}: {:#010X}..{:#010X}", region, start, end)
+            }
+            StateDifference::MissingRegion(region) => {
+                write!(f, "{:?}: only present in one state", region)
+            }
+        }
+    }
+}
+
+const SPSR_NAMES: [&str; 5] = ["spsr_fiq", "spsr_svc", "spsr_abt", "spsr_irq", "spsr_und"];
+
+fn cpu_fields(cpu: &CpuState) -> Vec<(String, Option<WORD>)> {
+    let mut fields = Vec::new();
+    for (i, value) in cpu.registers.iter().enumerate() {
+        fields.push((format!("r{}", i), Some(*value)));
+    }
+    for (i, value) in cpu.registers_fiq.iter().enumerate() {
+        fields.push((format!("r{}_fiq", i + 8), Some(*value)));
+    }
+    let banked = [
+        ("svc", &cpu.registers_svc),
+        ("abt", &cpu.registers_abt),
+        ("irq", &cpu.registers_irq),
+        ("und", &cpu.registers_und),
+    ];
+    for (mode, registers) in banked {
+        for (i, value) in registers.iter().enumerate() {
+            fields.push((format!("r{}_{}", i + 13, mode), Some(*value)));
+        }
+    }
+    fields.push((String::from("cpsr"), Some(cpu.cpsr)));
+    for (name, value) in SPSR_NAMES.iter().zip(cpu.spsr) {
+        fields.push((name.to_string(), Some(value)));
+    }
+    for (i, value) in cpu.prefetch.iter().enumerate() {
+        fields.push((format!("prefetch[{}]", i), *value));
+    }
+    fields
+}
+
+fn diff_region(region: MemoryRegion, left: &[u8], right: &[u8]) -> Vec<StateDifference> {
+    let mut differences = Vec::new();
+    let mut range_start = None;
+    let len = left.len().max(right.len());
+    for offset in 0..=len {
+        let differs = offset < len && left.get(offset) != right.get(offset);
+        match (differs, range_start) {
+            (true, None) => range_start = Some(offset),
+            (false, Some(start)) => {
+                differences.push(StateDifference::Memory {
+                    region,
+                    start: region.base_address() + start,
+                    end: region.base_address() + offset,
+                });
+                range_start = None;
+            }
+            _ => {}
+        }
+    }
+    differences
+}
+
+/// Lists every register and memory range that differs between two states.
+/// Adjacent differing bytes are reported as a single range.
+pub fn diff_states(left: &MachineState, right: &MachineState) -> Vec<StateDifference> {
+    let mut differences: Vec<StateDifference> = cpu_fields(&left.cpu)
+        .into_iter()
+        .zip(cpu_fields(&right.cpu))
+        .filter(|((_, left), (_, right))| left != right)
+        .map(|((name, left), (_, right))| StateDifference::Register { name, left, right })
+        .collect();
+
+    for region in MemoryRegion::ALL {
+        let find = |state: &MachineState| {
+            state
+                .memory
+                .iter()
+                .find(|snapshot| snapshot.region == region)
+                .map(|snapshot| snapshot.bytes.clone())
+        };
+        match (find(left), find(right)) {
+            (Some(left), Some(right)) => differences.extend(diff_region(region, &left, &right)),
+            (None, None) => {}
+            _ => differences.push(StateDifference::MissingRegion(region)),
+        }
+    }
+    differences
+}
+
+#[cfg(test)]
+mod state_tests {
+    use crate::gba::GBA;
+
+    use super::{diff_states, MemoryRegion, StateDifference};
+
+    #[test]
+    fn identical_states_have_no_differences() {
+        let gba = GBA::new_no_bios();
+        let state = gba.capture_state();
+
+        assert!(diff_states(&state, &state).is_empty());
+    }
+
+    #[test]
+    fn reports_single_register_difference() {
+        let mut gba = GBA::new_no_bios();
+        let before = gba.capture_state();
+        gba.cpu.set_register(3, 0x1234);
+        let after = gba.capture_state();
+
+        assert_eq!(
+            diff_states(&before, &after),
+            vec![StateDifference::Register {
+                name: String::from("r3"),
+                left: Some(0),
+                right: Some(0x1234),
+            }]
+        );
+    }
+
+    #[test]
+    fn coalesces_adjacent_memory_differences() {
+        let mut gba = GBA::new_no_bios();
+        let before = gba.capture_state();
+        gba.memory.writeu32(0x3000010, 0xFFFF_FFFF);
+        gba.memory.write(0x3000020, 0x1);
+        let after = gba.capture_state();
+
+        assert_eq!(
+            diff_states(&before, &after),
+            vec![
+                StateDifference::Memory {
+                    region: MemoryRegion::IWRAM,
+                    start: 0x3000010,
+                    end: 0x3000014,
+                },
+                StateDifference::Memory {
+                    region: MemoryRegion::IWRAM,
+                    start: 0x3000020,
+                    end: 0x3000021,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,322 @@
+use std::fmt::Display;
+
+use crate::{memory::io_handlers::HaltMode, types::WORD};
+
+use super::{CpuState, MemoryRegion};
+
+const STATE_MAGIC: &[u8; 4] = b"GBAS";
+/// Bumped whenever the layout changes, older snapshots are then rejected.
+pub const STATE_VERSION: u32 = 3;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StateError {
+    NotASaveState,
+    UnsupportedVersion(u32),
+    /// The snapshot ended before `needed` bytes at `offset` could be read.
+    Truncated { offset: usize, needed: usize },
+    RegionSize {
+        region: MemoryRegion,
+        expected: usize,
+        found: usize,
+    },
+    InvalidValue { offset: usize },
+}
+
+impl Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateError::NotASaveState => write!(f, "Not a save state"),
+            StateError::UnsupportedVersion(version) => write!(
+                f,
+                "Save state version {} is not supported, expected {}",
+                version, STATE_VERSION
+            ),
+            StateError::Truncated { offset, needed } => {
+                write!(f, "Save state ends before {} bytes at {:#X}", needed, offset)
+            }
+            StateError::RegionSize {
+                region,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{:?} is {:#X} bytes in the save state, expected {:#X}",
+                region, found, expected
+            ),
+            StateError::InvalidValue { offset } => {
+                write!(f, "Invalid value in save state at {:#X}", offset)
+            }
+        }
+    }
+}
+
+/// Little endian writer for the save state layout, starting with the
+/// versioned header.
+pub struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        let mut writer = Self { bytes: Vec::new() };
+        writer.bytes.extend_from_slice(STATE_MAGIC);
+        writer.word(STATE_VERSION);
+        writer
+    }
+
+    pub fn byte(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn flag(&mut self, value: bool) {
+        self.byte(value as u8);
+    }
+
+    pub fn hword(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn word(&mut self, value: WORD) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// A presence flag followed by the value, or 0 when there is none.
+    pub fn optional_word(&mut self, value: Option<WORD>) {
+        self.flag(value.is_some());
+        self.word(value.unwrap_or(0));
+    }
+
+    pub fn halt_mode(&mut self, halt_mode: Option<HaltMode>) {
+        self.byte(match halt_mode {
+            None => 0,
+            Some(HaltMode::Halt) => 1,
+            Some(HaltMode::Stop) => 2,
+        });
+    }
+
+    pub fn cpu(&mut self, cpu: &CpuState) {
+        let banks = [
+            &cpu.registers[..],
+            &cpu.registers_fiq,
+            &cpu.registers_svc,
+            &cpu.registers_abt,
+            &cpu.registers_irq,
+            &cpu.registers_und,
+        ];
+        for register in banks.concat() {
+            self.word(register);
+        }
+        self.word(cpu.cpsr);
+        for spsr in cpu.spsr {
+            self.word(spsr);
+        }
+        for prefetched in cpu.prefetch {
+            self.optional_word(prefetched);
+        }
+    }
+
+    /// Writes the length ahead of the bytes.
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.word(bytes.len() as WORD);
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn region(&mut self, bytes: &[u8]) {
+        self.bytes(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> StateReader<'a> {
+    /// Checks the header and positions the reader after it.
+    pub fn new(bytes: &'a [u8]) -> Result<Self, StateError> {
+        if !bytes.starts_with(STATE_MAGIC) {
+            return Err(StateError::NotASaveState);
+        }
+        let mut reader = Self {
+            bytes,
+            offset: STATE_MAGIC.len(),
+        };
+        match reader.word()? {
+            STATE_VERSION => Ok(reader),
+            version => Err(StateError::UnsupportedVersion(version)),
+        }
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn take(&mut self, needed: usize) -> Result<&'a [u8], StateError> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset + needed)
+            .ok_or(StateError::Truncated {
+                offset: self.offset,
+                needed,
+            })?;
+        self.offset += needed;
+        Ok(bytes)
+    }
+
+    pub fn byte(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn hword(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn word(&mut self) -> Result<WORD, StateError> {
+        Ok(WORD::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Reads a byte that must be 0 or 1.
+    pub fn flag(&mut self) -> Result<bool, StateError> {
+        let offset = self.offset;
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::InvalidValue { offset }),
+        }
+    }
+
+    /// Reads a word that has to be below `len`, for indexing with.
+    pub fn index(&mut self, len: usize) -> Result<usize, StateError> {
+        let offset = self.offset;
+        match self.word()? as usize {
+            index if index < len => Ok(index),
+            _ => Err(StateError::InvalidValue { offset }),
+        }
+    }
+
+    pub fn optional_word(&mut self) -> Result<Option<WORD>, StateError> {
+        let present = self.flag()?;
+        let value = self.word()?;
+        Ok(present.then_some(value))
+    }
+
+    pub fn halt_mode(&mut self) -> Result<Option<HaltMode>, StateError> {
+        let offset = self.offset;
+        match self.byte()? {
+            0 => Ok(None),
+            1 => Ok(Some(HaltMode::Halt)),
+            2 => Ok(Some(HaltMode::Stop)),
+            _ => Err(StateError::InvalidValue { offset }),
+        }
+    }
+
+    /// Reads bytes written by `StateWriter::bytes`.
+    pub fn bytes(&mut self) -> Result<&'a [u8], StateError> {
+        let length = self.word()? as usize;
+        self.take(length)
+    }
+
+    /// Reads bytes written by `StateWriter::bytes` into `buffer`, which
+    /// they have to fill exactly.
+    pub fn bytes_into(&mut self, buffer: &mut [u8]) -> Result<(), StateError> {
+        let offset = self.offset;
+        let bytes = self.bytes()?;
+        if bytes.len() != buffer.len() {
+            return Err(StateError::InvalidValue { offset });
+        }
+        buffer.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn cpu(&mut self) -> Result<CpuState, StateError> {
+        let mut cpu = CpuState::default();
+        let banks: [&mut [WORD]; 6] = [
+            &mut cpu.registers,
+            &mut cpu.registers_fiq,
+            &mut cpu.registers_svc,
+            &mut cpu.registers_abt,
+            &mut cpu.registers_irq,
+            &mut cpu.registers_und,
+        ];
+        for bank in banks {
+            for register in bank.iter_mut() {
+                *register = self.word()?;
+            }
+        }
+        cpu.cpsr = self.word()?;
+        for spsr in cpu.spsr.iter_mut() {
+            *spsr = self.word()?;
+        }
+        for prefetched in cpu.prefetch.iter_mut() {
+            *prefetched = self.optional_word()?;
+        }
+        Ok(cpu)
+    }
+
+    /// Reads a region written by `StateWriter::region`, which must be
+    /// `expected` bytes long.
+    pub fn region(&mut self, region: MemoryRegion, expected: usize) -> Result<&'a [u8], StateError> {
+        let found = self.word()? as usize;
+        if found != expected {
+            return Err(StateError::RegionSize {
+                region,
+                expected,
+                found,
+            });
+        }
+        self.take(found)
+    }
+}
+
+#[cfg(test)]
+mod savestate_tests {
+    use crate::state::CpuState;
+
+    use super::{StateError, StateReader, StateWriter};
+
+    #[test]
+    fn cpu_state_round_trips() {
+        let mut cpu = CpuState::default();
+        for (i, register) in cpu.registers.iter_mut().enumerate() {
+            *register = 0x1000 + i as u32;
+        }
+        cpu.registers_fiq[6] = 0xF1F1;
+        cpu.registers_und = [0xAAAA, 0xBBBB];
+        cpu.cpsr = 0x6000_003F;
+        cpu.spsr[3] = 0x1F;
+        cpu.prefetch = [Some(0xE3A00001), None];
+
+        let mut writer = StateWriter::new();
+        writer.cpu(&cpu);
+        let bytes = writer.finish();
+
+        assert_eq!(StateReader::new(&bytes).unwrap().cpu(), Ok(cpu));
+    }
+
+    #[test]
+    fn rejects_other_versions_and_files() {
+        let mut bytes = StateWriter::new().finish();
+        bytes[4] = 0;
+
+        assert_eq!(StateReader::new(&bytes).err(), Some(StateError::UnsupportedVersion(0)));
+        assert_eq!(StateReader::new(b"PK\x03\x04").err(), Some(StateError::NotASaveState));
+    }
+}
@@ -0,0 +1,164 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::types::WORD;
+
+use super::CpuState;
+
+/// Line format of the instruction trace, chosen to match whichever
+/// reference emulator the trace is diffed against.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TraceFormat {
+    /// r0-r15 then `cpsr: XXXXXXXX`, as mGBA's trace logs them.
+    Mgba,
+    /// `r0=XXXXXXXX ... cpsr=XXXXXXXX`, as NanoBoyAdvance logs them.
+    NanoBoyAdvance,
+    /// A template where `{r0}`-`{r15}`, `{sp}`, `{lr}`, `{pc}`, `{cpsr}` and
+    /// `{spsr}` are replaced by the register. Anything else is copied as is.
+    Custom(String),
+}
+
+impl TraceFormat {
+    pub fn format(&self, state: &CpuState) -> String {
+        match self {
+            TraceFormat::Mgba => {
+                let mut line = String::new();
+                for register in 0..16 {
+                    line.push_str(&format!("{:08X} ", state.register(register)));
+                }
+                line.push_str(&format!("cpsr: {:08X}", state.cpsr));
+                line
+            }
+            TraceFormat::NanoBoyAdvance => {
+                let mut line = String::new();
+                for register in 0..16 {
+                    line.push_str(&format!("r{}={:08X} ", register, state.register(register)));
+                }
+                line.push_str(&format!("cpsr={:08X}", state.cpsr));
+                line
+            }
+            TraceFormat::Custom(template) => format_template(template, state),
+        }
+    }
+}
+
+fn template_value(name: &str, state: &CpuState) -> Option<WORD> {
+    match name {
+        "sp" => Some(state.register(13)),
+        "lr" => Some(state.register(14)),
+        "pc" => Some(state.register(15)),
+        "cpsr" => Some(state.cpsr),
+        // user and system mode have no SPSR
+        "spsr" => Some(state.current_spsr().unwrap_or(0)),
+        _ => {
+            let register = name.strip_prefix('r')?.parse::<usize>().ok()?;
+            (register < 16).then(|| state.register(register))
+        }
+    }
+}
+
+fn format_template(template: &str, state: &CpuState) -> String {
+    let mut line = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        line.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest
+            .find('}')
+            .and_then(|end| Some((end, template_value(&rest[1..end], state)?)));
+        match value {
+            Some((end, value)) => {
+                line.push_str(&format!("{:08X}", value));
+                rest = &rest[end + 1..];
+            }
+            None => {
+                line.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    line.push_str(rest);
+    line
+}
+
+impl Display for TraceFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraceFormat::Mgba => "mgba".fmt(f),
+            TraceFormat::NanoBoyAdvance => "nba".fmt(f),
+            TraceFormat::Custom(template) => write!(f, "custom {}", template),
+        }
+    }
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    /// `mgba`, `nba` or `custom <template>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, template) = s.split_once(' ').unwrap_or((s, ""));
+        match name.to_lowercase().as_str() {
+            "mgba" => Ok(TraceFormat::Mgba),
+            "nba" | "nanoboyadvance" => Ok(TraceFormat::NanoBoyAdvance),
+            "custom" if !template.is_empty() => Ok(TraceFormat::Custom(template.to_string())),
+            _ => Err(format!("Unknown trace format {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod trace_tests {
+    use rstest::rstest;
+
+    use crate::state::CpuState;
+
+    use super::TraceFormat;
+
+    /// IRQ mode with a banked SP and LR, so the formats must show those.
+    fn known_state() -> CpuState {
+        let mut state = CpuState::default();
+        for (i, register) in state.registers.iter_mut().enumerate() {
+            *register = i as u32;
+        }
+        state.registers[15] = 0x0800_0010;
+        state.registers_irq = [0x0300_7FA0, 0x0800_0200];
+        state.cpsr = 0x6000_0092;
+        state.spsr[3] = 0x0000_001F;
+        state
+    }
+
+    #[rstest]
+    #[case::mgba(
+        TraceFormat::Mgba,
+        "00000000 00000001 00000002 00000003 00000004 00000005 00000006 00000007 \
+         00000008 00000009 0000000A 0000000B 0000000C 03007FA0 08000200 08000010 cpsr: 60000092"
+    )]
+    #[case::nanoboyadvance(
+        TraceFormat::NanoBoyAdvance,
+        "r0=00000000 r1=00000001 r2=00000002 r3=00000003 r4=00000004 r5=00000005 \
+         r6=00000006 r7=00000007 r8=00000008 r9=00000009 r10=0000000A r11=0000000B \
+         r12=0000000C r13=03007FA0 r14=08000200 r15=08000010 cpsr=60000092"
+    )]
+    #[case::custom(
+        TraceFormat::Custom(String::from("pc={pc} r1={r1} sp={sp} {cpsr}/{spsr} {bad}")),
+        "pc=08000010 r1=00000001 sp=03007FA0 60000092/0000001F {bad}"
+    )]
+    fn formats_a_known_state(#[case] format: TraceFormat, #[case] expected: &str) {
+        assert_eq!(format.format(&known_state()), expected);
+    }
+
+    #[rstest]
+    #[case("mgba", TraceFormat::Mgba)]
+    #[case("NBA", TraceFormat::NanoBoyAdvance)]
+    #[case("custom {pc} {r0}", TraceFormat::Custom(String::from("{pc} {r0}")))]
+    fn parses_format_names(#[case] name: &str, #[case] expected: TraceFormat) {
+        assert_eq!(name.parse::<TraceFormat>(), Ok(expected));
+    }
+
+    #[rstest]
+    #[case(TraceFormat::Mgba)]
+    #[case(TraceFormat::NanoBoyAdvance)]
+    #[case(TraceFormat::Custom(String::from("{pc}  \"{r0}\"")))]
+    fn displayed_formats_parse_back(#[case] format: TraceFormat) {
+        assert_eq!(format.to_string().parse::<TraceFormat>(), Ok(format));
+    }
+}
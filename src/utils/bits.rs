@@ -3,7 +3,6 @@ use std::mem::size_of;
 use crate::types::{BYTE, HWORD, WORD};
 
 pub trait Bits {
-    fn twos_complement(self) -> Self;
     fn bit_is_set(&self, bit: u8) -> bool;
     fn set_bit(&mut self, bit: u8);
     fn reset_bit(&mut self, bit: u8);
@@ -30,10 +29,6 @@ impl Bits for WORD {
         assert!(bit < 32);
         return (self >> bit & 0x01) as WORD;
     }
-    
-    fn twos_complement(self) -> WORD {
-        return !self + 1
-    }
 }
 
 impl Bits for HWORD {
@@ -56,10 +51,6 @@ impl Bits for HWORD {
         assert!(bit < size_of::<Self>() as u8);
         return (self >> bit & 0x01) as Self;
     }
-    
-    fn twos_complement(self) -> Self {
-        return !self + 1
-    }
 }
 
 impl Bits for BYTE {
@@ -82,17 +73,48 @@ impl Bits for BYTE {
         assert!(bit < 8);
         return (self >> bit & 0x01) as BYTE;
     }
-    
-    fn twos_complement(self) -> BYTE {
-        return !self + 1
-    }
 }
 
+/// Sign extends `word` treating `sign_bit` as its most significant bit.
+/// Bits above `sign_bit` are ignored.
 pub fn sign_extend(word: WORD, sign_bit: u8) -> u32 {
-    if word.bit_is_set(sign_bit) {
-        let mut mask: u32 = u32::MAX;
-        mask = mask << (sign_bit + 1);
-        return word | mask;
+    assert!(sign_bit < 32);
+    let shift = 31 - sign_bit;
+    (((word << shift) as i32) >> shift) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::sign_extend;
+
+    #[rstest]
+    #[case(0x80, 7, 0xFFFF_FF80)]
+    #[case(0x7F, 7, 0x7F)]
+    #[case(0xFF, 7, 0xFFFF_FFFF)]
+    #[case(0x00, 7, 0x00)]
+    #[case(0x8000, 15, 0xFFFF_8000)]
+    #[case(0x7FFF, 15, 0x7FFF)]
+    #[case(0xFFFE, 15, 0xFFFF_FFFE)]
+    #[case(0x100, 8, 0xFFFF_FF00)]
+    #[case(0x0FE, 8, 0xFE)]
+    #[case(0x800, 11, 0xFFFF_F800)]
+    #[case(0x200_0000, 25, 0xFE00_0000)]
+    #[case(0x1FF_FFFC, 25, 0x1FF_FFFC)]
+    #[case(0x800_0000, 27, 0xF800_0000)]
+    #[case(0x8000_0000, 31, 0x8000_0000)]
+    #[case(0x1, 0, 0xFFFF_FFFF)]
+    #[case(0x0, 0, 0x0)]
+    fn sign_extends_from_the_given_bit(#[case] word: u32, #[case] sign_bit: u8, #[case] expected: u32) {
+        assert_eq!(sign_extend(word, sign_bit), expected);
+    }
+
+    #[rstest]
+    #[case(0x17F, 7, 0x7F)]
+    #[case(0xF0_0080, 7, 0xFFFF_FF80)]
+    #[case(0x3_7FFF, 15, 0x7FFF)]
+    fn sign_extend_ignores_bits_above_the_sign_bit(#[case] word: u32, #[case] sign_bit: u8, #[case] expected: u32) {
+        assert_eq!(sign_extend(word, sign_bit), expected);
     }
-    word
 }
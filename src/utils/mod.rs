@@ -1,2 +1,4 @@
 pub mod bits;
 pub mod utils;
+#[cfg(test)]
+pub mod testing;
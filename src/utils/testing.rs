@@ -0,0 +1,293 @@
+use crate::{
+    arm7tdmi::{
+        cpu::{FlagsRegister, InstructionMode, CPU},
+        disassembler::CONDITIONS,
+    },
+    gba::GBA,
+    state::diff_states,
+    types::{CYCLES, HWORD, WORD},
+};
+
+/// Executes exactly one instruction and returns the cycles it consumed.
+pub fn step_one_cycles(gba: &mut GBA) -> CYCLES {
+    gba.step().cycles
+}
+
+/// Writes `program` as consecutive ARM words starting at `address`
+/// and points the pipeline at the first instruction.
+pub fn load_arm_program(gba: &mut GBA, address: usize, program: &[WORD]) {
+    for (i, instruction) in program.iter().enumerate() {
+        gba.memory.writeu32(address + i * 4, *instruction);
+    }
+    gba.cpu.set_pc(address as WORD);
+    gba.cpu.flush_pipeline(&mut gba.memory);
+}
+
+/// Writes `program` as consecutive Thumb halfwords starting at `address`,
+/// switches to Thumb state and points the pipeline at the first one.
+pub fn load_thumb_program(gba: &mut GBA, address: usize, program: &[HWORD]) {
+    for (i, instruction) in program.iter().enumerate() {
+        gba.memory.writeu16(address + i * 2, *instruction);
+    }
+    gba.cpu.set_instruction_mode(InstructionMode::THUMB);
+    gba.cpu.set_pc(address as WORD);
+    gba.cpu.flush_pipeline(&mut gba.memory);
+}
+
+/// Runs the ARM `opcode` once under each of the 16 condition codes, in
+/// place of its own, with the N, Z, C and V flags taken from bits 31-28 of
+/// `flags`. Asserts that it takes effect under exactly the `passing`
+/// conditions and otherwise leaves the machine as a NOP would, so `opcode`
+/// must have a visible effect when it runs.
+pub fn assert_condition_codes(opcode: WORD, flags: WORD, passing: &[&str]) {
+    let run = |instruction: WORD| {
+        let mut gba = GBA::new_no_bios();
+        load_arm_program(&mut gba, 0x3000000, &[instruction]);
+        gba.cpu.cpsr = (gba.cpu.cpsr & 0x0FFF_FFFF) | (flags & 0xF000_0000);
+        gba.step();
+        // only the instruction's effects should differ, not its encoding
+        gba.memory.writeu32(0x3000000, 0);
+        gba.capture_state()
+    };
+    let nop = run(0xE1A0_0000); // mov r0, r0
+
+    let mut mismatches = Vec::new();
+    for (condition, &suffix) in CONDITIONS.iter().enumerate() {
+        // AL has no suffix in the disassembly
+        let name = if suffix.is_empty() { "AL" } else { suffix };
+        let state = run((opcode & 0x0FFF_FFFF) | (condition as WORD) << 28);
+        let executed = !diff_states(&nop, &state).is_empty();
+        if executed != passing.contains(&name) {
+            mismatches.push(format!(
+                "{name}: {}",
+                if executed { "executed" } else { "did not execute" }
+            ));
+        }
+    }
+    if !mismatches.is_empty() {
+        panic!(
+            "condition codes with flags {:#X} wrong:\n  {}",
+            flags >> 28,
+            mismatches.join("\n  ")
+        );
+    }
+}
+
+pub trait AsCpu {
+    fn as_cpu(&self) -> &CPU;
+}
+
+impl AsCpu for CPU {
+    fn as_cpu(&self) -> &CPU {
+        self
+    }
+}
+
+impl AsCpu for GBA {
+    fn as_cpu(&self) -> &CPU {
+        &self.cpu
+    }
+}
+
+/// Compares each `(name, expected)` pair against the cpu and returns a
+/// description of every mismatch. Names are registers (`r0`-`r15`, `sp`,
+/// `lr`, `pc`) or flags (`N`, `Z`, `C`, `V`).
+pub fn cpu_state_mismatches(cpu: &CPU, expected: &[(&str, WORD)]) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    for &(name, expected_value) in expected {
+        let actual = match name {
+            "N" => cpu.get_flag(FlagsRegister::N),
+            "Z" => cpu.get_flag(FlagsRegister::Z),
+            "C" => cpu.get_flag(FlagsRegister::C),
+            "V" => cpu.get_flag(FlagsRegister::V),
+            "sp" => cpu.get_register(13),
+            "lr" => cpu.get_register(14),
+            "pc" => cpu.get_pc(),
+            _ => {
+                let register = name
+                    .strip_prefix('r')
+                    .and_then(|num| num.parse::<u32>().ok())
+                    .filter(|num| *num < 16)
+                    .unwrap_or_else(|| panic!("Unknown cpu state name `{name}`"));
+                cpu.get_register(register)
+            }
+        };
+        if actual != expected_value {
+            mismatches.push(format!(
+                "{name}: expected {expected_value:#x} ({expected_value}), got {actual:#x} ({actual})"
+            ));
+        }
+    }
+    mismatches
+}
+
+/// Asserts registers and flags in one go, e.g.
+/// `assert_cpu_state!(cpu, r1 = 10, N = 0, Z = 1, C = 1, V = 0)`.
+/// Accepts a `CPU` or a `GBA`.
+macro_rules! assert_cpu_state {
+    ($target:expr, $($name:ident = $value:expr),+ $(,)?) => {{
+        let mismatches = $crate::utils::testing::cpu_state_mismatches(
+            $crate::utils::testing::AsCpu::as_cpu(&$target),
+            &[$((stringify!($name), ($value) as $crate::types::WORD)),+],
+        );
+        if !mismatches.is_empty() {
+            panic!("cpu state mismatch:\n  {}", mismatches.join("\n  "));
+        }
+    }};
+}
+
+pub(crate) use assert_cpu_state;
+
+/// Where `Harness` loads its program, in IWRAM so it can be written.
+pub const HARNESS_ADDRESS: usize = 0x3000000;
+/// `Harness::run` gives up after this many instructions.
+const HARNESS_STEP_LIMIT: usize = 100_000;
+
+/// Loads a program, runs it and checks the result in one chain, e.g.
+/// `Harness::new().asm(&[0xe3a0102a]).run().assert_reg(1, 42)`.
+pub struct Harness {
+    pub gba: GBA,
+    program_end: usize,
+}
+
+impl Harness {
+    pub fn new() -> Self {
+        Self {
+            gba: GBA::new_no_bios(),
+            program_end: HARNESS_ADDRESS,
+        }
+    }
+
+    /// Sets a register before the program runs.
+    pub fn reg(mut self, register: u32, value: WORD) -> Self {
+        self.gba.cpu.set_register(register, value);
+        self
+    }
+
+    /// Loads ARM instructions at `HARNESS_ADDRESS`.
+    pub fn asm(mut self, program: &[WORD]) -> Self {
+        load_arm_program(&mut self.gba, HARNESS_ADDRESS, program);
+        self.program_end = HARNESS_ADDRESS + program.len() * 4;
+        self
+    }
+
+    /// Loads Thumb instructions at `HARNESS_ADDRESS` and switches to Thumb.
+    pub fn thumb(mut self, program: &[HWORD]) -> Self {
+        load_thumb_program(&mut self.gba, HARNESS_ADDRESS, program);
+        self.program_end = HARNESS_ADDRESS + program.len() * 2;
+        self
+    }
+
+    /// Executes `count` instructions.
+    pub fn step(mut self, count: usize) -> Self {
+        for _ in 0..count {
+            self.gba.step();
+        }
+        self
+    }
+
+    /// Executes until the next instruction is outside the loaded program,
+    /// which is usually falling off its end.
+    pub fn run(mut self) -> Self {
+        for _ in 0..HARNESS_STEP_LIMIT {
+            match self.gba.cpu.next_executed_pc() {
+                Some(pc) if (HARNESS_ADDRESS..self.program_end).contains(&(pc as usize)) => {
+                    self.gba.step();
+                }
+                _ => return self,
+            }
+        }
+        panic!("program still running after {} instructions", HARNESS_STEP_LIMIT);
+    }
+
+    pub fn assert_reg(self, register: u32, expected: WORD) -> Self {
+        let actual = self.gba.cpu.get_register(register);
+        assert_eq!(
+            actual, expected,
+            "r{register}: expected {expected:#x} ({expected}), got {actual:#x} ({actual})"
+        );
+        self
+    }
+}
+
+impl AsCpu for Harness {
+    fn as_cpu(&self) -> &CPU {
+        &self.gba.cpu
+    }
+}
+
+#[cfg(test)]
+mod testing_tests {
+    use crate::arm7tdmi::cpu::{FlagsRegister, CPU};
+
+    use super::{cpu_state_mismatches, Harness};
+
+    fn cpu_with_state() -> CPU {
+        let mut cpu = CPU::new();
+        cpu.set_register(1, 10);
+        cpu.set_flag(FlagsRegister::Z);
+        cpu.set_flag(FlagsRegister::C);
+        cpu
+    }
+
+    #[test]
+    fn assert_cpu_state_passes_on_matching_state() {
+        let cpu = cpu_with_state();
+        assert_cpu_state!(cpu, r1 = 10, N = 0, Z = 1, C = 1, V = 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "r1: expected 0xb (11), got 0xa (10)")]
+    fn assert_cpu_state_fails_on_wrong_state() {
+        let cpu = cpu_with_state();
+        assert_cpu_state!(cpu, r1 = 11, Z = 1);
+    }
+
+    #[test]
+    fn cpu_state_mismatches_reports_every_mismatch() {
+        let cpu = cpu_with_state();
+        let mismatches = cpu_state_mismatches(&cpu, &[("r1", 10), ("N", 1), ("V", 1)]);
+        assert_eq!(mismatches.len(), 2);
+    }
+
+    #[test]
+    fn harness_runs_an_arithmetic_sequence() {
+        Harness::new()
+            .asm(&[
+                0xe3a00005, // mov r0, #5
+                0xe2801025, // add r1, r0, #37
+                0xe0412000, // sub r2, r1, r0
+            ])
+            .run()
+            .assert_reg(0, 5)
+            .assert_reg(1, 42)
+            .assert_reg(2, 37);
+    }
+
+    #[test]
+    fn harness_runs_a_loop_until_it_falls_through() {
+        let harness = Harness::new()
+            .reg(1, 5)
+            .asm(&[
+                0xe3a00000, // mov r0, #0
+                0xe0800001, // loop: add r0, r0, r1
+                0xe2511001, // subs r1, r1, #1
+                0x1afffffc, // bne loop
+            ])
+            .run();
+        assert_cpu_state!(harness, r0 = 15, r1 = 0, Z = 1);
+    }
+
+    #[test]
+    fn harness_steps_thumb_one_instruction_at_a_time() {
+        Harness::new()
+            .thumb(&[
+                0x2001, // movs r0, #1
+                0x2002, // movs r0, #2
+            ])
+            .step(1)
+            .assert_reg(0, 1)
+            .step(1)
+            .assert_reg(0, 2);
+    }
+}
@@ -9,13 +9,15 @@ fn test_thumb_long_branch() {
     memory
         .initialize_bios(bios)
         .expect("Unable to initialize bios for CPU");
+    let mut memory: Box<dyn MemoryBus> = memory;
 
-    let cpu = Arc::new(Mutex::new(CPU::new(memory)));
+    let cpu = Arc::new(Mutex::new(CPU::new()));
 
     {
         let mut cpu = cpu.lock().unwrap();
+        cpu.flush_pipeline(&mut memory);
         for _ in 0..7 {
-            cpu.execute_cpu_cycle();
+            cpu.execute_cpu_cycle(&mut memory);
         }
         assert_eq!(cpu.get_pc(), 0x9c6);
     }